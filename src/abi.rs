@@ -0,0 +1,84 @@
+//! Contract interfaces the simulator calls, and the encoding of token calls per standard.
+
+use alloy::{
+    primitives::{Address, Bytes, U256},
+    sol,
+    sol_types::SolCall,
+};
+
+// The NatSpec of the artifacts is not Rust markdown, which doctests would try to compile
+sol!(
+    #[sol(rpc, docs = false)]
+    "artifacts/erc20.sol"
+);
+
+sol!(
+    #[sol(docs = false)]
+    "artifacts/erc1155.sol"
+);
+
+sol! {
+    interface IERC4626 {
+        function convertToAssets(uint256 shares) external view returns (uint256 assets);
+        function convertToShares(uint256 assets) external view returns (uint256 shares);
+    }
+}
+
+sol! {
+    interface IERC721 {
+        function ownerOf(uint256 tokenId) external view returns (address owner);
+    }
+}
+
+sol! {
+    interface IERC20Allowance {
+        function increaseAllowance(address spender, uint256 addedValue) external returns (bool);
+    }
+}
+
+sol! {
+    interface IPausable {
+        error EnforcedPause();
+    }
+}
+
+sol! {
+    interface IGasPriceOracle {
+        function getL1Fee(bytes memory _data) external view returns (uint256);
+    }
+}
+
+/// Interface of `token_in`, selecting how its balance and approval calls are encoded
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum TokenStandard {
+    #[default]
+    Erc20,
+    /// Multi-token contract, `id` selecting the token within it
+    Erc1155 { id: U256 },
+}
+
+impl TokenStandard {
+    pub fn balance_of_calldata(&self, account: Address) -> Bytes {
+        match self {
+            TokenStandard::Erc20 => IERC20::balanceOfCall { account }.abi_encode(),
+            TokenStandard::Erc1155 { id } => {
+                IERC1155::balanceOfCall { account, id: *id }.abi_encode()
+            }
+        }
+        .into()
+    }
+
+    /// `allowance` for ERC20, `isApprovedForAll` for ERC1155, whose approvals cover every
+    /// amount of every id
+    pub fn allowance_calldata(&self, owner: Address, spender: Address) -> Bytes {
+        match self {
+            TokenStandard::Erc20 => IERC20::allowanceCall { owner, spender }.abi_encode(),
+            TokenStandard::Erc1155 { .. } => IERC1155::isApprovedForAllCall {
+                account: owner,
+                operator: spender,
+            }
+            .abi_encode(),
+        }
+        .into()
+    }
+}
@@ -1,11 +1,10 @@
 use alloy::{
     network::Ethereum,
-    primitives::{Address, U256},
+    primitives::{Address, U256, keccak256},
     providers::{
         Identity, RootProvider,
         fillers::{BlobGasFiller, ChainIdFiller, FillProvider, GasFiller, JoinFill, NonceFiller},
     },
-    sol,
     sol_types::{SolCall, SolValue},
 };
 use revm::{
@@ -16,250 +15,1041 @@ use revm::{
         tx::TxEnvBuildError,
     },
     context_interface::result::ExecutionResult,
-    database::{AlloyDB, CacheDB, DBTransportError, EmptyDB, WrapDatabaseAsync},
+    database::{AlloyDB, CacheDB, DBTransportError, DatabaseRef, EmptyDB, WrapDatabaseAsync},
     interpreter::{
-        CallInputs, CallOutcome, Interpreter, interpreter::EthInterpreter, interpreter_types::Jumps,
+        CallInputs, CallOutcome, Interpreter,
+        interpreter::EthInterpreter,
+        interpreter_types::{Jumps, MemoryTr},
     },
-    primitives::{HashSet, TxKind},
+    primitives::{HashMap, HashSet, TxKind},
 };
 use std::convert::Infallible;
 use thiserror::Error;
 
-use crate::balance_slot::IERC20::balanceOfCall;
-
-sol!(
-    #[sol(rpc)]
-    "artifacts/erc20.sol"
-);
-
-pub type AlloyCacheDb = CacheDB<
-    WrapDatabaseAsync<
-        AlloyDB<
-            Ethereum,
-            FillProvider<
-                JoinFill<
-                    Identity,
-                    JoinFill<
-                        GasFiller,
-                        JoinFill<BlobGasFiller, JoinFill<NonceFiller, ChainIdFiller>>,
-                    >,
-                >,
-                RootProvider,
-            >,
-        >,
+use crate::{
+    abi::{IERC20, IERC721, TokenStandard},
+    cancellable_db::CancellableDb,
+    decode::decode_uint256,
+};
+
+pub type HttpProvider = FillProvider<
+    JoinFill<
+        Identity,
+        JoinFill<GasFiller, JoinFill<BlobGasFiller, JoinFill<NonceFiller, ChainIdFiller>>>,
     >,
+    RootProvider,
 >;
 
+/// Database caching the state `P` serves
+pub type ProviderCacheDb<P> = CacheDB<WrapDatabaseAsync<CancellableDb<AlloyDB<Ethereum, P>>>>;
+
+pub type AlloyCacheDb = ProviderCacheDb<HttpProvider>;
+
+/// Slots probed by [`find_balance_slot`] and [`find_allowance_slot`] before giving up
+pub const DEFAULT_MAX_CANDIDATES: usize = 64;
+
+const KECCAK256_OPCODE: u8 = 0x20;
 const SLOAD_OPCODE: u8 = 0x54;
+const TLOAD_OPCODE: u8 = 0x5c;
 
 #[derive(Eq, Hash, PartialEq, Clone, Debug)]
 pub struct SlotWithAddress {
     pub address: Address,
     pub slot: U256,
+    /// Set for rebasing tokens, whose `balanceOf` is the stored value scaled by an index
+    pub scale: Option<BalanceScale>,
+    /// Set when the balance only takes part of the slot, next to other fields
+    pub packing: Option<PackedBalance>,
+}
+
+/// Storing `probe` in the balance slot made `balanceOf` return `balance`.
+#[derive(Eq, Hash, PartialEq, Clone, Copy, Debug)]
+pub struct BalanceScale {
+    pub probe: U256,
+    pub balance: U256,
+}
+
+/// Balance stored in `width` bits of its slot, from bit `offset` up.
+#[derive(Eq, Hash, PartialEq, Clone, Copy, Debug)]
+pub struct PackedBalance {
+    pub offset: usize,
+    pub width: usize,
+    /// Word the slot held when it was found, whose bits outside the balance belong to
+    /// other fields and are kept by [`PackedBalance::splice`]
+    pub original: U256,
+}
+
+impl PackedBalance {
+    fn mask(&self) -> U256 {
+        (U256::MAX >> (256 - self.width)) << self.offset
+    }
+
+    /// Whether `balance` can be stored without losing its upper bits.
+    pub fn fits(&self, balance: U256) -> bool {
+        balance.bit_len() <= self.width
+    }
+
+    /// [`PackedBalance::original`] with the balance replaced by `balance`, truncated to
+    /// the field when it doesn't [fit](PackedBalance::fits).
+    pub fn splice(&self, balance: U256) -> U256 {
+        let mask = self.mask();
+        (self.original & !mask) | ((balance << self.offset) & mask)
+    }
+}
+
+/// Order in which a mapping hashes the key with its base slot
+#[derive(Eq, Hash, PartialEq, Clone, Copy, Debug)]
+pub enum MappingKind {
+    /// `keccak256(abi.encode(key, base_slot))`
+    Solidity,
+    /// `keccak256(abi.encode(base_slot, key))`, Vyper before 0.4
+    Vyper,
+}
+
+/// Mapping holding the balances of every holder of a token. Unlike a [`SlotWithAddress`],
+/// it doesn't depend on the holder it was discovered with, so it can be reused for any.
+#[derive(Eq, Hash, PartialEq, Clone, Debug)]
+pub struct BalanceLayout {
+    pub address: Address,
+    pub base_slot: U256,
+    pub kind: MappingKind,
+    /// Index of a rebasing token, the same for every holder
+    pub scale: Option<BalanceScale>,
+    /// Field the balance takes in each slot. Its `original` word is that of the holder the
+    /// layout was found with, so it has to be read again for any other.
+    pub packing: Option<PackedBalance>,
+}
+
+impl BalanceLayout {
+    /// Balance slot of `user` within the mapping.
+    pub fn user_slot(&self, user: Address) -> SlotWithAddress {
+        let slot = match self.kind {
+            MappingKind::Solidity => derive_user_slot(self.base_slot, user),
+            MappingKind::Vyper => {
+                U256::from_be_bytes(keccak256((self.base_slot, user).abi_encode()).0)
+            }
+        };
+
+        SlotWithAddress {
+            address: self.address,
+            slot,
+            scale: self.scale,
+            packing: self.packing,
+        }
+    }
+
+    /// Recovers the mapping `slot_with_address` belongs to from the `keccak256` preimages
+    /// hashed while reading it, when it is keyed by `user` directly.
+    fn recover(
+        slot_with_address: &SlotWithAddress,
+        user: Address,
+        preimages: &Preimages,
+    ) -> Option<Self> {
+        let [first, second] = preimages.get(&slot_with_address.slot)?;
+        let key = U256::from_be_bytes(user.into_word().0);
+
+        let (base_slot, kind) = if *first == key {
+            (*second, MappingKind::Solidity)
+        } else if *second == key {
+            (*first, MappingKind::Vyper)
+        } else {
+            return None;
+        };
+
+        Some(Self {
+            address: slot_with_address.address,
+            base_slot,
+            kind,
+            scale: slot_with_address.scale,
+            packing: slot_with_address.packing,
+        })
+    }
 }
 
+/// Slot of `user` in a Solidity mapping at `base_slot`, `keccak256(abi.encode(user, base_slot))`
+pub fn derive_user_slot(base_slot: U256, user: Address) -> U256 {
+    U256::from_be_bytes(keccak256((user, base_slot).abi_encode()).0)
+}
+
+/// Two-word inputs of `KECCAK256`, keyed by their hash
+type Preimages = HashMap<U256, [U256; 2]>;
+
 #[derive(Default)]
 struct SloadInspector {
     slots: HashSet<SlotWithAddress>,
-    current_address: Address,
+    /// Slots read with `TLOAD`, when `trace_transient` is set. Transient storage is
+    /// cleared after every transaction, so these can never be overridden persistently.
+    transient_slots: HashSet<SlotWithAddress>,
+    trace_transient: bool,
+    /// Inputs hashed into mapping slots, to tell the mapping a slot belongs to
+    preimages: Preimages,
+    /// Storage owner of each call frame, the innermost last, so that reads after a
+    /// nested call returns are attributed to the caller again
+    call_stack: Vec<Address>,
+}
+
+impl SloadInspector {
+    fn with_transient() -> Self {
+        Self {
+            trace_transient: true,
+            ..Default::default()
+        }
+    }
+
+    /// Records the input of a `KECCAK256` over two words already in memory.
+    fn record_preimage(&mut self, interp: &Interpreter<EthInterpreter>) {
+        let (Ok(offset), Ok(len)) = (interp.stack.peek(0), interp.stack.peek(1)) else {
+            return;
+        };
+        let Ok(offset) = usize::try_from(offset) else {
+            return;
+        };
+        if len != U256::from(64) || offset.saturating_add(64) > interp.memory.size() {
+            return;
+        }
+
+        let input = interp.memory.slice_len(offset, 64);
+        self.preimages.insert(
+            U256::from_be_bytes(keccak256(&*input).0),
+            [
+                U256::from_be_slice(&input[..32]),
+                U256::from_be_slice(&input[32..]),
+            ],
+        );
+    }
 }
 
 impl<CTX> Inspector<CTX> for SloadInspector {
     fn step(&mut self, interp: &mut Interpreter<EthInterpreter>, _: &mut CTX) {
-        let opcode = interp.bytecode.opcode();
-
-        if opcode != SLOAD_OPCODE {
-            return ();
+        let slots = match interp.bytecode.opcode() {
+            KECCAK256_OPCODE => {
+                self.record_preimage(interp);
+                return;
+            }
+            SLOAD_OPCODE => &mut self.slots,
+            TLOAD_OPCODE if self.trace_transient => &mut self.transient_slots,
+            _ => return,
         };
 
+        let address = self.call_stack.last().copied().unwrap_or_default();
         interp.stack.peek(0).ok().inspect(|storage_slot| {
-            self.slots.insert(SlotWithAddress {
-                address: self.current_address,
+            slots.insert(SlotWithAddress {
+                address,
                 slot: *storage_slot,
+                scale: None,
+                packing: None,
             });
         });
     }
 
     fn call(&mut self, _: &mut CTX, inputs: &mut CallInputs) -> Option<CallOutcome> {
-        self.current_address = inputs.target_address;
+        self.call_stack.push(inputs.target_address);
         None
     }
+
+    fn call_end(&mut self, _: &mut CTX, _: &CallInputs, _: &mut CallOutcome) {
+        self.call_stack.pop();
+    }
 }
 
 #[derive(Debug, Error)]
-#[error("getting balance failed")]
-enum BalanceOfError {
+enum ReadUintError {
+    #[error("building the view call failed: {0:?}")]
     TxBuild(TxEnvBuildError),
+    #[error("executing the view call failed")]
     TransactOne(#[from] EVMError<Infallible>),
+    #[error("execution failed: {0:?}")]
     Execution(ExecutionResult),
+    #[error("decoding the returned uint256 failed")]
     Decoding(#[from] alloy::sol_types::Error),
 }
 
-impl From<TxEnvBuildError> for BalanceOfError {
+impl From<TxEnvBuildError> for ReadUintError {
     fn from(value: TxEnvBuildError) -> Self {
-        BalanceOfError::TxBuild(value)
+        ReadUintError::TxBuild(value)
     }
 }
 
-impl From<ExecutionResult> for BalanceOfError {
+impl From<ExecutionResult> for ReadUintError {
     fn from(value: ExecutionResult) -> Self {
-        BalanceOfError::Execution(value)
+        ReadUintError::Execution(value)
     }
 }
 
-fn balance_of(
-    user_address: Address,
-    token_address: Address,
-    cache_db: &mut CacheDB<EmptyDB>,
-) -> Result<U256, BalanceOfError> {
+/// Runs a view call returning a single `uint256`, such as `balanceOf` or `allowance`.
+fn read_uint(tx_env: &TxEnv, cache_db: &mut CacheDB<EmptyDB>) -> Result<U256, ReadUintError> {
     let mut evm = Context::mainnet()
         .with_db(cache_db)
         .modify_cfg_chained(|cfg| cfg.disable_nonce_check = true)
         .build_mainnet();
 
-    let tx_env = build_balance_of_tx_env(token_address, user_address)?;
-
-    let result = evm.transact_one(tx_env)?;
+    let result = evm.transact_one(tx_env.clone())?;
 
     //TODO: check reason = return
     let output = match result {
         ExecutionResult::Success { output, .. } => output,
-        result => return Err(BalanceOfError::Execution(result)),
+        result => return Err(ReadUintError::Execution(result)),
     };
 
-    let balance = U256::abi_decode(output.data())?;
-
-    Ok(balance)
+    Ok(decode_uint256(output.data())?)
 }
 
 #[derive(Debug, Error)]
-#[error("finding balance slot failed")]
 pub enum FindSlotError {
+    #[error(transparent)]
     FindSlotByMutation(#[from] FindSlotByMutationError),
-    InspectBalanceOf(#[from] InspectBalanceOfError),
+    #[error("inspecting the storage reads of the view failed")]
+    InspectView(#[from] InspectViewError),
+    /// The slot round-tripped `balanceOf`, yet mutating it moves `totalSupply()` too,
+    /// so it likely holds a global rather than the user's balance
+    #[error(
+        "balance slot {}@{:#x} also moves totalSupply",
+        .slot.address,
+        .slot.slot
+    )]
+    AmbiguousSlot { slot: Box<SlotWithAddress> },
+    /// The view read more slots than were probed, and none of the probed ones matched
+    #[error("view read {candidates} slots, none of the {max_candidates} probed matched")]
+    TooManyCandidates {
+        candidates: usize,
+        max_candidates: usize,
+    },
 }
 
 #[derive(Debug, Error)]
-#[error("inspecting balanceOf call failed")]
-pub enum InspectBalanceOfError {
+pub enum InspectViewError {
+    #[error("building the view call failed: {0:?}")]
     TxBuild(TxEnvBuildError),
+    #[error("executing the view call failed")]
     InspectError(#[from] EVMError<DBTransportError>),
     #[error("execution failed: {0:?}")]
     Execution(ExecutionResult),
 }
 
-impl From<TxEnvBuildError> for InspectBalanceOfError {
+#[deprecated(note = "renamed to `InspectViewError`")]
+pub type InspectBalanceOfError = InspectViewError;
+
+impl From<TxEnvBuildError> for InspectViewError {
     fn from(value: TxEnvBuildError) -> Self {
-        InspectBalanceOfError::TxBuild(value)
+        InspectViewError::TxBuild(value)
     }
 }
 
-fn inspect_balance_of(
-    token_address: Address,
-    user_address: Address,
-    cache_db: &mut AlloyCacheDb,
-) -> Result<SloadInspector, InspectBalanceOfError> {
-    let inspector = SloadInspector::default();
+fn inspect_view<ExtDB: DatabaseRef<Error = DBTransportError>>(
+    tx_env: &TxEnv,
+    cache_db: &mut CacheDB<ExtDB>,
+) -> Result<SloadInspector, InspectViewError> {
+    let inspector = SloadInspector::with_transient();
 
     let mut evm = Context::mainnet()
         .with_db(cache_db)
         .modify_cfg_chained(|cfg| cfg.disable_nonce_check = true)
         .build_mainnet_with_inspector(inspector);
 
-    let tx = build_balance_of_tx_env(token_address, user_address)?;
-
-    let res = evm.inspect_one_tx(tx)?;
+    let res = evm.inspect_one_tx(tx_env.clone())?;
 
     match res {
         ExecutionResult::Success {
             reason: SuccessReason::Return,
             ..
         } => Ok(evm.inspector),
-        failed => Err(InspectBalanceOfError::Execution(failed)),
+        failed => Err(InspectViewError::Execution(failed)),
     }
 }
 
-fn build_balance_of_tx_env(
+pub fn build_balance_of_tx_env(
     token_address: Address,
+    token_standard: TokenStandard,
     user_address: Address,
 ) -> Result<TxEnv, TxEnvBuildError> {
-    let encoded = balanceOfCall {
-        account: user_address,
-    }
-    .abi_encode();
+    let tx_env = TxEnv::builder()
+        .kind(TxKind::Call(token_address))
+        .data(token_standard.balance_of_calldata(user_address))
+        .build()?;
 
+    Ok(tx_env)
+}
+
+pub fn build_allowance_tx_env(
+    token_address: Address,
+    token_standard: TokenStandard,
+    owner_address: Address,
+    spender_address: Address,
+) -> Result<TxEnv, TxEnvBuildError> {
     let tx_env = TxEnv::builder()
         .kind(TxKind::Call(token_address))
-        .data(encoded.into())
+        .data(token_standard.allowance_calldata(owner_address, spender_address))
         .build()?;
 
     Ok(tx_env)
 }
 
-pub fn find_balance_slot(
+pub fn build_owner_of_tx_env(
+    nft_address: Address,
+    token_id: U256,
+) -> Result<TxEnv, TxEnvBuildError> {
+    let tx_env = TxEnv::builder()
+        .kind(TxKind::Call(nft_address))
+        .data(
+            IERC721::ownerOfCall { tokenId: token_id }
+                .abi_encode()
+                .into(),
+        )
+        .build()?;
+
+    Ok(tx_env)
+}
+
+pub fn build_total_supply_tx_env(token_address: Address) -> Result<TxEnv, TxEnvBuildError> {
+    let tx_env = TxEnv::builder()
+        .kind(TxKind::Call(token_address))
+        .data(IERC20::totalSupplyCall {}.abi_encode().into())
+        .build()?;
+
+    Ok(tx_env)
+}
+
+/// Finds the storage slot holding `user_address`'s balance of `token_address`.
+///
+/// Every slot read during `balanceOf` is a candidate, and the first one whose mutation
+/// round-trips through `balanceOf` wins. This makes the search independent of how the
+/// mapping slot is derived: Solidity uses `keccak256(abi.encode(key, base_slot))`, while
+/// Vyper (before 0.4) hashes the operands the other way round, `keccak256(abi.encode(base_slot, key))`.
+/// Both end up as an SLOAD of the final slot, which is all the inspector needs.
+pub fn find_balance_slot<ExtDB: DatabaseRef<Error = DBTransportError>>(
+    token_address: Address,
+    user_address: Address,
+    cache_db: &mut CacheDB<ExtDB>,
+) -> Result<SlotWithAddress, FindSlotError> {
+    find_token_balance_slot(
+        token_address,
+        TokenStandard::Erc20,
+        user_address,
+        false,
+        DEFAULT_MAX_CANDIDATES,
+        cache_db,
+    )
+}
+
+/// Base slots [`find_well_known_balance_slot`] tries, where OpenZeppelin (slot 0) and
+/// most other ERC20s declare their balance mapping
+const WELL_KNOWN_BASE_SLOTS: u64 = 10;
+
+/// [`find_balance_slot`], trying [`find_well_known_balance_slot`] first. The full search
+/// only runs for tokens keeping their balances anywhere else.
+pub fn find_balance_slot_fast<ExtDB: DatabaseRef<Error = DBTransportError>>(
+    token_address: Address,
+    user_address: Address,
+    cache_db: &mut CacheDB<ExtDB>,
+) -> Result<SlotWithAddress, FindSlotError> {
+    match find_well_known_balance_slot(token_address, user_address, cache_db)? {
+        Some(slot_with_address) => Ok(slot_with_address),
+        None => find_balance_slot(token_address, user_address, cache_db),
+    }
+}
+
+/// Probes the slot of `user_address` in a Solidity mapping at each base slot from 0 to 9
+/// on the token itself, without tracing which slots `balanceOf`
+/// reads. Returns the first one round-tripping through `balanceOf` unscaled, or `None`
+/// when balances are kept anywhere else: hashed the Vyper way, scaled, in another
+/// contract, or at a higher base slot.
+pub fn find_well_known_balance_slot<ExtDB: DatabaseRef<Error = DBTransportError>>(
+    token_address: Address,
+    user_address: Address,
+    cache_db: &mut CacheDB<ExtDB>,
+) -> Result<Option<SlotWithAddress>, FindSlotError> {
+    let layout = find_well_known_balance_layout(token_address, user_address, cache_db)?;
+
+    Ok(layout.map(|layout| layout.user_slot(user_address)))
+}
+
+/// [`find_well_known_balance_slot`], as the mapping the slot belongs to
+pub fn find_well_known_balance_layout<ExtDB: DatabaseRef<Error = DBTransportError>>(
+    token_address: Address,
+    user_address: Address,
+    cache_db: &mut CacheDB<ExtDB>,
+) -> Result<Option<BalanceLayout>, FindSlotError> {
+    let tx_env = build_balance_of_tx_env(token_address, TokenStandard::Erc20, user_address)
+        .map_err(InspectViewError::from)?;
+    // Loads the code and state `balanceOf` runs with, for the isolated database to serve
+    inspect_view(&tx_env, cache_db)?;
+
+    let mut isolated_db = CacheDB::new(EmptyDB::default());
+    isolated_db.cache = cache_db.cache.clone();
+
+    let found = (0..WELL_KNOWN_BASE_SLOTS)
+        .map(|base_slot| BalanceLayout {
+            address: token_address,
+            base_slot: U256::from(base_slot),
+            kind: MappingKind::Solidity,
+            scale: None,
+            packing: None,
+        })
+        .find(|layout| {
+            test_slot(
+                &tx_env,
+                &layout.user_slot(user_address),
+                TARGET_VALUE,
+                &mut isolated_db,
+            )
+            .is_ok_and(|balance| balance == TARGET_VALUE)
+        });
+
+    Ok(found.map(|layout| BalanceLayout {
+        packing: find_packing(&tx_env, &layout.user_slot(user_address), &mut isolated_db),
+        ..layout
+    }))
+}
+
+/// How a balance slot was found, to tell apart slot detection behaviours when debugging
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SlotStrategy {
+    /// Probing well-known mapping slots, see [`find_well_known_balance_slot`]
+    FastPath,
+    /// Tracing the slots `balanceOf` reads and mutating each, see [`find_balance_slot`]
+    FullSearch,
+    /// Either of them, the balance taking only part of the slot, see [`PackedBalance`]
+    Packed,
+    /// The full search, `balanceOf` scaling the stored value, see [`BalanceScale`]
+    Rebasing,
+}
+
+impl SlotStrategy {
+    /// Strategy of `slot_with_address`, found by [`find_well_known_balance_slot`] when
+    /// `fast_path` is set
+    pub fn of(slot_with_address: &SlotWithAddress, fast_path: bool) -> Self {
+        if slot_with_address.scale.is_some() {
+            SlotStrategy::Rebasing
+        } else if slot_with_address.packing.is_some() {
+            SlotStrategy::Packed
+        } else if fast_path {
+            SlotStrategy::FastPath
+        } else {
+            SlotStrategy::FullSearch
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SlotStrategy::FastPath => "fast_path",
+            SlotStrategy::FullSearch => "full_search",
+            SlotStrategy::Packed => "packed",
+            SlotStrategy::Rebasing => "rebasing",
+        }
+    }
+}
+
+/// [`find_balance_slot`] for a token of any [`TokenStandard`], e.g. the balance of one
+/// id of an ERC1155 contract.
+///
+/// With `verify_total_supply`, an ERC20 slot whose mutation also changes `totalSupply()`
+/// is rejected with [`FindSlotError::AmbiguousSlot`]. Rebasing slots and tokens without
+/// `totalSupply()` are not checked.
+///
+/// At most `max_candidates` slots are probed, those that look like mapping entries first.
+/// Should `balanceOf` read more slots than that and none of the probed ones match, the
+/// search fails with [`FindSlotError::TooManyCandidates`].
+pub fn find_token_balance_slot<ExtDB: DatabaseRef<Error = DBTransportError>>(
+    token_address: Address,
+    token_standard: TokenStandard,
+    user_address: Address,
+    verify_total_supply: bool,
+    max_candidates: usize,
+    cache_db: &mut CacheDB<ExtDB>,
+) -> Result<SlotWithAddress, FindSlotError> {
+    find_token_balance_layout(
+        token_address,
+        token_standard,
+        user_address,
+        verify_total_supply,
+        max_candidates,
+        cache_db,
+    )
+    .map(|(slot_with_address, _)| slot_with_address)
+}
+
+/// [`find_token_balance_slot`], along with the [`BalanceLayout`] the slot derives from,
+/// when it is a mapping keyed by `user_address`. Other layouts (packed or checkpointed
+/// balances, keys mixed with more than the base slot) only yield the slot of `user_address`.
+pub fn find_token_balance_layout<ExtDB: DatabaseRef<Error = DBTransportError>>(
+    token_address: Address,
+    token_standard: TokenStandard,
+    user_address: Address,
+    verify_total_supply: bool,
+    max_candidates: usize,
+    cache_db: &mut CacheDB<ExtDB>,
+) -> Result<(SlotWithAddress, Option<BalanceLayout>), FindSlotError> {
+    let tx_env = build_balance_of_tx_env(token_address, token_standard, user_address)
+        .map_err(InspectViewError::from)?;
+
+    let total_supply_tx_env = match token_standard {
+        TokenStandard::Erc20 if verify_total_supply => {
+            Some(build_total_supply_tx_env(token_address).map_err(InspectViewError::from)?)
+        }
+        _ => None,
+    };
+
+    let (slot_with_address, preimages) = find_slot(
+        &tx_env,
+        &[RebaseStrategy::Exact, RebaseStrategy::Monotonic],
+        total_supply_tx_env.as_ref(),
+        true,
+        max_candidates,
+        cache_db,
+    )?;
+    let layout = BalanceLayout::recover(&slot_with_address, user_address, &preimages);
+
+    Ok((slot_with_address, layout))
+}
+
+/// [`find_token_balance_layout`], probing well-known mapping slots first for ERC20s as
+/// [`find_balance_slot_fast`] does, along with the [`SlotStrategy`] the slot was found
+/// with. With `verify_total_supply`, a well-known slot moving `totalSupply()` is passed
+/// over for the full search, which then rejects it.
+pub fn find_token_balance_layout_fast<ExtDB: DatabaseRef<Error = DBTransportError>>(
     token_address: Address,
+    token_standard: TokenStandard,
     user_address: Address,
-    alloy_cache_db: &mut AlloyCacheDb,
+    verify_total_supply: bool,
+    max_candidates: usize,
+    cache_db: &mut CacheDB<ExtDB>,
+) -> Result<(SlotWithAddress, Option<BalanceLayout>, SlotStrategy), FindSlotError> {
+    if token_standard == TokenStandard::Erc20
+        && let Some(layout) = find_well_known_balance_layout(token_address, user_address, cache_db)?
+        && !(verify_total_supply
+            && moves_total_supply(token_address, &layout.user_slot(user_address), cache_db)?)
+    {
+        let slot_with_address = layout.user_slot(user_address);
+        let strategy = SlotStrategy::of(&slot_with_address, true);
+        return Ok((slot_with_address, Some(layout), strategy));
+    }
+
+    let (slot_with_address, layout) = find_token_balance_layout(
+        token_address,
+        token_standard,
+        user_address,
+        verify_total_supply,
+        max_candidates,
+        cache_db,
+    )?;
+    let strategy = SlotStrategy::of(&slot_with_address, false);
+
+    Ok((slot_with_address, layout, strategy))
+}
+
+/// Finds the storage slot holding the allowance of `spender_address` over
+/// `owner_address`'s `token_address`, the same way [`find_balance_slot`] does with
+/// `allowance` in place of `balanceOf`. Allowances are never scaled, so only
+/// [`RebaseStrategy::Exact`] is tried.
+pub fn find_allowance_slot<ExtDB: DatabaseRef<Error = DBTransportError>>(
+    token_address: Address,
+    owner_address: Address,
+    spender_address: Address,
+    cache_db: &mut CacheDB<ExtDB>,
+) -> Result<SlotWithAddress, FindSlotError> {
+    let tx_env = build_allowance_tx_env(
+        token_address,
+        TokenStandard::Erc20,
+        owner_address,
+        spender_address,
+    )
+    .map_err(InspectViewError::from)?;
+
+    find_slot(
+        &tx_env,
+        &[RebaseStrategy::Exact],
+        None,
+        false,
+        DEFAULT_MAX_CANDIDATES,
+        cache_db,
+    )
+    .map(|(slot_with_address, _)| slot_with_address)
+}
+
+/// Finds the storage slot holding the owner of the ERC721 `token_id` of `nft_address`,
+/// the same way [`find_balance_slot`] does with `ownerOf` in place of `balanceOf`, the
+/// returned address read as a `uint256`. The owner usually comes with a [`PackedBalance`]
+/// of 160 bits, so that fields packed next to it, such as the timestamp and flags of
+/// ERC721A, are kept.
+pub fn find_owner_slot<ExtDB: DatabaseRef<Error = DBTransportError>>(
+    nft_address: Address,
+    token_id: U256,
+    cache_db: &mut CacheDB<ExtDB>,
 ) -> Result<SlotWithAddress, FindSlotError> {
-    let inspector = inspect_balance_of(token_address, user_address, alloy_cache_db)?;
+    let tx_env = build_owner_of_tx_env(nft_address, token_id).map_err(InspectViewError::from)?;
 
-    //TODO: remove clone
-    let cached_accounts = alloy_cache_db.cache.accounts.clone();
+    find_slot(
+        &tx_env,
+        &[RebaseStrategy::Exact],
+        None,
+        true,
+        DEFAULT_MAX_CANDIDATES,
+        cache_db,
+    )
+    .map(|(slot_with_address, _)| slot_with_address)
+}
+
+/// Whether writing to `slot_with_address` changes what `totalSupply()` of `token_address`
+/// returns. Tokens without `totalSupply()` never do.
+fn moves_total_supply<ExtDB: DatabaseRef<Error = DBTransportError>>(
+    token_address: Address,
+    slot_with_address: &SlotWithAddress,
+    cache_db: &mut CacheDB<ExtDB>,
+) -> Result<bool, FindSlotError> {
+    let tx_env = build_total_supply_tx_env(token_address).map_err(InspectViewError::from)?;
+    // Loads the storage `totalSupply` reads, for the isolated database to serve it
+    if inspect_view(&tx_env, cache_db).is_err() {
+        return Ok(false);
+    }
 
     let mut isolated_db = CacheDB::new(EmptyDB::default());
-    isolated_db.cache.accounts = cached_accounts;
+    isolated_db.cache = cache_db.cache.clone();
 
-    let slot_with_address =
-        find_slot_by_mutation(user_address, token_address, &inspector, &mut isolated_db)?;
+    Ok(moves_invariant(
+        &tx_env,
+        slot_with_address,
+        &mut isolated_db,
+    ))
+}
 
-    Ok(slot_with_address)
+/// Whether writing to `slot_with_address` changes what the `invariant` view returns
+fn moves_invariant(
+    invariant: &TxEnv,
+    slot_with_address: &SlotWithAddress,
+    isolated_db: &mut CacheDB<EmptyDB>,
+) -> bool {
+    let Ok(before) = read_uint(invariant, isolated_db) else {
+        return false;
+    };
+
+    test_slot(invariant, slot_with_address, TARGET_VALUE, isolated_db)
+        .is_ok_and(|after| before != after)
+}
+
+/// `invariant` is a view that must return the same value whatever the found slot holds,
+/// unless the slot is scaled. Also returns the `keccak256` preimages the view hashed.
+///
+/// With `detect_packing`, an unscaled slot is also checked for [`PackedBalance`].
+fn find_slot<ExtDB: DatabaseRef<Error = DBTransportError>>(
+    tx_env: &TxEnv,
+    strategies: &[RebaseStrategy],
+    invariant: Option<&TxEnv>,
+    detect_packing: bool,
+    max_candidates: usize,
+    cache_db: &mut CacheDB<ExtDB>,
+) -> Result<(SlotWithAddress, Preimages), FindSlotError> {
+    let inspector = inspect_view(tx_env, cache_db)?;
+    // Also loads the storage the invariant reads, for the isolated database to serve it
+    let invariant = invariant.filter(|invariant| inspect_view(invariant, cache_db).is_ok());
+
+    // The whole cache, as code the accounts only reference by hash lives in `contracts`
+    let mut isolated_db = CacheDB::new(EmptyDB::default());
+    isolated_db.cache = cache_db.cache.clone();
+
+    let slot_with_address = find_slot_by_mutation(
+        tx_env,
+        &inspector,
+        strategies,
+        max_candidates,
+        &mut isolated_db,
+    )?;
+
+    if let Some(invariant) = invariant
+        && slot_with_address.scale.is_none()
+        && moves_invariant(invariant, &slot_with_address, &mut isolated_db)
+    {
+        return Err(FindSlotError::AmbiguousSlot {
+            slot: Box::new(slot_with_address),
+        });
+    }
+
+    let packing = match detect_packing && slot_with_address.scale.is_none() {
+        true => find_packing(tx_env, &slot_with_address, &mut isolated_db),
+        false => None,
+    };
+
+    Ok((
+        SlotWithAddress {
+            packing,
+            ..slot_with_address
+        },
+        inspector.preimages,
+    ))
+}
+
+/// Finds the field `balanceOf` reads out of a slot holding more than the balance. With
+/// every bit of the slot set, a field of `width` bits reads as `2^width - 1`, and with
+/// only bit `offset` set, it reads as one. Solidity packs fields on byte boundaries, so
+/// only those offsets are tried. Returns `None` when the balance takes the whole slot.
+fn find_packing(
+    tx_env: &TxEnv,
+    slot_with_address: &SlotWithAddress,
+    cache_db: &mut CacheDB<EmptyDB>,
+) -> Option<PackedBalance> {
+    let all_set = test_slot(tx_env, slot_with_address, U256::MAX, cache_db).ok()?;
+    let width = all_set.bit_len();
+    if width == 0 || width == 256 || all_set != U256::MAX >> (256 - width) {
+        return None;
+    }
+
+    let offset = (0..=256 - width).step_by(8).find(|offset| {
+        test_slot(tx_env, slot_with_address, U256::ONE << offset, cache_db)
+            .is_ok_and(|balance| balance == U256::ONE)
+    })?;
+
+    let original = cache_db
+        .load_account(slot_with_address.address)
+        .ok()?
+        .storage
+        .get(&slot_with_address.slot)
+        .copied()
+        .unwrap_or_default();
+
+    Some(PackedBalance {
+        offset,
+        width,
+        original,
+    })
 }
 
 const TARGET_VALUE: U256 = U256::from_limbs([1234567890, 0, 0, 0]);
 
+/// No candidate slot round-tripped the probe.
 #[derive(Debug, Error)]
-#[error("finding slot by mutation failed")]
-pub struct FindSlotByMutationError;
+#[error(
+    "finding slot by mutation failed, tried: {}{}",
+    format_attempts(.attempts),
+    format_transient_slots(.transient_slots)
+)]
+pub struct FindSlotByMutationError {
+    /// Every candidate slot, with what the view call returned once the probe was stored in it
+    pub attempts: Vec<(SlotWithAddress, Result<U256, String>)>,
+    /// Transient slots the view read with `TLOAD`, which no override can set
+    pub transient_slots: Vec<SlotWithAddress>,
+}
 
+fn format_transient_slots(transient_slots: &[SlotWithAddress]) -> String {
+    if transient_slots.is_empty() {
+        return String::new();
+    }
+
+    let slots = transient_slots
+        .iter()
+        .map(|slot_with_address| {
+            format!(
+                "{}@{:#x}",
+                slot_with_address.address, slot_with_address.slot
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("; also read transient slots {slots}, which cannot be overridden persistently")
+}
+
+fn format_attempts(attempts: &[(SlotWithAddress, Result<U256, String>)]) -> String {
+    if attempts.is_empty() {
+        return "no slot was read".to_string();
+    }
+
+    attempts
+        .iter()
+        .map(|(slot_with_address, result)| {
+            let outcome = match result {
+                Ok(value) => format!("returned {value}"),
+                Err(error) => format!("failed: {error}"),
+            };
+            format!(
+                "{}@{:#x} {outcome}",
+                slot_with_address.address, slot_with_address.slot
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// How a mutated candidate slot has to show up in `balanceOf` for the slot to be accepted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RebaseStrategy {
+    /// `balanceOf` returns the stored value as is
+    Exact,
+    /// `balanceOf` multiplies the stored value by an index (aTokens, stETH shares), so it
+    /// only has to grow with it. The observed ratio is kept as the slot's [`BalanceScale`].
+    Monotonic,
+}
+
+/// Tries every candidate with each strategy in turn. Balances put [`RebaseStrategy::Exact`]
+/// first, so that plain tokens never match a global slot that merely scales balances.
+///
+/// Slots the view read from another contract, e.g. a helper it called into, are recorded
+/// under that contract. Should none of the candidates match, those slots are tried again
+/// on the token itself.
+///
+/// Each candidate costs a full execution of the view, so no more than `max_candidates`
+/// are probed across both rounds, in the order of [`prioritize_candidates`].
 fn find_slot_by_mutation(
-    user_address: Address,
-    token_address: Address,
+    tx_env: &TxEnv,
     inspector: &SloadInspector,
+    strategies: &[RebaseStrategy],
+    max_candidates: usize,
     cache_db: &mut CacheDB<EmptyDB>,
-) -> Result<SlotWithAddress, FindSlotByMutationError> {
-    for slot_with_address in inspector.slots.iter() {
-        let new_balance = test_slot(user_address, token_address, slot_with_address, cache_db);
+) -> Result<SlotWithAddress, FindSlotError> {
+    let on_token = match tx_env.kind {
+        TxKind::Call(token) => inspector
+            .slots
+            .iter()
+            .filter(|slot_with_address| slot_with_address.address != token)
+            .map(|slot_with_address| SlotWithAddress {
+                address: token,
+                ..slot_with_address.clone()
+            })
+            .filter(|slot_with_address| !inspector.slots.contains(slot_with_address))
+            .collect::<HashSet<_>>(),
+        TxKind::Create => HashSet::default(),
+    };
+    let candidates = inspector.slots.len() + on_token.len();
 
-        if let Ok(new_balance) = new_balance {
-            if new_balance == TARGET_VALUE {
-                return Ok(slot_with_address.clone());
+    let mut remaining = max_candidates;
+    let mut attempts = Vec::new();
+    for round in [inspector.slots.clone(), on_token] {
+        let round = prioritize_candidates(round, &inspector.preimages);
+        let probed = round.len().min(remaining);
+        remaining -= probed;
+
+        let round_attempts = test_candidates(tx_env, round.into_iter().take(probed), cache_db);
+        if let Some(slot_with_address) =
+            match_attempts(tx_env, &round_attempts, strategies, cache_db)
+        {
+            return Ok(slot_with_address);
+        }
+        attempts.extend(round_attempts);
+    }
+
+    if candidates > max_candidates {
+        return Err(FindSlotError::TooManyCandidates {
+            candidates,
+            max_candidates,
+        });
+    }
+
+    Err(FindSlotByMutationError {
+        attempts,
+        transient_slots: inspector.transient_slots.iter().cloned().collect(),
+    }
+    .into())
+}
+
+/// Orders candidates so that slots looking like mapping entries come first: outputs of a
+/// traced `KECCAK256`, then any slot above 128 bits, which plain state variables never
+/// reach. Ties are broken by slot, so that the same candidates are probed every time.
+fn prioritize_candidates(
+    candidates: HashSet<SlotWithAddress>,
+    preimages: &Preimages,
+) -> Vec<SlotWithAddress> {
+    let mut candidates = candidates.into_iter().collect::<Vec<_>>();
+    candidates.sort_by_key(|slot_with_address| {
+        let hashed = preimages.contains_key(&slot_with_address.slot);
+        let large = slot_with_address.slot > U256::from(u128::MAX);
+        (
+            !hashed,
+            !large,
+            slot_with_address.address,
+            slot_with_address.slot,
+        )
+    });
+
+    candidates
+}
+
+/// Stores [`TARGET_VALUE`] in each candidate, recording what the view returns.
+fn test_candidates(
+    tx_env: &TxEnv,
+    candidates: impl Iterator<Item = SlotWithAddress>,
+    cache_db: &mut CacheDB<EmptyDB>,
+) -> Vec<(SlotWithAddress, Result<U256, String>)> {
+    candidates
+        .map(|slot_with_address| {
+            let balance = test_slot(tx_env, &slot_with_address, TARGET_VALUE, cache_db)
+                .map_err(|e| format!("{:#}", anyhow::Error::from(e)));
+            (slot_with_address, balance)
+        })
+        .collect()
+}
+
+fn match_attempts(
+    tx_env: &TxEnv,
+    attempts: &[(SlotWithAddress, Result<U256, String>)],
+    strategies: &[RebaseStrategy],
+    cache_db: &mut CacheDB<EmptyDB>,
+) -> Option<SlotWithAddress> {
+    for strategy in strategies {
+        for (slot_with_address, balance) in attempts {
+            let Ok(balance) = balance else {
+                continue;
+            };
+
+            let scale = match_slot(*strategy, tx_env, slot_with_address, *balance, cache_db);
+
+            if let Some(scale) = scale {
+                return Some(SlotWithAddress {
+                    scale,
+                    ..slot_with_address.clone()
+                });
             }
         }
     }
 
-    Err(FindSlotByMutationError)
+    None
+}
+
+/// Returns the scale of an accepted slot, `Some(None)` meaning `balanceOf` is not scaled.
+/// `balance` is what the view returned with [`TARGET_VALUE`] stored in the slot.
+fn match_slot(
+    strategy: RebaseStrategy,
+    tx_env: &TxEnv,
+    slot_with_address: &SlotWithAddress,
+    balance: U256,
+    cache_db: &mut CacheDB<EmptyDB>,
+) -> Option<Option<BalanceScale>> {
+    match strategy {
+        RebaseStrategy::Exact => (balance == TARGET_VALUE).then_some(None),
+        RebaseStrategy::Monotonic => {
+            let doubled_balance = test_slot(
+                tx_env,
+                slot_with_address,
+                TARGET_VALUE * U256::from(2),
+                cache_db,
+            )
+            .ok()?;
+
+            (!balance.is_zero() && doubled_balance > balance).then_some(Some(BalanceScale {
+                probe: TARGET_VALUE,
+                balance,
+            }))
+        }
+    }
 }
 
 #[derive(Debug, Error)]
-#[error("testing slot failed")]
 enum TestSlotError {
-    BalanceOf(#[from] BalanceOfError),
+    #[error("reading the view back from the probed slot failed")]
+    ReadUint(#[from] ReadUintError),
+    #[error(transparent)]
     Infallible(#[from] Infallible),
 }
 
 fn test_slot(
-    user_address: Address,
-    token_address: Address,
+    tx_env: &TxEnv,
     slot_with_address: &SlotWithAddress,
+    value: U256,
     cache_db: &mut CacheDB<EmptyDB>,
 ) -> Result<U256, TestSlotError> {
     let acc = cache_db.load_account(slot_with_address.address)?;
 
     let original_value = acc.storage.get(&slot_with_address.slot).copied();
 
-    acc.storage.insert(slot_with_address.slot, TARGET_VALUE);
+    acc.storage.insert(slot_with_address.slot, value);
 
-    let new_balance = balance_of(user_address, token_address, cache_db);
+    let new_value = read_uint(tx_env, cache_db);
 
     let acc = cache_db
         .load_account(slot_with_address.address)
@@ -274,18 +1064,911 @@ fn test_slot(
         }
     }
 
-    Ok(new_balance?)
+    Ok(new_value?)
 }
 
 #[cfg(test)]
 mod tests {
     use alloy::{
         eips::BlockId,
+        primitives::keccak256,
         providers::{Provider, ProviderBuilder},
     };
-    use revm::primitives::address;
+    use revm::primitives::{Bytes, address, bytes};
+    use std::error::Error as _;
 
     use super::*;
+    use crate::offline_db::{ERC20, OWNER_OF, PACKED_BALANCE_OF, TOKEN, USER, offline_db};
+
+    // Minimal `balanceOf(address)` returning the mapping at slot 3, ignoring the selector.
+    // Solidity-style slot: keccak256(user . 3)
+    const SOLIDITY_BALANCE_OF: Bytes = bytes!("6004355f52600360205260405f20545f5260205ff3");
+    // Vyper-style slot: keccak256(3 . user)
+    const VYPER_BALANCE_OF: Bytes = bytes!("60043560205260035f5260405f20545f5260205ff3");
+    // Rebasing: shares at keccak256(user . 3), scaled by the index at slot 0 over 1e18
+    const REBASING_BALANCE_OF: Bytes =
+        bytes!("6004355f52600360205260405f20545f5402670de0b6b3a764000090045f5260205ff3");
+
+    fn find_slot_offline(code: Bytes) -> SlotWithAddress {
+        find_slot_offline_with_storage(code, &[])
+    }
+
+    fn find_slot_offline_with_storage(code: Bytes, storage: &[(U256, U256)]) -> SlotWithAddress {
+        let mut db = offline_db(&[(TOKEN, code)]);
+        for (slot, value) in storage {
+            db.insert_account_storage(TOKEN, *slot, *value).unwrap();
+        }
+
+        find_balance_slot(TOKEN, USER, &mut db).unwrap()
+    }
+
+    #[test]
+    fn test_find_solidity_mapping_slot() {
+        let expected = keccak256((USER, U256::from(3)).abi_encode());
+
+        let slot = find_slot_offline(SOLIDITY_BALANCE_OF);
+
+        assert_eq!(slot.slot, U256::from_be_bytes(expected.0));
+        assert_eq!(slot.packing, None);
+    }
+
+    #[test]
+    fn test_find_vyper_mapping_slot() {
+        let expected = keccak256((U256::from(3), USER).abi_encode());
+
+        let slot = find_slot_offline(VYPER_BALANCE_OF);
+
+        assert_eq!(slot.slot, U256::from_be_bytes(expected.0));
+    }
+
+    #[test]
+    fn test_layout_is_reused_across_users() {
+        let other_user = address!("0x282Cd0c363CCf32629BE74A0A2B1a0Ed6680aE8e");
+
+        for (code, kind) in [
+            (SOLIDITY_BALANCE_OF, MappingKind::Solidity),
+            (VYPER_BALANCE_OF, MappingKind::Vyper),
+        ] {
+            let mut db = offline_db(&[(TOKEN, code)]);
+            let (slot, layout) = find_token_balance_layout(
+                TOKEN,
+                TokenStandard::Erc20,
+                USER,
+                false,
+                DEFAULT_MAX_CANDIDATES,
+                &mut db,
+            )
+            .unwrap();
+            let layout = layout.unwrap();
+
+            assert_eq!(layout.base_slot, U256::from(3));
+            assert_eq!(layout.kind, kind);
+            assert_eq!(layout.user_slot(USER), slot);
+            assert_eq!(
+                layout.user_slot(other_user),
+                find_balance_slot(TOKEN, other_user, &mut db).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_derive_user_slot() {
+        let expected = keccak256((USER, U256::from(3)).abi_encode());
+
+        assert_eq!(
+            derive_user_slot(U256::from(3), USER),
+            U256::from_be_bytes(expected.0)
+        );
+    }
+
+    #[test]
+    fn test_layout_keeps_scale_and_nested_base() {
+        let index = U256::from(1_100_000_000_000_000_000u64);
+        let mut db = offline_db(&[(TOKEN, REBASING_BALANCE_OF)]);
+        db.insert_account_storage(TOKEN, U256::ZERO, index).unwrap();
+        let (slot, layout) = find_token_balance_layout(
+            TOKEN,
+            TokenStandard::Erc20,
+            USER,
+            false,
+            DEFAULT_MAX_CANDIDATES,
+            &mut db,
+        )
+        .unwrap();
+        assert!(slot.scale.is_some());
+        assert_eq!(layout.unwrap().scale, slot.scale);
+
+        // ERC1155 balances are keyed by account within the mapping of the id
+        let erc1155 = bytes!("6024355f525f60205260405f206020526004355f5260405f20545f5260205ff3");
+        let id = U256::from(7);
+        let mut db = offline_db(&[(TOKEN, erc1155)]);
+        let (_, layout) = find_token_balance_layout(
+            TOKEN,
+            TokenStandard::Erc1155 { id },
+            USER,
+            false,
+            DEFAULT_MAX_CANDIDATES,
+            &mut db,
+        )
+        .unwrap();
+        let inner = keccak256((id, U256::ZERO).abi_encode());
+        assert_eq!(layout.unwrap().base_slot, U256::from_be_bytes(inner.0));
+    }
+
+    #[test]
+    fn test_packed_balance_has_no_layout() {
+        // Reads the balance straight from slot 5, whoever asks
+        let mut db = offline_db(&[(TOKEN, bytes!("6005545f5260205ff3"))]);
+
+        let (slot, layout) = find_token_balance_layout(
+            TOKEN,
+            TokenStandard::Erc20,
+            USER,
+            false,
+            DEFAULT_MAX_CANDIDATES,
+            &mut db,
+        )
+        .unwrap();
+
+        assert_eq!(slot.slot, U256::from(5));
+        assert!(layout.is_none());
+    }
+
+    #[test]
+    fn test_find_packed_balance_field() {
+        let neighbour = U256::from(0xabcdef) << 96;
+        let original = neighbour | U256::from(42);
+
+        let slot = find_slot_offline_with_storage(PACKED_BALANCE_OF, &[(U256::from(5), original)]);
+
+        assert_eq!(slot.slot, U256::from(5));
+        let packing = slot.packing.unwrap();
+        assert_eq!(
+            packing,
+            PackedBalance {
+                offset: 0,
+                width: 96,
+                original,
+            }
+        );
+        assert_eq!(packing.splice(U256::from(7)), neighbour | U256::from(7));
+        assert!(packing.fits(U256::MAX >> 160));
+        assert!(!packing.fits(U256::ONE << 96));
+    }
+
+    #[test]
+    fn test_find_nested_allowance_slot() {
+        let spender = address!("0x282Cd0c363CCf32629BE74A0A2B1a0Ed6680aE8e");
+        let inner = keccak256((USER, U256::from(4)).abi_encode());
+        let expected = keccak256((spender, inner).abi_encode());
+
+        let mut db = offline_db(&[(TOKEN, ERC20)]);
+        let slot = find_allowance_slot(TOKEN, USER, spender, &mut db).unwrap();
+
+        assert_eq!(slot.slot, U256::from_be_bytes(expected.0));
+        assert_eq!(slot.scale, None);
+    }
+
+    #[test]
+    fn test_failed_detection_reports_attempted_slots() {
+        // Reads slot 5, yet always returns zero
+        let mut db = offline_db(&[(TOKEN, bytes!("600554505f5f5260205ff3"))]);
+
+        let Err(FindSlotError::FindSlotByMutation(error)) = find_balance_slot(TOKEN, USER, &mut db)
+        else {
+            panic!("no slot should round-trip the probe");
+        };
+
+        let slot_5 = SlotWithAddress {
+            address: TOKEN,
+            slot: U256::from(5),
+            scale: None,
+            packing: None,
+        };
+        assert_eq!(error.attempts, vec![(slot_5, Ok(U256::ZERO))]);
+        assert!(
+            error
+                .to_string()
+                .ends_with(&format!("{TOKEN}@0x5 returned 0"))
+        );
+    }
+
+    #[test]
+    fn test_find_erc1155_balance_slot() {
+        // `balanceOf(account, id)` returning the nested mapping at slot 0, keyed by id then account
+        let erc1155 = bytes!("6024355f525f60205260405f206020526004355f5260405f20545f5260205ff3");
+        let id = U256::from(7);
+        let inner = keccak256((id, U256::ZERO).abi_encode());
+        let expected = keccak256((USER, inner).abi_encode());
+
+        let mut db = offline_db(&[(TOKEN, erc1155)]);
+        let slot = find_token_balance_slot(
+            TOKEN,
+            TokenStandard::Erc1155 { id },
+            USER,
+            false,
+            DEFAULT_MAX_CANDIDATES,
+            &mut db,
+        )
+        .unwrap();
+
+        assert_eq!(slot.slot, U256::from_be_bytes(expected.0));
+        assert_eq!(slot.scale, None);
+    }
+
+    #[test]
+    fn test_find_rebasing_shares_slot() {
+        let expected = keccak256((USER, U256::from(3)).abi_encode());
+        // 1.1e18
+        let index = U256::from(1_100_000_000_000_000_000u64);
+
+        let slot = find_slot_offline_with_storage(REBASING_BALANCE_OF, &[(U256::ZERO, index)]);
+
+        assert_eq!(slot.slot, U256::from_be_bytes(expected.0));
+        assert_eq!(
+            slot.scale,
+            Some(BalanceScale {
+                probe: TARGET_VALUE,
+                balance: U256::from(1_358_024_679u64),
+            })
+        );
+    }
+
+    #[test]
+    fn test_token_reading_balances_after_helper_call() {
+        let helper = address!("0x4000000000000000000000000000000000000004");
+        // Pings the helper with a STATICCALL, then reads its own mapping at slot 3, once
+        // the helper's frame returned
+        let token = [
+            bytes!("5f5f5f5f73").as_ref(),
+            helper.as_slice(),
+            bytes!("5afa50").as_ref(),
+            SOLIDITY_BALANCE_OF.as_ref(),
+        ]
+        .concat();
+        let expected = keccak256((USER, U256::from(3)).abi_encode());
+
+        let mut db = offline_db(&[(TOKEN, token.into()), (helper, bytes!("00"))]);
+        let slot = find_balance_slot(TOKEN, USER, &mut db).unwrap();
+
+        assert_eq!(slot.address, TOKEN);
+        assert_eq!(slot.slot, U256::from_be_bytes(expected.0));
+
+        // The read is attributed to the token from the start, without a retry
+        let tx_env = build_balance_of_tx_env(TOKEN, TokenStandard::Erc20, USER).unwrap();
+        let inspector = inspect_view(&tx_env, &mut db).unwrap();
+        assert_eq!(
+            inspector.slots.into_iter().collect::<Vec<_>>(),
+            [SlotWithAddress {
+                address: TOKEN,
+                slot: U256::from_be_bytes(expected.0),
+                scale: None,
+                packing: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_find_diamond_storage_slot() {
+        let facet = address!("0x4000000000000000000000000000000000000004");
+        let base = keccak256("diamond.storage.erc20");
+        // Delegates the call to the facet and returns its first word
+        let diamond = [
+            bytes!("365f5f3760205f365f73").as_ref(),
+            facet.as_slice(),
+            bytes!("5af45060205ff3").as_ref(),
+        ]
+        .concat();
+        // Reads `balances[user]` from the mapping at the diamond storage base
+        let facet_code = [
+            bytes!("6004355f527f").as_ref(),
+            base.as_slice(),
+            bytes!("60205260405f20545f5260205ff3").as_ref(),
+        ]
+        .concat();
+        let mut db = offline_db(&[(TOKEN, diamond.into()), (facet, facet_code.into())]);
+        // The facet account only references its code by hash
+        db.cache.accounts.get_mut(&facet).unwrap().info.code = None;
+        let expected = keccak256((USER, base).abi_encode());
+
+        let slot = find_balance_slot(TOKEN, USER, &mut db).unwrap();
+
+        assert_eq!(slot.address, TOKEN);
+        assert_eq!(slot.slot, U256::from_be_bytes(expected.0));
+    }
+
+    #[test]
+    fn test_transient_slots_are_traced_apart() {
+        // TLOADs slot 7 and drops it, then reads the mapping at slot 3
+        let token = [bytes!("60075c50").as_ref(), SOLIDITY_BALANCE_OF.as_ref()].concat();
+        let expected = keccak256((USER, U256::from(3)).abi_encode());
+        let mut db = offline_db(&[(TOKEN, token.into())]);
+        let tx_env = build_balance_of_tx_env(TOKEN, TokenStandard::Erc20, USER).unwrap();
+
+        let inspector = inspect_view(&tx_env, &mut db).unwrap();
+        let slot = find_balance_slot(TOKEN, USER, &mut db).unwrap();
+
+        let transient_slot = SlotWithAddress {
+            address: TOKEN,
+            slot: U256::from(7),
+            scale: None,
+            packing: None,
+        };
+        assert_eq!(
+            inspector.transient_slots,
+            HashSet::from_iter([transient_slot.clone()])
+        );
+        assert!(!inspector.slots.contains(&transient_slot));
+        assert_eq!(slot.slot, U256::from_be_bytes(expected.0));
+    }
+
+    #[test]
+    fn test_transient_balance_is_reported() {
+        // Returns the transient slot 7 as the balance
+        let mut db = offline_db(&[(TOKEN, bytes!("60075c5f5260205ff3"))]);
+
+        let Err(FindSlotError::FindSlotByMutation(error)) = find_balance_slot(TOKEN, USER, &mut db)
+        else {
+            panic!("a transient balance cannot be overridden");
+        };
+
+        assert!(error.attempts.is_empty());
+        assert!(error.to_string().ends_with(&format!(
+            "also read transient slots {TOKEN}@0x7, which cannot be overridden persistently"
+        )));
+    }
+
+    #[test]
+    fn test_slot_moving_total_supply_is_ambiguous() {
+        // Returns slot 2 to every call, `balanceOf` and `totalSupply` alike
+        let mut db = offline_db(&[(TOKEN, bytes!("6002545f5260205ff3"))]);
+
+        let unverified = find_token_balance_slot(
+            TOKEN,
+            TokenStandard::Erc20,
+            USER,
+            false,
+            DEFAULT_MAX_CANDIDATES,
+            &mut db,
+        )
+        .unwrap();
+        let verified = find_token_balance_slot(
+            TOKEN,
+            TokenStandard::Erc20,
+            USER,
+            true,
+            DEFAULT_MAX_CANDIDATES,
+            &mut db,
+        );
+
+        assert_eq!(unverified.slot, U256::from(2));
+        let Err(FindSlotError::AmbiguousSlot { slot }) = verified else {
+            panic!("the slot also moves totalSupply");
+        };
+        assert_eq!(*slot, unverified);
+    }
+
+    #[test]
+    fn test_balance_slot_keeps_total_supply() {
+        let expected = keccak256((USER, U256::from(3)).abi_encode());
+        let mut db = offline_db(&[(TOKEN, SOLIDITY_BALANCE_OF)]);
+
+        let slot = find_token_balance_slot(
+            TOKEN,
+            TokenStandard::Erc20,
+            USER,
+            true,
+            DEFAULT_MAX_CANDIDATES,
+            &mut db,
+        )
+        .unwrap();
+
+        assert_eq!(slot.slot, U256::from_be_bytes(expected.0));
+    }
+
+    // Reads slots 0 to 99 in a loop, then returns the mapping at slot 3 like
+    // SOLIDITY_BALANCE_OF
+    const LOOPING_MAPPING_BALANCE_OF: Bytes =
+        bytes!("5f5b80545060010180606411600157506004355f52600360205260405f20545f5260205ff3");
+    // Reads slots 0 to 99 in a loop, then returns slot 99
+    const LOOPING_PLAIN_BALANCE_OF: Bytes =
+        bytes!("5f5b80545060010180606411600157506063545f5260205ff3");
+
+    fn find_capped_slot(
+        code: Bytes,
+        max_candidates: usize,
+    ) -> Result<SlotWithAddress, FindSlotError> {
+        let mut db = offline_db(&[(TOKEN, code)]);
+        find_token_balance_slot(
+            TOKEN,
+            TokenStandard::Erc20,
+            USER,
+            false,
+            max_candidates,
+            &mut db,
+        )
+    }
+
+    #[test]
+    fn test_mapping_slot_is_probed_first() {
+        let slot = find_capped_slot(LOOPING_MAPPING_BALANCE_OF, 1).unwrap();
+
+        assert_eq!(slot.slot, derive_user_slot(U256::from(3), USER));
+    }
+
+    #[test]
+    fn test_too_many_candidates() {
+        let error = find_capped_slot(LOOPING_PLAIN_BALANCE_OF, 16).unwrap_err();
+
+        assert!(matches!(
+            error,
+            FindSlotError::TooManyCandidates {
+                candidates: 100,
+                max_candidates: 16
+            }
+        ));
+
+        let slot = find_capped_slot(LOOPING_PLAIN_BALANCE_OF, 100).unwrap();
+        assert_eq!(slot.slot, U256::from(99));
+    }
+
+    #[test]
+    fn test_plain_token_has_no_scale() {
+        let slot = find_slot_offline(SOLIDITY_BALANCE_OF);
+
+        assert_eq!(slot.scale, None);
+    }
+
+    #[test]
+    fn test_well_known_slot_is_found_without_search() {
+        let mut db = offline_db(&[(TOKEN, SOLIDITY_BALANCE_OF)]);
+        let expected = find_balance_slot(TOKEN, USER, &mut db).unwrap();
+
+        let slot = find_well_known_balance_slot(TOKEN, USER, &mut db).unwrap();
+
+        assert_eq!(slot, Some(expected));
+    }
+
+    #[test]
+    fn test_fast_path_falls_back_to_search() {
+        let mut db = offline_db(&[(TOKEN, VYPER_BALANCE_OF)]);
+        let expected = keccak256((U256::from(3), USER).abi_encode());
+
+        assert_eq!(
+            find_well_known_balance_slot(TOKEN, USER, &mut db).unwrap(),
+            None
+        );
+        let slot = find_balance_slot_fast(TOKEN, USER, &mut db).unwrap();
+        assert_eq!(slot.slot, U256::from_be_bytes(expected.0));
+    }
+
+    #[test]
+    fn test_fast_path_checks_total_supply() {
+        // Returns `balances[USER]` of the mapping at slot 0 to every call, `totalSupply`
+        // included
+        let token =
+            bytes!("736698192c6e70186ebe73e2785ac85a8f5b85b0525f525f60205260405f20545f5260205ff3");
+        let mut db = offline_db(&[(TOKEN, token)]);
+
+        let (slot, _, strategy) = find_token_balance_layout_fast(
+            TOKEN,
+            TokenStandard::Erc20,
+            USER,
+            false,
+            DEFAULT_MAX_CANDIDATES,
+            &mut db,
+        )
+        .unwrap();
+        let verified = find_token_balance_layout_fast(
+            TOKEN,
+            TokenStandard::Erc20,
+            USER,
+            true,
+            DEFAULT_MAX_CANDIDATES,
+            &mut db,
+        );
+
+        assert_eq!(strategy, SlotStrategy::FastPath);
+        let Err(FindSlotError::AmbiguousSlot { slot: ambiguous }) = verified else {
+            panic!("the slot also moves totalSupply");
+        };
+        assert_eq!(*ambiguous, slot);
+    }
+
+    #[test]
+    fn test_fast_path_keeps_packing() {
+        // The balance in the low 96 bits of the mapping entry at slot 3
+        let token =
+            bytes!("6004355f52600360205260405f20546bffffffffffffffffffffffff165f5260205ff3");
+        let mut db = offline_db(&[(TOKEN, token)]);
+
+        let slot = find_well_known_balance_slot(TOKEN, USER, &mut db)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            slot.packing.map(|packing| (packing.offset, packing.width)),
+            Some((0, 96))
+        );
+    }
+
+    #[test]
+    fn test_slot_strategy_per_token_type() {
+        let packed =
+            bytes!("6004355f52600360205260405f20546bffffffffffffffffffffffff165f5260205ff3");
+        // 1.1e18
+        let index = U256::from(1_100_000_000_000_000_000u64);
+        let cases = [
+            (SOLIDITY_BALANCE_OF, U256::ZERO, SlotStrategy::FastPath),
+            (VYPER_BALANCE_OF, U256::ZERO, SlotStrategy::FullSearch),
+            (packed, U256::ZERO, SlotStrategy::Packed),
+            (REBASING_BALANCE_OF, index, SlotStrategy::Rebasing),
+        ];
+
+        for (code, index, expected) in cases {
+            let mut db = offline_db(&[(TOKEN, code)]);
+            db.insert_account_storage(TOKEN, U256::ZERO, index).unwrap();
+
+            let (_, _, strategy) = find_token_balance_layout_fast(
+                TOKEN,
+                TokenStandard::Erc20,
+                USER,
+                false,
+                DEFAULT_MAX_CANDIDATES,
+                &mut db,
+            )
+            .unwrap();
+
+            assert_eq!(strategy, expected, "{}", expected.as_str());
+        }
+    }
+
+    #[cfg(feature = "network-tests")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_well_known_slots_of_usdc_and_dai() {
+        dotenvy::dotenv().ok();
+        let rpc_url = std::env::var("ETH_RPC")
+            .expect("ETH_RPC not set in .env")
+            .parse()
+            .unwrap();
+
+        let provider = ProviderBuilder::new().connect_http(rpc_url);
+        let block_number = BlockId::number(provider.get_block_number().await.unwrap());
+
+        let alloy_db = AlloyDB::new(provider, block_number);
+        let alloy_db = WrapDatabaseAsync::new(alloy_db).expect("No Tokio runtime available");
+        let mut alloy_cache_db = CacheDB::new(alloy_db);
+
+        let user = address!("0x6698192C6e70186ebE73E2785aC85a8f5B85b052");
+        for (token, base_slot) in [
+            // USDC, behind its FiatToken proxy
+            (address!("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48"), 9),
+            // DAI
+            (address!("0x6B175474E89094C44Da98b954EedeAC495271d0F"), 2),
+        ] {
+            let slot = find_well_known_balance_slot(token, user, &mut alloy_cache_db)
+                .unwrap()
+                .unwrap();
+
+            assert_eq!(slot.address, token);
+            assert_eq!(slot.slot, derive_user_slot(U256::from(base_slot), user));
+        }
+    }
+
+    /// What [`find_token_balance_layout`] should recover for a known token
+    #[cfg(feature = "network-tests")]
+    #[derive(Debug)]
+    enum Known {
+        /// A mapping at this base slot, hashed in this order
+        Mapping(u64, MappingKind),
+        /// A mapping at this base slot of another contract, e.g. a separate token state
+        MappingAt(Address, u64, MappingKind),
+        /// A mapping of rebasing shares at this base slot, scaled by an index
+        Scaled(u64, MappingKind),
+        /// The latest MiniMe checkpoint of the holder, in a `balances` mapping of
+        /// checkpoint arrays at this base slot
+        Checkpoint(u64),
+    }
+
+    /// Resolves the balance slot of every `(name, token, known)` at the latest block of
+    /// the node at `rpc_env`, and reports every token whose slot went missing or moved.
+    #[cfg(feature = "network-tests")]
+    async fn assert_known_slots(rpc_env: &str, tokens: &[(&str, Address, Known)]) {
+        dotenvy::dotenv().ok();
+        let rpc_url = std::env::var(rpc_env)
+            .unwrap_or_else(|_| panic!("{rpc_env} not set in .env"))
+            .parse()
+            .unwrap();
+
+        let provider = ProviderBuilder::new().connect_http(rpc_url);
+        let block_number = BlockId::number(provider.get_block_number().await.unwrap());
+
+        let alloy_db = AlloyDB::new(provider, block_number);
+        let alloy_db = WrapDatabaseAsync::new(alloy_db).expect("No Tokio runtime available");
+        let mut alloy_cache_db = CacheDB::new(alloy_db);
+
+        let user = address!("0x6698192C6e70186ebE73E2785aC85a8f5B85b052");
+
+        let mut failures = Vec::new();
+        for (name, token, known) in tokens {
+            let found = find_token_balance_layout(
+                *token,
+                TokenStandard::Erc20,
+                user,
+                false,
+                DEFAULT_MAX_CANDIDATES,
+                &mut alloy_cache_db,
+            );
+
+            let mapping = |layout: &BalanceLayout, address, base_slot, kind| {
+                layout.address == address
+                    && layout.base_slot == U256::from(base_slot)
+                    && layout.kind == kind
+            };
+            let matches = match (&found, known) {
+                (Ok((slot, Some(layout))), Known::Mapping(base_slot, kind)) => {
+                    slot.scale.is_none() && mapping(layout, *token, *base_slot, *kind)
+                }
+                (Ok((slot, Some(layout))), Known::MappingAt(address, base_slot, kind)) => {
+                    slot.scale.is_none() && mapping(layout, *address, *base_slot, *kind)
+                }
+                (Ok((slot, Some(layout))), Known::Scaled(base_slot, kind)) => {
+                    slot.scale.is_some() && mapping(layout, *token, *base_slot, *kind)
+                }
+                (Ok((slot, _)), Known::Checkpoint(base_slot)) => {
+                    // The array length sits at the mapping entry, its elements from its hash
+                    let length_slot = derive_user_slot(U256::from(*base_slot), user);
+                    let length = alloy_cache_db.storage_ref(*token, length_slot).unwrap();
+                    let first = U256::from_be_bytes(keccak256(length_slot.to_be_bytes::<32>()).0);
+
+                    slot.address == *token
+                        && slot.scale.is_none()
+                        && slot.slot == first + length.saturating_sub(U256::ONE)
+                }
+                _ => false,
+            };
+            if !matches {
+                failures.push(format!(
+                    "{name} ({token}): expected {known:?}, got {found:?}"
+                ));
+            }
+        }
+
+        assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+    }
+
+    #[cfg(feature = "network-tests")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_known_ethereum_token_slots() {
+        use MappingKind::{Solidity, Vyper};
+
+        assert_known_slots(
+            "ETH_RPC",
+            &[
+                // FiatToken proxy, the balance shares its slot with the blacklist flag
+                (
+                    "USDC",
+                    address!("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48"),
+                    Known::Mapping(9, Solidity),
+                ),
+                (
+                    "USDT",
+                    address!("0xdAC17F958D2ee523a2206206994597C13D831ec7"),
+                    Known::Mapping(2, Solidity),
+                ),
+                (
+                    "DAI",
+                    address!("0x6B175474E89094C44Da98b954EedeAC495271d0F"),
+                    Known::Mapping(2, Solidity),
+                ),
+                (
+                    "WETH",
+                    address!("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2"),
+                    Known::Mapping(3, Solidity),
+                ),
+                (
+                    "WBTC",
+                    address!("0x2260FAC5E5542a773Aa44fBCfeDf7C193bc2C599"),
+                    Known::Mapping(0, Solidity),
+                ),
+                (
+                    "LINK",
+                    address!("0x514910771AF9Ca656af840dff83E8264EcF986CA"),
+                    Known::Mapping(1, Solidity),
+                ),
+                // uint96 balances
+                (
+                    "UNI",
+                    address!("0x1f9840a85d5aF5bf1D1762F925BDADdC4201F984"),
+                    Known::Mapping(4, Solidity),
+                ),
+                (
+                    "AAVE",
+                    address!("0x7Fc66500c84A76Ad7e9c93437bFc5Ac33E2DDaE9"),
+                    Known::Mapping(0, Solidity),
+                ),
+                // DSToken
+                (
+                    "MKR",
+                    address!("0x9f8F72aA9304c8B593d555F12eF6589cC3A579A2"),
+                    Known::Mapping(1, Solidity),
+                ),
+                (
+                    "SHIB",
+                    address!("0x95aD61b0a150d79219dCF64E1E6Cc01f0B64C4cE"),
+                    Known::Mapping(0, Solidity),
+                ),
+                (
+                    "CRV",
+                    address!("0xD533a949740bb3306d119CC777fa900bA034cd52"),
+                    Known::Mapping(3, Vyper),
+                ),
+                // Curve LP token, laid out like CRV
+                (
+                    "3Crv",
+                    address!("0x6c3F90f043a72FA612cbac8115EE7e52BDe6E490"),
+                    Known::Mapping(3, Vyper),
+                ),
+                (
+                    "FRAX",
+                    address!("0x853d955aCEf822Db058eb8505911ED77F175b99e"),
+                    Known::Mapping(0, Solidity),
+                ),
+                // Aragon app, `shares` is its first state variable and the index lives in
+                // unstructured storage
+                (
+                    "stETH",
+                    address!("0xae7ab96520DE3A18E5e111B5EaAb095312D7fE84"),
+                    Known::Scaled(0, Solidity),
+                ),
+                // MiniMe token, balances are checkpointed
+                (
+                    "LDO",
+                    address!("0x5A98FcBEA516Cf06857215779Fd812CA3beF1B32"),
+                    Known::Checkpoint(8),
+                ),
+                // Synthetix proxy, balances live in a separate TokenState contract
+                (
+                    "sUSD",
+                    address!("0x57Ab1ec28D129707052df4dF418D58a2D46d5f51"),
+                    Known::MappingAt(
+                        address!("0x05a9CBe762B36632b3594DA4F082340E0e5343e8"),
+                        3,
+                        Solidity,
+                    ),
+                ),
+            ],
+        )
+        .await;
+    }
+
+    #[cfg(feature = "network-tests")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_known_base_token_slots() {
+        use MappingKind::Solidity;
+
+        assert_known_slots(
+            "BASE_RPC",
+            &[
+                (
+                    "USDC",
+                    address!("0x833589fCD6eDb6E08f4c7C32D4f71b54bdA02913"),
+                    Known::Mapping(9, Solidity),
+                ),
+                (
+                    "EURC",
+                    address!("0x60a3E35Cc302bFA44Cb288Bc5a4F316Fdb1adb42"),
+                    Known::Mapping(9, Solidity),
+                ),
+                (
+                    "WETH",
+                    address!("0x4200000000000000000000000000000000000006"),
+                    Known::Mapping(3, Solidity),
+                ),
+                // Bridged through the standard bridge as OptimismMintableERC20
+                (
+                    "USDbC",
+                    address!("0xd9aAEc86B65D86f6A7B5B1b0c42FFA531710b6CA"),
+                    Known::Mapping(0, Solidity),
+                ),
+                (
+                    "DAI",
+                    address!("0x50c5725949A6F0c72E6C4a641F24049A917DB0Cb"),
+                    Known::Mapping(0, Solidity),
+                ),
+                (
+                    "AERO",
+                    address!("0x940181a94A35A4569E4529A3CDfB74e38FD98631"),
+                    Known::Mapping(0, Solidity),
+                ),
+                // Coinbase's FiatToken forks, laid out like USDC
+                (
+                    "cbETH",
+                    address!("0x2Ae3F1Ec7F1F5012CFEab0185bfc7aa3cf0DEc22"),
+                    Known::Mapping(9, Solidity),
+                ),
+                (
+                    "cbBTC",
+                    address!("0xcbB7C0000aB88B473b1f5aFd9ef808440eed33Bf"),
+                    Known::Mapping(9, Solidity),
+                ),
+                // Lido's bridged token behind a proxy, its metadata in unstructured storage
+                // and `balanceOf` after `totalSupply`
+                (
+                    "wstETH",
+                    address!("0xc1CBa3fCea344f92D9239c08C0568f6F2F0ee452"),
+                    Known::Mapping(1, Solidity),
+                ),
+                (
+                    "DEGEN",
+                    address!("0x4ed4E862860beD51a9570b96d89aF5E1B0Efefed"),
+                    Known::Mapping(0, Solidity),
+                ),
+            ],
+        )
+        .await;
+    }
+
+    #[cfg(feature = "network-tests")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_find_balance_slot_steth_shares() -> Result<(), Box<dyn std::error::Error>> {
+        dotenvy::dotenv().ok();
+        let rpc_url = std::env::var("ETH_RPC")
+            .expect("ETH_RPC not set in .env")
+            .parse()?;
+
+        let provider = ProviderBuilder::new().connect_http(rpc_url);
+
+        let block_number = provider.get_block_number().await?;
+        let block_number = BlockId::number(block_number);
+
+        let alloy_db = AlloyDB::new(provider, block_number);
+        let alloy_db = WrapDatabaseAsync::new(alloy_db).ok_or("No Tokio runtime available")?;
+
+        let mut alloy_cache_db = CacheDB::new(alloy_db);
+
+        let user = address!("0x6698192C6e70186ebE73E2785aC85a8f5B85b052");
+
+        // Lido stETH: balanceOf = shares * totalPooledEther / totalShares
+        let token = address!("0xae7ab96520DE3A18E5e111B5EaAb095312D7fE84");
+
+        let slot = find_balance_slot(token, user, &mut alloy_cache_db)?;
+
+        assert_eq!(slot.address, token);
+        assert!(slot.scale.is_some());
+
+        Ok(())
+    }
+
+    #[cfg(feature = "network-tests")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_find_balance_slot_vyper_curve_lp() -> Result<(), Box<dyn std::error::Error>> {
+        dotenvy::dotenv().ok();
+        let rpc_url = std::env::var("ETH_RPC")
+            .expect("ETH_RPC not set in .env")
+            .parse()?;
+
+        let provider = ProviderBuilder::new().connect_http(rpc_url);
+
+        let block_number = provider.get_block_number().await?;
+        let block_number = BlockId::number(block_number);
+
+        let alloy_db = AlloyDB::new(provider, block_number);
+        let alloy_db = WrapDatabaseAsync::new(alloy_db).ok_or("No Tokio runtime available")?;
+
+        let mut alloy_cache_db = CacheDB::new(alloy_db);
+
+        let user = address!("0x6698192C6e70186ebE73E2785aC85a8f5B85b052");
+
+        // Curve 3pool LP token (3Crv), compiled with Vyper
+        let token = address!("0x6c3F90f043a72FA612cbac8115EE7e52BDe6E490");
+
+        let slot = find_balance_slot(token, user, &mut alloy_cache_db)?;
+
+        assert_eq!(slot.address, token);
+
+        Ok(())
+    }
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_find_balance_slot() -> Result<(), Box<dyn std::error::Error>> {
@@ -314,4 +1997,57 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_slot_errors_display_their_cause() {
+        let by_mutation = FindSlotError::from(FindSlotByMutationError {
+            attempts: Vec::new(),
+            transient_slots: Vec::new(),
+        });
+        assert_eq!(
+            by_mutation.to_string(),
+            "finding slot by mutation failed, tried: no slot was read"
+        );
+
+        let inspect = FindSlotError::from(InspectViewError::from(
+            TxEnvBuildError::MissingTargetForEip4844,
+        ));
+        assert_eq!(
+            inspect.to_string(),
+            "inspecting the storage reads of the view failed"
+        );
+        assert_eq!(
+            inspect.source().unwrap().to_string(),
+            "building the view call failed: MissingTargetForEip4844"
+        );
+
+        let test_slot = TestSlotError::from(ReadUintError::from(ExecutionResult::Revert {
+            gas_used: 0,
+            output: Bytes::new(),
+        }));
+        assert_eq!(
+            test_slot.to_string(),
+            "reading the view back from the probed slot failed"
+        );
+        assert_eq!(
+            test_slot.source().unwrap().to_string(),
+            "execution failed: Revert { gas_used: 0, output: 0x }"
+        );
+    }
+
+    #[test]
+    fn test_find_owner_slot() {
+        let token_id = U256::from(1);
+        let owner_slot = U256::from_be_bytes(keccak256((token_id, U256::from(2)).abi_encode()).0);
+        let mut db = offline_db(&[(TOKEN, OWNER_OF)]);
+        db.insert_account_storage(TOKEN, owner_slot, USER.into_word().into())
+            .unwrap();
+
+        let slot = find_owner_slot(TOKEN, token_id, &mut db).unwrap();
+
+        assert_eq!(slot.address, TOKEN);
+        assert_eq!(slot.slot, owner_slot);
+        let packing = slot.packing.unwrap();
+        assert_eq!((packing.offset, packing.width), (0, 160));
+    }
 }
@@ -0,0 +1,61 @@
+//! Async database whose reads give up once a cancellation token fires.
+
+use alloy::transports::TransportErrorKind;
+use revm::{
+    database::DBTransportError,
+    database_interface::async_db::DatabaseAsyncRef,
+    primitives::{Address, B256, StorageKey, StorageValue},
+    state::{AccountInfo, Bytecode},
+};
+use tokio_util::sync::CancellationToken;
+
+/// Reads through to `db` until `cancellation` fires, then fails every pending and later
+/// read. Wrapped in a `WrapDatabaseAsync`, reads block the thread polling the
+/// simulation, so racing the simulation against the token cannot stop a read stuck on
+/// an unresponsive node: the read itself has to give up.
+pub struct CancellableDb<DB> {
+    db: DB,
+    cancellation: CancellationToken,
+}
+
+impl<DB> CancellableDb<DB> {
+    pub fn new(db: DB, cancellation: CancellationToken) -> Self {
+        Self { db, cancellation }
+    }
+
+    async fn read<T>(
+        &self,
+        read: impl Future<Output = Result<T, DBTransportError>>,
+    ) -> Result<T, DBTransportError> {
+        tokio::select! {
+            _ = self.cancellation.cancelled() => {
+                Err(DBTransportError(TransportErrorKind::custom_str("read cancelled")))
+            }
+            result = read => result,
+        }
+    }
+}
+
+impl<DB: DatabaseAsyncRef<Error = DBTransportError> + Sync> DatabaseAsyncRef for CancellableDb<DB> {
+    type Error = DBTransportError;
+
+    async fn basic_async_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        self.read(self.db.basic_async_ref(address)).await
+    }
+
+    async fn code_by_hash_async_ref(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        self.read(self.db.code_by_hash_async_ref(code_hash)).await
+    }
+
+    async fn storage_async_ref(
+        &self,
+        address: Address,
+        index: StorageKey,
+    ) -> Result<StorageValue, Self::Error> {
+        self.read(self.db.storage_async_ref(address, index)).await
+    }
+
+    async fn block_hash_async_ref(&self, number: u64) -> Result<B256, Self::Error> {
+        self.read(self.db.block_hash_async_ref(number)).await
+    }
+}
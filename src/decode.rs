@@ -0,0 +1,80 @@
+//! Decoding of the single-value outputs ERC20 calls return, e.g. `balanceOf`, `allowance`
+//! or `approve`, and of revert data.
+
+use alloy::{
+    primitives::{Bytes, U256},
+    sol_types::{Error, SolValue, decode_revert_reason},
+};
+
+/// Decodes the output of a call returning a single `uint256`. Anything shorter than a
+/// word is an error, extra bytes past it are ignored.
+pub fn decode_uint256(output: &Bytes) -> Result<U256, Error> {
+    U256::abi_decode(output)
+}
+
+/// Decodes the output of a call returning a single `bool`. A word holding anything but
+/// 0 or 1 is an error, as is an empty output, which tokens like USDT return from
+/// `approve` and `transfer`.
+pub fn decode_bool(output: &Bytes) -> Result<bool, Error> {
+    match decode_uint256(output)? {
+        U256::ZERO => Ok(false),
+        U256::ONE => Ok(true),
+        _ => Err(Error::type_check_fail(&output[..32], "bool")),
+    }
+}
+
+/// Decodes revert data into the reason both backends report, e.g. `revert: STF` for an
+/// `Error(string)` or the panic of a `Panic(uint256)`. `None` for empty data and data that
+/// is neither, such as a custom error.
+pub fn decode_revert_data(data: &[u8]) -> Option<String> {
+    decode_revert_reason(data).filter(|reason| !reason.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::{
+        primitives::{B256, bytes},
+        sol_types::{Revert, SolError},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_decode_uint256() {
+        let output = Bytes::from(B256::with_last_byte(42));
+        assert_eq!(decode_uint256(&output).unwrap(), U256::from(42));
+
+        let mut padded = output.to_vec();
+        padded.extend([0xff; 4]);
+        assert_eq!(decode_uint256(&padded.into()).unwrap(), U256::from(42));
+    }
+
+    #[test]
+    fn test_decode_uint256_short_output() {
+        assert!(decode_uint256(&Bytes::new()).is_err());
+        assert!(decode_uint256(&Bytes::from(vec![0; 31])).is_err());
+    }
+
+    #[test]
+    fn test_decode_bool() {
+        assert!(decode_bool(&Bytes::from(B256::with_last_byte(1))).unwrap());
+        assert!(!decode_bool(&Bytes::from(B256::ZERO)).unwrap());
+    }
+
+    #[test]
+    fn test_decode_bool_malformed_output() {
+        assert!(decode_bool(&Bytes::new()).is_err());
+        assert!(decode_bool(&bytes!("01")).is_err());
+        assert!(decode_bool(&Bytes::from(B256::with_last_byte(2))).is_err());
+    }
+
+    #[test]
+    fn test_decode_revert_data() {
+        let revert = Revert::from("STF").abi_encode();
+        assert_eq!(decode_revert_data(&revert).unwrap(), "revert: STF");
+
+        assert_eq!(decode_revert_data(&[]), None);
+        // Selector of a custom error `Unauthorized()`
+        assert_eq!(decode_revert_data(&[0x82, 0xb4, 0x29, 0x00]), None);
+    }
+}
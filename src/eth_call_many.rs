@@ -1,13 +1,16 @@
-use alloy::primitives::{Address, Bytes, FixedBytes, U256};
+use alloy::eips::eip7702::SignedAuthorization;
+use alloy::primitives::{Address, B256, Bytes, FixedBytes, U64, U256};
 use alloy::rpc::types::BlockId;
 use alloy::transports::TransportErrorKind;
 use alloy_json_rpc::RpcError;
-use alloy_rpc_client::RpcClient;
+use alloy_rpc_client::ClientRef;
 use serde::{Deserialize, Serialize};
 use serde_json::value::RawValue;
 use std::collections::HashMap;
 use thiserror::Error;
 
+use crate::decode::decode_revert_data;
+
 /// Represents a single transaction in the eth_callMany batch
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Transaction {
@@ -27,9 +30,20 @@ pub struct Transaction {
     /// Integer of the value sent with this transaction
     #[serde(skip_serializing_if = "Option::is_none")]
     pub value: Option<U256>,
+    /// Nonce of the sender, filled in by the node when absent
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "alloy::serde::quantity::opt"
+    )]
+    pub nonce: Option<u64>,
     /// Hash of the method signature and encoded parameters
     #[serde(skip_serializing_if = "Option::is_none")]
     pub data: Option<Bytes>,
+    /// EIP-7702 authorizations, making this a set-code transaction
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "authorizationList")]
+    pub authorization_list: Option<Vec<SignedAuthorization>>,
 }
 
 /// Block override options for customizing block header properties
@@ -118,43 +132,157 @@ impl StateOverride {
     }
 }
 
+/// Converts state overrides to their internal representation with hex strings
+fn to_internal_overrides(
+    state_overrides: HashMap<Address, StateOverride>,
+) -> HashMap<Address, StateOverrideInternal> {
+    state_overrides
+        .into_iter()
+        .map(|(addr, override_val)| (addr, override_val.to_internal()))
+        .collect()
+}
+
 /// Response from a single transaction in the batch
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(untagged)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "RawTransactionResponse", into = "RawTransactionResponse")]
 pub enum TransactionResponse {
     /// Successful transaction with return value
     Success {
-        /// The return value of the transaction, hex encoded
-        #[serde(with = "hex_bytes")]
+        /// The return value of the transaction
         value: Bytes,
     },
-    /// Failed transaction with error message
-    Error {
-        /// Error message if the transaction failed
-        error: String,
+    /// The EVM reverted the transaction
+    Reverted {
+        /// Reason following `execution reverted: `, empty if the node gave none
+        reason: String,
+        /// Revert data, when the node reports it
+        data: Option<Bytes>,
+    },
+    /// The node could not execute the transaction (gas too high, invalid params, ...)
+    NodeError {
+        /// Error message of the node
+        message: String,
     },
 }
 
-mod hex_bytes {
-    use alloy::primitives::Bytes;
-    use serde::{Deserialize, Deserializer, Serializer};
+const REVERTED: &str = "execution reverted";
+
+impl TransactionResponse {
+    /// Classifies an error message reported for a transaction
+    pub fn from_error(message: &str, data: Option<Bytes>) -> Self {
+        match message.strip_prefix(REVERTED) {
+            Some(reason) => TransactionResponse::Reverted {
+                reason: reason.trim_start_matches(':').trim_start().to_string(),
+                data,
+            },
+            None => TransactionResponse::NodeError {
+                message: message.to_string(),
+            },
+        }
+    }
+
+    /// The error message as the node reported it, `None` on success
+    pub fn error_message(&self) -> Option<String> {
+        match self {
+            TransactionResponse::Success { .. } => None,
+            TransactionResponse::Reverted { reason, .. } if reason.is_empty() => {
+                Some(REVERTED.to_string())
+            }
+            TransactionResponse::Reverted { reason, .. } => Some(format!("{REVERTED}: {reason}")),
+            TransactionResponse::NodeError { message } => Some(message.clone()),
+        }
+    }
+
+    /// Revert data of a revert: the `data` the node reported, or else the hex data some
+    /// nodes put in the message instead (`execution reverted: 0x...` or
+    /// `execution reverted: custom error 0x...`). `None` for anything but a revert.
+    pub fn revert_data(&self) -> Option<Bytes> {
+        let TransactionResponse::Reverted { reason, data } = self else {
+            return None;
+        };
+        if data.is_some() {
+            return data.clone();
+        }
 
-    pub fn serialize<S>(bytes: &Bytes, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        serializer.serialize_str(&bytes.to_string())
+        let hex = reason.strip_prefix("custom error ").unwrap_or(reason);
+        let hex = hex.split([':', ' ']).next().unwrap_or_default();
+        hex.starts_with("0x").then(|| hex.parse().ok()).flatten()
     }
 
-    pub fn deserialize<'de, D>(deserializer: D) -> Result<Bytes, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        let s = String::deserialize(deserializer)?;
-        s.parse().map_err(serde::de::Error::custom)
+    /// The failure as both backends report it: the revert data decoded like REVM decodes
+    /// it, e.g. `revert: STF`, or else the error message as the node reported it.
+    /// `None` on success.
+    pub fn revert_reason(&self) -> Option<String> {
+        self.revert_data()
+            .and_then(|data| decode_revert_data(&data))
+            .or_else(|| self.error_message())
     }
 }
 
+/// Wire format of a [`TransactionResponse`], where nodes report reverts and their own
+/// failures alike as an `error` string
+#[derive(Serialize, Deserialize)]
+struct RawTransactionResponse {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    value: Option<Bytes>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    data: Option<Bytes>,
+}
+
+impl TryFrom<RawTransactionResponse> for TransactionResponse {
+    type Error = String;
+
+    fn try_from(raw: RawTransactionResponse) -> Result<Self, Self::Error> {
+        match (raw.error, raw.value) {
+            (Some(error), _) => Ok(TransactionResponse::from_error(&error, raw.data)),
+            (None, Some(value)) => Ok(TransactionResponse::Success { value }),
+            (None, None) => Err("transaction response has neither value nor error".to_string()),
+        }
+    }
+}
+
+impl From<TransactionResponse> for RawTransactionResponse {
+    fn from(response: TransactionResponse) -> Self {
+        let error = response.error_message();
+        match response {
+            TransactionResponse::Success { value } => RawTransactionResponse {
+                value: Some(value),
+                error: None,
+                data: None,
+            },
+            TransactionResponse::Reverted { data, .. } => RawTransactionResponse {
+                value: None,
+                error,
+                data,
+            },
+            TransactionResponse::NodeError { .. } => RawTransactionResponse {
+                value: None,
+                error,
+                data: None,
+            },
+        }
+    }
+}
+
+/// Transaction response together with the gas it used, when the node reports it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DetailedTransactionResponse {
+    #[serde(flatten)]
+    pub response: TransactionResponse,
+    /// Gas used by the transaction, absent on nodes that don't report it
+    #[serde(rename = "gasUsed", default, skip_serializing_if = "Option::is_none")]
+    pub gas_used: Option<U256>,
+    /// Gas refunded to the transaction, e.g. for cleared storage, on nodes reporting it
+    #[serde(
+        rename = "gasRefunded",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub gas_refunded: Option<U256>,
+}
+
 /// Simulation context specifying where to execute the simulation
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SimulationContext {
@@ -165,26 +293,114 @@ pub struct SimulationContext {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename = "transactionIndex")]
     pub transaction_index: Option<u64>,
+    /// When `block_number` is a hash, check that the node still has it on its canonical
+    /// chain before simulating. Not sent to the node.
+    #[serde(skip)]
+    pub require_canonical: bool,
+}
+
+/// The fields of an `eth_getBlockBy*` response needed to check canonicality
+#[derive(Debug, Deserialize)]
+struct BlockHeader {
+    hash: B256,
+    number: U64,
 }
 
 /// Wrapper for making eth_callMany RPC calls
 pub struct EthCallMany<'a> {
-    client: &'a RpcClient,
+    client: ClientRef<'a>,
 }
 
 #[derive(Debug, Error)]
-#[error("call many failed")]
 pub enum EthCallManyError {
+    #[error("encoding the request or decoding the response failed")]
     Serialization(#[from] serde_json::Error),
     //TODO: check what is Box<RawValue>
+    #[error("RPC request failed")]
     Rpc(#[from] RpcError<TransportErrorKind, Box<RawValue>>),
+    #[error("block {hash} is not on the node's canonical chain")]
+    NonCanonicalBlock { hash: B256 },
+    /// The gas of every transaction of the request adds up past what the node allows a
+    /// single request. Transactions without `gas` are given the node's default, typically
+    /// the cap itself, so a lower `gas` per transaction, or fewer transactions, gets past it.
+    #[error(
+        "the transactions exceed the node's gas cap, give them a lower gas limit or split them across requests: {message}"
+    )]
+    GasCapExceeded { message: String },
+}
+
+/// Words in the errors of nodes capping the gas of a whole `eth_callMany` request
+const GAS_CAP_MESSAGES: [&str; 2] = ["gas cap", "gascap"];
+
+impl EthCallManyError {
+    /// Whether the node rejected the method itself (JSON-RPC `-32601`), as nodes without
+    /// `eth_callMany` support do.
+    pub fn is_method_not_found(&self) -> bool {
+        matches!(self, EthCallManyError::Rpc(RpcError::ErrorResp(payload)) if payload.code == -32601)
+    }
+
+    /// Whether the request never got a JSON-RPC answer (connection refused, timeout, HTTP
+    /// error status), which a retry may get past.
+    pub fn is_transport_error(&self) -> bool {
+        matches!(self, EthCallManyError::Rpc(RpcError::Transport(_)))
+    }
+
+    /// Tells [`EthCallManyError::GasCapExceeded`] apart from any other error response
+    fn from_call_many(error: RpcError<TransportErrorKind, Box<RawValue>>) -> Self {
+        match error {
+            RpcError::ErrorResp(payload)
+                if GAS_CAP_MESSAGES
+                    .iter()
+                    .any(|words| payload.message.to_lowercase().contains(words)) =>
+            {
+                EthCallManyError::GasCapExceeded {
+                    message: payload.message.to_string(),
+                }
+            }
+            error => error.into(),
+        }
+    }
+}
+
+/// Parses a response of [`EthCallMany::call_many_raw`], as
+/// [`EthCallMany::call_many_detailed`] returns it.
+pub fn parse_call_many(
+    raw: &RawValue,
+) -> Result<Vec<Vec<DetailedTransactionResponse>>, EthCallManyError> {
+    Ok(serde_json::from_str(raw.get())?)
 }
 
 impl<'a> EthCallMany<'a> {
-    pub fn new(client: &'a RpcClient) -> Self {
+    pub fn new(client: ClientRef<'a>) -> Self {
         Self { client }
     }
 
+    /// Executes a single transaction with `eth_call`, for nodes lacking `eth_callMany`.
+    /// A revert is reported as [`TransactionResponse::Reverted`], like `eth_callMany` does.
+    pub async fn call(
+        &self,
+        transaction: &Transaction,
+        block_number: BlockId,
+        state_overrides: Option<HashMap<Address, StateOverride>>,
+    ) -> Result<TransactionResponse, EthCallManyError> {
+        let params = vec![
+            serde_json::to_value(transaction)?,
+            serde_json::to_value(block_number)?,
+            serde_json::to_value(state_overrides.map(to_internal_overrides))?,
+        ];
+
+        match self.client.request("eth_call", params).await {
+            Ok(value) => Ok(TransactionResponse::Success { value }),
+            Err(RpcError::ErrorResp(payload))
+                if payload.code == 3 || payload.message.starts_with("execution reverted") =>
+            {
+                let data = payload.try_data_as::<Bytes>().and_then(Result::ok);
+                Ok(TransactionResponse::from_error(&payload.message, data))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
     /// Execute multiple transaction bundles in sequence using eth_callMany RPC method
     ///
     /// # Arguments
@@ -202,39 +418,109 @@ impl<'a> EthCallMany<'a> {
         state_overrides: Option<HashMap<Address, StateOverride>>,
         timeout: Option<u64>,
     ) -> Result<Vec<Vec<TransactionResponse>>, EthCallManyError> {
-        // Convert state overrides to internal representation with hex strings
-        let state_overrides_internal = state_overrides.map(|map| {
-            map.into_iter()
-                .map(|(addr, override_val)| (addr, override_val.to_internal()))
-                .collect::<HashMap<Address, StateOverrideInternal>>()
-        });
+        let result = self
+            .call_many_detailed(bundles, simulation_context, state_overrides, timeout)
+            .await?;
+
+        Ok(result
+            .into_iter()
+            .map(|bundle| bundle.into_iter().map(|tx| tx.response).collect())
+            .collect())
+    }
+
+    /// Same as [`EthCallMany::call_many`], but keeps the per-transaction gas usage
+    ///
+    /// # Returns
+    /// Vec of Vec of DetailedTransactionResponse - outer vec is per bundle, inner vec is per transaction
+    pub async fn call_many_detailed(
+        &self,
+        bundles: Vec<Bundle>,
+        simulation_context: SimulationContext,
+        state_overrides: Option<HashMap<Address, StateOverride>>,
+        timeout: Option<u64>,
+    ) -> Result<Vec<Vec<DetailedTransactionResponse>>, EthCallManyError> {
+        let raw = self
+            .call_many_raw(bundles, simulation_context, state_overrides, timeout)
+            .await?;
+
+        parse_call_many(&raw)
+    }
+
+    /// Same as [`EthCallMany::call_many`], but returns the response exactly as the node
+    /// serialized it, for debugging. [`parse_call_many`] gives its parsed form.
+    pub async fn call_many_raw(
+        &self,
+        bundles: Vec<Bundle>,
+        simulation_context: SimulationContext,
+        state_overrides: Option<HashMap<Address, StateOverride>>,
+        timeout: Option<u64>,
+    ) -> Result<Box<RawValue>, EthCallManyError> {
+        if simulation_context.require_canonical
+            && let BlockId::Hash(block_hash) = simulation_context.block_number
+        {
+            self.ensure_canonical(block_hash.block_hash).await?;
+        }
+
+        let state_overrides_internal = state_overrides.map(to_internal_overrides);
 
         let params = vec![
             serde_json::to_value(&bundles)?,
             serde_json::to_value(&simulation_context)?,
             serde_json::to_value(&state_overrides_internal)?,
-            serde_json::to_value(&timeout)?,
+            serde_json::to_value(timeout)?,
         ];
 
-        let result: Vec<Vec<TransactionResponse>> =
-            self.client.request("eth_callMany", params).await?;
+        let result: Box<RawValue> = self
+            .client
+            .request("eth_callMany", params)
+            .await
+            .map_err(EthCallManyError::from_call_many)?;
 
         Ok(result)
     }
+
+    /// Errors with [`EthCallManyError::NonCanonicalBlock`] if the node does not know `hash`
+    /// or its canonical block at the same height has a different hash (reorged out).
+    async fn ensure_canonical(&self, hash: B256) -> Result<(), EthCallManyError> {
+        let block: Option<BlockHeader> = self
+            .client
+            .request("eth_getBlockByHash", (hash, false))
+            .await?;
+        let Some(block) = block else {
+            return Err(EthCallManyError::NonCanonicalBlock { hash });
+        };
+
+        let canonical: Option<BlockHeader> = self
+            .client
+            .request("eth_getBlockByNumber", (block.number, false))
+            .await?;
+
+        match canonical {
+            Some(canonical) if canonical.hash == block.hash => Ok(()),
+            _ => Err(EthCallManyError::NonCanonicalBlock { hash }),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use alloy::{
+        eips::eip7702::Authorization,
+        primitives::b256,
+        sol_types::{Panic, Revert, SolError},
+    };
     use revm::primitives::{address, ruint::aliases::U256};
 
-    use crate::balance_slot::SlotWithAddress;
+    use serde_json::{Value, json};
+
+    use crate::{balance_slot::SlotWithAddress, mock_rpc::spawn_mock_rpc};
 
     use super::*;
 
     async fn call_usdc_transfer(
         state_overrides: Option<HashMap<Address, StateOverride>>,
     ) -> TransactionResponse {
-        use crate::balance_slot::IERC20::transferCall;
+        use crate::abi::IERC20::transferCall;
         use alloy::primitives::address;
         use alloy::sol_types::SolCall;
 
@@ -266,6 +552,7 @@ mod tests {
         let simulation_context = SimulationContext {
             block_number: BlockId::latest(),
             transaction_index: None,
+            require_canonical: false,
         };
 
         let client = alloy_rpc_client::RpcClient::new_http(rpc_url.parse().unwrap());
@@ -295,6 +582,8 @@ mod tests {
                 10,
             )
             .unwrap(),
+            scale: None,
+            packing: None,
         };
 
         let balance_amount = U256::from(1_000_000_000u64); // 1000 USDC
@@ -315,12 +604,15 @@ mod tests {
         match tx_response {
             TransactionResponse::Success { value } => {
                 // ERC20 transfer returns bool (true = 1)
-                let expected: Bytes = "0x0000000000000000000000000000000000000000000000000000000000000001".parse().unwrap();
+                let expected: Bytes =
+                    "0x0000000000000000000000000000000000000000000000000000000000000001"
+                        .parse()
+                        .unwrap();
                 assert_eq!(value, expected, "Transfer should return true");
                 println!("Transaction succeeded with return value: {}", value);
             }
-            TransactionResponse::Error { error } => {
-                panic!("Transaction reverted: {}", error);
+            failed => {
+                panic!("Transaction failed: {:?}", failed);
             }
         }
     }
@@ -336,14 +628,356 @@ mod tests {
                     value
                 );
             }
-            TransactionResponse::Error { error } => {
-                println!("Transaction reverted as expected: {}", error);
+            TransactionResponse::Reverted { reason, .. } => {
+                println!("Transaction reverted as expected: {}", reason);
                 assert!(
-                    error.contains("balance") || error.contains("insufficient"),
+                    reason.contains("balance") || reason.contains("insufficient"),
                     "Error should mention balance/insufficient, got: {}",
-                    error
+                    reason
                 );
             }
+            TransactionResponse::NodeError { message } => {
+                panic!("Node failed to execute the transaction: {}", message);
+            }
+        }
+    }
+
+    fn approve_and_revert_rpc(method: &str, _: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "eth_callMany" => Ok(json!([[
+                {
+                    "value": "0x0000000000000000000000000000000000000000000000000000000000000001",
+                    "gasUsed": "0xb411",
+                    "gasRefunded": "0x12c0"
+                },
+                { "error": "execution reverted: STF" }
+            ]])),
+            _ => Err((-32601, "method not found".to_string())),
         }
     }
+
+    #[tokio::test]
+    async fn test_call_many_detailed_returns_every_response() {
+        let rpc_url = spawn_mock_rpc(approve_and_revert_rpc).await;
+        let client = alloy_rpc_client::RpcClient::new_http(rpc_url);
+        let eth_call_many = EthCallMany::new(&client);
+
+        let simulation_context = SimulationContext {
+            block_number: BlockId::latest(),
+            transaction_index: None,
+            require_canonical: false,
+        };
+
+        let result = eth_call_many
+            .call_many_detailed(vec![], simulation_context, None, None)
+            .await
+            .unwrap();
+
+        let responses = &result[0];
+        assert_eq!(responses.len(), 2);
+        assert!(matches!(
+            responses[0].response,
+            TransactionResponse::Success { .. }
+        ));
+        assert_eq!(responses[0].gas_used, Some(U256::from(0xb411)));
+        assert_eq!(responses[0].gas_refunded, Some(U256::from(0x12c0)));
+        assert_eq!(
+            responses[1].response,
+            TransactionResponse::Reverted {
+                reason: "STF".to_string(),
+                data: None
+            }
+        );
+        assert_eq!(responses[1].gas_used, None);
+        assert_eq!(responses[1].gas_refunded, None);
+    }
+
+    #[tokio::test]
+    async fn test_call_many_raw_keeps_node_response() {
+        let rpc_url = spawn_mock_rpc(approve_and_revert_rpc).await;
+        let client = alloy_rpc_client::RpcClient::new_http(rpc_url);
+        let eth_call_many = EthCallMany::new(&client);
+
+        let simulation_context = SimulationContext {
+            block_number: BlockId::latest(),
+            transaction_index: None,
+            require_canonical: false,
+        };
+
+        let raw = eth_call_many
+            .call_many_raw(vec![], simulation_context, None, None)
+            .await
+            .unwrap();
+
+        let expected = approve_and_revert_rpc("eth_callMany", &Value::Null).unwrap();
+        assert_eq!(serde_json::from_str::<Value>(raw.get()).unwrap(), expected);
+        assert_eq!(parse_call_many(&raw).unwrap()[0].len(), 2);
+    }
+
+    fn gas_capped_rpc(method: &str, _: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "eth_callMany" => Err((
+                -32000,
+                "gas required exceeds the RPC gas cap of 50000000".to_string(),
+            )),
+            _ => Err((-32601, "method not found".to_string())),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_gas_cap_error_is_recognized() {
+        let rpc_url = spawn_mock_rpc(gas_capped_rpc).await;
+        let client = alloy_rpc_client::RpcClient::new_http(rpc_url);
+        let eth_call_many = EthCallMany::new(&client);
+
+        let simulation_context = SimulationContext {
+            block_number: BlockId::latest(),
+            transaction_index: None,
+            require_canonical: false,
+        };
+
+        let error = eth_call_many
+            .call_many(vec![], simulation_context, None, None)
+            .await
+            .unwrap_err();
+
+        assert!(
+            matches!(
+                &error,
+                EthCallManyError::GasCapExceeded { message }
+                    if message == "gas required exceeds the RPC gas cap of 50000000"
+            ),
+            "{error:?}"
+        );
+        assert!(!error.is_method_not_found());
+        assert!(!error.is_transport_error());
+    }
+
+    #[test]
+    fn test_serialize_transaction_nonce() {
+        let transaction = Transaction {
+            nonce: Some(26),
+            ..Default::default()
+        };
+        assert_eq!(
+            serde_json::to_value(&transaction).unwrap(),
+            json!({ "nonce": "0x1a" })
+        );
+
+        let transaction = Transaction::default();
+        assert_eq!(serde_json::to_value(&transaction).unwrap(), json!({}));
+    }
+
+    #[test]
+    fn test_serialize_transaction_authorization_list() {
+        let authorization = Authorization {
+            chain_id: U256::from(1),
+            address: Address::with_last_byte(0xd1),
+            nonce: 3,
+        };
+        let transaction = Transaction {
+            authorization_list: Some(vec![SignedAuthorization::new_unchecked(
+                authorization,
+                1,
+                U256::from(2),
+                U256::from(4),
+            )]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            serde_json::to_value(&transaction).unwrap(),
+            json!({
+                "authorizationList": [{
+                    "chainId": "0x1",
+                    "address": Address::with_last_byte(0xd1),
+                    "nonce": "0x3",
+                    "yParity": "0x1",
+                    "r": "0x2",
+                    "s": "0x4",
+                }]
+            })
+        );
+    }
+
+    #[test]
+    fn test_revert_reason_of_node_formats() {
+        let revert = Bytes::from(Revert::from("STF").abi_encode());
+        let panic = Bytes::from(
+            Panic {
+                code: U256::from(0x11),
+            }
+            .abi_encode(),
+        );
+
+        let cases = [
+            // Geth: decoded reason in the message, revert data in `data`
+            (
+                json!({ "error": "execution reverted: STF", "data": revert }),
+                "revert: STF",
+            ),
+            // Geth, custom error: no reason, undecodable data
+            (
+                json!({ "error": "execution reverted", "data": "0x82b42900" }),
+                "execution reverted",
+            ),
+            // Reth: revert data in the message when it can't decode it
+            (
+                json!({ "error": format!("execution reverted: custom error {panic}") }),
+                "panic: arithmetic underflow or overflow (0x11)",
+            ),
+            (
+                json!({ "error": "execution reverted: custom error 0x82b42900" }),
+                "execution reverted: custom error 0x82b42900",
+            ),
+            // Erigon: raw revert data as the reason
+            (
+                json!({ "error": format!("execution reverted: {revert}") }),
+                "revert: STF",
+            ),
+            // No revert data at all
+            (
+                json!({ "error": "execution reverted: UniswapV2Router: EXPIRED" }),
+                "execution reverted: UniswapV2Router: EXPIRED",
+            ),
+            (
+                json!({ "error": "gas required exceeds allowance (30000000)" }),
+                "gas required exceeds allowance (30000000)",
+            ),
+        ];
+
+        for (raw, expected) in cases {
+            let response: TransactionResponse = serde_json::from_value(raw).unwrap();
+            assert_eq!(response.revert_reason().unwrap(), expected);
+        }
+
+        let success = TransactionResponse::Success { value: revert };
+        assert_eq!(success.revert_data(), None);
+        assert_eq!(success.revert_reason(), None);
+    }
+
+    #[test]
+    fn test_deserialize_transaction_responses() {
+        let cases = [
+            (
+                json!({ "value": "0x01" }),
+                TransactionResponse::Success {
+                    value: Bytes::from_static(&[1]),
+                },
+            ),
+            (
+                json!({ "error": "execution reverted" }),
+                TransactionResponse::Reverted {
+                    reason: String::new(),
+                    data: None,
+                },
+            ),
+            (
+                json!({ "error": "execution reverted: STF", "data": "0x08c379a0" }),
+                TransactionResponse::Reverted {
+                    reason: "STF".to_string(),
+                    data: Some(Bytes::from_static(&[0x08, 0xc3, 0x79, 0xa0])),
+                },
+            ),
+            (
+                json!({ "error": "gas required exceeds allowance (30000000)" }),
+                TransactionResponse::NodeError {
+                    message: "gas required exceeds allowance (30000000)".to_string(),
+                },
+            ),
+        ];
+
+        for (raw, expected) in cases {
+            let response: TransactionResponse = serde_json::from_value(raw.clone()).unwrap();
+            assert_eq!(response, expected);
+            assert_eq!(serde_json::to_value(&response).unwrap(), raw);
+        }
+
+        assert!(serde_json::from_value::<TransactionResponse>(json!({})).is_err());
+    }
+
+    #[test]
+    fn test_deserialize_detailed_node_error() {
+        let response: DetailedTransactionResponse = serde_json::from_value(
+            json!({ "error": "insufficient funds for gas * price + value" }),
+        )
+        .unwrap();
+
+        assert!(matches!(
+            response.response,
+            TransactionResponse::NodeError { .. }
+        ));
+        assert_eq!(response.gas_used, None);
+    }
+
+    // Mainnet genesis block
+    const GENESIS: B256 =
+        b256!("0xd4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3");
+    // A sibling of the genesis block the node saw but did not keep
+    const ORPHAN: B256 =
+        b256!("0x00000000000000000000000000000000000000000000000000000000000000aa");
+
+    fn canonical_chain_rpc(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "eth_getBlockByHash" => {
+                let hash: B256 = serde_json::from_value(params[0].clone()).unwrap();
+                match hash {
+                    GENESIS | ORPHAN => Ok(json!({ "hash": hash, "number": "0x0" })),
+                    _ => Ok(Value::Null),
+                }
+            }
+            "eth_getBlockByNumber" if params[0] == json!("0x0") => {
+                Ok(json!({ "hash": GENESIS, "number": "0x0" }))
+            }
+            "eth_callMany" => Ok(json!([[{ "value": "0x01" }]])),
+            _ => Err((-32601, "method not found".to_string())),
+        }
+    }
+
+    async fn call_many_at(
+        hash: B256,
+    ) -> Result<Vec<Vec<DetailedTransactionResponse>>, EthCallManyError> {
+        let rpc_url = spawn_mock_rpc(canonical_chain_rpc).await;
+        let client = alloy_rpc_client::RpcClient::new_http(rpc_url);
+        let eth_call_many = EthCallMany::new(&client);
+
+        let simulation_context = SimulationContext {
+            block_number: BlockId::hash(hash),
+            transaction_index: None,
+            require_canonical: true,
+        };
+
+        eth_call_many
+            .call_many_detailed(vec![], simulation_context, None, None)
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_canonical_block_hash() {
+        let result = call_many_at(GENESIS).await.unwrap();
+
+        assert_eq!(result[0].len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_orphaned_block_hash_is_rejected() {
+        let result = call_many_at(ORPHAN).await;
+
+        assert!(matches!(
+            result,
+            Err(EthCallManyError::NonCanonicalBlock { hash: ORPHAN })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_block_hash_is_rejected() {
+        let unknown = B256::repeat_byte(0xbb);
+
+        let result = call_many_at(unknown).await;
+
+        assert!(matches!(
+            result,
+            Err(EthCallManyError::NonCanonicalBlock { hash }) if hash == unknown
+        ));
+    }
 }
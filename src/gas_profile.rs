@@ -0,0 +1,248 @@
+use revm::{
+    Inspector,
+    bytecode::opcode,
+    interpreter::{
+        CallInputs, CallOutcome, CreateInputs, CreateOutcome, Interpreter,
+        gas::{COLD_SLOAD_COST, SSTORE_RESET, SSTORE_SET, WARM_STORAGE_READ_COST},
+        interpreter::EthInterpreter,
+        interpreter_types::Jumps,
+    },
+};
+
+/// Execution gas of a call broken down by opcode class.
+/// Intrinsic gas (base cost, calldata) is not included.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct GasProfile {
+    /// SLOAD, SSTORE, TLOAD, TSTORE
+    pub storage: u64,
+    /// Overhead of CALL-like and CREATE opcodes, precompiles included
+    pub call: u64,
+    /// Arithmetic, comparison, bitwise and shift opcodes
+    pub arithmetic: u64,
+    /// LOG0-LOG4
+    pub logging: u64,
+    /// Everything else (stack, memory, hashing, environment, ...)
+    pub other: u64,
+    pub storage_access: StorageAccess,
+}
+
+/// What SSTORE charges for a warm no-op, reset and set, plus COLD_SLOAD_COST when the slot
+/// is cold
+const COLD_SSTORE_COSTS: [u64; 3] = [
+    WARM_STORAGE_READ_COST + COLD_SLOAD_COST,
+    SSTORE_RESET,
+    SSTORE_SET + COLD_SLOAD_COST,
+];
+
+/// Number of SLOAD and SSTORE executed, split by whether the slot was cold (first access
+/// in the transaction) or warm, as told by the gas charged under EIP-2929
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StorageAccess {
+    pub cold_sloads: u64,
+    pub warm_sloads: u64,
+    pub cold_sstores: u64,
+    pub warm_sstores: u64,
+}
+
+impl StorageAccess {
+    pub fn sloads(&self) -> u64 {
+        self.cold_sloads + self.warm_sloads
+    }
+
+    pub fn sstores(&self) -> u64 {
+        self.cold_sstores + self.warm_sstores
+    }
+
+    fn record(&mut self, op: u8, charged: u64) {
+        match op {
+            opcode::SLOAD if charged >= COLD_SLOAD_COST => self.cold_sloads += 1,
+            opcode::SLOAD => self.warm_sloads += 1,
+            opcode::SSTORE if COLD_SSTORE_COSTS.contains(&charged) => self.cold_sstores += 1,
+            opcode::SSTORE => self.warm_sstores += 1,
+            _ => {}
+        }
+    }
+}
+
+impl GasProfile {
+    pub fn total(&self) -> u64 {
+        self.storage + self.call + self.arithmetic + self.logging + self.other
+    }
+
+    fn bucket(&mut self, op: u8) -> &mut u64 {
+        match op {
+            opcode::SLOAD | opcode::SSTORE | opcode::TLOAD | opcode::TSTORE => &mut self.storage,
+            opcode::CALL
+            | opcode::CALLCODE
+            | opcode::DELEGATECALL
+            | opcode::STATICCALL
+            | opcode::CREATE
+            | opcode::CREATE2 => &mut self.call,
+            opcode::ADD..=opcode::SIGNEXTEND | opcode::LT..=opcode::SAR => &mut self.arithmetic,
+            opcode::LOG0..=opcode::LOG4 => &mut self.logging,
+            _ => &mut self.other,
+        }
+    }
+}
+
+/// Accumulates the gas charged by every executed opcode into a [`GasProfile`].
+#[derive(Default)]
+pub struct GasProfileInspector {
+    profile: GasProfile,
+    /// Opcode being executed and the gas remaining before it
+    pending: Option<(u8, u64)>,
+    depth: usize,
+}
+
+impl GasProfileInspector {
+    pub fn profile(&self) -> GasProfile {
+        self.profile
+    }
+
+    /// CALL-like and CREATE opcodes are charged the gas forwarded to the new frame, whose
+    /// opcodes are profiled on their own, so it is taken back out of the call bucket.
+    /// Precompiles run no opcodes, so what they spent stays in it.
+    fn frame_end(&mut self, forwarded: u64, precompile_spent: u64) {
+        self.depth -= 1;
+        if self.depth > 0 {
+            self.profile.call = self
+                .profile
+                .call
+                .saturating_sub(forwarded)
+                .saturating_add(precompile_spent);
+        }
+    }
+}
+
+impl<CTX> Inspector<CTX> for GasProfileInspector {
+    fn step(&mut self, interp: &mut Interpreter<EthInterpreter>, _: &mut CTX) {
+        self.pending = Some((interp.bytecode.opcode(), interp.gas.remaining()));
+    }
+
+    fn step_end(&mut self, interp: &mut Interpreter<EthInterpreter>, _: &mut CTX) {
+        if let Some((op, remaining_before)) = self.pending.take() {
+            let charged = remaining_before.saturating_sub(interp.gas.remaining());
+            *self.profile.bucket(op) += charged;
+            self.profile.storage_access.record(op, charged);
+        }
+    }
+
+    fn call(&mut self, _: &mut CTX, _: &mut CallInputs) -> Option<CallOutcome> {
+        self.depth += 1;
+        None
+    }
+
+    fn call_end(&mut self, _: &mut CTX, _: &CallInputs, outcome: &mut CallOutcome) {
+        let gas = outcome.gas();
+        let precompile_spent = if outcome.was_precompile_called {
+            gas.spent()
+        } else {
+            0
+        };
+        self.frame_end(gas.limit(), precompile_spent);
+    }
+
+    fn create(&mut self, _: &mut CTX, _: &mut CreateInputs) -> Option<CreateOutcome> {
+        self.depth += 1;
+        None
+    }
+
+    fn create_end(&mut self, _: &mut CTX, _: &CreateInputs, outcome: &mut CreateOutcome) {
+        self.frame_end(outcome.gas().limit(), 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use revm::{
+        Context, InspectEvm, MainBuilder, MainContext,
+        bytecode::Bytecode,
+        context::TxEnv,
+        database::{CacheDB, EmptyDB},
+        primitives::{Address, Bytes, TxKind, address, bytes},
+        state::AccountInfo,
+    };
+
+    use super::*;
+
+    const CALLER: Address = address!("0x282Cd0c363CCf32629BE74A0A2B1a0Ed6680aE8e");
+    const TOKEN: Address = address!("0x1000000000000000000000000000000000000001");
+    const ROUTER: Address = address!("0x1000000000000000000000000000000000000002");
+
+    // Sums three cold storage slots and returns the result, like a rebasing token's
+    // balanceOf reading shares, total shares and total supply
+    const SLOAD_HEAVY: Bytes = bytes!("5f54600154016002540160005260205ff3");
+
+    // Reads slot 0 twice, then sets slot 1 and overwrites it, like a transfer
+    // reading and writing balances
+    const SLOAD_SSTORE: Bytes = bytes!("5f54505f545060016001556002600155");
+
+    // PUSH0 x4, PUSH20 TOKEN, GAS, STATICCALL, STOP
+    const STATICCALL_TOKEN: Bytes =
+        bytes!("5f5f5f5f7310000000000000000000000000000000000000015afa00");
+
+    fn profile_call(to: Address) -> (GasProfile, u64) {
+        profile_code(to, SLOAD_HEAVY)
+    }
+
+    fn profile_code(to: Address, token_code: Bytes) -> (GasProfile, u64) {
+        let mut db = CacheDB::new(EmptyDB::default());
+        db.insert_account_info(
+            TOKEN,
+            AccountInfo::default().with_code(Bytecode::new_raw(token_code)),
+        );
+        db.insert_account_info(
+            ROUTER,
+            AccountInfo::default().with_code(Bytecode::new_raw(STATICCALL_TOKEN)),
+        );
+
+        let tx_env = TxEnv::builder()
+            .kind(TxKind::Call(to))
+            .caller(CALLER)
+            .build_fill();
+
+        let mut evm = Context::mainnet()
+            .with_db(&mut db)
+            .build_mainnet_with_inspector(GasProfileInspector::default());
+        let res = evm.inspect_one_tx(tx_env).unwrap();
+
+        assert!(res.is_success(), "{res:?}");
+        (evm.inspector.profile(), res.gas_used())
+    }
+
+    #[test]
+    fn test_sload_heavy_token_is_storage_dominated() {
+        let (profile, gas_used) = profile_call(TOKEN);
+
+        assert_eq!(profile.storage, 3 * 2100);
+        assert!(profile.storage > profile.total() / 2, "{profile:?}");
+        assert_eq!(profile.total(), gas_used - 21_000);
+    }
+
+    #[test]
+    fn test_nested_call_is_not_counted_twice() {
+        let (profile, gas_used) = profile_call(ROUTER);
+
+        assert_eq!(profile.storage, 3 * 2100);
+        // Cold account access of the token
+        assert_eq!(profile.call, 2600);
+        assert_eq!(profile.total(), gas_used - 21_000);
+    }
+
+    #[test]
+    fn test_storage_accesses_are_counted() {
+        let (profile, _) = profile_code(TOKEN, SLOAD_SSTORE);
+
+        assert_eq!(
+            profile.storage_access,
+            StorageAccess {
+                cold_sloads: 1,
+                warm_sloads: 1,
+                cold_sstores: 1,
+                warm_sstores: 1,
+            }
+        );
+        assert_eq!(profile.storage_access.sloads(), 2);
+        assert_eq!(profile.storage_access.sstores(), 2);
+    }
+}
@@ -0,0 +1,106 @@
+//! Hardfork REVM runs a simulation with, as active at the simulated block, so that opcodes
+//! and gas costs match what the chain executed then.
+
+use revm::primitives::hardfork::SpecId;
+
+use crate::l1_fee::is_op_stack;
+
+/// Ethereum forks by activation block, up to Osaka (Fusaka)
+const ETHEREUM_FORKS: [(u64, SpecId); 19] = [
+    (0, SpecId::FRONTIER),
+    (200_000, SpecId::FRONTIER_THAWING),
+    (1_150_000, SpecId::HOMESTEAD),
+    (1_920_000, SpecId::DAO_FORK),
+    (2_463_000, SpecId::TANGERINE),
+    (2_675_000, SpecId::SPURIOUS_DRAGON),
+    (4_370_000, SpecId::BYZANTIUM),
+    (7_280_000, SpecId::PETERSBURG),
+    (9_069_000, SpecId::ISTANBUL),
+    (9_200_000, SpecId::MUIR_GLACIER),
+    (12_244_000, SpecId::BERLIN),
+    (12_965_000, SpecId::LONDON),
+    (13_773_000, SpecId::ARROW_GLACIER),
+    (15_050_000, SpecId::GRAY_GLACIER),
+    (15_537_394, SpecId::MERGE),
+    (17_034_870, SpecId::SHANGHAI),
+    (19_426_587, SpecId::CANCUN),
+    (22_431_084, SpecId::PRAGUE),
+    (23_935_694, SpecId::OSAKA),
+];
+
+/// OP-stack forks changing the EVM, by activation timestamp on the Superchain: Bedrock,
+/// Canyon (Shanghai), Ecotone (Cancun) and Isthmus (Prague). Osaka is to be added once
+/// the Superchain schedules it.
+const SUPERCHAIN_FORKS: [(u64, SpecId); 4] = [
+    (0, SpecId::MERGE),
+    (1_704_992_401, SpecId::SHANGHAI),
+    (1_710_374_401, SpecId::CANCUN),
+    (1_746_806_401, SpecId::PRAGUE),
+];
+
+/// [`SUPERCHAIN_FORKS`] on OP Sepolia and Base Sepolia
+const SUPERCHAIN_SEPOLIA_FORKS: [(u64, SpecId); 4] = [
+    (0, SpecId::MERGE),
+    (1_699_981_200, SpecId::SHANGHAI),
+    (1_708_534_800, SpecId::CANCUN),
+    (1_744_905_600, SpecId::PRAGUE),
+];
+
+/// OP Sepolia and Base Sepolia
+const SUPERCHAIN_SEPOLIA_CHAIN_IDS: [u32; 2] = [11155420, 84532];
+
+/// Spec active on `chain_id` at `block_number`, or at `timestamp` on chains scheduling
+/// forks by time. Chains without a known schedule, and OP-stack chains without a
+/// timestamp, run with REVM's default spec.
+pub fn spec_at(chain_id: u32, block_number: u64, timestamp: Option<u64>) -> SpecId {
+    match (chain_id, timestamp) {
+        (1, _) => active_fork(&ETHEREUM_FORKS, block_number),
+        (chain_id, Some(timestamp)) if SUPERCHAIN_SEPOLIA_CHAIN_IDS.contains(&chain_id) => {
+            active_fork(&SUPERCHAIN_SEPOLIA_FORKS, timestamp)
+        }
+        (chain_id, Some(timestamp)) if is_op_stack(chain_id) => {
+            active_fork(&SUPERCHAIN_FORKS, timestamp)
+        }
+        _ => SpecId::default(),
+    }
+}
+
+/// Last fork of `forks`, sorted by activation, activated by `at`
+fn active_fork(forks: &[(u64, SpecId)], at: u64) -> SpecId {
+    forks
+        .iter()
+        .rev()
+        .find(|(activation, _)| *activation <= at)
+        .map_or(SpecId::FRONTIER, |(_, spec)| *spec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ethereum_spec_by_block() {
+        assert_eq!(spec_at(1, 16_000_000, None), SpecId::MERGE);
+        assert_eq!(spec_at(1, 17_034_870, None), SpecId::SHANGHAI);
+        assert_eq!(spec_at(1, 19_426_586, None), SpecId::SHANGHAI);
+        assert_eq!(spec_at(1, 20_000_000, None), SpecId::CANCUN);
+        assert_eq!(spec_at(1, 23_000_000, None), SpecId::PRAGUE);
+        assert_eq!(spec_at(1, 23_935_693, None), SpecId::PRAGUE);
+        assert_eq!(spec_at(1, 23_935_694, None), SpecId::OSAKA);
+        assert_eq!(spec_at(1, 24_000_000, None), SpecId::OSAKA);
+    }
+
+    #[test]
+    fn test_op_stack_spec_by_timestamp() {
+        assert_eq!(spec_at(8453, 1, Some(1_700_000_000)), SpecId::MERGE);
+        assert_eq!(spec_at(8453, 1, Some(1_705_000_000)), SpecId::SHANGHAI);
+        assert_eq!(spec_at(10, 1, Some(1_720_000_000)), SpecId::CANCUN);
+        assert_eq!(spec_at(84532, 1, Some(1_709_000_000)), SpecId::CANCUN);
+        assert_eq!(spec_at(8453, 1, None), SpecId::default());
+    }
+
+    #[test]
+    fn test_unknown_chain_runs_default_spec() {
+        assert_eq!(spec_at(137, 1, Some(1)), SpecId::default());
+    }
+}
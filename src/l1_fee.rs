@@ -0,0 +1,181 @@
+//! L1 data fee charged by OP-stack chains on top of L2 execution gas, which REVM's
+//! mainnet context knows nothing about.
+
+use alloy::{
+    consensus::{SignableTransaction, TxEip1559},
+    primitives::{Address, TxKind, U256, address},
+    sol_types::SolCall,
+};
+use revm::{
+    Context, ExecuteEvm, MainBuilder, MainContext,
+    context::{
+        TxEnv,
+        result::{EVMError, ExecutionResult},
+    },
+    database::{CacheDB, DBTransportError, DatabaseRef},
+};
+use thiserror::Error;
+
+use crate::{
+    abi::IGasPriceOracle::getL1FeeCall, decode::decode_uint256, simulator::SimulationParams,
+};
+
+/// `GasPriceOracle` predeploy, at the same address on every OP-stack chain
+const GAS_PRICE_ORACLE: Address = address!("0x420000000000000000000000000000000000000F");
+
+/// OP Mainnet, Base, Zora, Mode, Fraxtal, World Chain, Unichain, Ink, Soneium,
+/// OP Sepolia and Base Sepolia
+const OP_STACK_CHAIN_IDS: [u32; 11] = [
+    10, 8453, 7777777, 34443, 252, 480, 130, 57073, 1868, 11155420, 84532,
+];
+
+pub fn is_op_stack(chain_id: u32) -> bool {
+    OP_STACK_CHAIN_IDS.contains(&chain_id)
+}
+
+#[derive(Debug, Error)]
+pub enum L1FeeError {
+    #[error("loading the sender account failed")]
+    LoadAccount(#[from] DBTransportError),
+    #[error("calling the GasPriceOracle failed")]
+    Transact(#[from] EVMError<DBTransportError>),
+    #[error("execution failed: {0:?}")]
+    Execution(ExecutionResult),
+    #[error("decoding the L1 fee failed")]
+    Decoding(#[from] alloy::sol_types::Error),
+}
+
+/// Sums the L1 data fee the `GasPriceOracle` charges for each step, each sent by `user`
+/// as an EIP-1559 transaction. The oracle reads the L1 fee parameters from the chain,
+/// so the estimate follows the fee formula of the fork active at the simulated block.
+pub fn estimate_l1_fee<ExtDB: DatabaseRef<Error = DBTransportError>>(
+    cache_db: &mut CacheDB<ExtDB>,
+    chain_id: u32,
+    params: &SimulationParams,
+) -> Result<U256, L1FeeError> {
+    let nonce = cache_db.load_account(params.user)?.info.nonce;
+
+    let mut l1_fee = U256::ZERO;
+    for step in params.steps() {
+        let tx = TxEip1559 {
+            chain_id: chain_id.into(),
+            nonce,
+            gas_limit: params.gas_limit.unwrap_or_default(),
+            max_fee_per_gas: params.effective_gas_price().unwrap_or_default(),
+            max_priority_fee_per_gas: params.effective_gas_price().unwrap_or_default(),
+            to: TxKind::Call(step.to),
+            value: step.value,
+            input: step.calldata,
+            ..Default::default()
+        };
+
+        l1_fee += l1_fee_of(cache_db, tx.encoded_for_signing())?;
+    }
+
+    Ok(l1_fee)
+}
+
+fn l1_fee_of<ExtDB: DatabaseRef<Error = DBTransportError>>(
+    cache_db: &mut CacheDB<ExtDB>,
+    unsigned_tx: Vec<u8>,
+) -> Result<U256, L1FeeError> {
+    let tx_env = TxEnv::builder()
+        .kind(TxKind::Call(GAS_PRICE_ORACLE))
+        .data(
+            getL1FeeCall {
+                _data: unsigned_tx.into(),
+            }
+            .abi_encode()
+            .into(),
+        )
+        .build_fill();
+
+    let mut evm = Context::mainnet()
+        .with_db(cache_db)
+        .modify_cfg_chained(|cfg| cfg.disable_nonce_check = true)
+        .build_mainnet();
+
+    match evm.transact_one(tx_env)? {
+        ExecutionResult::Success { output, .. } => Ok(decode_uint256(output.data())?),
+        failed => Err(L1FeeError::Execution(failed)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "network-tests")]
+    use alloy::{
+        eips::BlockId,
+        providers::{Provider, ProviderBuilder},
+    };
+    #[cfg(feature = "network-tests")]
+    use revm::database::{AlloyDB, WrapDatabaseAsync};
+    use revm::primitives::{Bytes, bytes};
+
+    use super::*;
+    use crate::{
+        offline_db::{USER, offline_db},
+        simulator::CallStep,
+    };
+
+    // Oracle charging one wei per byte of its calldata
+    const CALLDATASIZE_ORACLE: Bytes = bytes!("365f5260205ff3");
+
+    fn transfer_params(steps: usize) -> SimulationParams {
+        SimulationParams {
+            user: USER,
+            steps: vec![
+                CallStep {
+                    to: address!("0x833589fcd6edb6e08f4c7c32d4f71b54bda02913"),
+                    calldata: bytes!("a9059cbb"),
+                    ..Default::default()
+                };
+                steps
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_op_stack_chains() {
+        assert!(is_op_stack(8453));
+        assert!(is_op_stack(10));
+        assert!(!is_op_stack(1));
+        assert!(!is_op_stack(42161));
+    }
+
+    #[test]
+    fn test_l1_fee_is_charged_per_step() {
+        let mut db = offline_db(&[(GAS_PRICE_ORACLE, CALLDATASIZE_ORACLE)]);
+
+        let one_step = estimate_l1_fee(&mut db, 8453, &transfer_params(1)).unwrap();
+        let two_steps = estimate_l1_fee(&mut db, 8453, &transfer_params(2)).unwrap();
+
+        assert!(!one_step.is_zero());
+        assert_eq!(two_steps, one_step * U256::from(2));
+    }
+
+    #[cfg(feature = "network-tests")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_base_l1_fee_is_nonzero() -> Result<(), Box<dyn std::error::Error>> {
+        dotenvy::dotenv().ok();
+        let rpc_url = std::env::var("BASE_RPC")
+            .expect("BASE_RPC not set in .env")
+            .parse()?;
+
+        let provider = ProviderBuilder::new().connect_http(rpc_url);
+
+        let block_number = provider.get_block_number().await?;
+
+        let alloy_db = AlloyDB::new(provider, BlockId::number(block_number));
+        let alloy_db = WrapDatabaseAsync::new(alloy_db).ok_or("No Tokio runtime available")?;
+
+        let mut alloy_cache_db = CacheDB::new(alloy_db);
+
+        let l1_fee = estimate_l1_fee(&mut alloy_cache_db, 8453, &transfer_params(1))?;
+
+        assert!(!l1_fee.is_zero());
+
+        Ok(())
+    }
+}
@@ -1,145 +1,2082 @@
-mod balance_slot;
-mod eth_call_many;
-mod simulator;
+pub mod abi;
+pub mod balance_slot;
+pub mod cancellable_db;
+pub mod decode;
+pub mod eth_call_many;
+pub mod gas_profile;
+pub mod hardfork;
+mod l1_fee;
+#[cfg(test)]
+mod mock_rpc;
+#[cfg(test)]
+mod offline_db;
+pub mod prepared_state;
+pub mod provider_pool;
+pub mod simulator;
 
-use alloy::transports::http::reqwest::Url;
-use napi::bindgen_prelude::Either3;
+pub use decode::{decode_bool, decode_revert_data, decode_uint256};
+pub use simulator::{Outcome, SimulateError, SimulationOutput};
+
+use alloy::{
+    eips::eip7702::{Authorization as AuthorizationInternal, SignedAuthorization},
+    primitives::{Address, B256, U256},
+    transports::http::reqwest::Url,
+};
+use napi::{
+    Env, Status,
+    bindgen_prelude::{AbortSignal, Either, Either3, PromiseRaw},
+    threadsafe_function::{ThreadsafeFunction, ThreadsafeFunctionCallMode, UnknownReturnValue},
+};
 use napi_derive::napi;
-use std::str::FromStr;
+use revm::context::result::SuccessReason;
+use serde::Serialize;
+use std::{collections::HashMap, str::FromStr, sync::Arc};
+use tokio::sync::{Mutex, MutexGuard};
+use tokio_util::sync::CancellationToken;
 
-use crate::simulator::{SimulationParams as SimulationParamsInternal, Simulator as SimulatorImpl};
+use crate::simulator::{
+    ApproveMode, BackendResult as BackendResultInternal, CacheStats as CacheStatsInternal,
+    CallResult, CallStep as CallStepInternal, ChainInfo as ChainInfoInternal,
+    DivergenceReport as DivergenceReportInternal, FailedExecution, HopResult as HopResultInternal,
+    PreRevertState as PreRevertStateInternal, ProviderProfile, RevmCfg, SimulationBlock,
+    SimulationParams as SimulationParamsInternal, SimulationStrategy, Simulator as SimulatorImpl,
+    SimulatorConfig as SimulatorConfigInternal, SlippageReport as SlippageReportInternal,
+    SlotVerification as SlotVerificationInternal, SwapParams as SwapParamsInternal,
+    VaultConversion, format_error_chain,
+};
+use crate::{
+    abi::TokenStandard,
+    eth_call_many::{DetailedTransactionResponse, TransactionResponse},
+    gas_profile::{GasProfile as GasProfileInternal, StorageAccess as StorageAccessInternal},
+};
 
 const STATUS_SUCCESS: &str = "simulation_success";
 const STATUS_FAILED: &str = "simulation_failed";
 const STATUS_ERROR: &str = "error";
 
+const CODE_INVALID_PARAMS: &str = "invalid_params";
+
 fn parse_or_error<T: FromStr>(value: &str, field_name: &str) -> Result<T, Error>
 where
     T::Err: std::fmt::Display,
 {
-    value.parse().map_err(|e| Error {
-        status: STATUS_ERROR.to_string(),
-        error: format!("Invalid {}: {}", field_name, e),
-    })
+    value
+        .parse()
+        .map_err(|e| Error::invalid_params(format!("Invalid {}: {}", field_name, e)))
 }
 
-fn validate_and_convert(
-    params: SimulationParams,
+fn parse_optional_or_error<T: FromStr>(
+    value: Option<String>,
+    field_name: &str,
+) -> Result<Option<T>, Error>
+where
+    T::Err: std::fmt::Display,
+{
+    value
+        .map(|value| parse_or_error(&value, field_name))
+        .transpose()
+}
+
+/// Parses an amount written in decimal digits, or in hex with a `0x` prefix.
+/// `U256::from_str` also takes `0b`/`0o` prefixes and `_` separators, which would
+/// silently read a mistyped amount as another number.
+fn parse_amount(value: &str, field_name: &str) -> Result<U256, Error> {
+    let (digits, radix) = match value.strip_prefix("0x") {
+        Some(hex) => (hex, 16),
+        None => (value, 10),
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return Err(Error::invalid_params(format!(
+            "Invalid {field_name}: {value:?} is neither decimal digits nor 0x-prefixed hex"
+        )));
+    }
+
+    U256::from_str_radix(digits, radix.into())
+        .map_err(|e| Error::invalid_params(format!("Invalid {field_name}: {e}")))
+}
+
+fn parse_optional_amount(value: Option<String>, field_name: &str) -> Result<Option<U256>, Error> {
+    value
+        .map(|value| parse_amount(&value, field_name))
+        .transpose()
+}
+
+fn validate_and_convert<P, T: TryFrom<P, Error = Error>>(
+    params: P,
     rpc_url: String,
-) -> Result<(SimulationParamsInternal, Url), Error> {
+) -> Result<(T, Url), Error> {
     let rpc_url = parse_or_error::<Url>(&rpc_url, "RPC URL")?;
     let simulation_params = params.try_into()?;
     Ok((simulation_params, rpc_url))
 }
 
 #[napi(object)]
+pub struct CallStep {
+    pub to_address: String,
+    pub calldata: String,
+    /// Native value sent with the call, in wei
+    pub value: Option<String>,
+    /// Gas limit of this step, in place of the simulation's `gas_limit`
+    pub gas_limit: Option<String>,
+}
+
+impl TryFrom<CallStep> for CallStepInternal {
+    type Error = Error;
+
+    fn try_from(step: CallStep) -> Result<Self, Self::Error> {
+        Ok(CallStepInternal {
+            to: parse_or_error(&step.to_address, "step to address")?,
+            calldata: parse_or_error(&step.calldata, "step calldata")?,
+            value: parse_optional_amount(step.value, "step value")?.unwrap_or_default(),
+            gas_limit: parse_optional_or_error(step.gas_limit, "step gas limit")?,
+        })
+    }
+}
+
+/// Signed EIP-7702 authorization, delegating the code of its signer to `address`
+#[napi(object)]
+pub struct Authorization {
+    pub chain_id: String,
+    pub address: String,
+    pub nonce: String,
+    /// 0 or 1
+    pub y_parity: u32,
+    pub r: String,
+    pub s: String,
+}
+
+impl TryFrom<Authorization> for SignedAuthorization {
+    type Error = Error;
+
+    fn try_from(authorization: Authorization) -> Result<Self, Self::Error> {
+        let y_parity = match authorization.y_parity {
+            0 | 1 => authorization.y_parity as u8,
+            y_parity => {
+                return Err(Error::invalid_params(format!(
+                    "Invalid authorization y_parity: {y_parity} is neither 0 nor 1"
+                )));
+            }
+        };
+
+        Ok(SignedAuthorization::new_unchecked(
+            AuthorizationInternal {
+                chain_id: parse_or_error(&authorization.chain_id, "authorization chain id")?,
+                address: parse_or_error(&authorization.address, "authorization address")?,
+                nonce: parse_or_error(&authorization.nonce, "authorization nonce")?,
+            },
+            y_parity,
+            parse_or_error(&authorization.r, "authorization r")?,
+            parse_or_error(&authorization.s, "authorization s")?,
+        ))
+    }
+}
+
+#[napi(object)]
+pub struct TokenBalance {
+    pub holder_address: String,
+    pub token_address: String,
+    pub amount: String,
+}
+
+/// ERC721 token handed to `user_address` before the call
+#[napi(object)]
+pub struct OwnedNft {
+    pub nft_address: String,
+    pub token_id: String,
+}
+
+#[napi(object)]
+#[derive(Default)]
 pub struct SimulationParams {
     pub user_address: String,
     pub token_in_address: String,
     pub to_address: String,
     pub calldata: String,
+    /// Decimal digits, or hex with a `0x` prefix, like every amount
     pub amount_in: String,
+    /// Address approved to pull `token_in`, defaults to `to_address`
+    pub spender_address: Option<String>,
+    /// Native value sent with the call, in wei
+    pub value: Option<String>,
+    pub gas_limit: Option<String>,
+    /// Gas price in wei
+    pub gas_price: Option<String>,
+    /// Tip per gas in wei on top of the block's base fee, pricing `gas_cost_wei` when
+    /// `gas_price` is unset
+    pub priority_fee: Option<String>,
+    /// Nonce of `user_address` before the approval, overriding the on-chain one. The
+    /// approval and each step then carry explicit increasing nonces.
+    pub nonce: Option<String>,
+    /// EIP-7702 authorizations, e.g. delegating `user_address` to a smart account. REVM
+    /// applies them before the approval, the RPC path sends them with the first
+    /// transaction of `user_address`.
+    pub authorization_list: Option<Vec<Authorization>>,
+    /// Token whose balance delta of `user_address` is reported as `amount_out`
+    pub token_out_address: Option<String>,
+    /// Convert `amount_out` through an ERC-4626 vault after the call, reported as
+    /// `amount_out_converted`. `"to_assets"` when `token_out_address` is the vault,
+    /// `"to_shares"` when it is the asset of `vault_address`.
+    #[napi(ts_type = "\"to_assets\" | \"to_shares\"")]
+    pub amount_out_conversion: Option<String>,
+    /// Vault converting `amount_out` for `"to_shares"`
+    pub vault_address: Option<String>,
+    /// Ordered calls executed after the approval instead of the single `to_address` call
+    pub steps: Option<Vec<CallStep>>,
+    /// Index of the step whose output is reported, defaults to the last one
+    pub result_step: Option<u32>,
+    /// Break the gas of the call down by opcode class, off by default as it costs an extra REVM run
+    pub gas_profile: Option<bool>,
+    /// Runtime bytecode replacing the deployed code of each address (mock contracts)
+    pub code_overrides: Option<HashMap<String, String>>,
+    /// Storage slots set before the call in both engines, keyed by address then slot,
+    /// as 32-byte hex strings (the RPC `stateDiff` overrides)
+    pub storage_overrides: Option<HashMap<String, HashMap<String, String>>>,
+    /// Read the allowance back after the approval and report it as `allowance`
+    pub report_allowance: Option<bool>,
+    /// Attach the `eth_callMany` response as the node sent it, as `raw_rpc_response`
+    pub capture_raw_response: Option<bool>,
+    /// Additional balances to fund, e.g. for flows involving several accounts
+    pub balances: Option<Vec<TokenBalance>>,
+    /// ERC721 tokens `user_address` owns for the call, e.g. one a gated function requires
+    pub owned_nfts: Option<Vec<OwnedNft>>,
+    /// How REVM grants the allowance, `"slot_override"` skips the approve transaction.
+    /// Defaults to the simulator's `approve_mode`.
+    #[napi(ts_type = "\"transaction\" | \"slot_override\" | \"increase_allowance\"")]
+    pub approve_mode: Option<String>,
+    /// Report the storage slots written by the call as `state_diff`
+    pub state_diff: Option<bool>,
+    /// Holder whose `token_in` balance increase is reported as `actual_amount_in`
+    pub token_in_recipient_address: Option<String>,
+    /// Simulate after this many transactions of the block instead of at its top, e.g. to
+    /// land behind pending transactions. Needs a node supporting mid-block `eth_callMany`.
+    pub transaction_index: Option<u32>,
+    /// Let REVM run a gas price below the block's base fee, on by default
+    pub disable_base_fee: Option<bool>,
+    /// Let REVM run a gas limit above the block's, on by default
+    pub disable_block_gas_limit: Option<bool>,
+    /// Let REVM run a caller short of ETH for gas and value, on by default
+    pub disable_balance_check: Option<bool>,
+    /// Let REVM run a caller with code (a smart-contract wallet), on by default
+    pub disable_eip3607: Option<bool>,
+    /// Leave out the approval when the on-chain allowance already covers `amount_in`
+    pub skip_approve_if_allowed: Option<bool>,
+    /// Interface of `token_in`, defaults to `"erc20"`
+    #[napi(ts_type = "\"erc20\" | \"erc1155\"")]
+    pub token_standard: Option<String>,
+    /// Id of the `token_in` token within its contract, required for `"erc1155"`
+    pub token_id: Option<String>,
+    /// Reject a discovered balance slot whose mutation also changes `totalSupply()`
+    pub verify_total_supply: Option<bool>,
+    /// Slots probed before the balance slot lookup gives up with `find_slot_failed`,
+    /// defaults to the simulator's `max_slot_candidates`
+    pub max_slot_candidates: Option<u32>,
+    /// Kind of node behind the RPC URL, `"local_fork"` for Anvil or Hardhat forks
+    #[napi(ts_type = "\"remote\" | \"local_fork\"")]
+    pub provider_profile: Option<String>,
+    /// Report the amount paid out at each hop of the route as `hops`
+    pub trace_hops: Option<bool>,
+    /// `block.coinbase` seen by the call, e.g. to simulate builder payments
+    pub coinbase_address: Option<String>,
+    /// `block.timestamp` seen by the call, in seconds, as decimal digits
+    pub block_timestamp: Option<String>,
+    /// Without `block_timestamp`, simulate at the time of the next block, so that swap
+    /// deadlines set from the current time pass
+    pub deadline_safe: Option<bool>,
+    /// Headers sent with every request to the RPC, e.g. `{ "x-api-key": "..." }`
+    pub rpc_headers: Option<HashMap<String, String>>,
+    /// `"both"` runs RPC and REVM and compares them as `divergence`, defaults to the
+    /// simulator's `strategy`
+    #[napi(ts_type = "\"fallback\" | \"both\"")]
+    pub strategy: Option<String>,
+    /// When the call fails, probe a self-transfer of `token_in_address` and report why
+    /// the token refuses it as `diagnostic_hint`
+    pub diagnose_failures: Option<bool>,
+    /// Run the call against the chain as it is, without funding `user_address` nor
+    /// approving, e.g. for a view call. `token_in_address` and `amount_in` are ignored.
+    pub raw: Option<bool>,
+    /// Compare the value the discovered balance slot holds on chain with the user's
+    /// `balanceOf`, reported as `slot_verification`
+    pub verify_against_chain: Option<bool>,
+    /// `"pending"` simulates on top of the pending block, mempool included, on nodes
+    /// serving its state. Defaults to `"latest"`.
+    #[napi(ts_type = "\"latest\" | \"pending\"")]
+    pub block: Option<String>,
+}
+
+fn parse_approve_mode(mode: &str) -> Result<ApproveMode, Error> {
+    match mode {
+        "transaction" => Ok(ApproveMode::Transaction),
+        "slot_override" => Ok(ApproveMode::SlotOverride),
+        "increase_allowance" => Ok(ApproveMode::IncreaseAllowance),
+        mode => Err(Error::invalid_params(format!(
+            "Invalid approve mode: {mode}"
+        ))),
+    }
+}
+
+fn parse_strategy(strategy: &str) -> Result<SimulationStrategy, Error> {
+    match strategy {
+        "fallback" => Ok(SimulationStrategy::Fallback),
+        "both" => Ok(SimulationStrategy::Both),
+        strategy => Err(Error::invalid_params(format!(
+            "Invalid strategy: {strategy}"
+        ))),
+    }
+}
+
+/// Defaults of a `Simulator`, for the settings each call can still override
+#[napi(object)]
+#[derive(Default)]
+pub struct SimulatorConfig {
+    /// `strategy` of calls that don't set one, defaults to `"fallback"`
+    #[napi(ts_type = "\"fallback\" | \"both\"")]
+    pub strategy: Option<String>,
+    /// `approve_mode` of calls that don't set one, defaults to `"transaction"`
+    #[napi(ts_type = "\"transaction\" | \"slot_override\" | \"increase_allowance\"")]
+    pub approve_mode: Option<String>,
+    /// Timeout the node is given for each `eth_callMany`, defaults to 5000
+    pub call_many_timeout_ms: Option<u32>,
+    /// Extra attempts when the RPC doesn't answer a simulation, defaults to 0
+    pub rpc_retries: Option<u32>,
+    /// `max_slot_candidates` of calls that don't set one, and of `warm()`, defaults to 64
+    pub max_slot_candidates: Option<u32>,
+    /// Keep the storage simulations read for later ones at the same block, defaults to false
+    pub cache_storage: Option<bool>,
+    /// Wall-clock limit of a whole simulation, unlimited when unset
+    pub simulate_timeout_ms: Option<u32>,
+}
+
+impl TryFrom<SimulatorConfig> for SimulatorConfigInternal {
+    type Error = Error;
+
+    fn try_from(config: SimulatorConfig) -> Result<Self, Self::Error> {
+        let defaults = SimulatorConfigInternal::default();
+
+        Ok(SimulatorConfigInternal {
+            strategy: match config.strategy.as_deref() {
+                Some(strategy) => parse_strategy(strategy)?,
+                None => defaults.strategy,
+            },
+            approve_mode: match config.approve_mode.as_deref() {
+                Some(mode) => parse_approve_mode(mode)?,
+                None => defaults.approve_mode,
+            },
+            call_many_timeout_ms: config
+                .call_many_timeout_ms
+                .map_or(defaults.call_many_timeout_ms, u64::from),
+            rpc_retries: config.rpc_retries.unwrap_or(defaults.rpc_retries),
+            max_slot_candidates: config
+                .max_slot_candidates
+                .map_or(defaults.max_slot_candidates, |max| max as usize),
+            cache_storage: config.cache_storage.unwrap_or(defaults.cache_storage),
+            simulate_timeout_ms: config.simulate_timeout_ms.map(u64::from),
+            ..defaults
+        })
+    }
 }
 
 impl TryFrom<SimulationParams> for SimulationParamsInternal {
     type Error = Error;
 
     fn try_from(params: SimulationParams) -> Result<Self, Self::Error> {
+        let steps = params
+            .steps
+            .unwrap_or_default()
+            .into_iter()
+            .map(CallStepInternal::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let result_step = params.result_step.map(|step| step as usize);
+        if let Some(step) = result_step
+            && step >= steps.len().max(1)
+        {
+            return Err(Error::invalid_params(format!(
+                "Invalid result step: {step} is out of range"
+            )));
+        }
+
+        let code_overrides = params
+            .code_overrides
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(address, code)| {
+                Ok((
+                    parse_or_error(&address, "code override address")?,
+                    parse_or_error(&code, "code override")?,
+                ))
+            })
+            .collect::<Result<_, Error>>()?;
+
+        let storage_overrides = params
+            .storage_overrides
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(address, slots)| {
+                let slots = slots
+                    .into_iter()
+                    .map(|(slot, value)| {
+                        Ok((
+                            parse_or_error(&slot, "storage override slot")?,
+                            parse_or_error(&value, "storage override value")?,
+                        ))
+                    })
+                    .collect::<Result<_, Error>>()?;
+                Ok((parse_or_error(&address, "storage override address")?, slots))
+            })
+            .collect::<Result<_, Error>>()?;
+
+        let balances = params
+            .balances
+            .unwrap_or_default()
+            .into_iter()
+            .map(|balance| {
+                Ok((
+                    parse_or_error(&balance.holder_address, "balance holder address")?,
+                    parse_or_error(&balance.token_address, "balance token address")?,
+                    parse_amount(&balance.amount, "balance amount")?,
+                ))
+            })
+            .collect::<Result<_, Error>>()?;
+
+        let owned_nfts = params
+            .owned_nfts
+            .unwrap_or_default()
+            .into_iter()
+            .map(|owned_nft| {
+                Ok((
+                    parse_or_error(&owned_nft.nft_address, "nft address")?,
+                    parse_amount(&owned_nft.token_id, "nft token id")?,
+                ))
+            })
+            .collect::<Result<_, Error>>()?;
+
+        let authorization_list = params
+            .authorization_list
+            .unwrap_or_default()
+            .into_iter()
+            .map(SignedAuthorization::try_from)
+            .collect::<Result<_, _>>()?;
+
+        let approve_mode = params
+            .approve_mode
+            .as_deref()
+            .map(parse_approve_mode)
+            .transpose()?;
+
+        let provider_profile = match params.provider_profile.as_deref() {
+            None | Some("remote") => ProviderProfile::Remote,
+            Some("local_fork") => ProviderProfile::LocalFork,
+            Some(profile) => {
+                return Err(Error::invalid_params(format!(
+                    "Invalid provider profile: {profile}"
+                )));
+            }
+        };
+
+        let strategy = params.strategy.as_deref().map(parse_strategy).transpose()?;
+
+        let block = match params.block.as_deref() {
+            None | Some("latest") => SimulationBlock::Latest,
+            Some("pending") => SimulationBlock::Pending,
+            Some(block) => {
+                return Err(Error::invalid_params(format!("Invalid block: {block}")));
+            }
+        };
+
+        let token_standard = match params.token_standard.as_deref() {
+            None | Some("erc20") => TokenStandard::Erc20,
+            Some("erc1155") => TokenStandard::Erc1155 {
+                id: parse_optional_or_error(params.token_id, "token id")?.ok_or_else(|| {
+                    Error::invalid_params("Missing token id for an erc1155 token".to_string())
+                })?,
+            },
+            Some(standard) => {
+                return Err(Error::invalid_params(format!(
+                    "Invalid token standard: {standard}"
+                )));
+            }
+        };
+
+        let amount_out_conversion = match params.amount_out_conversion.as_deref() {
+            None => None,
+            Some("to_assets") => Some(VaultConversion::ToAssets),
+            Some("to_shares") => Some(VaultConversion::ToShares {
+                vault: parse_optional_or_error(params.vault_address, "vault address")?.ok_or_else(
+                    || {
+                        Error::invalid_params(
+                            "Missing vault address for a to_shares conversion".to_string(),
+                        )
+                    },
+                )?,
+            }),
+            Some(conversion) => {
+                return Err(Error::invalid_params(format!(
+                    "Invalid amount out conversion: {conversion}"
+                )));
+            }
+        };
+
         Ok(SimulationParamsInternal {
             user: parse_or_error(&params.user_address, "user address")?,
             token_in: parse_or_error(&params.token_in_address, "token address")?,
             to: parse_or_error(&params.to_address, "to address")?,
             calldata: parse_or_error(&params.calldata, "calldata")?,
-            amount_in: parse_or_error(&params.amount_in, "amount in")?,
+            amount_in: parse_amount(&params.amount_in, "amount in")?,
+            spender: parse_optional_or_error(params.spender_address, "spender address")?,
+            value: parse_optional_amount(params.value, "value")?.unwrap_or_default(),
+            gas_limit: parse_optional_or_error(params.gas_limit, "gas limit")?,
+            gas_price: parse_optional_or_error(params.gas_price, "gas price")?,
+            priority_fee: parse_optional_or_error(params.priority_fee, "priority fee")?
+                .unwrap_or_default(),
+            max_fee_per_gas: None,
+            nonce: parse_optional_or_error(params.nonce, "nonce")?,
+            authorization_list,
+            token_out: parse_optional_or_error(params.token_out_address, "token out address")?,
+            amount_out_conversion,
+            steps,
+            result_step,
+            gas_profile: params.gas_profile.unwrap_or_default(),
+            code_overrides,
+            storage_overrides,
+            report_allowance: params.report_allowance.unwrap_or_default(),
+            capture_raw_response: params.capture_raw_response.unwrap_or_default(),
+            balances,
+            owned_nfts,
+            approve_mode,
+            state_diff: params.state_diff.unwrap_or_default(),
+            token_in_recipient: parse_optional_or_error(
+                params.token_in_recipient_address,
+                "token in recipient address",
+            )?,
+            transaction_index: params.transaction_index.map(u64::from),
+            revm_cfg: RevmCfg {
+                disable_base_fee: params.disable_base_fee.unwrap_or(true),
+                disable_block_gas_limit: params.disable_block_gas_limit.unwrap_or(true),
+                disable_balance_check: params.disable_balance_check.unwrap_or(true),
+                disable_eip3607: params.disable_eip3607.unwrap_or(true),
+                spec: None,
+                gas_price: None,
+            },
+            skip_approve_if_allowed: params.skip_approve_if_allowed.unwrap_or_default(),
+            token_standard,
+            verify_total_supply: params.verify_total_supply.unwrap_or_default(),
+            max_slot_candidates: params.max_slot_candidates.map(|max| max as usize),
+            provider_profile,
+            trace_hops: params.trace_hops.unwrap_or_default(),
+            coinbase: parse_optional_or_error(params.coinbase_address, "coinbase address")?,
+            timestamp: parse_optional_or_error(params.block_timestamp, "block timestamp")?,
+            deadline_safe: params.deadline_safe.unwrap_or_default(),
+            rpc_headers: params.rpc_headers.unwrap_or_default(),
+            strategy,
+            diagnose_failures: params.diagnose_failures.unwrap_or_default(),
+            raw: params.raw.unwrap_or_default(),
+            verify_against_chain: params.verify_against_chain.unwrap_or_default(),
+            block,
         })
     }
 }
 
 #[napi(object)]
+#[derive(Serialize)]
 pub struct SimulationSuccess {
     //TODO: figure out how to use constants here to avoid multiple status declaration
     #[napi(ts_type = "\"simulation_success\"")]
     pub status: String,
+    /// Backend that produced the result, and whether the RPC path failed before REVM did
+    #[napi(ts_type = "\"rpc_success\" | \"rpc_failed_revm_success\" | \"revm_success\"")]
+    pub outcome: String,
     pub output: String,
+    /// Engine that executed the call
+    #[napi(ts_type = "\"rpc\" | \"revm\"")]
+    pub source: String,
+    /// JSON-RPC method the simulation used, when RPC produced the result. `eth_call` when
+    /// the node rejected `eth_callMany`.
+    #[napi(ts_type = "\"eth_call_many\" | \"eth_call\"")]
+    pub rpc_backend: Option<String>,
     pub rpc_err: Option<String>,
+    /// Why the allowance slot wasn't found with `approve_mode: "slot_override"`, the
+    /// approve transaction being sent instead
+    pub allowance_slot_err: Option<String>,
+    /// Contract whose storage holds the balance (may differ from the token for proxies)
+    pub balance_slot_address: Option<String>,
+    /// Balance storage slot as a 32-byte hex string
+    pub balance_slot: Option<String>,
+    /// How `balance_slot` was found, unset when an earlier simulation had found it
+    #[napi(ts_type = "\"fast_path\" | \"full_search\" | \"packed\" | \"rebasing\"")]
+    pub slot_strategy: Option<String>,
+    /// Balance delta of `token_out_address`, when it was provided
+    pub amount_out: Option<String>,
+    /// `amount_out` converted by the vault after the call, when `amount_out_conversion`
+    /// was set
+    pub amount_out_converted: Option<String>,
+    /// `token_in` received by `token_in_recipient_address`, when it was provided.
+    /// Below `amount_in` for fee-on-transfer tokens.
+    pub actual_amount_in: Option<String>,
+    /// Every `eth_callMany` response of the bundle, when RPC produced the result
+    pub rpc_responses: Option<Vec<RpcTransactionResponse>>,
+    /// JSON of the `eth_callMany` response as the node sent it, when
+    /// `capture_raw_response` was set, even if REVM produced the result as the response
+    /// failed the simulation
+    pub raw_rpc_response: Option<String>,
+    pub gas_profile: Option<GasProfile>,
+    /// Allowance of the spender right after the approval, when `report_allowance` was set
+    pub allowance: Option<String>,
+    /// Block the simulation ran on top of
+    pub block_number: i64,
+    /// Final value of each storage slot written by the call, keyed by address then slot,
+    /// as 32-byte hex strings, when `state_diff` was set
+    pub state_diff: Option<HashMap<String, HashMap<String, String>>>,
+    /// L1 data fee of the call in wei, on OP-stack chains
+    pub l1_fee: Option<String>,
+    /// `gas_price`, or else the block's base fee plus `priority_fee`, in wei
+    pub effective_gas_price: Option<String>,
+    /// Gas used priced at `effective_gas_price`, plus `l1_fee`, in wei
+    pub gas_cost_wei: Option<String>,
+    /// Token and amount paid out at each hop of the route, in order, when `trace_hops`
+    /// was set
+    pub hops: Option<Vec<HopResult>>,
+    /// RPC and REVM results side by side, when `strategy` was `"both"`
+    pub divergence: Option<DivergenceReport>,
+    /// JSON-RPC requests the simulation sent to the node, fewer once the cache is warm
+    pub rpc_request_count: i64,
+    /// What the approve transaction did, when one was sent
+    pub approve_result: Option<ApproveResult>,
+    /// 4-byte function selector of the call, in hex
+    pub selector: Option<String>,
+    /// How the call ended in REVM, the node doesn't report it
+    #[napi(ts_type = "\"stop\" | \"return\" | \"self_destruct\"")]
+    pub success_reason: Option<String>,
+    /// Balance slot checked against `balanceOf`, when `verify_against_chain` was set
+    pub slot_verification: Option<SlotVerification>,
 }
 
 #[napi(object)]
+#[derive(Serialize)]
 pub struct SimulationFailed {
     #[napi(ts_type = "\"simulation_failed\"")]
     pub status: String,
+    /// Backend that produced the result, and whether the RPC path failed before REVM did
+    #[napi(ts_type = "\"rpc_revert\" | \"rpc_failed_revm_revert\" | \"revm_revert\"")]
+    pub outcome: String,
     pub output: String,
+    /// Engine that executed the call
+    #[napi(ts_type = "\"rpc\" | \"revm\"")]
+    pub source: String,
+    /// JSON-RPC method the simulation used, when RPC produced the result. `eth_call` when
+    /// the node rejected `eth_callMany`.
+    #[napi(ts_type = "\"eth_call_many\" | \"eth_call\"")]
+    pub rpc_backend: Option<String>,
     pub rpc_err: Option<String>,
+    /// Why the allowance slot wasn't found with `approve_mode: "slot_override"`, the
+    /// approve transaction being sent instead
+    pub allowance_slot_err: Option<String>,
+    /// Every `eth_callMany` response of the bundle, when RPC produced the result
+    pub rpc_responses: Option<Vec<RpcTransactionResponse>>,
+    /// JSON of the `eth_callMany` response as the node sent it, when
+    /// `capture_raw_response` was set, even if REVM produced the result as the response
+    /// failed the simulation
+    pub raw_rpc_response: Option<String>,
+    pub gas_profile: Option<GasProfile>,
+    /// Allowance of the spender right after the approval, when `report_allowance` was set
+    pub allowance: Option<String>,
+    /// Block the simulation ran on top of
+    pub block_number: i64,
+    /// Final value of each storage slot written by the call, keyed by address then slot,
+    /// as 32-byte hex strings, when `state_diff` was set
+    pub state_diff: Option<HashMap<String, HashMap<String, String>>>,
+    /// L1 data fee of the call in wei, on OP-stack chains
+    pub l1_fee: Option<String>,
+    /// `gas_price`, or else the block's base fee plus `priority_fee`, in wei
+    pub effective_gas_price: Option<String>,
+    /// Gas used priced at `effective_gas_price`, plus `l1_fee`, in wei
+    pub gas_cost_wei: Option<String>,
+    /// Structured form of the failure, when REVM produced the result
+    pub execution_result: Option<ExecutionResult>,
+    /// RPC and REVM results side by side, when `strategy` was `"both"`
+    pub divergence: Option<DivergenceReport>,
+    /// State after funding and the approval, right before the call
+    pub pre_revert_state: Option<PreRevertState>,
+    /// JSON-RPC requests the simulation sent to the node, fewer once the cache is warm
+    pub rpc_request_count: i64,
+    /// Why `token_in_address` refuses a self-transfer of the user, when
+    /// `diagnose_failures` was set and the token does
+    #[napi(ts_type = "\"token_paused\" | \"blacklisted\" | \"transfer_disabled\"")]
+    pub diagnostic_hint: Option<String>,
+    /// What the approve transaction did, when one was sent
+    pub approve_result: Option<ApproveResult>,
+    /// 4-byte function selector of the call, in hex
+    pub selector: Option<String>,
+    /// Hex revert data as the call returned it, e.g. the selector and arguments of a
+    /// custom error that `output` can't decode
+    pub revert_data: Option<String>,
+}
+
+/// Execution gas of the call by opcode class, intrinsic gas excluded
+#[napi(object)]
+#[derive(Serialize)]
+pub struct GasProfile {
+    pub storage: i64,
+    pub call: i64,
+    pub arithmetic: i64,
+    pub logging: i64,
+    pub other: i64,
+    pub storage_access: StorageAccess,
+}
+
+/// SLOAD and SSTORE executed by the call, by whether the slot was cold or warm
+#[napi(object)]
+#[derive(Serialize)]
+pub struct StorageAccess {
+    pub cold_sloads: i64,
+    pub warm_sloads: i64,
+    pub cold_sstores: i64,
+    pub warm_sstores: i64,
+}
+
+/// Value of the balance slot on chain next to the user's `balanceOf`, before any override
+#[napi(object)]
+#[derive(Serialize)]
+pub struct SlotVerification {
+    pub stored: String,
+    pub balance: String,
+    /// `"high"` when the slot holds the non-zero balance, `"medium"` when both are zero,
+    /// `"low"` when they differ, e.g. for packed or rebasing balances
+    #[napi(ts_type = "\"high\" | \"medium\" | \"low\"")]
+    pub confidence: String,
+}
+
+impl From<SlotVerificationInternal> for SlotVerification {
+    fn from(verification: SlotVerificationInternal) -> Self {
+        SlotVerification {
+            stored: verification.stored.to_string(),
+            balance: verification.balance.to_string(),
+            confidence: verification.confidence.as_str().to_string(),
+        }
+    }
+}
+
+impl From<StorageAccessInternal> for StorageAccess {
+    fn from(access: StorageAccessInternal) -> Self {
+        StorageAccess {
+            cold_sloads: access.cold_sloads as i64,
+            warm_sloads: access.warm_sloads as i64,
+            cold_sstores: access.cold_sstores as i64,
+            warm_sstores: access.warm_sstores as i64,
+        }
+    }
+}
+
+impl From<GasProfileInternal> for GasProfile {
+    fn from(profile: GasProfileInternal) -> Self {
+        GasProfile {
+            storage: profile.storage as i64,
+            call: profile.call as i64,
+            arithmetic: profile.arithmetic as i64,
+            logging: profile.logging as i64,
+            other: profile.other as i64,
+            storage_access: profile.storage_access.into(),
+        }
+    }
 }
 
+/// Output of one hop of a multi-pool route
 #[napi(object)]
+#[derive(Serialize)]
+pub struct HopResult {
+    pub token: String,
+    pub amount: String,
+}
+
+impl From<HopResultInternal> for HopResult {
+    fn from(hop: HopResultInternal) -> Self {
+        HopResult {
+            token: hop.token.to_string(),
+            amount: hop.amount.to_string(),
+        }
+    }
+}
+
+/// Result of one engine, when `strategy` was `"both"`
+#[napi(object)]
+#[derive(Serialize)]
+pub struct BackendResult {
+    /// Whether the call succeeded, `output` is the revert reason otherwise
+    pub success: bool,
+    pub output: String,
+    pub gas_used: Option<String>,
+    pub amount_out: Option<String>,
+}
+
+impl From<BackendResultInternal> for BackendResult {
+    fn from(backend: BackendResultInternal) -> Self {
+        let (success, output) = match backend.result {
+            Ok(bytes) => (true, bytes.to_string()),
+            Err(reason) => (false, reason),
+        };
+
+        BackendResult {
+            success,
+            output,
+            gas_used: backend.gas_used.map(|gas_used| gas_used.to_string()),
+            amount_out: backend.amount_out.map(|amount_out| amount_out.to_string()),
+        }
+    }
+}
+
+/// Outcome of the approve transaction
+#[napi(object)]
+#[derive(Serialize)]
+pub struct ApproveResult {
+    pub success: bool,
+    /// Hex return data, e.g. an encoded `true`, when it succeeded
+    pub output: Option<String>,
+    /// Revert reason, when it reverted. The call still ran, without the allowance.
+    pub reason: Option<String>,
+}
+
+impl From<CallResult> for ApproveResult {
+    fn from(result: CallResult) -> Self {
+        match result {
+            Ok(data) => ApproveResult {
+                success: true,
+                output: Some(data.to_string()),
+                reason: None,
+            },
+            Err(reason) => ApproveResult {
+                success: false,
+                output: None,
+                reason: Some(reason),
+            },
+        }
+    }
+}
+
+/// Results of both engines for the same call
+#[napi(object)]
+#[derive(Serialize)]
+pub struct DivergenceReport {
+    pub rpc_output: Option<BackendResult>,
+    /// Full cause chain, when the RPC attempt failed
+    pub rpc_error: Option<String>,
+    pub revm_output: Option<BackendResult>,
+    /// Full cause chain, when the REVM attempt failed
+    pub revm_error: Option<String>,
+    /// Both engines returned the same output and `amount_out`
+    pub agree: bool,
+}
+
+impl From<DivergenceReportInternal> for DivergenceReport {
+    fn from(report: DivergenceReportInternal) -> Self {
+        let (rpc_output, rpc_error) = match report.rpc_output {
+            Ok(output) => (Some(output.into()), None),
+            Err(error) => (None, Some(error)),
+        };
+        let (revm_output, revm_error) = match report.revm_output {
+            Ok(output) => (Some(output.into()), None),
+            Err(error) => (None, Some(error)),
+        };
+
+        DivergenceReport {
+            rpc_output,
+            rpc_error,
+            revm_output,
+            revm_error,
+            agree: report.agree,
+        }
+    }
+}
+
+/// Failed REVM execution of the call
+#[napi(object)]
+#[derive(Serialize)]
+pub struct ExecutionResult {
+    #[napi(ts_type = "\"revert\" | \"halt\"")]
+    pub kind: String,
+    pub gas_used: String,
+    /// Decoded revert string or panic of a revert, the halt or stop reason otherwise
+    pub reason: String,
+    /// Revert data as a hex string
+    pub output: String,
+}
+
+impl From<FailedExecution> for ExecutionResult {
+    fn from(failed: FailedExecution) -> Self {
+        ExecutionResult {
+            kind: failed.kind.as_str().to_string(),
+            gas_used: failed.gas_used.to_string(),
+            reason: failed.reason,
+            output: failed.output.to_string(),
+        }
+    }
+}
+
+/// State the call started from, to tell why it failed
+#[napi(object)]
+#[derive(Serialize)]
+pub struct PreRevertState {
+    /// The approval went through, `false` when it reverted or none was needed
+    pub approve_succeeded: bool,
+    /// `token_in` balance of the user, when REVM produced the result
+    pub token_in_balance: Option<String>,
+    pub allowance: Option<String>,
+    /// `token_out` balance of the user, when `token_out_address` was set
+    pub token_out_balance: Option<String>,
+}
+
+impl From<PreRevertStateInternal> for PreRevertState {
+    fn from(state: PreRevertStateInternal) -> Self {
+        PreRevertState {
+            approve_succeeded: state.approve_succeeded,
+            token_in_balance: state.token_in_balance.map(|balance| balance.to_string()),
+            allowance: state.allowance.map(|allowance| allowance.to_string()),
+            token_out_balance: state.token_out_balance.map(|balance| balance.to_string()),
+        }
+    }
+}
+
+#[napi(object)]
+#[derive(Serialize)]
+pub struct RpcTransactionResponse {
+    pub value: Option<String>,
+    pub error: Option<String>,
+    pub gas_used: Option<String>,
+    pub gas_refunded: Option<String>,
+}
+
+impl From<DetailedTransactionResponse> for RpcTransactionResponse {
+    fn from(tx: DetailedTransactionResponse) -> Self {
+        let error = tx.response.error_message();
+        let value = match tx.response {
+            TransactionResponse::Success { value } => Some(value.to_string()),
+            _ => None,
+        };
+
+        RpcTransactionResponse {
+            value,
+            error,
+            gas_used: tx.gas_used.map(|gas_used| gas_used.to_string()),
+            gas_refunded: tx.gas_refunded.map(|gas_refunded| gas_refunded.to_string()),
+        }
+    }
+}
+
+#[napi(object)]
+#[derive(Serialize)]
 pub struct Error {
     #[napi(ts_type = "\"error\"")]
     pub status: String,
+    #[napi(
+        ts_type = "\"invalid_params\" | \"find_slot_failed\" | \"rpc_error\" | \"both_simulations_failed\" | \"cancelled\" | \"timeout\" | \"chain_id_mismatch\" | \"target_not_contract\" | \"gas_cap_exceeded\" | \"swap_reverted\" | \"amount_out_negative\" | \"amount_overflow\" | \"state_unavailable\" | \"pending_unsupported\" | \"revm_failed\""
+    )]
+    pub code: String,
     pub error: String,
+    /// Full cause chain of the RPC attempt, for `both_simulations_failed`
+    pub rpc_error: Option<String>,
+    /// Full cause chain of the REVM attempt, for `both_simulations_failed`
+    pub revm_error: Option<String>,
+}
+
+impl Error {
+    fn invalid_params(error: String) -> Self {
+        Error {
+            status: STATUS_ERROR.to_string(),
+            code: CODE_INVALID_PARAMS.to_string(),
+            error,
+            rpc_error: None,
+            revm_error: None,
+        }
+    }
+}
+
+impl From<SimulateError> for Error {
+    fn from(e: SimulateError) -> Self {
+        let (rpc_error, revm_error) = match &e {
+            SimulateError::BothSimulationsFailed(both) => (
+                Some(format_error_chain(&both.rpc_error, "  ")),
+                Some(format_error_chain(&both.revm_error, "  ")),
+            ),
+            _ => (None, None),
+        };
+
+        Error {
+            status: STATUS_ERROR.to_string(),
+            code: e.code().to_string(),
+            error: format!("{:#}", anyhow::Error::from(e)),
+            rpc_error,
+            revm_error,
+        }
+    }
+}
+
+#[napi(object)]
+pub struct SwapParams {
+    pub user_address: String,
+    pub token_in_address: String,
+    pub amount_in: String,
+    pub token_out_address: String,
+    pub router_address: String,
+    pub calldata: String,
+    /// Headers sent with every request to the RPC, e.g. `{ "x-api-key": "..." }`
+    pub rpc_headers: Option<HashMap<String, String>>,
+    /// Least `amount_out` the swap must deliver, reported as `slippage.min_met`
+    pub min_amount_out: Option<String>,
+    /// Quoted `amount_out`, which `slippage.slippage_bps` is measured against
+    pub expected_amount_out: Option<String>,
+}
+
+impl TryFrom<SwapParams> for SwapParamsInternal {
+    type Error = Error;
+
+    fn try_from(params: SwapParams) -> Result<Self, Self::Error> {
+        Ok(SwapParamsInternal {
+            user: parse_or_error(&params.user_address, "user address")?,
+            token_in: parse_or_error(&params.token_in_address, "token address")?,
+            amount_in: parse_amount(&params.amount_in, "amount in")?,
+            token_out: parse_or_error(&params.token_out_address, "token out address")?,
+            router: parse_or_error(&params.router_address, "router address")?,
+            calldata: parse_or_error(&params.calldata, "calldata")?,
+            rpc_headers: params.rpc_headers.unwrap_or_default(),
+            min_amount_out: parse_optional_amount(params.min_amount_out, "min amount out")?,
+            expected_amount_out: parse_optional_amount(
+                params.expected_amount_out,
+                "expected amount out",
+            )?,
+        })
+    }
+}
+
+#[napi(object)]
+pub struct SwapResult {
+    #[napi(ts_type = "\"simulation_success\"")]
+    pub status: String,
+    pub amount_out: String,
+    /// Zero when the node produced the result without reporting it
+    pub gas_used: i64,
+    #[napi(ts_type = "\"rpc\" | \"revm\"")]
+    pub path: String,
+    /// When `min_amount_out` or `expected_amount_out` was provided
+    pub slippage: Option<SlippageReport>,
+}
+
+#[napi(object)]
+pub struct SlippageReport {
+    pub amount_out: String,
+    /// `amount_out` reaches `min_amount_out`, always `true` without one
+    pub min_met: bool,
+    /// Shortfall against `expected_amount_out` in basis points, negative when the swap
+    /// delivered more
+    pub slippage_bps: Option<i64>,
+}
+
+impl From<SlippageReportInternal> for SlippageReport {
+    fn from(report: SlippageReportInternal) -> Self {
+        SlippageReport {
+            amount_out: report.amount_out.to_string(),
+            min_met: report.min_met,
+            slippage_bps: report.slippage_bps,
+        }
+    }
+}
+
+#[napi(object)]
+pub struct WarmToken {
+    pub token_address: String,
+    pub user_address: String,
+}
+
+#[napi(object)]
+pub struct ChainInfo {
+    pub chain_id: u32,
+    pub block_number: i64,
 }
 
+impl From<ChainInfoInternal> for ChainInfo {
+    fn from(info: ChainInfoInternal) -> Self {
+        ChainInfo {
+            chain_id: info.chain_id as u32,
+            block_number: info.block_number as i64,
+        }
+    }
+}
+
+/// Size of the per-chain state caches
+#[napi(object)]
+pub struct CacheStats {
+    /// Chains with a state cache
+    pub chains: u32,
+    /// Cached accounts, keyed by chain id
+    pub accounts_per_chain: HashMap<String, u32>,
+    /// Estimate of the memory the caches hold, in bytes
+    pub approx_bytes: i64,
+}
+
+impl From<CacheStatsInternal> for CacheStats {
+    fn from(stats: CacheStatsInternal) -> Self {
+        CacheStats {
+            chains: stats.chains as u32,
+            accounts_per_chain: stats
+                .accounts_per_chain
+                .into_iter()
+                .map(|(chain_id, accounts)| (chain_id.to_string(), accounts as u32))
+                .collect(),
+            approx_bytes: stats.approx_bytes as i64,
+        }
+    }
+}
+
+type SimulateOutcome = Either3<SimulationSuccess, SimulationFailed, Error>;
+
+/// Reported to the `simulateBatch` progress callback once an item is done
+#[napi(object)]
+pub struct BatchProgress {
+    pub index: u32,
+    pub total: u32,
+    #[napi(ts_type = "\"simulation_success\" | \"simulation_failed\" | \"error\"")]
+    pub status: String,
+}
+
+/// JS progress callback, called without waiting for it to return
+type ProgressCallback =
+    ThreadsafeFunction<BatchProgress, UnknownReturnValue, BatchProgress, Status, false>;
+
+/// Handed to the `simulateBatchStream` callback once an item is done
+#[napi(object)]
+pub struct BatchResult {
+    /// Position of the item in `params`
+    pub index: u32,
+    pub total: u32,
+    #[napi(ts_type = "SimulationSuccess | SimulationFailed | Error")]
+    pub outcome: SimulateOutcome,
+}
+
+/// JS result callback, called without waiting for it to return
+type ResultCallback =
+    ThreadsafeFunction<BatchResult, UnknownReturnValue, BatchResult, Status, false>;
+
 #[napi]
 pub struct Simulator {
-    inner: SimulatorImpl,
+    inner: Arc<Mutex<SimulatorImpl>>,
+}
+
+impl Default for Simulator {
+    fn default() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(SimulatorImpl::new())),
+        }
+    }
 }
 
 #[napi]
 impl Simulator {
+    /// Throws on an invalid `config`.
     #[napi(constructor)]
-    pub fn new() -> Self {
-        Self {
-            inner: SimulatorImpl::new(),
-        }
+    pub fn new(config: Option<SimulatorConfig>) -> napi::Result<Self> {
+        let config = SimulatorConfigInternal::try_from(config.unwrap_or_default())
+            .map_err(|e| napi::Error::from_reason(e.error))?;
+
+        Ok(Self {
+            inner: Arc::new(Mutex::new(SimulatorImpl::new_with_config(config))),
+        })
     }
 
     /// Simulates a transaction with token balance manipulation.
     ///
-    /// **WARNING**: Not safe for concurrent calls - cache will be overwritten.
-    /// Always await each call before starting the next one.
+    /// Concurrent calls are queued and executed one at a time.
+    /// Aborting `signal` resolves the pending call with an `Error` whose message is `"cancelled"`.
     #[napi(ts_return_type = "Promise<SimulationSuccess | SimulationFailed | Error>")]
-    pub async unsafe fn simulate(
-        &mut self,
+    pub fn simulate<'env>(
+        &self,
+        env: &'env Env,
         params: SimulationParams,
         chain_id: u32,
         rpc_url: String,
-    ) -> napi::Result<Either3<SimulationSuccess, SimulationFailed, Error>> {
-        let (simulation_params, rpc_url) = match validate_and_convert(params, rpc_url) {
-            Ok(validated) => validated,
-            Err(e) => return Ok(Either3::C(e)),
+        signal: Option<AbortSignal>,
+    ) -> napi::Result<PromiseRaw<'env, SimulateOutcome>> {
+        let cancellation = CancellationToken::new();
+
+        if let Some(signal) = signal {
+            let cancellation = cancellation.clone();
+            signal.on_abort(move || cancellation.cancel());
+        }
+
+        let inner = self.inner.clone();
+
+        env.spawn_future(async move {
+            Ok(simulate_outcome(inner, params, chain_id, rpc_url, cancellation).await)
+        })
+    }
+
+    /// Simulates each of `params` in turn on the same chain, resolving to their outcomes in
+    /// order. `on_progress` is called after each item, from the runtime, without blocking it.
+    /// Aborting `signal` resolves the remaining items with a `"cancelled"` `Error`.
+    #[napi(ts_return_type = "Promise<Array<SimulationSuccess | SimulationFailed | Error>>")]
+    pub fn simulate_batch<'env>(
+        &self,
+        env: &'env Env,
+        params: Vec<SimulationParams>,
+        chain_id: u32,
+        rpc_url: String,
+        on_progress: Option<ProgressCallback>,
+        signal: Option<AbortSignal>,
+    ) -> napi::Result<PromiseRaw<'env, Vec<SimulateOutcome>>> {
+        let cancellation = CancellationToken::new();
+
+        if let Some(signal) = signal {
+            let cancellation = cancellation.clone();
+            signal.on_abort(move || cancellation.cancel());
+        }
+
+        let inner = self.inner.clone();
+
+        env.spawn_future(async move {
+            let report = |progress| {
+                if let Some(on_progress) = &on_progress {
+                    on_progress.call(progress, ThreadsafeFunctionCallMode::NonBlocking);
+                }
+            };
+
+            Ok(
+                simulate_batch_outcomes(inner, params, chain_id, rpc_url, cancellation, report)
+                    .await,
+            )
+        })
+    }
+
+    /// Simulates each of `params` in turn like `simulateBatch`, but hands each outcome to
+    /// `on_result` as soon as it is done instead of collecting them, resolving once every
+    /// item has been reported. Aborting `signal` reports the remaining items as
+    /// `"cancelled"` `Error`s.
+    #[napi(ts_return_type = "Promise<void>")]
+    pub fn simulate_batch_stream<'env>(
+        &self,
+        env: &'env Env,
+        params: Vec<SimulationParams>,
+        chain_id: u32,
+        rpc_url: String,
+        on_result: ResultCallback,
+        signal: Option<AbortSignal>,
+    ) -> napi::Result<PromiseRaw<'env, ()>> {
+        let cancellation = CancellationToken::new();
+
+        if let Some(signal) = signal {
+            let cancellation = cancellation.clone();
+            signal.on_abort(move || cancellation.cancel());
+        }
+
+        let inner = self.inner.clone();
+
+        env.spawn_future(async move {
+            stream_batch_outcomes(inner, params, chain_id, rpc_url, cancellation, |result| {
+                on_result.call(result, ThreadsafeFunctionCallMode::NonBlocking);
+            })
+            .await;
+
+            Ok(())
+        })
+    }
+
+    /// Simulates a swap through a router and reports the `token_out` amount received.
+    #[napi(ts_return_type = "Promise<SwapResult | Error>")]
+    pub fn simulate_swap<'env>(
+        &self,
+        env: &'env Env,
+        params: SwapParams,
+        chain_id: u32,
+        rpc_url: String,
+        signal: Option<AbortSignal>,
+    ) -> napi::Result<PromiseRaw<'env, Either<SwapResult, Error>>> {
+        let cancellation = CancellationToken::new();
+
+        if let Some(signal) = signal {
+            let cancellation = cancellation.clone();
+            signal.on_abort(move || cancellation.cancel());
+        }
+
+        let inner = self.inner.clone();
+
+        env.spawn_future(async move {
+            Ok(simulate_swap_outcome(inner, params, chain_id, rpc_url, cancellation).await)
+        })
+    }
+
+    /// Verifies the RPC is reachable and serves `chain_id`. Useful to validate config at startup.
+    #[napi(ts_return_type = "Promise<ChainInfo | Error>")]
+    pub async fn check_rpc(
+        &self,
+        chain_id: u32,
+        rpc_url: String,
+        rpc_headers: Option<HashMap<String, String>>,
+    ) -> napi::Result<Either<ChainInfo, Error>> {
+        let rpc_url = match parse_or_error::<Url>(&rpc_url, "RPC URL") {
+            Ok(rpc_url) => rpc_url,
+            Err(e) => return Ok(Either::B(e)),
         };
 
-        let output = match self
+        let result = self
             .inner
-            .simulate(chain_id, rpc_url, simulation_params)
+            .lock()
             .await
+            .check_rpc(chain_id, rpc_url, &rpc_headers.unwrap_or_default())
+            .await;
+
+        Ok(match result {
+            Ok(chain_info) => Either::A(chain_info.into()),
+            Err(e) => Either::B(e.into()),
+        })
+    }
+
+    /// Resolves and caches the balance slot of each token for its user ahead of time,
+    /// so the first `simulate` for them is faster. Resolves to `null` on success.
+    #[napi(ts_return_type = "Promise<Error | null>")]
+    pub async fn warm(
+        &self,
+        chain_id: u32,
+        rpc_url: String,
+        tokens: Vec<WarmToken>,
+        rpc_headers: Option<HashMap<String, String>>,
+    ) -> napi::Result<Option<Error>> {
+        let rpc_url = match parse_or_error::<Url>(&rpc_url, "RPC URL") {
+            Ok(rpc_url) => rpc_url,
+            Err(e) => return Ok(Some(e)),
+        };
+
+        let tokens = match tokens
+            .into_iter()
+            .map(|token| {
+                Ok((
+                    parse_or_error(&token.token_address, "token address")?,
+                    parse_or_error(&token.user_address, "user address")?,
+                ))
+            })
+            .collect::<Result<_, Error>>()
         {
-            Ok(output) => output,
-            Err(e) => {
-                return Ok(Either3::C(Error {
-                    status: STATUS_ERROR.to_string(),
-                    error: format!("{:#}", anyhow::Error::from(e)),
-                }));
-            }
+            Ok(tokens) => tokens,
+            Err(e) => return Ok(Some(e)),
+        };
+
+        let result = self
+            .inner
+            .lock()
+            .await
+            .warm(chain_id, rpc_url, &rpc_headers.unwrap_or_default(), tokens)
+            .await;
+
+        Ok(result.err().map(Into::into))
+    }
+
+    /// Reads the ERC20 balance of `user_address` through REVM, at `block_number` or else
+    /// the latest block, without simulating anything. Resolves to the balance in the
+    /// token's smallest unit.
+    #[napi(ts_return_type = "Promise<string | Error>")]
+    pub async fn balance_of(
+        &self,
+        token_address: String,
+        user_address: String,
+        chain_id: u32,
+        rpc_url: String,
+        block_number: Option<i64>,
+        rpc_headers: Option<HashMap<String, String>>,
+    ) -> napi::Result<Either<String, Error>> {
+        let parsed = (|| {
+            Ok((
+                parse_or_error::<Address>(&token_address, "token address")?,
+                parse_or_error::<Address>(&user_address, "user address")?,
+                parse_or_error::<Url>(&rpc_url, "RPC URL")?,
+                block_number
+                    .map(u64::try_from)
+                    .transpose()
+                    .map_err(|e| Error::invalid_params(format!("Invalid block number: {e}")))?,
+            ))
+        })();
+        let (token, user, rpc_url, block_number) = match parsed {
+            Ok(parsed) => parsed,
+            Err(e) => return Ok(Either::B(e)),
+        };
+
+        let result = self
+            .inner
+            .lock()
+            .await
+            .balance_of(
+                chain_id,
+                rpc_url,
+                &rpc_headers.unwrap_or_default(),
+                token,
+                user,
+                block_number,
+            )
+            .await;
+
+        Ok(match result {
+            Ok(balance) => Either::A(balance.to_string()),
+            Err(e) => Either::B(e.into()),
+        })
+    }
+
+    /// Drops the cached state and slots of `chain_id`, or of every chain along with the
+    /// pooled RPC connections when omitted. Call it after a reorg, or to free memory in a
+    /// long-running process.
+    #[napi]
+    pub async fn clear_cache(&self, chain_id: Option<u32>) {
+        self.inner.lock().await.clear_cache(chain_id);
+    }
+
+    /// Size of the cached state, e.g. to monitor memory and decide when to `clearCache`
+    #[napi]
+    pub async fn cache_stats(&self) -> CacheStats {
+        self.inner.lock().await.cache_stats().into()
+    }
+}
+
+async fn lock_or_cancel<'a>(
+    inner: &'a Mutex<SimulatorImpl>,
+    cancellation: &CancellationToken,
+) -> Result<MutexGuard<'a, SimulatorImpl>, Error> {
+    tokio::select! {
+        _ = cancellation.cancelled() => Err(SimulateError::Cancelled.into()),
+        inner = inner.lock() => Ok(inner),
+    }
+}
+
+async fn simulate_batch_outcomes(
+    inner: Arc<Mutex<SimulatorImpl>>,
+    params: Vec<SimulationParams>,
+    chain_id: u32,
+    rpc_url: String,
+    cancellation: CancellationToken,
+    mut on_progress: impl FnMut(BatchProgress),
+) -> Vec<SimulateOutcome> {
+    let mut outcomes = Vec::with_capacity(params.len());
+
+    stream_batch_outcomes(inner, params, chain_id, rpc_url, cancellation, |result| {
+        let status = match &result.outcome {
+            Either3::A(success) => success.status.clone(),
+            Either3::B(failed) => failed.status.clone(),
+            Either3::C(error) => error.status.clone(),
         };
+        on_progress(BatchProgress {
+            index: result.index,
+            total: result.total,
+            status,
+        });
+
+        outcomes.push(result.outcome);
+    })
+    .await;
+
+    outcomes
+}
+
+/// Simulates each of `params` in turn, handing each outcome to `on_result` as it is done
+async fn stream_batch_outcomes(
+    inner: Arc<Mutex<SimulatorImpl>>,
+    params: Vec<SimulationParams>,
+    chain_id: u32,
+    rpc_url: String,
+    cancellation: CancellationToken,
+    mut on_result: impl FnMut(BatchResult),
+) {
+    let total = params.len() as u32;
+
+    for (index, params) in params.into_iter().enumerate() {
+        let outcome = simulate_outcome(
+            inner.clone(),
+            params,
+            chain_id,
+            rpc_url.clone(),
+            cancellation.clone(),
+        )
+        .await;
+
+        on_result(BatchResult {
+            index: index as u32,
+            total,
+            outcome,
+        });
+    }
+}
+
+async fn simulate_outcome(
+    inner: Arc<Mutex<SimulatorImpl>>,
+    params: SimulationParams,
+    chain_id: u32,
+    rpc_url: String,
+    cancellation: CancellationToken,
+) -> SimulateOutcome {
+    let (simulation_params, rpc_url) = match validate_and_convert(params, rpc_url) {
+        Ok(validated) => validated,
+        Err(e) => return Either3::C(e),
+    };
+
+    let mut inner = match lock_or_cancel(&inner, &cancellation).await {
+        Ok(inner) => inner,
+        Err(e) => return Either3::C(e),
+    };
+
+    inner
+        .simulate_outcome(chain_id, rpc_url, simulation_params, &cancellation)
+        .await
+        .into()
+}
+
+/// Serializes whichever variant `outcome` holds, so results can be logged as structured
+/// JSON on the Rust side, with the same field names JS sees.
+pub fn outcome_to_json(outcome: &SimulateOutcome) -> serde_json::Value {
+    match outcome {
+        Either3::A(success) => serde_json::to_value(success),
+        Either3::B(failed) => serde_json::to_value(failed),
+        Either3::C(error) => serde_json::to_value(error),
+    }
+    .expect("result fields are JSON-compatible")
+}
+
+impl From<Outcome> for SimulateOutcome {
+    fn from(outcome: Outcome) -> Self {
+        let name = outcome.as_str().to_string();
+        match outcome {
+            Outcome::RpcSuccess(output)
+            | Outcome::RpcRevert(output)
+            | Outcome::RpcFailedRevmSuccess(output)
+            | Outcome::RpcFailedRevmRevert(output)
+            | Outcome::RevmSuccess(output)
+            | Outcome::RevmRevert(output) => simulation_result(name, output),
+            Outcome::BothFailed(error) => {
+                Either3::C(SimulateError::BothSimulationsFailed(error).into())
+            }
+            Outcome::Error(error) => Either3::C(error.into()),
+        }
+    }
+}
+
+/// Converts the output of a simulation that ran into the JS result of its `outcome`
+fn simulation_result(outcome: String, output: SimulationOutput) -> SimulateOutcome {
+    let source = output.path.as_str().to_string();
 
-        let rpc_err = output
-            .simulation_via_rpc_err
-            .map(|e| format!("{:#}", anyhow::Error::from(e)));
+    let rpc_backend = output
+        .rpc_backend
+        .map(|rpc_backend| rpc_backend.as_str().to_string());
 
-        let ts_result = match output.result {
-            Ok(bytes) => Either3::A(SimulationSuccess {
-                status: STATUS_SUCCESS.to_string(),
-                output: bytes.to_string(),
-                rpc_err,
+    let rpc_err = output
+        .simulation_via_rpc_err
+        .map(|e| format!("{:#}", anyhow::Error::from(e)));
+    let allowance_slot_err = output
+        .allowance_slot_err
+        .map(|e| format!("{:#}", anyhow::Error::from(e)));
+
+    let selector = output.selector.map(|selector| selector.to_string());
+    let approve_result = output.approve_result.map(ApproveResult::from);
+
+    let rpc_responses = output
+        .rpc_responses
+        .map(|responses| responses.into_iter().map(Into::into).collect());
+
+    let raw_rpc_response = output
+        .raw_rpc_response
+        .map(|raw_rpc_response| raw_rpc_response.get().to_string());
+
+    let gas_profile = output.gas_profile.map(Into::into);
+
+    let allowance = output.allowance.map(|allowance| allowance.to_string());
+
+    let state_diff = output.state_diff.map(|state_diff| {
+        state_diff
+            .into_iter()
+            .map(|(address, slots)| {
+                let slots = slots
+                    .into_iter()
+                    .map(|(slot, value)| {
+                        (B256::from(slot).to_string(), B256::from(value).to_string())
+                    })
+                    .collect();
+                (address.to_string(), slots)
+            })
+            .collect()
+    });
+
+    let l1_fee = output.l1_fee.map(|l1_fee| l1_fee.to_string());
+    let effective_gas_price = output
+        .effective_gas_price
+        .map(|effective_gas_price| effective_gas_price.to_string());
+    let gas_cost_wei = output
+        .gas_cost_wei
+        .map(|gas_cost_wei| gas_cost_wei.to_string());
+
+    let divergence = output.divergence.map(Into::into);
+
+    match output.result {
+        Ok(bytes) => Either3::A(SimulationSuccess {
+            status: STATUS_SUCCESS.to_string(),
+            outcome,
+            output: bytes.to_string(),
+            source,
+            rpc_backend,
+            rpc_err,
+            allowance_slot_err,
+            balance_slot_address: output
+                .balance_slot
+                .as_ref()
+                .map(|slot| slot.address.to_string()),
+            balance_slot: output
+                .balance_slot
+                .as_ref()
+                .map(|slot| B256::from(slot.slot).to_string()),
+            slot_strategy: output
+                .slot_strategy
+                .map(|slot_strategy| slot_strategy.as_str().to_string()),
+            amount_out: output.amount_out.map(|amount_out| amount_out.to_string()),
+            amount_out_converted: output
+                .amount_out_converted
+                .map(|amount_out_converted| amount_out_converted.to_string()),
+            actual_amount_in: output
+                .actual_amount_in
+                .map(|actual_amount_in| actual_amount_in.to_string()),
+            rpc_responses,
+            raw_rpc_response,
+            gas_profile,
+            allowance,
+            block_number: output.block_number as i64,
+            state_diff,
+            l1_fee,
+            effective_gas_price,
+            gas_cost_wei,
+            hops: output
+                .hops
+                .map(|hops| hops.into_iter().map(Into::into).collect()),
+            divergence,
+            rpc_request_count: output.rpc_request_count as i64,
+            approve_result,
+            selector,
+            success_reason: output.success_reason.map(|reason| {
+                match reason {
+                    SuccessReason::Stop => "stop",
+                    SuccessReason::Return => "return",
+                    SuccessReason::SelfDestruct => "self_destruct",
+                }
+                .to_string()
             }),
-            Err(reason) => Either3::B(SimulationFailed {
-                status: STATUS_FAILED.to_string(),
-                output: reason,
-                rpc_err,
+            slot_verification: output.slot_verification.map(Into::into),
+        }),
+        Err(reason) => Either3::B(SimulationFailed {
+            status: STATUS_FAILED.to_string(),
+            outcome,
+            output: reason,
+            source,
+            rpc_backend,
+            rpc_err,
+            allowance_slot_err,
+            rpc_responses,
+            raw_rpc_response,
+            gas_profile,
+            allowance,
+            block_number: output.block_number as i64,
+            state_diff,
+            l1_fee,
+            effective_gas_price,
+            gas_cost_wei,
+            execution_result: output.failed_execution.map(Into::into),
+            divergence,
+            pre_revert_state: output.pre_revert_state.map(Into::into),
+            rpc_request_count: output.rpc_request_count as i64,
+            diagnostic_hint: output
+                .diagnostic_hint
+                .map(|diagnostic_hint| diagnostic_hint.as_str().to_string()),
+            approve_result,
+            selector,
+            revert_data: output
+                .revert_data
+                .map(|revert_data| revert_data.to_string()),
+        }),
+    }
+}
+
+async fn simulate_swap_outcome(
+    inner: Arc<Mutex<SimulatorImpl>>,
+    params: SwapParams,
+    chain_id: u32,
+    rpc_url: String,
+    cancellation: CancellationToken,
+) -> Either<SwapResult, Error> {
+    let (swap_params, rpc_url) = match validate_and_convert(params, rpc_url) {
+        Ok(validated) => validated,
+        Err(e) => return Either::B(e),
+    };
+
+    let mut inner = match lock_or_cancel(&inner, &cancellation).await {
+        Ok(inner) => inner,
+        Err(e) => return Either::B(e),
+    };
+
+    match inner
+        .simulate_swap(chain_id, rpc_url, swap_params, &cancellation)
+        .await
+    {
+        Ok(swap_result) => Either::A(SwapResult {
+            status: STATUS_SUCCESS.to_string(),
+            amount_out: swap_result.amount_out.to_string(),
+            gas_used: swap_result.gas_used as i64,
+            path: swap_result.path.as_str().to_string(),
+            slippage: swap_result.slippage.map(Into::into),
+        }),
+        Err(e) => Either::B(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::sol_types::{Revert, SolError};
+    use alloy_json_rpc::RpcError;
+    use revm::{
+        context::result::ExecutionResult,
+        primitives::{Address, Bytes, U256},
+    };
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        balance_slot::SlotWithAddress,
+        eth_call_many::EthCallManyError,
+        simulator::{
+            ApproveError, BothSimulationsFailed, SimulateViaRevmError, SimulateViaRpcError,
+            SimulationPath,
+        },
+    };
+
+    #[test]
+    fn test_both_failed_error_keeps_cause_chains() {
+        let error: Error = SimulateError::from(BothSimulationsFailed {
+            rpc_error: SimulateViaRpcError::EthCallMany(EthCallManyError::Rpc(
+                RpcError::local_usage_str("connection refused"),
+            )),
+            revm_error: SimulateViaRevmError::Approve(ApproveError::Execution(
+                ExecutionResult::Revert {
+                    gas_used: 0,
+                    output: Bytes::new(),
+                },
+            )),
+        })
+        .into();
+
+        assert_eq!(error.code, "both_simulations_failed");
+
+        let rpc_error = error.rpc_error.unwrap();
+        assert!(rpc_error.starts_with("eth_callMany call failed\n  caused by: "));
+        assert!(rpc_error.contains("connection refused"), "{rpc_error}");
+
+        let revm_error = error.revm_error.unwrap();
+        assert!(
+            revm_error
+                .starts_with("approving the spender failed\n  caused by: execution failed: Revert"),
+            "{revm_error}"
+        );
+    }
+
+    #[test]
+    fn test_other_errors_have_no_cause_chains() {
+        let error: Error = SimulateError::Cancelled.into();
+
+        assert!(error.rpc_error.is_none());
+        assert!(error.revm_error.is_none());
+    }
+
+    fn output(path: SimulationPath, result: Result<Bytes, String>) -> SimulationOutput {
+        SimulationOutput {
+            result,
+            simulation_via_rpc_err: None,
+            balance_slot: Some(SlotWithAddress {
+                address: Address::ZERO,
+                slot: U256::from(9),
+                scale: None,
+                packing: None,
+            }),
+            balance_layout: None,
+            slot_strategy: None,
+            path,
+            gas_used: None,
+            gas_refunded: None,
+            amount_out: None,
+            token_out_loss: None,
+            amount_out_converted: None,
+            rpc_responses: None,
+            raw_rpc_response: None,
+            gas_profile: None,
+            allowance: None,
+            block_number: 16,
+            allowance_slot: None,
+            allowance_slot_err: None,
+            state_diff: None,
+            l1_fee: None,
+            effective_gas_price: None,
+            gas_cost_wei: None,
+            actual_amount_in: None,
+            rpc_backend: None,
+            failed_execution: None,
+            hops: None,
+            divergence: None,
+            pre_revert_state: None,
+            rpc_request_count: 3,
+            diagnostic_hint: None,
+            approve_result: None,
+            selector: None,
+            success_reason: None,
+            revert_data: None,
+            slot_verification: None,
+        }
+    }
+
+    #[test]
+    fn test_success_serializes_to_json() {
+        let json = outcome_to_json(
+            &Outcome::from(Ok(output(SimulationPath::Revm, Ok(Bytes::new())))).into(),
+        );
+
+        assert_eq!(json["status"], "simulation_success");
+        assert_eq!(json["output"], "0x");
+        assert_eq!(json["source"], "revm");
+        assert_eq!(json["balance_slot_address"], Address::ZERO.to_string());
+        assert_eq!(json["block_number"], 16);
+        assert!(json["amount_out"].is_null());
+    }
+
+    #[test]
+    fn test_failed_serializes_to_json() {
+        let json = outcome_to_json(
+            &Outcome::from(Ok(output(SimulationPath::Rpc, Err("STF".to_string())))).into(),
+        );
+
+        assert_eq!(json["status"], "simulation_failed");
+        assert_eq!(json["output"], "STF");
+        assert_eq!(json["source"], "rpc");
+    }
+
+    #[test]
+    fn test_revm_revert_is_structured() {
+        let revert_data = Revert::from("STF").abi_encode();
+        let failed_output = SimulationOutput {
+            failed_execution: FailedExecution::from_result(&ExecutionResult::Revert {
+                gas_used: 23_000,
+                output: revert_data.clone().into(),
             }),
+            ..output(SimulationPath::Revm, Err("Revert".to_string()))
         };
 
-        Ok(ts_result)
+        let json = outcome_to_json(&Outcome::from(Ok(failed_output)).into());
+
+        assert_eq!(
+            json["execution_result"],
+            json!({
+                "kind": "revert",
+                "gas_used": "23000",
+                "reason": "revert: STF",
+                "output": Bytes::from(revert_data).to_string(),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_batch_reports_progress_per_item() {
+        let inner = Arc::new(Mutex::new(SimulatorImpl::new()));
+        let params = (0..3).map(|_| SimulationParams::default()).collect();
+
+        let mut progress = Vec::new();
+        let outcomes = simulate_batch_outcomes(
+            inner,
+            params,
+            1,
+            "not a url".to_string(),
+            CancellationToken::new(),
+            |item| progress.push((item.index, item.total, item.status)),
+        )
+        .await;
+
+        assert_eq!(outcomes.len(), 3);
+        assert_eq!(
+            progress,
+            [
+                (0, 3, STATUS_ERROR.to_string()),
+                (1, 3, STATUS_ERROR.to_string()),
+                (2, 3, STATUS_ERROR.to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_batch_stream_reports_every_item() {
+        let inner = Arc::new(Mutex::new(SimulatorImpl::new()));
+        let params = (0..3).map(|_| SimulationParams::default()).collect();
+
+        let mut results = Vec::new();
+        stream_batch_outcomes(
+            inner,
+            params,
+            1,
+            "not a url".to_string(),
+            CancellationToken::new(),
+            |result| results.push(result),
+        )
+        .await;
+
+        assert_eq!(
+            results
+                .iter()
+                .map(|result| (result.index, result.total))
+                .collect::<Vec<_>>(),
+            [(0, 3), (1, 3), (2, 3)]
+        );
+        assert!(results.iter().all(
+            |result| matches!(&result.outcome, Either3::C(error) if error.code == "invalid_params")
+        ));
+    }
+
+    #[test]
+    fn test_error_serializes_to_json() {
+        let json = outcome_to_json(&Either3::C(SimulateError::Cancelled.into()));
+
+        assert_eq!(json["status"], "error");
+        assert_eq!(json["code"], "cancelled");
+        assert_eq!(json["error"], "cancelled");
+        assert!(json["rpc_error"].is_null());
+    }
+
+    #[test]
+    fn test_outcome_reports_executing_engine() {
+        let outcome = |path, result| SimulateOutcome::from(Outcome::from(Ok(output(path, result))));
+
+        match outcome(SimulationPath::Revm, Ok(Bytes::new())) {
+            Either3::A(success) => assert_eq!(success.source, "revm"),
+            _ => panic!("expected success"),
+        }
+
+        match outcome(SimulationPath::Rpc, Err("STF".to_string())) {
+            Either3::B(failed) => assert_eq!(failed.source, "rpc"),
+            _ => panic!("expected failed simulation"),
+        }
+    }
+
+    #[test]
+    fn test_outcome_maps_to_status() {
+        let status = |outcome: Outcome| match SimulateOutcome::from(outcome) {
+            Either3::A(success) => (success.status, success.outcome),
+            Either3::B(failed) => (failed.status, failed.outcome),
+            Either3::C(error) => (error.status, String::new()),
+        };
+        let rpc_err = || Some(SimulateViaRpcError::NoResponse);
+        let cases = [
+            (
+                SimulationPath::Rpc,
+                None,
+                Ok(Bytes::new()),
+                STATUS_SUCCESS,
+                "rpc_success",
+            ),
+            (
+                SimulationPath::Rpc,
+                None,
+                Err("STF"),
+                STATUS_FAILED,
+                "rpc_revert",
+            ),
+            (
+                SimulationPath::Revm,
+                rpc_err(),
+                Ok(Bytes::new()),
+                STATUS_SUCCESS,
+                "rpc_failed_revm_success",
+            ),
+            (
+                SimulationPath::Revm,
+                rpc_err(),
+                Err("STF"),
+                STATUS_FAILED,
+                "rpc_failed_revm_revert",
+            ),
+            // REVM ran first or alone
+            (
+                SimulationPath::Revm,
+                None,
+                Ok(Bytes::new()),
+                STATUS_SUCCESS,
+                "revm_success",
+            ),
+            (
+                SimulationPath::Revm,
+                None,
+                Err("STF"),
+                STATUS_FAILED,
+                "revm_revert",
+            ),
+        ];
+
+        for (path, simulation_via_rpc_err, result, expected_status, expected_outcome) in cases {
+            let output = SimulationOutput {
+                simulation_via_rpc_err,
+                ..output(path, result.map_err(str::to_string))
+            };
+
+            assert_eq!(
+                status(Outcome::from(Ok(output))),
+                (expected_status.to_string(), expected_outcome.to_string())
+            );
+        }
+        assert_eq!(
+            status(Outcome::from(Err(SimulateError::Cancelled))).0,
+            STATUS_ERROR
+        );
+    }
+
+    #[test]
+    fn test_amount_parses_decimal_and_hex() {
+        let parse = |value| parse_amount(value, "amount in").ok();
+
+        assert_eq!(parse("100"), Some(U256::from(100)));
+        assert_eq!(parse("0x100"), Some(U256::from(0x100)));
+        assert_eq!(parse(&format!("0x{}", "f".repeat(64))), Some(U256::MAX));
+    }
+
+    #[test]
+    fn test_amount_rejects_other_forms() {
+        for value in ["", "0x", "0b101", "1_000", "1e18", "-1", " 100", "0xg"] {
+            let error = parse_amount(value, "amount in").unwrap_err();
+            assert_eq!(error.code, "invalid_params");
+            assert!(
+                error.error.starts_with("Invalid amount in: "),
+                "{}",
+                error.error
+            );
+        }
+
+        let overflow = format!("1{}", "0".repeat(78));
+        assert!(parse_amount(&overflow, "amount in").is_err());
+    }
+
+    #[test]
+    fn test_block_timestamp_parses_to_u64() {
+        let params = |block_timestamp: &str| SimulationParams {
+            user_address: Address::ZERO.to_string(),
+            token_in_address: Address::ZERO.to_string(),
+            to_address: Address::ZERO.to_string(),
+            calldata: "0x".to_string(),
+            amount_in: "1".to_string(),
+            block_timestamp: Some(block_timestamp.to_string()),
+            ..Default::default()
+        };
+
+        // Past 2106, out of reach of a u32
+        let params_internal = SimulationParamsInternal::try_from(params("4294967296"))
+            .map_err(|error| error.error)
+            .unwrap();
+        assert_eq!(params_internal.timestamp, Some(1 << 32));
+
+        for value in ["", "-1", "1.5", "0x10", "18446744073709551616"] {
+            let error = SimulationParamsInternal::try_from(params(value))
+                .map(|_| ())
+                .unwrap_err();
+            assert_eq!(error.code, "invalid_params");
+            assert!(
+                error.error.starts_with("Invalid block timestamp: "),
+                "{}",
+                error.error
+            );
+        }
     }
 }
@@ -1,4 +1,7 @@
-mod balance_slot;
+// Shared with the library tests, which use more of the fixtures than these do
+#[cfg(test)]
+#[allow(dead_code)]
+mod offline_db;
 
 use std::time::Instant;
 
@@ -10,11 +13,20 @@ use alloy::{
 use alloy_rpc_client::ClientBuilder;
 use revm::{
     database::{AlloyDB, CacheDB, WrapDatabaseAsync},
-    primitives::address,
+    primitives::{U256, address},
 };
+use simulator::{
+    abi::TokenStandard,
+    balance_slot::{
+        AlloyCacheDb, DEFAULT_MAX_CANDIDATES, find_allowance_slot, find_balance_slot,
+        find_token_balance_layout, find_token_balance_slot,
+    },
+    cancellable_db::CancellableDb,
+};
+use tokio_util::sync::CancellationToken;
 
-use crate::balance_slot::find_balance_slot;
-
+// Keeps the alternative endpoints and addresses around for switching by hand
+#[allow(unused_variables)]
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let rpc_url: Url = "https://rpc.flashbots.net".parse()?;
@@ -35,10 +47,10 @@ async fn main() -> anyhow::Result<()> {
     let block_id = BlockId::number(block_number);
 
     // Create AlloyDB with the provider that has logging
-    let alloy_db =
-        WrapDatabaseAsync::new(AlloyDB::new(provider, block_id)).expect("No Tokio runtime");
+    let alloy_db = CancellableDb::new(AlloyDB::new(provider, block_id), CancellationToken::new());
+    let alloy_db = WrapDatabaseAsync::new(alloy_db).expect("No Tokio runtime");
 
-    let mut alloy_cache_db = CacheDB::new(alloy_db);
+    let mut alloy_cache_db: AlloyCacheDb = CacheDB::new(alloy_db);
 
     println!("Finding balance slot for USDC...");
 
@@ -49,5 +61,82 @@ async fn main() -> anyhow::Result<()> {
     println!("USDC slot: {usdc_slot:?}");
     println!("time taken: {:?}", start.elapsed());
 
+    // Uniswap V2 router
+    let spender = address!("0x7a250d5630B4cF539739dF2C5dAcb4c659F2488D");
+
+    println!("Finding allowance slot for USDC...");
+
+    let start = Instant::now();
+
+    let allowance_slot =
+        find_allowance_slot(usdc_address, empty_address, spender, &mut alloy_cache_db)?;
+
+    println!("USDC allowance slot: {allowance_slot:?}");
+    println!("time taken: {:?}", start.elapsed());
+
+    // OpenSea Shared Storefront (ERC1155)
+    let storefront_address = address!("0x495f947276749Ce646f68AC8c248420045cb7b5e");
+    let token_standard = TokenStandard::Erc1155 { id: U256::from(1) };
+
+    println!("Finding ERC1155 balance slot for the OpenSea Shared Storefront...");
+
+    let start = Instant::now();
+
+    let storefront_slot = find_token_balance_slot(
+        storefront_address,
+        token_standard,
+        empty_address,
+        false,
+        DEFAULT_MAX_CANDIDATES,
+        &mut alloy_cache_db,
+    )?;
+
+    println!("Storefront slot: {storefront_slot:?}");
+    println!("time taken: {:?}", start.elapsed());
+
+    println!("Finding the USDC balance layout...");
+
+    let start = Instant::now();
+
+    let (_, usdc_layout) = find_token_balance_layout(
+        usdc_address,
+        TokenStandard::Erc20,
+        empty_address,
+        false,
+        DEFAULT_MAX_CANDIDATES,
+        &mut alloy_cache_db,
+    )?;
+
+    println!("USDC layout: {usdc_layout:?}");
+    if let Some(usdc_layout) = &usdc_layout {
+        println!("Router USDC slot: {:?}", usdc_layout.user_slot(spender));
+    }
+    println!("time taken: {:?}", start.elapsed());
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use revm::state::AccountInfo;
+    use simulator::balance_slot::derive_user_slot;
+
+    use super::*;
+    use crate::offline_db::{ERC20, TOKEN, USER, offline_db};
+
+    #[test]
+    fn test_binary_finds_library_slots() {
+        let spender = address!("0x7a250d5630B4cF539739dF2C5dAcb4c659F2488D");
+        let mut db = offline_db(&[(TOKEN, ERC20)]);
+        // A user who already sent transactions, which the probes must not trip over
+        db.insert_account_info(USER, AccountInfo::default().with_nonce(7));
+
+        let slot = find_balance_slot(TOKEN, USER, &mut db).unwrap();
+        assert_eq!(slot.address, TOKEN);
+        assert_eq!(slot.slot, derive_user_slot(U256::from(3), USER));
+
+        let allowance = find_allowance_slot(TOKEN, USER, spender, &mut db).unwrap();
+        let owner_slot = derive_user_slot(U256::from(4), USER);
+        assert_eq!(allowance.slot, derive_user_slot(owner_slot, spender));
+    }
+}
@@ -0,0 +1,124 @@
+//! Minimal JSON-RPC over HTTP server for tests that must not touch a live node.
+
+use alloy::transports::http::reqwest::Url;
+use serde_json::{Value, json};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+/// Answers every request with the handler's result for `(method, params)`.
+/// Returning `Err((code, message))` produces a JSON-RPC error response.
+pub type Handler = fn(&str, &Value) -> Result<Value, (i64, String)>;
+
+/// Error code a [`Handler`] returns to leave the request unanswered, like a stalled node
+pub const NO_ANSWER: i64 = i64::MIN;
+
+/// `(name, value)` of a header every request must carry, or get a 401 response
+pub type RequiredHeader = (&'static str, &'static str);
+
+pub async fn spawn_mock_rpc(handler: Handler) -> Url {
+    spawn_mock_rpc_requiring(handler, None).await
+}
+
+/// Like [`spawn_mock_rpc`], but rejects requests lacking `header`, as an RPC
+/// authenticated by an API key header does.
+pub async fn spawn_mock_rpc_with_header(handler: Handler, header: RequiredHeader) -> Url {
+    spawn_mock_rpc_requiring(handler, Some(header)).await
+}
+
+async fn spawn_mock_rpc_requiring(handler: Handler, header: Option<RequiredHeader>) -> Url {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        while let Ok((socket, _)) = listener.accept().await {
+            tokio::spawn(serve_connection(socket, handler, header));
+        }
+    });
+
+    format!("http://{addr}").parse().unwrap()
+}
+
+async fn serve_connection(socket: TcpStream, handler: Handler, header: Option<RequiredHeader>) {
+    let mut reader = BufReader::new(socket);
+
+    loop {
+        let mut content_length = 0;
+        let mut authorized = header.is_none();
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).await.unwrap_or(0) == 0 {
+                return;
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap_or(0);
+                }
+                if let Some((required_name, required_value)) = header
+                    && name.eq_ignore_ascii_case(required_name)
+                    && value.trim() == required_value
+                {
+                    authorized = true;
+                }
+            }
+        }
+
+        let mut body = vec![0; content_length];
+        if reader.read_exact(&mut body).await.is_err() {
+            return;
+        }
+
+        if !authorized {
+            let head = "HTTP/1.1 401 Unauthorized\r\ncontent-length: 0\r\n\r\n";
+            if reader.get_mut().write_all(head.as_bytes()).await.is_err() {
+                return;
+            }
+            continue;
+        }
+
+        let request: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+        let response = match request {
+            Value::Array(requests) => requests
+                .iter()
+                .map(|r| respond(r, handler))
+                .collect::<Option<_>>()
+                .map(Value::Array),
+            request => respond(&request, handler),
+        };
+        let Some(response) = response else {
+            return std::future::pending().await;
+        };
+
+        let body = response.to_string();
+        let head = format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n",
+            body.len()
+        );
+        let socket = reader.get_mut();
+        if socket.write_all(head.as_bytes()).await.is_err()
+            || socket.write_all(body.as_bytes()).await.is_err()
+        {
+            return;
+        }
+    }
+}
+
+/// `None` when the handler leaves the request unanswered
+fn respond(request: &Value, handler: Handler) -> Option<Value> {
+    let method = request["method"].as_str().unwrap_or_default();
+
+    Some(match handler(method, &request["params"]) {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }),
+        Err((NO_ANSWER, _)) => return None,
+        Err((code, message)) => json!({
+            "jsonrpc": "2.0",
+            "id": request["id"],
+            "error": { "code": code, "message": message },
+        }),
+    })
+}
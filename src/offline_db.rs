@@ -0,0 +1,47 @@
+//! In-memory chain state for tests that must not touch a live node.
+
+use revm::{
+    bytecode::Bytecode,
+    database::{CacheDB, DBTransportError, EmptyDBTyped},
+    primitives::{Address, Bytes, address, bytes},
+    state::AccountInfo,
+};
+
+/// Empty chain with the error type of the RPC-backed database, so that it can stand in
+/// for it wherever a `CacheDB<ExtDB: DatabaseRef<Error = DBTransportError>>` is taken.
+pub type OfflineDb = CacheDB<EmptyDBTyped<DBTransportError>>;
+
+pub const TOKEN: Address = address!("0x1000000000000000000000000000000000000001");
+pub const USER: Address = address!("0x6698192C6e70186ebE73E2785aC85a8f5B85b052");
+
+/// Minimal ERC20 dispatching `balanceOf` and `transfer` (mapping at slot 3), `approve`
+/// and `allowance` (nested mapping at slot 4, keyed by owner then spender), Solidity
+/// storage layout. `transfer` reverts on insufficient balance, any other selector reverts.
+pub const ERC20: Bytes = bytes!(
+    "5f3560e01c806370a0823114610035578063095ea7b31461004b578063dd62ed3e14610070578063a9059cbb14610092575b5f5ffd5b6004355f52600360205260405f20545f5260205ff35b335f52600460205260405f206020526004355f5260243560405f205560015f5260205ff35b6004355f52600460205260405f206020526024355f5260405f20545f5260205ff35b335f52600360205260405f20805460243580821061003157900390556004355f5260405f20805460243501905560015f5260205ff3"
+);
+
+/// `ownerOf(uint256)` reading the address in `owners[tokenId]` at mapping slot 2,
+/// reverting for unminted tokens
+pub const OWNER_OF: Bytes = bytes!(
+    "6004355f52600260205260405f205473ffffffffffffffffffffffffffffffffffffffff1680602c575f5ffd5b5f5260205ff3"
+);
+
+/// `balanceOf` returns the low 96 bits of slot 5, any other call the 160 bits above
+pub const PACKED_BALANCE_OF: Bytes = bytes!(
+    "5f3560e01c6370a0823114601a5760055460601c5f5260205ff35b6005546bffffffffffffffffffffffff165f5260205ff3"
+);
+
+/// Deploys each `(address, runtime code)` pair into an otherwise empty chain.
+pub fn offline_db(contracts: &[(Address, Bytes)]) -> OfflineDb {
+    let mut db = CacheDB::new(EmptyDBTyped::new());
+
+    for (address, code) in contracts {
+        db.insert_account_info(
+            *address,
+            AccountInfo::default().with_code(Bytecode::new_raw(code.clone())),
+        );
+    }
+
+    db
+}
@@ -0,0 +1,143 @@
+//! Chain state a simulation read from the node, captured so that it can be replayed
+//! without any RPC.
+
+use std::{cell::RefCell, collections::HashMap};
+
+use revm::{
+    bytecode::Bytecode,
+    database::{Cache, CacheDB, DBTransportError, DatabaseRef, EmptyDBTyped},
+    primitives::{Address, B256, StorageKey, StorageValue, U256},
+    state::AccountInfo,
+};
+
+/// Database replaying a [`PreparedState`], where anything it lacks reads as empty
+pub type OfflineCacheDb = CacheDB<EmptyDBTyped<DBTransportError>>;
+
+/// Accounts, code, storage and block hashes read by a simulation, as the node served them
+/// at `block_number`, before any override or transaction applied.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PreparedState {
+    pub block_number: u64,
+    /// Existing accounts only, an absent account reads as empty
+    pub accounts: HashMap<Address, AccountInfo>,
+    pub contracts: HashMap<B256, Bytecode>,
+    pub storage: HashMap<Address, HashMap<U256, U256>>,
+    pub block_hashes: HashMap<u64, B256>,
+}
+
+impl PreparedState {
+    /// Seeds an otherwise empty database with the prepared state.
+    pub fn into_cache_db(self) -> OfflineCacheDb {
+        let mut db = CacheDB::new(EmptyDBTyped::new());
+
+        db.cache.contracts.extend(self.contracts);
+        for (address, info) in self.accounts {
+            db.insert_account_info(address, info);
+        }
+        for (address, slots) in self.storage {
+            for (slot, value) in slots {
+                db.insert_account_storage(address, slot, value)
+                    .expect("the empty database never fails");
+            }
+        }
+        db.cache.block_hashes.extend(
+            self.block_hashes
+                .into_iter()
+                .map(|(number, hash)| (U256::from(number), hash)),
+        );
+
+        db
+    }
+
+    /// Adds the prepared state to `cache` wherever it holds nothing yet. Storage is only
+    /// added to accounts the cache then holds, as any other account reads as empty.
+    pub fn extend_cache(self, cache: &mut Cache) {
+        for (code_hash, code) in self.contracts {
+            cache.contracts.entry(code_hash).or_insert(code);
+        }
+        for (address, info) in self.accounts {
+            cache.accounts.entry(address).or_insert_with(|| info.into());
+        }
+        for (address, slots) in self.storage {
+            if let Some(account) = cache.accounts.get_mut(&address) {
+                for (slot, value) in slots {
+                    account.storage.entry(slot).or_insert(value);
+                }
+            }
+        }
+        for (number, hash) in self.block_hashes {
+            cache.block_hashes.entry(U256::from(number)).or_insert(hash);
+        }
+    }
+}
+
+/// Reads through to `db`, recording every value it serves into a [`PreparedState`].
+pub struct RecordingDb<DB> {
+    db: DB,
+    state: RefCell<PreparedState>,
+}
+
+impl<DB> RecordingDb<DB> {
+    pub fn new(db: DB, block_number: u64) -> Self {
+        Self {
+            db,
+            state: RefCell::new(PreparedState {
+                block_number,
+                ..Default::default()
+            }),
+        }
+    }
+
+    pub fn into_state(self) -> PreparedState {
+        self.state.into_inner()
+    }
+}
+
+impl<DB: DatabaseRef> DatabaseRef for RecordingDb<DB> {
+    type Error = DB::Error;
+
+    fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        let info = self.db.basic_ref(address)?;
+        if let Some(info) = &info {
+            self.state
+                .borrow_mut()
+                .accounts
+                .insert(address, info.clone());
+        }
+
+        Ok(info)
+    }
+
+    fn code_by_hash_ref(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        let code = self.db.code_by_hash_ref(code_hash)?;
+        self.state
+            .borrow_mut()
+            .contracts
+            .insert(code_hash, code.clone());
+
+        Ok(code)
+    }
+
+    fn storage_ref(
+        &self,
+        address: Address,
+        index: StorageKey,
+    ) -> Result<StorageValue, Self::Error> {
+        let value = self.db.storage_ref(address, index)?;
+        self.state
+            .borrow_mut()
+            .storage
+            .entry(address)
+            .or_default()
+            .insert(index, value);
+
+        Ok(value)
+    }
+
+    fn block_hash_ref(&self, number: u64) -> Result<B256, Self::Error> {
+        let hash = self.db.block_hash_ref(number)?;
+        self.state.borrow_mut().block_hashes.insert(number, hash);
+
+        Ok(hash)
+    }
+}
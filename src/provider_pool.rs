@@ -0,0 +1,371 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    task::{Context, Poll},
+};
+
+use alloy::{
+    providers::{Provider, ProviderBuilder},
+    transports::{
+        RpcError, TransportError, TransportErrorKind,
+        http::reqwest::{
+            self, Url,
+            header::{HeaderMap, HeaderName, HeaderValue},
+        },
+    },
+};
+use alloy_json_rpc::{RequestPacket, ResponsePacket};
+use alloy_rpc_client::RpcClient;
+use thiserror::Error;
+use tower::{Layer, Service};
+
+use crate::balance_slot::HttpProvider;
+
+/// Extra HTTP headers sent with every request to an RPC, e.g. an API key
+pub type RpcHeaders = HashMap<String, String>;
+
+#[derive(Debug, Error)]
+pub enum ConnectError {
+    #[error("invalid RPC header {0:?}")]
+    InvalidHeader(String),
+    #[error("building HTTP client failed")]
+    Client(#[from] reqwest::Error),
+}
+
+#[derive(Debug, Error)]
+pub enum ChainIdError {
+    #[error(transparent)]
+    Connect(#[from] ConnectError),
+    #[error(transparent)]
+    Rpc(#[from] RpcError<TransportErrorKind>),
+}
+
+/// An RPC URL along with its headers, with lowercase names and sorted so that equal
+/// header sets share a provider
+type ProviderKey = (Url, Vec<(String, String)>);
+
+/// Providers the pool holds before it starts over, so that headers changing on every
+/// call, e.g. short-lived bearer tokens, don't pile up one HTTP client each
+const MAX_PROVIDERS: usize = 64;
+
+struct PooledProvider {
+    provider: HttpProvider,
+    chain_id: Option<u64>,
+    requests: RequestCounter,
+}
+
+/// Number of JSON-RPC requests sent through a provider, each request of a batch counting
+/// as one. Clones share the same count.
+#[derive(Clone, Debug, Default)]
+pub struct RequestCounter(Arc<AtomicU64>);
+
+impl RequestCounter {
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl<S> Layer<S> for RequestCounter {
+    type Service = CountingService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CountingService {
+            inner,
+            requests: self.clone(),
+        }
+    }
+}
+
+/// Transport counting the requests it forwards, see [`RequestCounter`]
+#[derive(Clone, Debug)]
+pub struct CountingService<S> {
+    inner: S,
+    requests: RequestCounter,
+}
+
+impl<S> Service<RequestPacket> for CountingService<S>
+where
+    S: Service<RequestPacket, Response = ResponsePacket, Error = TransportError>,
+{
+    type Response = ResponsePacket;
+    type Error = TransportError;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: RequestPacket) -> Self::Future {
+        let count = match &request {
+            RequestPacket::Single(_) => 1,
+            RequestPacket::Batch(batch) => batch.len() as u64,
+        };
+        self.requests.0.fetch_add(count, Ordering::Relaxed);
+
+        self.inner.call(request)
+    }
+}
+
+/// Keeps one provider per RPC URL and headers so the fill stack and the underlying HTTP
+/// client (and its keep-alive connections) are built once and reused across simulations.
+/// Past 64 providers, or on [`ProviderPool::clear`], the pool drops them all, while
+/// clones handed out keep working.
+#[derive(Default)]
+pub struct ProviderPool {
+    providers: HashMap<ProviderKey, PooledProvider>,
+}
+
+impl ProviderPool {
+    fn entry(
+        &mut self,
+        rpc_url: &Url,
+        rpc_headers: &RpcHeaders,
+    ) -> Result<&mut PooledProvider, ConnectError> {
+        let mut headers = rpc_headers
+            .iter()
+            .map(|(name, value)| {
+                let name = HeaderName::from_bytes(name.as_bytes())
+                    .map_err(|_| ConnectError::InvalidHeader(name.clone()))?;
+                Ok((name.as_str().to_string(), value.clone()))
+            })
+            .collect::<Result<Vec<_>, ConnectError>>()?;
+        headers.sort();
+
+        let key = (rpc_url.clone(), headers);
+        if self.providers.len() >= MAX_PROVIDERS && !self.providers.contains_key(&key) {
+            self.providers.clear();
+        }
+
+        let entry = match self.providers.entry(key) {
+            std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+            std::collections::hash_map::Entry::Vacant(entry) => {
+                let requests = RequestCounter::default();
+                entry.insert(PooledProvider {
+                    provider: connect(rpc_url, rpc_headers, requests.clone())?,
+                    chain_id: None,
+                    requests,
+                })
+            }
+        };
+
+        Ok(entry)
+    }
+
+    /// Drops every pooled provider, connecting anew on next use
+    pub fn clear(&mut self) {
+        self.providers.clear();
+    }
+
+    /// Returns the pooled provider for `rpc_url` and `rpc_headers`, connecting a new one
+    /// on first use.
+    ///
+    /// Providers are cheap to clone and share the same client.
+    pub fn get_or_connect(
+        &mut self,
+        rpc_url: &Url,
+        rpc_headers: &RpcHeaders,
+    ) -> Result<HttpProvider, ConnectError> {
+        Ok(self.entry(rpc_url, rpc_headers)?.provider.clone())
+    }
+
+    /// Returns the counter of the requests sent through the provider for `rpc_url` and
+    /// `rpc_headers`, connecting it on first use.
+    pub fn request_counter(
+        &mut self,
+        rpc_url: &Url,
+        rpc_headers: &RpcHeaders,
+    ) -> Result<RequestCounter, ConnectError> {
+        Ok(self.entry(rpc_url, rpc_headers)?.requests.clone())
+    }
+
+    /// Returns the chain id served by `rpc_url`, fetching `eth_chainId` only on first use.
+    pub async fn chain_id(
+        &mut self,
+        rpc_url: &Url,
+        rpc_headers: &RpcHeaders,
+    ) -> Result<u64, ChainIdError> {
+        let pooled = self.entry(rpc_url, rpc_headers)?;
+
+        if let Some(chain_id) = pooled.chain_id {
+            return Ok(chain_id);
+        }
+
+        let chain_id = pooled.provider.get_chain_id().await?;
+        pooled.chain_id = Some(chain_id);
+
+        Ok(chain_id)
+    }
+}
+
+fn connect(
+    rpc_url: &Url,
+    rpc_headers: &RpcHeaders,
+    requests: RequestCounter,
+) -> Result<HttpProvider, ConnectError> {
+    let client = RpcClient::builder().layer(requests);
+    if rpc_headers.is_empty() {
+        return Ok(ProviderBuilder::new().connect_client(client.http(rpc_url.clone())));
+    }
+
+    let mut headers = HeaderMap::new();
+    for (name, value) in rpc_headers {
+        let invalid = || ConnectError::InvalidHeader(name.clone());
+        headers.insert(
+            HeaderName::from_bytes(name.as_bytes()).map_err(|_| invalid())?,
+            HeaderValue::from_str(value).map_err(|_| invalid())?,
+        );
+    }
+
+    let http_client = reqwest::Client::builder()
+        .default_headers(headers)
+        .build()?;
+
+    Ok(
+        ProviderBuilder::new()
+            .connect_client(client.http_with_client(http_client, rpc_url.clone())),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use serde_json::{Value, json};
+
+    use super::*;
+    use crate::mock_rpc::{spawn_mock_rpc, spawn_mock_rpc_with_header};
+
+    #[test]
+    fn test_provider_reused_for_same_url() {
+        let mut pool = ProviderPool::default();
+        let rpc_url: Url = "http://127.0.0.1:8545".parse().unwrap();
+        let other_url: Url = "http://127.0.0.1:8546".parse().unwrap();
+
+        let headers = RpcHeaders::from([("x-api-key".to_string(), "secret".to_string())]);
+
+        let first = pool.get_or_connect(&rpc_url, &RpcHeaders::new()).unwrap();
+        let second = pool.get_or_connect(&rpc_url, &RpcHeaders::new()).unwrap();
+        let other = pool.get_or_connect(&other_url, &RpcHeaders::new()).unwrap();
+        let authenticated = pool.get_or_connect(&rpc_url, &headers).unwrap();
+
+        assert!(std::ptr::eq(first.client(), second.client()));
+        assert!(!std::ptr::eq(first.client(), other.client()));
+        assert!(!std::ptr::eq(first.client(), authenticated.client()));
+        assert_eq!(pool.providers.len(), 3);
+    }
+
+    #[test]
+    fn test_header_names_are_case_insensitive() {
+        let mut pool = ProviderPool::default();
+        let rpc_url: Url = "http://127.0.0.1:8545".parse().unwrap();
+        let headers = |name: &str| RpcHeaders::from([(name.to_string(), "secret".to_string())]);
+
+        let upper = pool
+            .get_or_connect(&rpc_url, &headers("X-Api-Key"))
+            .unwrap();
+        let lower = pool
+            .get_or_connect(&rpc_url, &headers("x-api-key"))
+            .unwrap();
+
+        assert!(std::ptr::eq(upper.client(), lower.client()));
+        assert_eq!(pool.providers.len(), 1);
+    }
+
+    #[test]
+    fn test_pool_is_bounded_and_cleared() {
+        let mut pool = ProviderPool::default();
+        let rpc_url: Url = "http://127.0.0.1:8545".parse().unwrap();
+        let token =
+            |n: usize| RpcHeaders::from([("authorization".to_string(), format!("Bearer {n}"))]);
+
+        for n in 0..MAX_PROVIDERS {
+            pool.get_or_connect(&rpc_url, &token(n)).unwrap();
+        }
+        assert_eq!(pool.providers.len(), MAX_PROVIDERS);
+
+        // A known key is reused without evicting the others
+        pool.get_or_connect(&rpc_url, &token(0)).unwrap();
+        assert_eq!(pool.providers.len(), MAX_PROVIDERS);
+
+        // A new one past the bound starts the pool over
+        let rotated = pool
+            .get_or_connect(&rpc_url, &token(MAX_PROVIDERS))
+            .unwrap();
+        assert_eq!(pool.providers.len(), 1);
+        assert!(std::ptr::eq(
+            rotated.client(),
+            pool.get_or_connect(&rpc_url, &token(MAX_PROVIDERS))
+                .unwrap()
+                .client()
+        ));
+
+        pool.clear();
+        assert!(pool.providers.is_empty());
+    }
+
+    static CHAIN_ID_REQUESTS: AtomicUsize = AtomicUsize::new(0);
+
+    fn counting_rpc(method: &str, _: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "eth_chainId" => {
+                CHAIN_ID_REQUESTS.fetch_add(1, Ordering::SeqCst);
+                Ok(json!("0x1"))
+            }
+            _ => Err((-32601, "method not found".to_string())),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chain_id_fetched_once() {
+        let rpc_url = spawn_mock_rpc(counting_rpc).await;
+        let mut pool = ProviderPool::default();
+
+        assert_eq!(
+            pool.chain_id(&rpc_url, &RpcHeaders::new()).await.unwrap(),
+            1
+        );
+        assert_eq!(
+            pool.chain_id(&rpc_url, &RpcHeaders::new()).await.unwrap(),
+            1
+        );
+        assert_eq!(CHAIN_ID_REQUESTS.load(Ordering::SeqCst), 1);
+    }
+
+    fn chain_id_rpc(method: &str, _: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "eth_chainId" => Ok(json!("0x1")),
+            _ => Err((-32601, "method not found".to_string())),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rpc_headers_are_sent() {
+        let rpc_url = spawn_mock_rpc_with_header(chain_id_rpc, ("x-api-key", "secret")).await;
+        let mut pool = ProviderPool::default();
+        let headers = RpcHeaders::from([("X-Api-Key".to_string(), "secret".to_string())]);
+        let wrong_headers = RpcHeaders::from([("X-Api-Key".to_string(), "wrong".to_string())]);
+
+        assert_eq!(pool.chain_id(&rpc_url, &headers).await.unwrap(), 1);
+        assert!(matches!(
+            pool.chain_id(&rpc_url, &wrong_headers).await,
+            Err(ChainIdError::Rpc(_))
+        ));
+        assert!(matches!(
+            pool.chain_id(&rpc_url, &RpcHeaders::new()).await,
+            Err(ChainIdError::Rpc(_))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_rpc_header() {
+        let rpc_url: Url = "http://127.0.0.1:8545".parse().unwrap();
+        let headers = RpcHeaders::from([("x-api-key".to_string(), "line\nbreak".to_string())]);
+
+        let result = ProviderPool::default().get_or_connect(&rpc_url, &headers);
+
+        assert!(matches!(result, Err(ConnectError::InvalidHeader(name)) if name == "x-api-key"));
+    }
+}
@@ -1,332 +1,8552 @@
 use crate::{
-    balance_slot::FindSlotError,
+    balance_slot::{FindSlotError, HttpProvider},
+    cancellable_db::CancellableDb,
+    decode::{decode_revert_data, decode_uint256},
     eth_call_many::{
-        Bundle, EthCallMany, SimulationContext, StateOverride, Transaction, TransactionResponse,
+        BlockOverride, Bundle, DetailedTransactionResponse, EthCallMany, EthCallManyError,
+        SimulationContext, StateOverride, Transaction, TransactionResponse, parse_call_many,
     },
+    gas_profile::{GasProfile, GasProfileInspector},
+    hardfork::spec_at,
+    l1_fee::{estimate_l1_fee, is_op_stack},
+    prepared_state::{PreparedState, RecordingDb},
+    provider_pool::{ChainIdError, ConnectError, ProviderPool, RpcHeaders},
 };
 use alloy::{
-    eips::BlockId,
-    providers::{Provider, ProviderBuilder},
-    sol_types::SolCall,
+    consensus::{Transaction as _, TxEnvelope, transaction::Recovered},
+    eips::{
+        BlockId, BlockNumberOrTag,
+        eip2718::{Decodable2718, Eip2718Error},
+        eip7702::SignedAuthorization,
+    },
+    primitives::{Selector, SignatureError, U64, U128},
+    providers::Provider,
+    sol_types::{SolCall, SolError, SolEvent},
     transports::{TransportErrorKind, http::reqwest::Url},
 };
 use alloy_json_rpc::RpcError;
 use revm::{
-    Context, ExecuteCommitEvm, ExecuteEvm, MainBuilder, MainContext,
+    Context, DatabaseCommit, ExecuteCommitEvm, ExecuteEvm, InspectEvm, MainBuilder, MainContext,
+    bytecode::Bytecode,
     context::{
-        TxEnv,
-        result::{EVMError, ExecutionResult, SuccessReason},
+        BlockEnv, CfgEnv, TxEnv,
+        result::{EVMError, ExecResultAndState, ExecutionResult, HaltReason, SuccessReason},
+        tx::TxEnvBuildError,
+    },
+    database::{
+        AlloyDB, Cache, CacheDB, DBTransportError, DatabaseRef, DbAccount, WrapDatabaseAsync,
     },
-    database::{AlloyDB, Cache, CacheDB, DBTransportError, WrapDatabaseAsync},
-    primitives::{Address, Bytes, TxKind, U256},
+    precompile::Precompiles,
+    primitives::{Address, B256, Bytes, Log, TxKind, U256, hardfork::SpecId},
+    state::EvmState,
 };
-use std::collections::HashMap;
+use serde::Deserialize;
+use serde_json::value::RawValue;
+use std::{collections::HashMap, time::Duration};
 use thiserror::Error;
+use tokio_util::sync::CancellationToken;
 
-use crate::balance_slot::{AlloyCacheDb, IERC20::approveCall, SlotWithAddress, find_balance_slot};
+use crate::abi::{
+    IERC20::{Transfer, approveCall, transferCall},
+    IERC20Allowance::increaseAllowanceCall,
+    IERC1155::setApprovalForAllCall,
+    IERC4626,
+    IPausable::EnforcedPause,
+    TokenStandard,
+};
+use crate::balance_slot::{
+    AlloyCacheDb, BalanceLayout, DEFAULT_MAX_CANDIDATES, ProviderCacheDb, SlotStrategy,
+    SlotWithAddress, build_allowance_tx_env, build_balance_of_tx_env, find_allowance_slot,
+    find_owner_slot, find_token_balance_layout_fast, find_token_balance_slot,
+};
 
-pub struct SimulationParams {
-    pub user: Address,
-    pub token_in: Address,
-    pub amount_in: U256,
+/// A single call of a [`SimulationParams::steps`] sequence
+#[derive(Clone, Debug, Default)]
+pub struct CallStep {
     pub to: Address,
     pub calldata: Bytes,
+    pub value: U256,
+    /// Overrides [`SimulationParams::gas_limit`] for this step, e.g. to keep a long
+    /// sequence under the node's gas cap for a whole `eth_callMany`
+    pub gas_limit: Option<u64>,
 }
 
-pub struct Simulator {
-    db_caches: HashMap<u32, Cache>,
+/// Calls sent as one `eth_callMany` bundle by [`Simulator::simulate_bundles`]
+#[derive(Clone, Debug, Default)]
+pub struct CallBundle {
+    pub steps: Vec<CallStep>,
+    /// `block.timestamp` seen by the bundle, defaults to [`SimulationParams::timestamp`]
+    pub timestamp: Option<u64>,
+    /// `block.coinbase` seen by the bundle, defaults to [`SimulationParams::coinbase`]
+    pub coinbase: Option<Address>,
 }
 
-type SimulationResult = Result<Bytes, String>;
-
-pub struct SimulationOutput {
-    pub result: SimulationResult,
-    pub simulation_via_rpc_err: Option<SimulateViaRpcError>,
+/// What the node returned for one [`CallBundle`]
+#[derive(Debug)]
+pub struct BundleOutput {
+    /// Result of each step, in order
+    pub results: Vec<CallResult>,
+    /// Gas used by each step, when the node reports it
+    pub gas_used: Vec<Option<u64>>,
 }
 
 #[derive(Debug)]
-pub struct BothSimulationsFailed {
-    pub rpc_error: SimulateViaRpcError,
-    pub revm_error: SimulateViaRevmError,
+pub struct BundlesOutput {
+    /// One output per bundle, the approval left out
+    pub bundles: Vec<BundleOutput>,
+    /// Block the bundles ran on top of
+    pub block_number: u64,
+    /// `None` when `amount_in` is zero, as no balance is overridden then
+    pub balance_slot: Option<SlotWithAddress>,
+    /// Mapping `balance_slot` belongs to, when it is keyed by `user` directly
+    pub balance_layout: Option<BalanceLayout>,
+    /// Allowance slot resolved for [`ApproveMode::SlotOverride`]
+    pub allowance_slot: Option<SlotWithAddress>,
 }
 
-impl std::fmt::Display for BothSimulationsFailed {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "both RPC and REVM simulations failed")?;
+/// ERC-4626 conversion of `amount_out`, read once the steps ran
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VaultConversion {
+    /// `token_out` is a vault, whose shares convert with its `convertToAssets`
+    ToAssets,
+    /// `token_out` is the asset of `vault`, converting with `vault.convertToShares`
+    ToShares { vault: Address },
+}
 
-        // Format RPC error chain (REVM chain will be handled by source())
-        write!(f, "\n  RPC error: {}", self.rpc_error)?;
-        let mut rpc_source = std::error::Error::source(&self.rpc_error);
-        while let Some(source) = rpc_source {
-            write!(f, "\n    caused by: {}", source)?;
-            rpc_source = std::error::Error::source(source);
+impl VaultConversion {
+    /// Vault answering the conversion of `token_out` amounts
+    pub fn vault(&self, token_out: Address) -> Address {
+        match self {
+            VaultConversion::ToAssets => token_out,
+            VaultConversion::ToShares { vault } => *vault,
         }
+    }
 
-        Ok(())
+    pub fn calldata(&self, amount: U256) -> Bytes {
+        match self {
+            VaultConversion::ToAssets => {
+                IERC4626::convertToAssetsCall { shares: amount }.abi_encode()
+            }
+            VaultConversion::ToShares { .. } => {
+                IERC4626::convertToSharesCall { assets: amount }.abi_encode()
+            }
+        }
+        .into()
     }
 }
 
-impl std::error::Error for BothSimulationsFailed {
-    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        Some(&self.revm_error)
+/// How the REVM path grants `spender` its allowance over `token_in`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ApproveMode {
+    /// Commit `approve(spender, MAX)` sent by `user`
+    #[default]
+    Transaction,
+    /// Write `U256::MAX` straight into the allowance slot, sparing the approve execution
+    /// and its nonce bump. Falls back to [`ApproveMode::Transaction`] when the slot
+    /// cannot be found. The RPC path always sends the approve transaction.
+    SlotOverride,
+    /// Send `increaseAllowance(spender, amount_in)` instead of `approve`, for tokens that
+    /// refuse to change a nonzero allowance. Falls back to [`ApproveMode::Transaction`]
+    /// when `token_in` lacks the function.
+    IncreaseAllowance,
+}
+
+/// Kind of node behind the RPC URL
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ProviderProfile {
+    /// Public or hosted node, simulated with `eth_callMany` first and REVM as fallback
+    #[default]
+    Remote,
+    /// Local fork (Anvil, Hardhat) that may report its development chain id instead of
+    /// the forked one. REVM runs first, as the node is local anyway, and the node is only
+    /// called through `eth_call`, as these lack `eth_callMany`.
+    LocalFork,
+}
+
+impl ProviderProfile {
+    /// A local fork holds state of the caller's own, diverging from the chain it reports,
+    /// so its simulations neither read from nor add to the per-chain caches.
+    fn shares_caches(self) -> bool {
+        self == ProviderProfile::Remote
     }
 }
 
-#[derive(Debug, Error)]
-pub enum SimulateError {
-    #[error("failed to find balance slot")]
-    FindSlot(#[from] FindSlotError),
-    #[error("RPC error while getting block number")]
-    Rpc(#[from] RpcError<TransportErrorKind>),
-    #[error(transparent)]
-    BothSimulationsFailed(#[from] BothSimulationsFailed),
+/// Which backends [`Simulator::simulate`] runs
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SimulationStrategy {
+    /// The primary backend of the [`ProviderProfile`], and the other one only if it fails
+    #[default]
+    Fallback,
+    /// Both backends, whose results are compared in a [`DivergenceReport`]. A diagnostic
+    /// mode to catch the REVM configuration drifting from what the node executes.
+    Both,
 }
 
-impl Simulator {
-    pub fn new() -> Self {
-        Self {
-            db_caches: HashMap::new(),
+/// State [`Simulator::simulate`] runs on top of
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SimulationBlock {
+    /// The latest block, pinned by number for the whole simulation
+    #[default]
+    Latest,
+    /// The pending block: the latest one plus the transactions the node has queued for
+    /// the next. It can't be pinned, so reads may observe the mempool moving on, and not
+    /// every node serves its state.
+    Pending,
+}
+
+impl SimulationBlock {
+    /// Block both backends read, `block_number` being the one the simulation resolved
+    fn at(self, block_number: u64) -> BlockNumberOrTag {
+        match self {
+            SimulationBlock::Latest => BlockNumberOrTag::Number(block_number),
+            SimulationBlock::Pending => BlockNumberOrTag::Pending,
         }
     }
+}
 
-    pub async fn simulate(
-        &mut self,
-        chain_id: u32,
-        rpc_url: Url,
-        params: SimulationParams,
-    ) -> Result<SimulationOutput, SimulateError> {
-        let cache = self.db_caches.entry(chain_id).or_default();
+/// Chain ids Anvil (31337) and Hardhat (1337) report unless told the forked one
+const LOCAL_FORK_CHAIN_IDS: [u64; 2] = [31337, 1337];
 
-        let provider = ProviderBuilder::new().connect_http(rpc_url.clone());
+/// Runs `simulate` with a token cancelled by `cancellation` and, after `timeout_ms`, by a
+/// timer. Reads cut short by either can surface as any error, or leave a partial
+/// output, so the outcome is replaced with what stopped them.
+async fn within_timeout<T>(
+    timeout_ms: Option<u64>,
+    cancellation: &CancellationToken,
+    simulate: impl AsyncFnOnce(&CancellationToken) -> Result<T, SimulateError>,
+) -> Result<T, SimulateError> {
+    let Some(timeout_ms) = timeout_ms else {
+        return simulate(cancellation).await;
+    };
 
-        let block_number = provider.get_block_number().await?;
-        let block_number = BlockId::number(block_number);
+    let bounded = cancellation.child_token();
+    let timer = tokio::spawn({
+        let bounded = bounded.clone();
+        async move {
+            tokio::time::sleep(Duration::from_millis(timeout_ms)).await;
+            bounded.cancel();
+        }
+    });
+    let result = simulate(&bounded).await;
+    timer.abort();
 
-        let alloy_db = AlloyDB::new(provider, block_number);
-        let alloy_db = WrapDatabaseAsync::new(alloy_db).expect("No Tokio runtime");
+    if cancellation.is_cancelled() {
+        Err(SimulateError::Cancelled)
+    } else if bounded.is_cancelled() {
+        Err(SimulateError::Timeout { timeout_ms })
+    } else {
+        result
+    }
+}
 
-        let mut alloy_cache_db = CacheDB::new(alloy_db);
+/// Fails unless the node reported `chain_id`, or a development chain id when it is a
+/// [`ProviderProfile::LocalFork`]
+fn check_chain_id(
+    chain_id: u32,
+    actual: u64,
+    provider_profile: ProviderProfile,
+) -> Result<u64, SimulateError> {
+    let local_fork =
+        provider_profile == ProviderProfile::LocalFork && LOCAL_FORK_CHAIN_IDS.contains(&actual);
+    if actual != u64::from(chain_id) && !local_fork {
+        return Err(SimulateError::ChainIdMismatch {
+            expected: chain_id.into(),
+            actual,
+        });
+    }
 
-        //TODO: RAII bug?
-        alloy_cache_db.cache = std::mem::take(cache);
+    Ok(actual)
+}
 
-        let balance_slot = find_balance_slot(params.token_in, params.user, &mut alloy_cache_db)?;
+/// Arbitrum One, Arbitrum Nova and Arbitrum Sepolia
+const ARBITRUM_CHAIN_IDS: [u32; 3] = [42161, 42170, 421614];
 
-        let result: Result<SimulationOutput, SimulateError> =
-            match simulate_via_rpc(&params, rpc_url, &balance_slot).await {
-                Ok(rpc_result) => Ok(SimulationOutput {
-                    result: rpc_result,
-                    simulation_via_rpc_err: None,
-                }),
-                Err(rpc_error) => {
-                    match simulate_via_revm(&params, &mut alloy_cache_db, balance_slot) {
-                        Ok(revm_result) => Ok(SimulationOutput {
-                            result: revm_result,
-                            simulation_via_rpc_err: Some(rpc_error),
-                        }),
-                        Err(revm_error) => Err(BothSimulationsFailed {
-                            rpc_error,
-                            revm_error,
-                        }
-                        .into()),
-                    }
-                }
-            };
+/// Whether REVM, which runs with Ethereum's semantics, cannot stand in for the node.
+/// On Arbitrum, `block.number` returns an L1 block number and gas is accounted in L2
+/// and L1 components, so a REVM result would be off on gas and block-dependent logic.
+fn is_rpc_only(chain_id: u32) -> bool {
+    ARBITRUM_CHAIN_IDS.contains(&chain_id)
+}
 
-        *cache = alloy_cache_db.cache;
+/// Pre-execution checks the REVM path skips, all of them by default, so that a caller
+/// short of ETH for gas or a gas price below the base fee don't fail the simulation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RevmCfg {
+    pub disable_base_fee: bool,
+    pub disable_block_gas_limit: bool,
+    pub disable_balance_check: bool,
+    /// Accept a caller with code (a smart-contract wallet), like `eth_call` does. Its code
+    /// is kept and its nonce bumped as for an EOA, and `CALLER` returns its address.
+    pub disable_eip3607: bool,
+    /// Hardfork the steps run under. [`Simulator::simulate`] defaults it to the one active
+    /// at the pinned block, see [`spec_at`]; elsewhere it is REVM's default, the latest.
+    pub spec: Option<SpecId>,
+    /// `tx.gasprice` of the steps when [`SimulationParams::gas_price`] is unset.
+    /// [`Simulator::simulate`] defaults it to the pinned block's base fee plus
+    /// `priority_fee`, what a transaction in that block would pay. The node is not sent
+    /// it, as it would charge it to a `user` that may hold no ETH.
+    pub gas_price: Option<u128>,
+}
 
-        cache.accounts.iter_mut().for_each(|(_, db_account)| {
-            db_account.storage.clear();
-        });
+impl Default for RevmCfg {
+    fn default() -> Self {
+        Self {
+            disable_base_fee: true,
+            disable_block_gas_limit: true,
+            disable_balance_check: true,
+            disable_eip3607: true,
+            spec: None,
+            gas_price: None,
+        }
+    }
+}
 
-        result
+impl RevmCfg {
+    fn apply(&self, cfg: &mut CfgEnv) {
+        cfg.disable_base_fee = self.disable_base_fee;
+        cfg.disable_block_gas_limit = self.disable_block_gas_limit;
+        cfg.disable_balance_check = self.disable_balance_check;
+        cfg.disable_eip3607 = self.disable_eip3607;
+        if let Some(spec) = self.spec {
+            cfg.spec = spec;
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct SimulationParams {
+    pub user: Address,
+    pub token_in: Address,
+    pub amount_in: U256,
+    pub to: Address,
+    pub calldata: Bytes,
+    /// Address approved to pull `token_in`, defaults to `to`
+    pub spender: Option<Address>,
+    /// Native value sent with the call
+    pub value: U256,
+    pub gas_limit: Option<u64>,
+    /// `tx.gasprice` of the steps in both backends. When unset, REVM runs them at
+    /// [`RevmCfg::gas_price`] and the node at its own default.
+    pub gas_price: Option<u128>,
+    /// Nonce of `user` before the approval, overriding the on-chain one in both backends.
+    /// The RPC path then numbers its transactions from it, as REVM does, instead of
+    /// leaving them to the node.
+    pub nonce: Option<u64>,
+    /// EIP-7702 authorizations delegating the code of their signers, typically `user`.
+    /// REVM sets the delegations up front without checking chain id or nonce, while the
+    /// RPC path sends them with the first transaction of `user`, where the node checks both.
+    pub authorization_list: Vec<SignedAuthorization>,
+    /// Tip per gas on top of the block's base fee, pricing `gas_cost_wei` when `gas_price`
+    /// is unset
+    pub priority_fee: u128,
+    /// Cap on the base fee plus `priority_fee` when `gas_price` is unset, as the max fee
+    /// of an EIP-1559 transaction
+    pub max_fee_per_gas: Option<u128>,
+    /// Token whose `user` balance delta across the call is reported as `amount_out`
+    pub token_out: Option<Address>,
+    /// Convert `amount_out` through an ERC-4626 vault after the call, reported as
+    /// `amount_out_converted`. Ignored without `token_out`.
+    pub amount_out_conversion: Option<VaultConversion>,
+    /// Ordered calls executed after the approval, replacing the single `to`/`calldata`/`value`
+    /// call when non-empty. `gas_limit`, unless the step sets its own, and `gas_price` apply
+    /// to every step.
+    pub steps: Vec<CallStep>,
+    /// Index of the step whose output is reported, defaults to the last one
+    pub result_step: Option<usize>,
+    /// Break the gas of the result step down by opcode class, see [`GasProfile`]
+    pub gas_profile: bool,
+    /// Runtime bytecode replacing the deployed code of these accounts (mock contracts)
+    pub code_overrides: HashMap<Address, Bytes>,
+    /// Storage slots set on these accounts before the call, in both backends. Funded
+    /// balance and allowance slots are written over them.
+    pub storage_overrides: HashMap<Address, HashMap<U256, U256>>,
+    /// Read `allowance(user, spender)` back right after the approval, to spot tokens
+    /// that silently ignore `approve`
+    pub report_allowance: bool,
+    /// Keep the `eth_callMany` response as the node sent it, see
+    /// [`SimulationOutput::raw_rpc_response`]
+    pub capture_raw_response: bool,
+    /// Additional `(holder, token, amount)` balances to fund besides `user`'s `token_in`
+    pub balances: Vec<(Address, Address, U256)>,
+    /// ERC721 `(contract, token_id)` pairs handed to `user`, by overriding the slot
+    /// `ownerOf` reads, e.g. for calls gated on holding an NFT. `balanceOf` is left as is.
+    pub owned_nfts: Vec<(Address, U256)>,
+    /// Defaults to [`SimulatorConfig::approve_mode`] in [`Simulator::simulate`], to the type's
+    /// default elsewhere
+    pub approve_mode: Option<ApproveMode>,
+    /// Report the storage written by the steps, see [`StateDiff`]
+    pub state_diff: bool,
+    /// Holder whose `token_in` balance increase across the steps is reported as
+    /// `actual_amount_in`, e.g. the pool a router forwards `token_in` to
+    pub token_in_recipient: Option<Address>,
+    /// Position within the block the RPC path simulates at, after that many of its
+    /// transactions, instead of at the top of the block. Needs a node supporting
+    /// mid-block `eth_callMany`. REVM only sees block boundaries and ignores it.
+    pub transaction_index: Option<u64>,
+    pub revm_cfg: RevmCfg,
+    /// Leave out the approval, in both backends, when the on-chain
+    /// `allowance(user, spender)` already covers `amount_in`, so that it doesn't bump the
+    /// user's nonce
+    pub skip_approve_if_allowed: bool,
+    /// Interface of `token_in`. `token_out` and `balances` are always ERC20.
+    pub token_standard: TokenStandard,
+    /// Reject a discovered `token_in` balance slot whose mutation also changes
+    /// `totalSupply()`, see [`find_token_balance_slot`]
+    pub verify_total_supply: bool,
+    /// Balance slots probed before the lookup gives up. Defaults to
+    /// [`SimulatorConfig::max_slot_candidates`] in [`Simulator::simulate`], to
+    /// [`DEFAULT_MAX_CANDIDATES`] elsewhere.
+    pub max_slot_candidates: Option<usize>,
+    /// Report the amount reaching each hop of a multi-pool route, see [`HopResult`]
+    pub trace_hops: bool,
+    /// `block.coinbase` seen by the steps, e.g. to simulate builder payments
+    pub coinbase: Option<Address>,
+    /// `block.timestamp` seen by the steps, in seconds
+    pub timestamp: Option<u64>,
+    /// Without `timestamp`, simulate at the time of the block following the pinned one,
+    /// so that deadlines set from the current time pass
+    pub deadline_safe: bool,
+    pub provider_profile: ProviderProfile,
+    /// Headers sent with every request to the RPC, e.g. an API key
+    pub rpc_headers: RpcHeaders,
+    /// Defaults to [`SimulatorConfig::strategy`] in [`Simulator::simulate`], to the type's
+    /// default elsewhere
+    pub strategy: Option<SimulationStrategy>,
+    /// When the call fails, probe a self-transfer of `token_in` to report a
+    /// [`DiagnosticHint`]
+    pub diagnose_failures: bool,
+    /// Run the calls against the chain as it is: no balance slot lookup, no funding of
+    /// `user` or `balances`, and no approval. `token_in`, `amount_in`, `balances`,
+    /// `approve_mode` and `skip_approve_if_allowed` are ignored.
+    pub raw: bool,
+    /// Compare the value the discovered `token_in` balance slot holds on chain with the
+    /// user's `balanceOf`, and report how well they agree as a [`SlotVerification`]
+    pub verify_against_chain: bool,
+    /// Block the simulation runs on top of, see [`SimulationBlock`]
+    pub block: SimulationBlock,
+}
+
+impl SimulationOutput {
+    /// Prices `gas_used` at `effective_gas_price`, adding the L1 data fee.
+    fn with_gas_cost(mut self, effective_gas_price: Option<u128>) -> Self {
+        self.effective_gas_price = effective_gas_price;
+        self.gas_cost_wei = self
+            .gas_used
+            .zip(effective_gas_price)
+            .map(|(gas_used, gas_price)| {
+                U256::from(gas_used) * U256::from(gas_price) + self.l1_fee.unwrap_or_default()
+            });
+        self
+    }
+}
+
+impl SimulationParams {
+    pub fn spender(&self) -> Address {
+        self.spender.unwrap_or(self.to)
+    }
+
+    /// `gas_price`, or else the one REVM defaults to
+    pub fn effective_gas_price(&self) -> Option<u128> {
+        self.gas_price.or(self.revm_cfg.gas_price)
+    }
+
+    pub fn steps(&self) -> Vec<CallStep> {
+        if self.steps.is_empty() {
+            vec![CallStep {
+                to: self.to,
+                calldata: self.calldata.clone(),
+                value: self.value,
+                gas_limit: None,
+            }]
+        } else {
+            self.steps.clone()
+        }
+    }
+
+    pub fn result_step(&self) -> usize {
+        let last = self.steps.len().saturating_sub(1);
+        self.result_step.map_or(last, |step| step.min(last))
+    }
+
+    /// Function selector the result step calls, `None` for calldata shorter than 4 bytes
+    pub fn selector(&self) -> Option<Selector> {
+        let calldata = match self.steps.get(self.result_step()) {
+            Some(step) => &step.calldata,
+            None => &self.calldata,
+        };
+
+        calldata.get(..4).map(Selector::from_slice)
+    }
+
+    /// Takes the sender, nonce, call, gas limit, pricing and authorizations of a signed
+    /// transaction, which replaces any `steps`. The rest, `token_in` and `amount_in`
+    /// included, is kept, so the transaction runs with the same balance overrides. An
+    /// approval sent ahead of it takes its nonce, the transaction running at the next one.
+    pub fn with_raw_tx(self, tx: &Recovered<TxEnvelope>) -> Self {
+        Self {
+            user: tx.signer(),
+            to: tx.to().unwrap_or_default(),
+            calldata: tx.input().clone(),
+            value: tx.value(),
+            gas_limit: Some(tx.gas_limit()),
+            gas_price: tx.gas_price(),
+            nonce: Some(tx.nonce()),
+            priority_fee: tx.max_priority_fee_per_gas().unwrap_or(self.priority_fee),
+            max_fee_per_gas: tx.is_dynamic_fee().then(|| tx.max_fee_per_gas()),
+            authorization_list: tx
+                .authorization_list()
+                .map(<[SignedAuthorization]>::to_vec)
+                .unwrap_or_default(),
+            steps: Vec::new(),
+            result_step: None,
+            ..self
+        }
     }
 }
 
+/// A raw transaction [`Simulator::simulate_raw_tx`] cannot run
 #[derive(Debug, Error)]
-#[error("simulation via revm failed")]
-pub enum ApproveError {
-    LoadAccount(#[from] DBTransportError),
-    Transact(#[from] EVMError<DBTransportError>),
-    #[error("execution failed: {0:?}")]
-    Execution(ExecutionResult),
+pub enum RawTxError {
+    #[error("decoding the transaction failed")]
+    Decode(#[from] Eip2718Error),
+    #[error("recovering the signer failed")]
+    Recover(#[from] SignatureError),
+    #[error("contract creations are not supported")]
+    Create,
 }
 
-fn approve(
-    token: Address,
-    spender: Address,
-    user: Address,
-    alloy_cache_db: &mut AlloyCacheDb,
-) -> Result<(), ApproveError> {
-    let calldata = get_approve_max_calldata(spender);
+/// Decodes an EIP-2718 encoded signed transaction, as sent with `eth_sendRawTransaction`,
+/// and recovers its signer.
+pub fn decode_raw_tx(raw_tx: &[u8]) -> Result<Recovered<TxEnvelope>, RawTxError> {
+    let tx = TxEnvelope::decode_2718_exact(raw_tx)?;
+    if tx.is_create() {
+        return Err(RawTxError::Create);
+    }
+    let signer = tx
+        .signature()
+        .recover_address_from_prehash(&tx.signature_hash())?;
 
-    let tx_env = build_tx_env(alloy_cache_db, user, token, calldata)?;
+    Ok(Recovered::new_unchecked(tx, signer))
+}
 
-    let mut evm = Context::mainnet().with_db(alloy_cache_db).build_mainnet();
+/// Defaults of a [`Simulator`] for settings that would otherwise be repeated on every call
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SimulatorConfig {
+    /// Used when [`SimulationParams::strategy`] is unset
+    pub strategy: SimulationStrategy,
+    /// Used when [`SimulationParams::approve_mode`] is unset
+    pub approve_mode: ApproveMode,
+    /// Method the RPC path starts with. [`RpcBackend::EthCallFallback`] skips
+    /// `eth_callMany` for simulations sequential `eth_call`s can reproduce.
+    pub rpc_backend: RpcBackend,
+    /// `timeout` the node is given for each `eth_callMany`, in milliseconds
+    pub call_many_timeout_ms: u64,
+    /// Extra attempts of the RPC path when a request gets no answer from the node
+    pub rpc_retries: u32,
+    /// Used when [`SimulationParams::max_slot_candidates`] is unset, and by
+    /// [`Simulator::warm`]
+    pub max_slot_candidates: usize,
+    /// Keep the storage a simulation read for later simulations at the same block, rather
+    /// than fetching it again. Dropped once a simulation resolves another block.
+    pub cache_storage: bool,
+    /// Wall-clock limit of a whole [`Simulator::simulate`], slot lookup and both
+    /// backends included, in milliseconds. Unlimited when unset.
+    pub simulate_timeout_ms: Option<u64>,
+}
 
-    let approve_res = evm.transact_commit(tx_env)?;
+impl Default for SimulatorConfig {
+    fn default() -> Self {
+        Self {
+            strategy: SimulationStrategy::default(),
+            approve_mode: ApproveMode::default(),
+            rpc_backend: RpcBackend::CallMany,
+            call_many_timeout_ms: 5000,
+            rpc_retries: 0,
+            max_slot_candidates: DEFAULT_MAX_CANDIDATES,
+            cache_storage: false,
+            simulate_timeout_ms: None,
+        }
+    }
+}
 
-    match approve_res {
-        ExecutionResult::Success {
-            reason: SuccessReason::Return,
-            ..
-        } => Ok(()),
-        failed => Err(ApproveError::Execution(failed)),
+impl SimulatorConfig {
+    /// Fills the settings `params` leaves unset with the configured defaults.
+    fn apply_defaults(&self, params: &mut SimulationParams) {
+        params.strategy.get_or_insert(self.strategy);
+        params.approve_mode.get_or_insert(self.approve_mode);
+        params
+            .max_slot_candidates
+            .get_or_insert(self.max_slot_candidates);
+    }
+}
+
+/// `(token, standard, user, verify_total_supply)`
+type BalanceSlotKey = (Address, TokenStandard, Address, bool);
+
+pub struct Simulator {
+    config: SimulatorConfig,
+    db_caches: HashMap<u32, Cache>,
+    /// Block the storage kept in `db_caches` was read at, per chain, with
+    /// [`SimulatorConfig::cache_storage`]
+    storage_blocks: HashMap<u32, u64>,
+    /// Balance mapping of each `(token, standard, verify_total_supply)`, shared by all its
+    /// holders, per chain
+    balance_layouts: HashMap<u32, HashMap<(Address, TokenStandard, bool), BalanceLayout>>,
+    /// Balance slot of each `(token, standard, user, verify_total_supply)` whose token
+    /// stores balances other than in a plain mapping, per chain
+    balance_slots: HashMap<u32, HashMap<BalanceSlotKey, SlotWithAddress>>,
+    /// Allowance slot of each `(token, owner, spender)` triple, per chain
+    allowance_slots: HashMap<u32, HashMap<(Address, Address, Address), SlotWithAddress>>,
+    providers: ProviderPool,
+}
+
+/// Return data of the call, or the reason it failed
+pub type CallResult = Result<Bytes, String>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimulationPath {
+    Rpc,
+    Revm,
+}
+
+impl SimulationPath {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SimulationPath::Rpc => "rpc",
+            SimulationPath::Revm => "revm",
+        }
     }
 }
 
-fn get_approve_max_calldata(spender: Address) -> Bytes {
-    let encoded = approveCall {
-        spender,
-        value: U256::MAX,
+/// JSON-RPC method the RPC path simulated with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RpcBackend {
+    CallMany,
+    /// Sequential `eth_call`s, for nodes that reject `eth_callMany`
+    EthCallFallback,
+}
+
+impl RpcBackend {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RpcBackend::CallMany => "eth_call_many",
+            RpcBackend::EthCallFallback => "eth_call",
+        }
     }
-    .abi_encode();
+}
 
-    encoded.into()
+#[derive(Debug)]
+pub struct SimulationOutput {
+    pub result: CallResult,
+    pub simulation_via_rpc_err: Option<SimulateViaRpcError>,
+    /// `None` when `amount_in` is zero, as no balance is overridden then
+    pub balance_slot: Option<SlotWithAddress>,
+    /// Mapping `balance_slot` belongs to, when it is keyed by `user` directly
+    pub balance_layout: Option<BalanceLayout>,
+    /// How `balance_slot` was found, `None` when it came from an earlier simulation
+    pub slot_strategy: Option<SlotStrategy>,
+    pub path: SimulationPath,
+    /// Always reported by REVM, by RPC only when the node includes `gasUsed`
+    pub gas_used: Option<u64>,
+    /// Gas refunded on top of `gas_used`, e.g. for cleared storage, capped at a fifth of
+    /// the gas used since EIP-3529. Always reported by REVM, by RPC only when the node
+    /// includes `gasRefunded`.
+    pub gas_refunded: Option<u64>,
+    /// `token_out` balance delta of `user`, when `token_out` is set. Zero when the
+    /// balance fell, see `token_out_loss`.
+    pub amount_out: Option<U256>,
+    /// How much `token_out` `user` lost across the call, when the balance fell
+    pub token_out_loss: Option<U256>,
+    /// `amount_out` converted by the vault in the state the steps left, when
+    /// `amount_out_conversion` is set
+    pub amount_out_converted: Option<U256>,
+    /// Every `eth_callMany` response of the bundle (approve included), when RPC produced the result
+    pub rpc_responses: Option<Vec<DetailedTransactionResponse>>,
+    /// Untyped `eth_callMany` response `rpc_responses` was parsed from, when
+    /// `capture_raw_response` is set. Also kept when the node's response failed the
+    /// simulation and REVM produced the result instead.
+    pub raw_rpc_response: Option<Box<RawValue>>,
+    /// Measured by REVM when `gas_profile` is set
+    pub gas_profile: Option<GasProfile>,
+    /// `allowance(user, spender)` after the approval, when `report_allowance` is set
+    pub allowance: Option<U256>,
+    /// Block both backends simulated on top of
+    pub block_number: u64,
+    /// Allowance slot resolved for [`ApproveMode::SlotOverride`]
+    pub allowance_slot: Option<SlotWithAddress>,
+    /// Why the lookup of the allowance slot failed under [`ApproveMode::SlotOverride`],
+    /// the approve transaction being sent instead
+    pub allowance_slot_err: Option<FindSlotError>,
+    /// Storage written by the steps, measured by REVM when `state_diff` is set
+    pub state_diff: Option<StateDiff>,
+    /// L1 data fee of the steps in wei, on OP-stack chains
+    pub l1_fee: Option<U256>,
+    /// `gas_price`, or else the pinned block's base fee plus `priority_fee`
+    pub effective_gas_price: Option<u128>,
+    /// `gas_used` priced at `effective_gas_price`, plus `l1_fee`, in wei
+    pub gas_cost_wei: Option<U256>,
+    /// `token_in` balance delta of `token_in_recipient`, when it is set. Falls short of
+    /// `amount_in` for fee-on-transfer tokens.
+    pub actual_amount_in: Option<U256>,
+    /// Method the RPC path used, when it produced the result
+    pub rpc_backend: Option<RpcBackend>,
+    /// Structured form of the failed execution, when REVM produced a failed result
+    pub failed_execution: Option<FailedExecution>,
+    /// Amount paid out at each hop of the result step, measured by REVM when `trace_hops`
+    /// is set
+    pub hops: Option<Vec<HopResult>>,
+    /// How the RPC and REVM results compare, with [`SimulationStrategy::Both`]
+    pub divergence: Option<DivergenceReport>,
+    /// State the steps started from, when the call failed
+    pub pre_revert_state: Option<PreRevertState>,
+    /// JSON-RPC requests sent to the node during the simulation: block number, state
+    /// fetches and `eth_callMany` alike. Drops as the cache warms up, zero offline.
+    pub rpc_request_count: u64,
+    /// Why `token_in` itself refuses transfers, when the call failed with
+    /// `diagnose_failures` set
+    pub diagnostic_hint: Option<DiagnosticHint>,
+    /// What the approve transaction returned, when one was sent: its return data, e.g. an
+    /// encoded `true` or nothing for tokens whose `approve` returns no value, or else its
+    /// revert reason. The steps still run after a reverted approval, without the
+    /// allowance.
+    pub approve_result: Option<CallResult>,
+    /// See [`SimulationParams::selector`]
+    pub selector: Option<Selector>,
+    /// How the result step ended when it succeeded in REVM, which the node doesn't
+    /// report
+    pub success_reason: Option<SuccessReason>,
+    /// Raw revert data of the result step, e.g. the 4-byte selector and arguments of a
+    /// custom error, when it reverted. `result` only holds it decoded.
+    pub revert_data: Option<Bytes>,
+    /// How the balance slot agrees with `balanceOf` at the pinned block, when
+    /// `verify_against_chain` is set and a slot was resolved
+    pub slot_verification: Option<SlotVerification>,
 }
 
-#[derive(Debug, Error)]
-pub enum SimulateViaRpcError {
-    #[error("eth_callMany call failed")]
-    EthCallMany(#[from] crate::eth_call_many::EthCallManyError),
-    #[error("approve transaction failed: {0}")]
-    ApproveFailed(String),
-    #[error("no valid response from simulation")]
-    NoResponse,
+/// What one backend returned under [`SimulationStrategy::Both`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackendResult {
+    pub result: CallResult,
+    pub gas_used: Option<u64>,
+    pub amount_out: Option<U256>,
 }
 
-#[derive(Debug, Error)]
-#[error("simulation via revm failed")]
-pub enum SimulateViaRevmError {
-    LoadAccount(#[from] DBTransportError),
-    Approve(#[from] ApproveError),
-    Transact(#[from] EVMError<DBTransportError>),
+impl From<&BackendOutput> for BackendResult {
+    fn from(output: &BackendOutput) -> Self {
+        Self {
+            result: output.result.clone(),
+            gas_used: output.gas_used,
+            amount_out: output.amount_out,
+        }
+    }
 }
 
-fn simulate_via_revm(
-    params: &SimulationParams,
-    alloy_cache_db: &mut AlloyCacheDb,
-    balance_slot: SlotWithAddress,
-) -> Result<SimulationResult, SimulateViaRevmError> {
-    let account = alloy_cache_db.load_account(balance_slot.address)?;
-    account.storage.insert(balance_slot.slot, params.amount_in);
+/// Results of both backends for the same simulation. Failed backends hold their error
+/// chain instead.
+#[derive(Debug)]
+pub struct DivergenceReport {
+    pub rpc_output: Result<BackendResult, String>,
+    pub revm_output: Result<BackendResult, String>,
+    /// Both backends returned the same result and `amount_out`. Gas is left out, as the
+    /// node may not report it.
+    pub agree: bool,
+}
 
-    approve(params.token_in, params.to, params.user, alloy_cache_db)?;
+impl DivergenceReport {
+    fn new(
+        rpc_output: Result<BackendResult, String>,
+        revm_output: Result<BackendResult, String>,
+    ) -> Self {
+        let agree = match (&rpc_output, &revm_output) {
+            (Ok(rpc), Ok(revm)) => rpc.result == revm.result && rpc.amount_out == revm.amount_out,
+            _ => false,
+        };
 
-    let tx_env = build_tx_env(
-        alloy_cache_db,
-        params.user,
-        params.to,
-        params.calldata.clone(),
-    )?;
+        Self {
+            rpc_output,
+            revm_output,
+            agree,
+        }
+    }
+}
 
-    let mut evm = Context::mainnet().with_db(alloy_cache_db).build_mainnet();
+/// ERC20 `Transfer` of the result step sent by a pool, i.e. by neither `user` nor the
+/// called router: the output of one hop, which the next hop takes as input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HopResult {
+    pub token: Address,
+    pub amount: U256,
+}
 
-    let res = evm.transact_one(tx_env)?;
+/// Hops of a route, in execution order, from the `Transfer` logs it emitted. Transfers
+/// sent by `user` or `router` only pay pools in and are left out.
+fn hops_from_logs(logs: &[Log], user: Address, router: Address) -> Vec<HopResult> {
+    logs.iter()
+        .filter_map(|log| Transfer::decode_log(log).ok())
+        .filter(|transfer| transfer.from != user && transfer.from != router)
+        .map(|transfer| HopResult {
+            token: transfer.address,
+            amount: transfer.value,
+        })
+        .collect()
+}
 
-    match res {
-        ExecutionResult::Success {
-            reason: SuccessReason::Return,
-            output,
-            ..
-        } => Ok(Ok(output.into_data())),
-        failed => Ok(Err(format!("{:?}", failed))),
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailedExecutionKind {
+    Revert,
+    Halt,
+}
+
+impl FailedExecutionKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FailedExecutionKind::Revert => "revert",
+            FailedExecutionKind::Halt => "halt",
+        }
     }
 }
 
-fn build_tx_env(
-    alloy_cache_db: &mut AlloyCacheDb,
-    from: Address,
-    to: Address,
-    calldata: Bytes,
-) -> Result<TxEnv, DBTransportError> {
-    let nonce = alloy_cache_db.load_account(from)?.info.nonce;
+/// An [`ExecutionResult`] that did not succeed, decoded for callers that can't parse its
+/// debug representation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FailedExecution {
+    pub kind: FailedExecutionKind,
+    pub gas_used: u64,
+    /// Decoded `Error(string)` or `Panic(uint256)` of a revert, if any, and the halt
+    /// reason otherwise
+    pub reason: String,
+    pub output: Bytes,
+}
 
-    let tx_env = TxEnv::builder()
-        .kind(TxKind::Call(to))
-        .data(calldata)
-        .caller(from)
-        .nonce(nonce)
-        .build_fill();
+impl FailedExecution {
+    pub fn from_result(result: &ExecutionResult) -> Option<Self> {
+        match result {
+            ExecutionResult::Success { .. } => None,
+            ExecutionResult::Revert { gas_used, output } => Some(Self {
+                kind: FailedExecutionKind::Revert,
+                gas_used: *gas_used,
+                reason: decode_revert_data(output).unwrap_or_default(),
+                output: output.clone(),
+            }),
+            ExecutionResult::Halt { reason, gas_used } => Some(Self {
+                kind: FailedExecutionKind::Halt,
+                gas_used: *gas_used,
+                reason: format!("{reason:?}"),
+                output: Bytes::new(),
+            }),
+        }
+    }
+}
 
-    Ok(tx_env)
+/// How much the value stored in a balance slot can be trusted to be the balance
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlotConfidence {
+    /// The slot holds exactly the non-zero `balanceOf`
+    High,
+    /// The slot and `balanceOf` both read zero, as any unused slot would
+    Medium,
+    /// The slot holds something else than `balanceOf`: a packed or rebasing balance, or
+    /// a slot that is not the balance after all
+    Low,
 }
 
-async fn simulate_via_rpc(
-    params: &SimulationParams,
-    rpc_url: Url,
-    balance_slot: &SlotWithAddress,
-) -> Result<SimulationResult, SimulateViaRpcError> {
-    let client = alloy_rpc_client::RpcClient::new_http(rpc_url);
-    let eth_call_many = EthCallMany::new(&client);
+impl SlotConfidence {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SlotConfidence::High => "high",
+            SlotConfidence::Medium => "medium",
+            SlotConfidence::Low => "low",
+        }
+    }
+}
 
-    let mut storage = HashMap::new();
-    storage.insert(balance_slot.slot.into(), params.amount_in.into());
+/// Value of a balance slot on chain next to the `balanceOf` of its holder, both read
+/// before any override
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlotVerification {
+    pub stored: U256,
+    pub balance: U256,
+    pub confidence: SlotConfidence,
+}
 
-    let state_override = StateOverride {
-        state_diff: Some(storage),
-        ..Default::default()
-    };
+impl SlotVerification {
+    pub fn new(stored: U256, balance: U256) -> Self {
+        let confidence = match (stored == balance, balance.is_zero()) {
+            (true, false) => SlotConfidence::High,
+            (true, true) => SlotConfidence::Medium,
+            (false, _) => SlotConfidence::Low,
+        };
 
-    let mut state_overrides = HashMap::new();
-    state_overrides.insert(params.token_in, state_override);
+        Self {
+            stored,
+            balance,
+            confidence,
+        }
+    }
+}
 
-    let approve_calldata = get_approve_max_calldata(params.to);
+/// Why a self-transfer of `token_in` by the user failed, telling a token that blocks
+/// transfers apart from a call failing on its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticHint {
+    /// Reverted with a pause reason, or OpenZeppelin's `EnforcedPause()`
+    TokenPaused,
+    /// Reverted with a reason naming a blacklist, blocklist or frozen account
+    Blacklisted,
+    /// Failed for any other reason, e.g. trading not enabled yet
+    TransferDisabled,
+}
 
-    let approve_tx = Transaction {
-        from: Some(params.user),
-        to: Some(params.token_in),
-        data: Some(approve_calldata),
-        ..Default::default()
-    };
+impl DiagnosticHint {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DiagnosticHint::TokenPaused => "token_paused",
+            DiagnosticHint::Blacklisted => "blacklisted",
+            DiagnosticHint::TransferDisabled => "transfer_disabled",
+        }
+    }
 
-    let call_tx = Transaction {
-        from: Some(params.user),
-        to: Some(params.to),
-        data: Some(params.calldata.clone()),
-        ..Default::default()
-    };
+    /// Reads the revert pattern of a failed transfer
+    fn from_failed_transfer(output: &[u8]) -> Self {
+        if output.starts_with(&EnforcedPause::SELECTOR) {
+            return DiagnosticHint::TokenPaused;
+        }
 
-    let bundle = Bundle {
-        transactions: vec![approve_tx, call_tx],
-        block_override: None,
-    };
+        let reason = decode_revert_data(output)
+            .unwrap_or_default()
+            .to_lowercase();
+        if reason.contains("pause") {
+            DiagnosticHint::TokenPaused
+        } else if BLACKLIST_REASONS.iter().any(|word| reason.contains(word)) {
+            DiagnosticHint::Blacklisted
+        } else {
+            DiagnosticHint::TransferDisabled
+        }
+    }
+}
 
-    let simulation_context = SimulationContext {
-        block_number: BlockId::latest(),
-        transaction_index: None,
-    };
+/// Words in the revert reasons of tokens refusing an account
+const BLACKLIST_REASONS: [&str; 6] = [
+    "blacklist",
+    "blocklist",
+    "blocked",
+    "denylist",
+    "frozen",
+    "sanction",
+];
 
-    let result = eth_call_many
-        .call_many(
-            vec![bundle],
-            simulation_context,
-            Some(state_overrides),
-            Some(5000),
-        )
-        .await?;
+/// State after funding and the approval, right before the steps ran, to tell why a call
+/// failed: whether the approval went through and what the user held going in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreRevertState {
+    /// An approval was sent, or its slot written, and went through. `false` when the
+    /// approve transaction reverted, see [`SimulationOutput::approve_result`], or when the
+    /// allowance already covered `amount_in` and none was sent (`skip_approve_if_allowed`).
+    pub approve_succeeded: bool,
+    /// `token_in` balance of the user, only read by REVM
+    pub token_in_balance: Option<U256>,
+    /// `allowance(user, spender)`, read by RPC only with `report_allowance`
+    pub allowance: Option<U256>,
+    /// `token_out` balance of the user, when `token_out` is set
+    pub token_out_balance: Option<U256>,
+}
 
-    let tx_responses = &result[0];
+/// What a single backend produced, before it is tagged with its [`SimulationPath`].
+struct BackendOutput {
+    result: CallResult,
+    gas_used: Option<u64>,
+    gas_refunded: Option<u64>,
+    amount_out: Option<U256>,
+    token_out_loss: Option<U256>,
+    amount_out_converted: Option<U256>,
+    rpc_responses: Option<Vec<DetailedTransactionResponse>>,
+    raw_rpc_response: Option<Box<RawValue>>,
+    gas_profile: Option<GasProfile>,
+    allowance: Option<U256>,
+    state_diff: Option<StateDiff>,
+    actual_amount_in: Option<U256>,
+    rpc_backend: Option<RpcBackend>,
+    failed_execution: Option<FailedExecution>,
+    hops: Option<Vec<HopResult>>,
+    pre_revert_state: Option<PreRevertState>,
+    approve_result: Option<CallResult>,
+    success_reason: Option<SuccessReason>,
+    revert_data: Option<Bytes>,
+}
 
-    for (idx, tx_response) in tx_responses.iter().enumerate() {
-        match tx_response {
-            TransactionResponse::Success { value, .. } => {
-                if idx == 1 {
-                    // Return the output from the second transaction (the actual call)
-                    return Ok(Ok(value.clone()));
-                }
-            }
-            TransactionResponse::Error { error } => {
-                if idx == 1 {
-                    // The main transaction reverted
-                    return Ok(Err(error.clone()));
+pub struct SwapParams {
+    pub user: Address,
+    pub token_in: Address,
+    pub amount_in: U256,
+    pub token_out: Address,
+    pub router: Address,
+    pub calldata: Bytes,
+    /// Headers sent with every request to the RPC, e.g. an API key
+    pub rpc_headers: RpcHeaders,
+    /// Least `amount_out` the swap must deliver, checked in [`SlippageReport::min_met`]
+    pub min_amount_out: Option<U256>,
+    /// Quoted `amount_out`, the reference of [`SlippageReport::slippage_bps`]
+    pub expected_amount_out: Option<U256>,
+}
+
+pub struct SwapResult {
+    pub amount_out: U256,
+    /// Gas used by the swap, zero when the node produced the result without reporting it
+    pub gas_used: u64,
+    pub path: SimulationPath,
+    /// Set when `min_amount_out` or `expected_amount_out` is
+    pub slippage: Option<SlippageReport>,
+}
+
+/// How the amount a swap delivered compares with the minimum and the quote it was given
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlippageReport {
+    pub amount_out: U256,
+    /// `amount_out` reaches `min_amount_out`, always `true` without one
+    pub min_met: bool,
+    /// Shortfall of `amount_out` against `expected_amount_out`, in basis points of it,
+    /// negative when the swap delivered more than expected. `None` without a non-zero
+    /// `expected_amount_out`.
+    pub slippage_bps: Option<i64>,
+}
+
+impl SlippageReport {
+    pub fn new(
+        amount_out: U256,
+        min_amount_out: Option<U256>,
+        expected_amount_out: Option<U256>,
+    ) -> Self {
+        let slippage_bps = expected_amount_out
+            .filter(|expected| !expected.is_zero())
+            .map(|expected| {
+                let bps = |difference: U256| -> i64 {
+                    (difference.saturating_mul(U256::from(10_000)) / expected).saturating_to()
+                };
+                if amount_out <= expected {
+                    bps(expected - amount_out)
                 } else {
-                    // Approve transaction failed - this is an error
-                    return Err(SimulateViaRpcError::ApproveFailed(error.clone()));
+                    -bps(amount_out - expected)
                 }
-            }
+            });
+
+        Self {
+            amount_out,
+            min_met: min_amount_out.is_none_or(|min| amount_out >= min),
+            slippage_bps,
         }
     }
+}
+
+#[derive(Debug)]
+pub struct BothSimulationsFailed {
+    pub rpc_error: SimulateViaRpcError,
+    pub revm_error: SimulateViaRevmError,
+}
+
+impl std::fmt::Display for BothSimulationsFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "both RPC and REVM simulations failed")?;
+
+        // Format RPC error chain (REVM chain will be handled by source())
+        write!(
+            f,
+            "\n  RPC error: {}",
+            format_error_chain(&self.rpc_error, "    ")
+        )
+    }
+}
 
-    Err(SimulateViaRpcError::NoResponse)
+/// Renders `error` followed by each of its sources on an indented `caused by:` line.
+pub fn format_error_chain(error: &(dyn std::error::Error + 'static), indent: &str) -> String {
+    let mut formatted = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        formatted.push_str(&format!("\n{indent}caused by: {cause}"));
+        source = cause.source();
+    }
+    formatted
+}
+
+impl std::error::Error for BothSimulationsFailed {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.revm_error)
+    }
+}
+
+/// Fragments of the errors nodes answer with for the state of a block they no longer
+/// keep: Geth's hash-based and path-based schemes, then Reth, Erigon and Nethermind.
+const STATE_UNAVAILABLE_MESSAGES: [&str; 4] = [
+    "missing trie node",
+    "historical state",
+    "pruned",
+    "state not available",
+];
+
+fn state_unavailable_message<E>(error: &RpcError<TransportErrorKind, E>) -> Option<String> {
+    let RpcError::ErrorResp(payload) = error else {
+        return None;
+    };
+    let message = payload.message.to_lowercase();
+
+    STATE_UNAVAILABLE_MESSAGES
+        .iter()
+        .any(|fragment| message.contains(fragment))
+        .then(|| payload.message.to_string())
+}
+
+/// Message of the node lacking the state asked for, found anywhere along `error`'s
+/// source chain.
+fn find_state_unavailable(error: &(dyn std::error::Error + 'static)) -> Option<String> {
+    let mut source = Some(error);
+    while let Some(cause) = source {
+        let message = if let Some(DBTransportError(rpc_error)) = cause.downcast_ref() {
+            state_unavailable_message(rpc_error)
+        } else if let Some(rpc_error) = cause.downcast_ref::<RpcError<TransportErrorKind>>() {
+            state_unavailable_message(rpc_error)
+        } else if let Some(rpc_error) =
+            cause.downcast_ref::<RpcError<TransportErrorKind, Box<RawValue>>>()
+        {
+            state_unavailable_message(rpc_error)
+        } else {
+            None
+        };
+        if message.is_some() {
+            return message;
+        }
+
+        source = cause.source();
+    }
+
+    None
+}
+
+/// How [`Simulator::simulate`] ended, told apart by the backend that produced the result
+/// and whether the RPC path failed before REVM produced it. `simulateOutcome` maps each
+/// variant onto one JS result, whose `outcome` is [`Outcome::as_str`].
+///
+/// ```no_run
+/// use alloy::primitives::{U256, address};
+/// use simulator::{
+///     Outcome,
+///     simulator::{SimulationParams, Simulator},
+/// };
+/// use tokio_util::sync::CancellationToken;
+///
+/// # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut simulator = Simulator::new();
+///
+/// let params = SimulationParams {
+///     user: address!("0x282Cd0c363CCf32629BE74A0A2B1a0Ed6680aE8e"),
+///     token_in: address!("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48"),
+///     amount_in: U256::from(1_000_000u64),
+///     to: address!("0x7a250d5630B4cF539739dF2C5dAcb4c659F2488D"),
+///     ..Default::default()
+/// };
+///
+/// let outcome = simulator
+///     .simulate_outcome(1, "https://rpc.example.com".parse()?, params, &CancellationToken::new())
+///     .await;
+///
+/// match outcome {
+///     Outcome::Error(error) => println!("{}: {error}", error.code()),
+///     Outcome::BothFailed(error) => println!("both failed: {error}"),
+///     outcome => println!("{}: {:?}", outcome.as_str(), outcome.output().map(|o| &o.result)),
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub enum Outcome {
+    /// The node ran the call and it returned, `output.result` holds its return data
+    RpcSuccess(SimulationOutput),
+    /// The node ran the call and it reverted, `output.result` holds the reason
+    RpcRevert(SimulationOutput),
+    /// The RPC path failed, see `output.simulation_via_rpc_err`, and REVM's call returned
+    RpcFailedRevmSuccess(SimulationOutput),
+    /// The RPC path failed and REVM's call reverted, halted or stopped
+    RpcFailedRevmRevert(SimulationOutput),
+    /// REVM's call returned without the RPC path failing, REVM running first as with
+    /// [`ProviderProfile::LocalFork`]
+    RevmSuccess(SimulationOutput),
+    /// REVM's call reverted, halted or stopped without the RPC path failing
+    RevmRevert(SimulationOutput),
+    BothFailed(Box<BothSimulationsFailed>),
+    /// The simulation could not run, e.g. the RPC is unreachable or the params invalid
+    Error(SimulateError),
+}
+
+impl Outcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::RpcSuccess(_) => "rpc_success",
+            Outcome::RpcRevert(_) => "rpc_revert",
+            Outcome::RpcFailedRevmSuccess(_) => "rpc_failed_revm_success",
+            Outcome::RpcFailedRevmRevert(_) => "rpc_failed_revm_revert",
+            Outcome::RevmSuccess(_) => "revm_success",
+            Outcome::RevmRevert(_) => "revm_revert",
+            Outcome::BothFailed(_) => "both_failed",
+            Outcome::Error(_) => "error",
+        }
+    }
+
+    /// The output of a simulation that ran, however its call ended
+    pub fn output(&self) -> Option<&SimulationOutput> {
+        match self {
+            Outcome::RpcSuccess(output)
+            | Outcome::RpcRevert(output)
+            | Outcome::RpcFailedRevmSuccess(output)
+            | Outcome::RpcFailedRevmRevert(output)
+            | Outcome::RevmSuccess(output)
+            | Outcome::RevmRevert(output) => Some(output),
+            Outcome::BothFailed(_) | Outcome::Error(_) => None,
+        }
+    }
+}
+
+impl From<Result<SimulationOutput, SimulateError>> for Outcome {
+    fn from(result: Result<SimulationOutput, SimulateError>) -> Self {
+        let output = match result {
+            Ok(output) => output,
+            Err(SimulateError::BothSimulationsFailed(error)) => return Outcome::BothFailed(error),
+            Err(error) => return Outcome::Error(error),
+        };
+
+        let rpc_failed = output.simulation_via_rpc_err.is_some();
+        match (output.path, rpc_failed, output.result.is_ok()) {
+            (SimulationPath::Rpc, _, true) => Outcome::RpcSuccess(output),
+            (SimulationPath::Rpc, _, false) => Outcome::RpcRevert(output),
+            (SimulationPath::Revm, true, true) => Outcome::RpcFailedRevmSuccess(output),
+            (SimulationPath::Revm, true, false) => Outcome::RpcFailedRevmRevert(output),
+            (SimulationPath::Revm, false, true) => Outcome::RevmSuccess(output),
+            (SimulationPath::Revm, false, false) => Outcome::RevmRevert(output),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SimulateError {
+    #[error("failed to find balance slot")]
+    FindSlot(#[from] FindSlotError),
+    #[error("RPC request failed")]
+    Rpc(#[from] RpcError<TransportErrorKind>),
+    #[error("connecting to the RPC failed")]
+    Connect(#[from] ConnectError),
+    #[error(transparent)]
+    BothSimulationsFailed(Box<BothSimulationsFailed>),
+    #[error("cancelled")]
+    Cancelled,
+    /// See [`SimulatorConfig::simulate_timeout_ms`]
+    #[error("simulation did not finish within {timeout_ms} ms")]
+    Timeout { timeout_ms: u64 },
+    #[error("chain id mismatch: expected {expected}, RPC reports {actual}")]
+    ChainIdMismatch { expected: u64, actual: u64 },
+    #[error("swap reverted: {0}")]
+    SwapReverted(String),
+    /// The swap left `user` with less `token_out` than before
+    #[error("swap lost {loss} of token_out")]
+    AmountOutNegative { loss: U256 },
+    #[error("amount {amount} does not fit the balance slot of token {token}")]
+    AmountOverflow { token: Address, amount: U256 },
+    #[error("simulation via revm failed")]
+    Revm(#[source] Box<SimulateViaRevmError>),
+    /// On chains where REVM cannot stand in for the node, see [`Simulator::simulate`]
+    #[error("simulation via RPC failed, with no REVM fallback on this chain")]
+    RpcOnly(#[source] Box<SimulateViaRpcError>),
+    #[error("invalid raw transaction")]
+    RawTx(#[from] RawTxError),
+    /// A step sends calldata to an account without code, where it would do nothing
+    #[error("call target {0} has no code")]
+    TargetNotContract(Address),
+    /// See [`Simulator::simulate_bundles`]
+    #[error("simulating the bundles via RPC failed")]
+    Bundles(#[source] Box<SimulateViaRpcError>),
+    /// The node no longer keeps the state of `block`, as non-archive nodes prune it
+    #[error(
+        "state of block {block} is unavailable on the node ({message}), simulating it needs an archive node"
+    )]
+    StateUnavailable { block: u64, message: String },
+    /// [`SimulationBlock::Pending`] on a node without pending state
+    #[error("the node does not serve pending state ({message})")]
+    PendingUnsupported { message: String },
+    /// [`SimulationParams::nonce`] leaves no room for the transactions of `user`
+    #[error("nonce {nonce} overflows over {transactions} transactions")]
+    NonceOverflow { nonce: u64, transactions: usize },
+}
+
+impl From<ChainIdError> for SimulateError {
+    fn from(error: ChainIdError) -> Self {
+        match error {
+            ChainIdError::Connect(error) => SimulateError::Connect(error),
+            ChainIdError::Rpc(error) => SimulateError::Rpc(error),
+        }
+    }
+}
+
+impl From<BothSimulationsFailed> for SimulateError {
+    fn from(error: BothSimulationsFailed) -> Self {
+        SimulateError::BothSimulationsFailed(Box::new(error))
+    }
+}
+
+impl From<SimulateViaRevmError> for SimulateError {
+    fn from(error: SimulateViaRevmError) -> Self {
+        SimulateError::Revm(Box::new(error))
+    }
+}
+
+impl SimulateError {
+    /// Stable machine-readable identifier of the variant.
+    pub fn code(&self) -> &'static str {
+        match self {
+            SimulateError::FindSlot(_) => "find_slot_failed",
+            SimulateError::Rpc(_) => "rpc_error",
+            SimulateError::Connect(ConnectError::InvalidHeader(_)) => "invalid_params",
+            SimulateError::Connect(ConnectError::Client(_)) => "rpc_error",
+            SimulateError::BothSimulationsFailed(_) => "both_simulations_failed",
+            SimulateError::RpcOnly(error) | SimulateError::Bundles(error)
+                if matches!(
+                    **error,
+                    SimulateViaRpcError::EthCallMany(EthCallManyError::GasCapExceeded { .. })
+                ) =>
+            {
+                "gas_cap_exceeded"
+            }
+            SimulateError::Cancelled => "cancelled",
+            SimulateError::Timeout { .. } => "timeout",
+            SimulateError::ChainIdMismatch { .. } => "chain_id_mismatch",
+            SimulateError::SwapReverted(_) => "swap_reverted",
+            SimulateError::AmountOutNegative { .. } => "amount_out_negative",
+            SimulateError::AmountOverflow { .. } => "amount_overflow",
+            SimulateError::Revm(_) => "revm_failed",
+            SimulateError::RpcOnly(_) => "rpc_error",
+            SimulateError::RawTx(_) => "invalid_params",
+            SimulateError::TargetNotContract(_) => "target_not_contract",
+            SimulateError::Bundles(_) => "rpc_error",
+            SimulateError::StateUnavailable { .. } => "state_unavailable",
+            SimulateError::PendingUnsupported { .. } => "pending_unsupported",
+            SimulateError::NonceOverflow { .. } => "invalid_params",
+        }
+    }
+
+    /// Replaces an error the node caused by lacking the state of `block` with
+    /// [`SimulateError::StateUnavailable`], wherever along the chain the node said so.
+    fn or_state_unavailable(self, block: u64) -> Self {
+        let message = match &self {
+            SimulateError::BothSimulationsFailed(both) => find_state_unavailable(&both.rpc_error)
+                .or_else(|| find_state_unavailable(&both.revm_error)),
+            error => find_state_unavailable(error),
+        };
+
+        match message {
+            Some(message) => SimulateError::StateUnavailable { block, message },
+            None => self,
+        }
+    }
+}
+
+pub struct ChainInfo {
+    pub chain_id: u64,
+    pub block_number: u64,
+}
+
+/// Size of the per-chain state caches, see [`Simulator::cache_stats`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Chains with a state cache
+    pub chains: usize,
+    pub accounts_per_chain: HashMap<u32, usize>,
+    /// Estimate of the memory the caches hold: accounts, storage slots, code and block
+    /// hashes, leaving out the overhead of their maps
+    pub approx_bytes: usize,
+}
+
+/// See [`CacheStats::approx_bytes`]
+fn approx_cache_bytes(cache: &Cache) -> usize {
+    let accounts: usize = cache
+        .accounts
+        .values()
+        .map(|account| {
+            size_of::<(Address, DbAccount)>() + account.storage.len() * size_of::<(U256, U256)>()
+        })
+        .sum();
+    let contracts: usize = cache
+        .contracts
+        .values()
+        .map(|code| size_of::<(B256, Bytecode)>() + code.len())
+        .sum();
+
+    accounts
+        + contracts
+        + cache.logs.len() * size_of::<Log>()
+        + cache.block_hashes.len() * size_of::<(U256, B256)>()
+}
+
+impl Default for Simulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Simulator {
+    pub fn new() -> Self {
+        Self::new_with_config(SimulatorConfig::default())
+    }
+
+    pub fn new_with_config(config: SimulatorConfig) -> Self {
+        Self {
+            config,
+            db_caches: HashMap::new(),
+            storage_blocks: HashMap::new(),
+            balance_layouts: HashMap::new(),
+            balance_slots: HashMap::new(),
+            allowance_slots: HashMap::new(),
+            providers: ProviderPool::default(),
+        }
+    }
+
+    /// Verifies that `rpc_url` is reachable and serves `chain_id`.
+    pub async fn check_rpc(
+        &mut self,
+        chain_id: u32,
+        rpc_url: Url,
+        rpc_headers: &RpcHeaders,
+    ) -> Result<ChainInfo, SimulateError> {
+        let chain_id = self
+            .verify_chain_id(chain_id, &rpc_url, rpc_headers, ProviderProfile::Remote)
+            .await?;
+
+        let block_number = self
+            .providers
+            .get_or_connect(&rpc_url, rpc_headers)?
+            .get_block_number()
+            .await?;
+
+        Ok(ChainInfo {
+            chain_id,
+            block_number,
+        })
+    }
+
+    /// Rejects RPCs serving a different chain than `chain_id`, so one chain's cache
+    /// is never filled with another chain's state. A local fork may also report a
+    /// development chain id.
+    async fn verify_chain_id(
+        &mut self,
+        chain_id: u32,
+        rpc_url: &Url,
+        rpc_headers: &RpcHeaders,
+        provider_profile: ProviderProfile,
+    ) -> Result<u64, SimulateError> {
+        let actual = self.providers.chain_id(rpc_url, rpc_headers).await?;
+
+        check_chain_id(chain_id, actual, provider_profile)
+    }
+
+    /// [`Simulator::simulate`], with how it ended as an [`Outcome`]
+    pub async fn simulate_outcome(
+        &mut self,
+        chain_id: u32,
+        rpc_url: Url,
+        params: SimulationParams,
+        cancellation: &CancellationToken,
+    ) -> Outcome {
+        self.simulate(chain_id, rpc_url, params, cancellation)
+            .await
+            .into()
+    }
+
+    /// Returns [`SimulateError::Cancelled`] as soon as `cancellation` fires, and
+    /// [`SimulateError::Timeout`] once [`SimulatorConfig::simulate_timeout_ms`] passes.
+    /// The per-chain cache is restored even when cancelled.
+    ///
+    /// On Arbitrum, only the node's result is reported whatever the [`ProviderProfile`],
+    /// and its failure is [`SimulateError::RpcOnly`] rather than a REVM fallback. REVM
+    /// doesn't run beside the node either, for the divergence check or a replay.
+    pub async fn simulate(
+        &mut self,
+        chain_id: u32,
+        rpc_url: Url,
+        params: SimulationParams,
+        cancellation: &CancellationToken,
+    ) -> Result<SimulationOutput, SimulateError> {
+        within_timeout(
+            self.config.simulate_timeout_ms,
+            cancellation,
+            async |cancellation| {
+                self.simulate_unbounded(chain_id, rpc_url, params, cancellation)
+                    .await
+            },
+        )
+        .await
+    }
+
+    async fn simulate_unbounded(
+        &mut self,
+        chain_id: u32,
+        rpc_url: Url,
+        mut params: SimulationParams,
+        cancellation: &CancellationToken,
+    ) -> Result<SimulationOutput, SimulateError> {
+        self.config.apply_defaults(&mut params);
+
+        let requests = self
+            .providers
+            .request_counter(&rpc_url, &params.rpc_headers)?;
+        let requests_before = requests.get();
+
+        tokio::select! {
+            _ = cancellation.cancelled() => return Err(SimulateError::Cancelled),
+            verified = self.verify_chain_id(
+                chain_id,
+                &rpc_url,
+                &params.rpc_headers,
+                params.provider_profile,
+            ) => verified?,
+        };
+        let provider = self
+            .providers
+            .get_or_connect(&rpc_url, &params.rpc_headers)?;
+
+        let output = self
+            .simulate_on(chain_id, provider, params, cancellation)
+            .await?;
+
+        Ok(SimulationOutput {
+            rpc_request_count: requests.get() - requests_before,
+            ..output
+        })
+    }
+
+    /// [`Simulator::simulate`] through `provider` instead of a connection of the
+    /// simulator's own, e.g. one with a custom transport, caching layer or load balancer.
+    /// `rpc_url` and `rpc_headers` of `params` are ignored, and `rpc_request_count` is
+    /// left at 0 as the requests of `provider` are not counted.
+    ///
+    /// The per-chain caches are shared with simulations through a URL, so `provider` must
+    /// serve `chain_id`, which is checked on each call.
+    pub async fn simulate_with_provider<P: Provider + Clone>(
+        &mut self,
+        chain_id: u32,
+        provider: P,
+        mut params: SimulationParams,
+        cancellation: &CancellationToken,
+    ) -> Result<SimulationOutput, SimulateError> {
+        self.config.apply_defaults(&mut params);
+
+        within_timeout(
+            self.config.simulate_timeout_ms,
+            cancellation,
+            async |cancellation| {
+                let actual = tokio::select! {
+                    _ = cancellation.cancelled() => return Err(SimulateError::Cancelled),
+                    actual = provider.get_chain_id() => actual?,
+                };
+                check_chain_id(chain_id, actual, params.provider_profile)?;
+
+                self.simulate_on(chain_id, provider, params, cancellation)
+                    .await
+            },
+        )
+        .await
+    }
+
+    /// Simulates `params` at the latest block of `provider`, whose chain was verified
+    async fn simulate_on<P: Provider + Clone>(
+        &mut self,
+        chain_id: u32,
+        provider: P,
+        mut params: SimulationParams,
+        cancellation: &CancellationToken,
+    ) -> Result<SimulationOutput, SimulateError> {
+        let config = self.config;
+
+        let (mut alloy_cache_db, block_number) = self
+            .open_provider_cache_db(
+                chain_id,
+                provider.clone(),
+                params.block,
+                params.provider_profile,
+                cancellation,
+            )
+            .await?;
+
+        let cached = self.cached_slots(chain_id, &params);
+
+        // With `cache_storage`, the simulation writes to a layer of its own, so that the
+        // chain's cache only ever holds what the node served. Pending state is not kept,
+        // as it changes without the block number moving.
+        let (result, read_state) = if config.cache_storage
+            && params.block == SimulationBlock::Latest
+        {
+            let mut recording_db = CacheDB::new(RecordingDb::new(&alloy_cache_db, block_number));
+            let result = tokio::select! {
+                _ = cancellation.cancelled() => Err(SimulateError::Cancelled),
+                result = simulate_at_block(
+                    &mut params,
+                    chain_id,
+                    &provider,
+                    &mut recording_db,
+                    block_number,
+                    cached,
+                    &config,
+                ) => result,
+            };
+            (result, Some(recording_db.db.into_state()))
+        } else {
+            let result = tokio::select! {
+                _ = cancellation.cancelled() => Err(SimulateError::Cancelled),
+                result = simulate_at_block(
+                    &mut params,
+                    chain_id,
+                    &provider,
+                    &mut alloy_cache_db,
+                    block_number,
+                    cached,
+                    &config,
+                ) => result,
+            };
+            (result, None)
+        };
+
+        if params.provider_profile.shares_caches() {
+            let cache = match read_state {
+                Some(read_state) => {
+                    self.retain_cache(chain_id, block_number, alloy_cache_db, read_state)
+                }
+                None => self.restore_cache(chain_id, alloy_cache_db),
+            };
+
+            // Mocked code, EIP-7702 delegations and overridden nonces must not leak into
+            // later simulations
+            let authorities = params
+                .authorization_list
+                .iter()
+                .filter_map(|authorization| authorization.recover_authority().ok());
+            let overridden = params.code_overrides.keys().copied().chain(authorities);
+            for address in overridden.chain(params.nonce.map(|_| params.user)) {
+                cache.accounts.remove(&address);
+            }
+        }
+
+        if let Ok(output) = &result {
+            let slots = CachedSlots {
+                layout: output.balance_layout.clone(),
+                balance_slot: output.balance_slot.clone(),
+                allowance_slot: output.allowance_slot.clone(),
+            };
+            self.store_slots(chain_id, &params, slots);
+        }
+
+        result.map_err(|error| error.or_state_unavailable(block_number))
+    }
+
+    /// Lookups of earlier simulations on `chain_id` for the token and user of `params`
+    fn cached_slots(&self, chain_id: u32, params: &SimulationParams) -> CachedSlots {
+        if !params.provider_profile.shares_caches() {
+            return CachedSlots::default();
+        }
+
+        let layout_key = (
+            params.token_in,
+            params.token_standard,
+            params.verify_total_supply,
+        );
+        let balance_key = (
+            params.token_in,
+            params.token_standard,
+            params.user,
+            params.verify_total_supply,
+        );
+        let allowance_key = (params.token_in, params.user, params.spender());
+
+        CachedSlots {
+            layout: self
+                .balance_layouts
+                .get(&chain_id)
+                .and_then(|layouts| layouts.get(&layout_key))
+                .cloned(),
+            balance_slot: self
+                .balance_slots
+                .get(&chain_id)
+                .and_then(|slots| slots.get(&balance_key))
+                .cloned(),
+            allowance_slot: self
+                .allowance_slots
+                .get(&chain_id)
+                .and_then(|slots| slots.get(&allowance_key))
+                .cloned(),
+        }
+    }
+
+    /// Caches the lookups a simulation of `params` resolved, for the ones after it. A
+    /// layout stands in for the slot of every user, so the slot is only kept without one.
+    fn store_slots(&mut self, chain_id: u32, params: &SimulationParams, slots: CachedSlots) {
+        if !params.provider_profile.shares_caches() {
+            return;
+        }
+
+        let layout_key = (
+            params.token_in,
+            params.token_standard,
+            params.verify_total_supply,
+        );
+        let balance_key = (
+            params.token_in,
+            params.token_standard,
+            params.user,
+            params.verify_total_supply,
+        );
+        let allowance_key = (params.token_in, params.user, params.spender());
+
+        match (slots.layout, slots.balance_slot) {
+            (Some(balance_layout), _) => {
+                self.balance_layouts
+                    .entry(chain_id)
+                    .or_default()
+                    .insert(layout_key, balance_layout);
+            }
+            (None, Some(balance_slot)) => {
+                self.balance_slots
+                    .entry(chain_id)
+                    .or_default()
+                    .insert(balance_key, balance_slot);
+            }
+            (None, None) => {}
+        }
+
+        if let Some(allowance_slot) = slots.allowance_slot {
+            self.allowance_slots
+                .entry(chain_id)
+                .or_default()
+                .insert(allowance_key, allowance_slot);
+        }
+    }
+
+    /// Runs `bundles` one after the other in a single `eth_callMany`, each seeing the
+    /// state the previous ones left, e.g. to let time pass between two calls. `user` is
+    /// funded with `amount_in` of `token_in` as in [`Simulator::simulate`], and the
+    /// approval opens the first bundle. The calls of `params` are not sent, nor are the
+    /// reads of `token_out`, `token_in_recipient` and `report_allowance`.
+    ///
+    /// Only the node runs bundles, there is no REVM fallback.
+    pub async fn simulate_bundles(
+        &mut self,
+        chain_id: u32,
+        rpc_url: Url,
+        mut params: SimulationParams,
+        bundles: Vec<CallBundle>,
+        cancellation: &CancellationToken,
+    ) -> Result<BundlesOutput, SimulateError> {
+        self.config.apply_defaults(&mut params);
+        let timeout_ms = self.config.call_many_timeout_ms;
+
+        let cached = self.cached_slots(chain_id, &params);
+        let (mut alloy_cache_db, provider, block_number) = self
+            .open_cache_db(
+                chain_id,
+                &rpc_url,
+                &params.rpc_headers,
+                params.provider_profile,
+                params.block,
+                cancellation,
+            )
+            .await?;
+
+        let result = tokio::select! {
+            _ = cancellation.cancelled() => Err(SimulateError::Cancelled),
+            result = simulate_bundles_at_block(
+                &params,
+                &bundles,
+                &provider,
+                &mut alloy_cache_db,
+                block_number,
+                cached,
+                timeout_ms,
+            ) => result,
+        };
+        if params.provider_profile.shares_caches() {
+            self.restore_cache(chain_id, alloy_cache_db);
+        }
+
+        if let Ok(output) = &result {
+            let slots = CachedSlots {
+                layout: output.balance_layout.clone(),
+                balance_slot: output.balance_slot.clone(),
+                allowance_slot: output.allowance_slot.clone(),
+            };
+            self.store_slots(chain_id, &params, slots);
+        }
+
+        result.map_err(|error| error.or_state_unavailable(block_number))
+    }
+
+    /// Resolves and caches the balance slot of each `(token, user)` pair, or the mapping
+    /// of the token when it has one, along with the accounts involved, so that the first
+    /// simulation for them skips those lookups.
+    pub async fn warm(
+        &mut self,
+        chain_id: u32,
+        rpc_url: Url,
+        rpc_headers: &RpcHeaders,
+        tokens: Vec<(Address, Address)>,
+    ) -> Result<(), SimulateError> {
+        let (mut alloy_cache_db, _, _) = self
+            .open_cache_db(
+                chain_id,
+                &rpc_url,
+                rpc_headers,
+                ProviderProfile::Remote,
+                SimulationBlock::Latest,
+                &CancellationToken::new(),
+            )
+            .await?;
+
+        let mut result = Ok(());
+        let mut layouts = Vec::new();
+        let mut slots = Vec::new();
+        for (token, user) in tokens {
+            match find_token_balance_layout_fast(
+                token,
+                TokenStandard::Erc20,
+                user,
+                false,
+                self.config.max_slot_candidates,
+                &mut alloy_cache_db,
+            ) {
+                Ok((_, Some(layout), _)) => {
+                    layouts.push(((token, TokenStandard::Erc20, false), layout))
+                }
+                Ok((slot, None, _)) => {
+                    slots.push(((token, TokenStandard::Erc20, user, false), slot))
+                }
+                Err(e) => {
+                    result = Err(e.into());
+                    break;
+                }
+            }
+        }
+
+        self.restore_cache(chain_id, alloy_cache_db);
+        self.balance_layouts
+            .entry(chain_id)
+            .or_default()
+            .extend(layouts);
+        self.balance_slots
+            .entry(chain_id)
+            .or_default()
+            .extend(slots);
+
+        result
+    }
+
+    /// Reads `user`'s balance of the ERC20 `token` through REVM, without overriding
+    /// anything. Reads at the latest block share the chain's cache, while a past
+    /// `block_number` is read apart from it.
+    pub async fn balance_of(
+        &mut self,
+        chain_id: u32,
+        rpc_url: Url,
+        rpc_headers: &RpcHeaders,
+        token: Address,
+        user: Address,
+        block_number: Option<u64>,
+    ) -> Result<U256, SimulateError> {
+        let read = |cache_db: &mut CacheDB<_>| {
+            read_balance(token, TokenStandard::Erc20, user, cache_db)
+                .map_err(|e| SimulateError::from(SimulateViaRevmError::from(e)))
+        };
+
+        let Some(block_number) = block_number else {
+            let (mut alloy_cache_db, _, _) = self
+                .open_cache_db(
+                    chain_id,
+                    &rpc_url,
+                    rpc_headers,
+                    ProviderProfile::Remote,
+                    SimulationBlock::Latest,
+                    &CancellationToken::new(),
+                )
+                .await?;
+
+            let balance = read(&mut alloy_cache_db);
+            self.restore_cache(chain_id, alloy_cache_db);
+
+            return balance;
+        };
+
+        self.verify_chain_id(chain_id, &rpc_url, rpc_headers, ProviderProfile::Remote)
+            .await?;
+        let provider = self.providers.get_or_connect(&rpc_url, rpc_headers)?;
+
+        let alloy_db = AlloyDB::new(provider, BlockId::number(block_number));
+        let alloy_db = CancellableDb::new(alloy_db, CancellationToken::new());
+        let alloy_db = WrapDatabaseAsync::new(alloy_db).expect("No Tokio runtime");
+
+        read(&mut CacheDB::new(alloy_db))
+    }
+
+    /// Runs `params` through REVM against the node and records the state it reads, so
+    /// that [`Simulator::simulate_offline`] can replay it without any RPC.
+    pub async fn prepare(
+        &mut self,
+        chain_id: u32,
+        rpc_url: Url,
+        params: &SimulationParams,
+    ) -> Result<PreparedState, SimulateError> {
+        let (alloy_cache_db, _, block_number) = self
+            .open_cache_db(
+                chain_id,
+                &rpc_url,
+                &params.rpc_headers,
+                params.provider_profile,
+                params.block,
+                &CancellationToken::new(),
+            )
+            .await?;
+
+        // Reads go through the chain's cache, while writes stay in the recording layer
+        let mut recording_db = CacheDB::new(RecordingDb::new(&alloy_cache_db, block_number));
+        let result =
+            resolve_funding(params, &mut recording_db, None, None, None).and_then(|funding| {
+                simulate_via_revm(
+                    params,
+                    &mut recording_db,
+                    &funding.funded_slots,
+                    &funding.approval,
+                )
+                .map_err(SimulateError::from)
+            });
+        let state = recording_db.db.into_state();
+
+        if params.provider_profile.shares_caches() {
+            self.restore_cache(chain_id, alloy_cache_db);
+        }
+
+        result.map(|_| state)
+    }
+
+    /// Simulates `params` through REVM on top of `state` alone, without any RPC. Only
+    /// `timestamp` sets the block timestamp, as `deadline_safe` needs the node.
+    pub fn simulate_offline(
+        state: PreparedState,
+        params: SimulationParams,
+    ) -> Result<SimulationOutput, SimulateError> {
+        let block_number = state.block_number;
+        let mut db = state.into_cache_db();
+
+        let funding = resolve_funding(&params, &mut db, None, None, None)?;
+        let output = simulate_via_revm(&params, &mut db, &funding.funded_slots, &funding.approval)?;
+        let allowance_slot = funding.allowance_slot();
+
+        Ok(SimulationOutput {
+            result: output.result,
+            simulation_via_rpc_err: None,
+            balance_slot: funding.balance_slot,
+            balance_layout: funding.balance_layout,
+            slot_strategy: funding.slot_strategy,
+            path: SimulationPath::Revm,
+            gas_used: output.gas_used,
+            gas_refunded: output.gas_refunded,
+            amount_out: output.amount_out,
+            token_out_loss: output.token_out_loss,
+            amount_out_converted: output.amount_out_converted,
+            rpc_responses: None,
+            raw_rpc_response: None,
+            gas_profile: output.gas_profile,
+            allowance: output.allowance,
+            block_number,
+            allowance_slot,
+            allowance_slot_err: funding.allowance_slot_err,
+            state_diff: output.state_diff,
+            l1_fee: None,
+            effective_gas_price: None,
+            gas_cost_wei: None,
+            actual_amount_in: output.actual_amount_in,
+            rpc_backend: None,
+            failed_execution: output.failed_execution,
+            hops: output.hops,
+            divergence: None,
+            pre_revert_state: output.pre_revert_state,
+            rpc_request_count: 0,
+            diagnostic_hint: None,
+            approve_result: output.approve_result,
+            selector: params.selector(),
+            success_reason: output.success_reason,
+            revert_data: output.revert_data,
+            slot_verification: funding.slot_verification,
+        }
+        .with_gas_cost(params.effective_gas_price()))
+    }
+
+    /// Drops the cached accounts, code and slots of `chain_id`, or of every chain along
+    /// with the pooled RPC connections when `None`. Later simulations fetch them from the
+    /// RPC again.
+    pub fn clear_cache(&mut self, chain_id: Option<u32>) {
+        match chain_id {
+            Some(chain_id) => {
+                self.db_caches.remove(&chain_id);
+                self.storage_blocks.remove(&chain_id);
+                self.balance_layouts.remove(&chain_id);
+                self.balance_slots.remove(&chain_id);
+                self.allowance_slots.remove(&chain_id);
+            }
+            None => {
+                self.db_caches.clear();
+                self.storage_blocks.clear();
+                self.balance_layouts.clear();
+                self.balance_slots.clear();
+                self.allowance_slots.clear();
+                self.providers.clear();
+            }
+        }
+    }
+
+    /// How many chains and accounts the state caches hold and roughly how much memory,
+    /// e.g. to decide when to [`Simulator::clear_cache`]. Slot lookups are left out, as
+    /// they take a few bytes per token.
+    pub fn cache_stats(&self) -> CacheStats {
+        CacheStats {
+            chains: self.db_caches.len(),
+            accounts_per_chain: self
+                .db_caches
+                .iter()
+                .map(|(chain_id, cache)| (*chain_id, cache.accounts.len()))
+                .collect(),
+            approx_bytes: self.db_caches.values().map(approx_cache_bytes).sum(),
+        }
+    }
+
+    /// Builds a database on top of the chain's cache, which is moved out until
+    /// [`Simulator::restore_cache`] hands it back. A local fork gets an empty cache of its
+    /// own instead, see [`ProviderProfile::shares_caches`].
+    ///
+    /// The latest block number is resolved once and the database pinned to it, so every
+    /// fetch reads the same block even if the chain advances meanwhile. That number is
+    /// returned so the RPC path can simulate at the same block.
+    async fn open_cache_db(
+        &mut self,
+        chain_id: u32,
+        rpc_url: &Url,
+        rpc_headers: &RpcHeaders,
+        provider_profile: ProviderProfile,
+        block: SimulationBlock,
+        cancellation: &CancellationToken,
+    ) -> Result<(AlloyCacheDb, HttpProvider, u64), SimulateError> {
+        tokio::select! {
+            _ = cancellation.cancelled() => return Err(SimulateError::Cancelled),
+            verified = self.verify_chain_id(
+                chain_id,
+                rpc_url,
+                rpc_headers,
+                provider_profile,
+            ) => verified?,
+        };
+
+        let provider = self.providers.get_or_connect(rpc_url, rpc_headers)?;
+
+        let (alloy_cache_db, block_number) = self
+            .open_provider_cache_db(
+                chain_id,
+                provider.clone(),
+                block,
+                provider_profile,
+                cancellation,
+            )
+            .await?;
+
+        Ok((alloy_cache_db, provider, block_number))
+    }
+
+    /// [`Simulator::open_cache_db`] on a `provider` whose chain was verified
+    async fn open_provider_cache_db<P: Provider + Clone>(
+        &mut self,
+        chain_id: u32,
+        provider: P,
+        block: SimulationBlock,
+        provider_profile: ProviderProfile,
+        cancellation: &CancellationToken,
+    ) -> Result<(ProviderCacheDb<P>, u64), SimulateError> {
+        let block_number = tokio::select! {
+            _ = cancellation.cancelled() => return Err(SimulateError::Cancelled),
+            block_number = fetch_block_number(&provider, block) => block_number?,
+        };
+        // Reads of the slot lookup and of REVM block the thread, so they have to observe
+        // `cancellation` themselves
+        let alloy_db = AlloyDB::new(provider, block.at(block_number).into());
+        let alloy_db = CancellableDb::new(alloy_db, cancellation.clone());
+        let alloy_db = WrapDatabaseAsync::new(alloy_db).expect("No Tokio runtime");
+
+        let mut alloy_cache_db = CacheDB::new(alloy_db);
+        if !provider_profile.shares_caches() {
+            return Ok((alloy_cache_db, block_number));
+        }
+
+        //TODO: RAII bug?
+        alloy_cache_db.cache = std::mem::take(self.db_caches.entry(chain_id).or_default());
+
+        // Storage kept by `cache_storage` only holds for the block it was read at
+        if block == SimulationBlock::Pending
+            || self.storage_blocks.get(&chain_id) != Some(&block_number)
+        {
+            self.storage_blocks.remove(&chain_id);
+            clear_storage(&mut alloy_cache_db.cache);
+        }
+
+        Ok((alloy_cache_db, block_number))
+    }
+
+    /// Puts the cache back, keeping account info and code but dropping storage, which
+    /// goes stale from one block to the next.
+    fn restore_cache<ExtDB>(
+        &mut self,
+        chain_id: u32,
+        alloy_cache_db: CacheDB<ExtDB>,
+    ) -> &mut Cache {
+        self.storage_blocks.remove(&chain_id);
+        let cache = self.db_caches.entry(chain_id).or_default();
+        *cache = alloy_cache_db.cache;
+
+        clear_storage(cache);
+
+        cache
+    }
+
+    /// Puts back a cache the simulation only read through, adding the state it read from
+    /// the node, storage included, for later simulations at `block_number`.
+    fn retain_cache<ExtDB>(
+        &mut self,
+        chain_id: u32,
+        block_number: u64,
+        alloy_cache_db: CacheDB<ExtDB>,
+        read_state: PreparedState,
+    ) -> &mut Cache {
+        self.storage_blocks.insert(chain_id, block_number);
+        let cache = self.db_caches.entry(chain_id).or_default();
+        *cache = alloy_cache_db.cache;
+
+        read_state.extend_cache(cache);
+
+        cache
+    }
+
+    /// Simulates a swap through `router` and reports the `token_out` amount received by `user`.
+    pub async fn simulate_swap(
+        &mut self,
+        chain_id: u32,
+        rpc_url: Url,
+        params: SwapParams,
+        cancellation: &CancellationToken,
+    ) -> Result<SwapResult, SimulateError> {
+        let simulation_params = SimulationParams {
+            user: params.user,
+            token_in: params.token_in,
+            amount_in: params.amount_in,
+            to: params.router,
+            calldata: params.calldata,
+            token_out: Some(params.token_out),
+            rpc_headers: params.rpc_headers,
+            ..Default::default()
+        };
+
+        let output = self
+            .simulate(chain_id, rpc_url, simulation_params, cancellation)
+            .await?;
+
+        let amount_out = output.amount_out.unwrap_or_default();
+        let slippage = (params.min_amount_out.is_some() || params.expected_amount_out.is_some())
+            .then(|| {
+                SlippageReport::new(
+                    amount_out,
+                    params.min_amount_out,
+                    params.expected_amount_out,
+                )
+            });
+
+        if let Some(loss) = output.token_out_loss {
+            return Err(SimulateError::AmountOutNegative { loss });
+        }
+
+        match output.result {
+            Ok(_) => Ok(SwapResult {
+                amount_out,
+                gas_used: output.gas_used.unwrap_or_default(),
+                path: output.path,
+                slippage,
+            }),
+            Err(reason) => Err(SimulateError::SwapReverted(reason)),
+        }
+    }
+
+    /// Simulates a signed transaction, as sent with `eth_sendRawTransaction`, from its
+    /// recovered signer. `overrides` supplies everything the transaction doesn't, such as
+    /// the `token_in` balance to fund, see [`SimulationParams::with_raw_tx`].
+    ///
+    /// A transaction signed for another chain than `chain_id` fails with
+    /// [`SimulateError::ChainIdMismatch`].
+    pub async fn simulate_raw_tx(
+        &mut self,
+        chain_id: u32,
+        rpc_url: Url,
+        raw_tx: Bytes,
+        overrides: SimulationParams,
+        cancellation: &CancellationToken,
+    ) -> Result<SimulationOutput, SimulateError> {
+        let tx = decode_raw_tx(&raw_tx)?;
+        if let Some(tx_chain_id) = tx.chain_id()
+            && tx_chain_id != u64::from(chain_id)
+        {
+            return Err(SimulateError::ChainIdMismatch {
+                expected: chain_id.into(),
+                actual: tx_chain_id,
+            });
+        }
+
+        self.simulate(chain_id, rpc_url, overrides.with_raw_tx(&tx), cancellation)
+            .await
+    }
+}
+
+/// Drops every cached storage slot, keeping account info and code.
+fn clear_storage(cache: &mut Cache) {
+    cache.accounts.iter_mut().for_each(|(_, db_account)| {
+        db_account.storage.clear();
+    });
+}
+
+/// Lookups [`Simulator::simulate`] found cached for the simulated token and user
+#[derive(Default)]
+struct CachedSlots {
+    layout: Option<BalanceLayout>,
+    balance_slot: Option<SlotWithAddress>,
+    allowance_slot: Option<SlotWithAddress>,
+}
+
+/// Funds and runs a simulation on `cache_db`, reading `block_number`, and prices its gas.
+async fn simulate_at_block<ExtDB: DatabaseRef<Error = DBTransportError>>(
+    params: &mut SimulationParams,
+    chain_id: u32,
+    provider: &impl Provider,
+    cache_db: &mut CacheDB<ExtDB>,
+    block_number: u64,
+    cached: CachedSlots,
+    config: &SimulatorConfig,
+) -> Result<SimulationOutput, SimulateError> {
+    // Only needed for the timestamp of deadline-safe simulations, otherwise a missing
+    // header just leaves the gas price unset and `gas_cost_wei` unknown
+    let deadline_safe = params.deadline_safe && params.timestamp.is_none();
+    let header = match fetch_block_header(provider, params.block.at(block_number)).await {
+        Ok(header) => Some(header),
+        Err(e) if deadline_safe => return Err(e),
+        Err(_) => None,
+    };
+    if deadline_safe {
+        params.timestamp = header.as_ref().map(BlockHeader::next_block_timestamp);
+    }
+    if params.gas_price.is_none() && params.revm_cfg.gas_price.is_none() {
+        params.revm_cfg.gas_price = header
+            .as_ref()
+            .and_then(|header| header.base_fee_per_gas)
+            .map(|base_fee| base_fee.to::<u128>().saturating_add(params.priority_fee))
+            .map(|gas_price| {
+                params
+                    .max_fee_per_gas
+                    .map_or(gas_price, |max| gas_price.min(max))
+            });
+    }
+    let timestamp = params
+        .timestamp
+        .or_else(|| header.as_ref().map(|header| header.timestamp.to()));
+    params
+        .revm_cfg
+        .spec
+        .get_or_insert_with(|| spec_at(chain_id, block_number, timestamp));
+
+    check_call_targets(params, cache_db)?;
+
+    let funding = resolve_funding(
+        params,
+        cache_db,
+        cached.layout,
+        cached.balance_slot,
+        cached.allowance_slot,
+    )?;
+    let approve = funding.approval != Approval::Existing;
+    check_nonce(params, params.steps().len() + usize::from(approve))?;
+
+    let output = simulate_with_fallback(
+        params,
+        chain_id,
+        provider,
+        cache_db,
+        block_number,
+        funding,
+        config,
+    )
+    .await?;
+
+    Ok(output.with_gas_cost(params.effective_gas_price()))
+}
+
+/// Fails with [`SimulateError::NonceOverflow`] when the `transactions` of `user`, numbered
+/// from the `nonce` override, would leave no nonce after them.
+fn check_nonce(params: &SimulationParams, transactions: usize) -> Result<(), SimulateError> {
+    match params.nonce {
+        Some(nonce) if nonce.checked_add(transactions as u64).is_none() => {
+            Err(SimulateError::NonceOverflow {
+                nonce,
+                transactions,
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Fails with [`SimulateError::TargetNotContract`] when a step sends calldata to an
+/// account that has no code, nor gets any from `code_overrides` or an EIP-7702
+/// authorization, and isn't a precompile. Plain value transfers may go to any account.
+fn check_call_targets<ExtDB: DatabaseRef<Error = DBTransportError>>(
+    params: &SimulationParams,
+    cache_db: &CacheDB<ExtDB>,
+) -> Result<(), SimulateError> {
+    for step in params.steps() {
+        if step.calldata.is_empty()
+            || params.code_overrides.contains_key(&step.to)
+            || Precompiles::latest().contains(&step.to)
+            || params
+                .authorization_list
+                .iter()
+                .any(|authorization| authorization.recover_authority().ok() == Some(step.to))
+        {
+            continue;
+        }
+
+        let info = cache_db
+            .basic_ref(step.to)
+            .map_err(|DBTransportError(error)| SimulateError::Rpc(error))?;
+        if info.is_none_or(|info| info.is_empty_code_hash()) {
+            return Err(SimulateError::TargetNotContract(step.to));
+        }
+    }
+
+    Ok(())
+}
+
+/// Funds `user` on `cache_db` and runs `bundles` through the node at `block_number`
+async fn simulate_bundles_at_block<ExtDB: DatabaseRef<Error = DBTransportError>>(
+    params: &SimulationParams,
+    bundles: &[CallBundle],
+    provider: &impl Provider,
+    cache_db: &mut CacheDB<ExtDB>,
+    block_number: u64,
+    cached: CachedSlots,
+    timeout_ms: u64,
+) -> Result<BundlesOutput, SimulateError> {
+    let funding = resolve_funding(
+        params,
+        cache_db,
+        cached.layout,
+        cached.balance_slot,
+        cached.allowance_slot,
+    )?;
+    let approve = funding.approval != Approval::Existing;
+    let steps = bundles
+        .iter()
+        .map(|bundle| bundle.steps.len())
+        .sum::<usize>();
+    check_nonce(params, steps + usize::from(approve))?;
+    let rpc_failed = |error: SimulateViaRpcError| SimulateError::Bundles(Box::new(error));
+
+    let responses = EthCallMany::new(provider.client())
+        .call_many_detailed(
+            build_sequential_bundles(params, &funding.approval, bundles),
+            build_simulation_context(params, block_number),
+            Some(build_state_overrides(params, &funding.funded_slots)),
+            Some(timeout_ms),
+        )
+        .await
+        .map_err(|error| rpc_failed(error.into()))?;
+
+    Ok(BundlesOutput {
+        bundles: parse_sequential_bundles(approve, responses).map_err(rpc_failed)?,
+        block_number,
+        allowance_slot: funding.allowance_slot(),
+        balance_slot: funding.balance_slot,
+        balance_layout: funding.balance_layout,
+    })
+}
+
+async fn simulate_with_fallback<ExtDB: DatabaseRef<Error = DBTransportError>>(
+    params: &SimulationParams,
+    chain_id: u32,
+    provider: &impl Provider,
+    cache_db: &mut CacheDB<ExtDB>,
+    block_number: u64,
+    funding: Funding,
+    config: &SimulatorConfig,
+) -> Result<SimulationOutput, SimulateError> {
+    let allowance_slot = funding.allowance_slot();
+    let Funding {
+        balance_slot,
+        balance_layout,
+        slot_strategy,
+        funded_slots,
+        approval,
+        allowance_slot_err,
+        slot_verification,
+    } = funding;
+
+    let (backend_output, path, simulation_via_rpc_err, revm_err) = match params.provider_profile {
+        _ if is_rpc_only(chain_id) => {
+            let rpc_output = simulate_via_profile_node(
+                params,
+                provider,
+                cache_db,
+                &funded_slots,
+                &approval,
+                block_number,
+                config,
+            )
+            .await
+            .map_err(|rpc_error| SimulateError::RpcOnly(Box::new(rpc_error)))?;
+
+            (rpc_output, SimulationPath::Rpc, None, None)
+        }
+        ProviderProfile::Remote => {
+            let rpc_result = simulate_via_profile_node(
+                params,
+                provider,
+                cache_db,
+                &funded_slots,
+                &approval,
+                block_number,
+                config,
+            )
+            .await;
+
+            match rpc_result {
+                Ok(rpc_output) => (rpc_output, SimulationPath::Rpc, None, None),
+                Err(rpc_error) => {
+                    match simulate_via_revm(params, cache_db, &funded_slots, &approval) {
+                        Ok(revm_output) => {
+                            (revm_output, SimulationPath::Revm, Some(rpc_error), None)
+                        }
+                        Err(revm_error) => {
+                            return Err(BothSimulationsFailed {
+                                rpc_error,
+                                revm_error,
+                            }
+                            .into());
+                        }
+                    }
+                }
+            }
+        }
+        ProviderProfile::LocalFork => {
+            match simulate_via_revm(params, cache_db, &funded_slots, &approval) {
+                Ok(revm_output) => (revm_output, SimulationPath::Revm, None, None),
+                Err(revm_error) => {
+                    let rpc_result = simulate_via_profile_node(
+                        params,
+                        provider,
+                        cache_db,
+                        &funded_slots,
+                        &approval,
+                        block_number,
+                        config,
+                    )
+                    .await;
+
+                    match rpc_result {
+                        Ok(rpc_output) => (rpc_output, SimulationPath::Rpc, None, Some(revm_error)),
+                        Err(rpc_error) => {
+                            return Err(BothSimulationsFailed {
+                                rpc_error,
+                                revm_error,
+                            }
+                            .into());
+                        }
+                    }
+                }
+            }
+        }
+    };
+
+    // REVM runs nowhere on chains it cannot reproduce, not even beside the node
+    let rpc_only = is_rpc_only(chain_id);
+
+    // REVM's run beside the node, kept for what only REVM reports. It runs on a layer of
+    // its own, as it commits the approval and steps the rest of the simulation must not see.
+    let mut revm_replay = None;
+
+    // Whichever backend didn't produce the result runs too, unless it already failed
+    let divergence = match params.strategy.unwrap_or_default() {
+        SimulationStrategy::Fallback => None,
+        SimulationStrategy::Both if rpc_only => None,
+        SimulationStrategy::Both => {
+            let (rpc_output, revm_output) = match path {
+                SimulationPath::Rpc => {
+                    let revm_output = match revm_err {
+                        Some(revm_error) => Err(revm_error),
+                        None => simulate_via_revm(
+                            params,
+                            &mut CacheDB::new(&*cache_db),
+                            &funded_slots,
+                            &approval,
+                        ),
+                    };
+                    let revm_result = revm_output
+                        .as_ref()
+                        .map(BackendResult::from)
+                        .map_err(|e| format_error_chain(e, ""));
+                    revm_replay = Some(revm_output);
+
+                    (Ok(BackendResult::from(&backend_output)), revm_result)
+                }
+                SimulationPath::Revm => {
+                    let rpc_output = match &simulation_via_rpc_err {
+                        Some(rpc_error) => Err(format_error_chain(rpc_error, "")),
+                        None => simulate_via_profile_node(
+                            params,
+                            provider,
+                            cache_db,
+                            &funded_slots,
+                            &approval,
+                            block_number,
+                            config,
+                        )
+                        .await
+                        .as_ref()
+                        .map(BackendResult::from)
+                        .map_err(|e| format_error_chain(e, "")),
+                    };
+                    (rpc_output, Ok(BackendResult::from(&backend_output)))
+                }
+            };
+
+            Some(DivergenceReport::new(rpc_output, revm_output))
+        }
+    };
+
+    // Only REVM can profile, record writes and see logs, so it is replayed when RPC
+    // produced the result, unless it already ran beside the node
+    let (gas_profile, state_diff, hops) = match path {
+        SimulationPath::Rpc
+            if !rpc_only && (params.gas_profile || params.state_diff || params.trace_hops) =>
+        {
+            revm_replay
+                .unwrap_or_else(|| {
+                    simulate_via_revm(
+                        params,
+                        &mut CacheDB::new(&*cache_db),
+                        &funded_slots,
+                        &approval,
+                    )
+                })
+                .map_or((None, None, None), |revm_output| {
+                    (
+                        revm_output.gas_profile,
+                        revm_output.state_diff,
+                        revm_output.hops,
+                    )
+                })
+        }
+        _ => (
+            backend_output.gas_profile,
+            backend_output.state_diff,
+            backend_output.hops,
+        ),
+    };
+
+    // Not fatal, the simulation itself succeeded
+    let l1_fee = is_op_stack(chain_id)
+        .then(|| estimate_l1_fee(cache_db, chain_id, params).ok())
+        .flatten();
+
+    let diagnostic_hint = (params.diagnose_failures && !rpc_only && backend_output.result.is_err())
+        .then(|| diagnose_transfer(params, cache_db, &funded_slots))
+        .flatten();
+
+    // The node's answer is kept even when it failed the simulation, to see why
+    let raw_rpc_response = backend_output.raw_rpc_response.or_else(|| {
+        simulation_via_rpc_err
+            .as_ref()
+            .and_then(SimulateViaRpcError::raw_rpc_response)
+            .map(ToOwned::to_owned)
+    });
+
+    Ok(SimulationOutput {
+        result: backend_output.result,
+        simulation_via_rpc_err,
+        balance_slot,
+        balance_layout,
+        slot_strategy,
+        path,
+        gas_used: backend_output.gas_used,
+        gas_refunded: backend_output.gas_refunded,
+        amount_out: backend_output.amount_out,
+        token_out_loss: backend_output.token_out_loss,
+        amount_out_converted: backend_output.amount_out_converted,
+        rpc_responses: backend_output.rpc_responses,
+        raw_rpc_response,
+        gas_profile,
+        allowance: backend_output.allowance,
+        block_number,
+        allowance_slot,
+        allowance_slot_err,
+        state_diff,
+        l1_fee,
+        effective_gas_price: None,
+        gas_cost_wei: None,
+        actual_amount_in: backend_output.actual_amount_in,
+        rpc_backend: backend_output.rpc_backend,
+        failed_execution: backend_output.failed_execution,
+        hops,
+        divergence,
+        pre_revert_state: backend_output.pre_revert_state,
+        // Counted by `Simulator::simulate`, around the whole simulation
+        rpc_request_count: 0,
+        diagnostic_hint,
+        approve_result: backend_output.approve_result,
+        selector: params.selector(),
+        success_reason: backend_output.success_reason,
+        revert_data: backend_output.revert_data,
+        slot_verification,
+    })
+}
+
+/// Balances and allowance a simulation is set up with, before either backend runs
+struct Funding {
+    /// `None` when `amount_in` is zero, as no balance is overridden then
+    balance_slot: Option<SlotWithAddress>,
+    balance_layout: Option<BalanceLayout>,
+    slot_strategy: Option<SlotStrategy>,
+    funded_slots: Vec<FundedSlot>,
+    approval: Approval,
+    /// See [`SimulationOutput::allowance_slot_err`]
+    allowance_slot_err: Option<FindSlotError>,
+    slot_verification: Option<SlotVerification>,
+}
+
+impl Funding {
+    fn allowance_slot(&self) -> Option<SlotWithAddress> {
+        match &self.approval {
+            Approval::SlotOverride(allowance_slot) => Some(allowance_slot.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Resolves the slots funding `user` and the other `balances`, and how the allowance
+/// is granted, looking up whatever isn't cached.
+fn resolve_funding<ExtDB: DatabaseRef<Error = DBTransportError>>(
+    params: &SimulationParams,
+    cache_db: &mut CacheDB<ExtDB>,
+    cached_layout: Option<BalanceLayout>,
+    cached_slot: Option<SlotWithAddress>,
+    cached_allowance_slot: Option<SlotWithAddress>,
+) -> Result<Funding, SimulateError> {
+    if params.raw {
+        return Ok(Funding {
+            balance_slot: None,
+            balance_layout: None,
+            slot_strategy: None,
+            funded_slots: Vec::new(),
+            approval: Approval::Existing,
+            allowance_slot_err: None,
+            slot_verification: None,
+        });
+    }
+
+    let max_candidates = params.max_slot_candidates.unwrap_or(DEFAULT_MAX_CANDIDATES);
+
+    // A zero amount needs no balance, so neither the slot lookup nor the override is done
+    let (balance_slot, balance_layout, slot_strategy) = if params.amount_in.is_zero() {
+        (None, None, None)
+    } else {
+        let (balance_slot, balance_layout, slot_strategy) = match (cached_layout, cached_slot) {
+            (Some(layout), _) => (layout.user_slot(params.user), Some(layout), None),
+            (None, Some(balance_slot)) => (balance_slot, None, None),
+            (None, None) => {
+                let (balance_slot, balance_layout, slot_strategy) = find_token_balance_layout_fast(
+                    params.token_in,
+                    params.token_standard,
+                    params.user,
+                    params.verify_total_supply,
+                    max_candidates,
+                    cache_db,
+                )?;
+                (balance_slot, balance_layout, Some(slot_strategy))
+            }
+        };
+        (Some(balance_slot), balance_layout, slot_strategy)
+    };
+    // Read before any override, so that both reflect the chain
+    let slot_verification = balance_slot
+        .as_ref()
+        .filter(|_| params.verify_against_chain)
+        .and_then(|balance_slot| verify_balance_slot(params, balance_slot, cache_db));
+
+    let mut funded_slots: Vec<FundedSlot> = balance_slot
+        .iter()
+        .map(|balance_slot| (balance_slot.clone(), params.amount_in))
+        .collect();
+    for (holder, token, amount) in &params.balances {
+        let balance_slot = find_token_balance_slot(
+            *token,
+            TokenStandard::Erc20,
+            *holder,
+            false,
+            max_candidates,
+            cache_db,
+        )?;
+        funded_slots.push((balance_slot, *amount));
+    }
+    for (nft, token_id) in &params.owned_nfts {
+        let owner_slot = find_owner_slot(*nft, *token_id, cache_db)?;
+        funded_slots.push((owner_slot, params.user.into_word().into()));
+    }
+    // Fields packed next to a balance are kept as they are at this block, for this holder
+    for (balance_slot, _) in &mut funded_slots {
+        if let Some(packing) = &mut balance_slot.packing {
+            packing.original = cache_db
+                .storage_ref(balance_slot.address, balance_slot.slot)
+                .map_err(|error| SimulateError::Rpc(error.0))?;
+        }
+    }
+    if let Some((balance_slot, amount)) = overflowing_amount(&funded_slots) {
+        return Err(SimulateError::AmountOverflow {
+            token: balance_slot.address,
+            amount: *amount,
+        });
+    }
+
+    // Without a slot, REVM falls back to executing the approve transaction
+    let mut allowance_slot_err = None;
+    let approval = if params.skip_approve_if_allowed && allowance_covers_amount(params, cache_db) {
+        Approval::Existing
+    } else {
+        // ERC1155 approvals are booleans, which the slot mutation cannot recognize
+        match params.approve_mode.unwrap_or_default() {
+            ApproveMode::SlotOverride if params.token_standard == TokenStandard::Erc20 => {
+                let allowance_slot = match cached_allowance_slot {
+                    Some(allowance_slot) => Ok(allowance_slot),
+                    None => find_allowance_slot(
+                        params.token_in,
+                        params.user,
+                        params.spender(),
+                        cache_db,
+                    ),
+                };
+                match allowance_slot {
+                    Ok(allowance_slot) => Approval::SlotOverride(allowance_slot),
+                    Err(error) => {
+                        allowance_slot_err = Some(error);
+                        Approval::Transaction
+                    }
+                }
+            }
+            ApproveMode::IncreaseAllowance
+                if params.token_standard == TokenStandard::Erc20
+                    && supports_increase_allowance(params, cache_db) =>
+            {
+                Approval::IncreaseAllowance
+            }
+            _ => Approval::Transaction,
+        }
+    };
+
+    Ok(Funding {
+        balance_slot,
+        balance_layout,
+        slot_strategy,
+        funded_slots,
+        approval,
+        allowance_slot_err,
+        slot_verification,
+    })
+}
+
+/// Reads what `balance_slot` holds and the user's `balanceOf`, or nothing when either
+/// read fails, as the simulation doesn't depend on them.
+fn verify_balance_slot<ExtDB: DatabaseRef<Error = DBTransportError>>(
+    params: &SimulationParams,
+    balance_slot: &SlotWithAddress,
+    cache_db: &mut CacheDB<ExtDB>,
+) -> Option<SlotVerification> {
+    let stored = cache_db
+        .storage_ref(balance_slot.address, balance_slot.slot)
+        .ok()?;
+    let balance = read_balance(
+        params.token_in,
+        params.token_standard,
+        params.user,
+        cache_db,
+    )
+    .ok()?;
+
+    Some(SlotVerification::new(stored, balance))
+}
+
+/// Simulates through the node the way its [`ProviderProfile`] calls it
+async fn simulate_via_profile_node<ExtDB: DatabaseRef<Error = DBTransportError>>(
+    params: &SimulationParams,
+    provider: &impl Provider,
+    cache_db: &mut CacheDB<ExtDB>,
+    funded_slots: &[FundedSlot],
+    approval: &Approval,
+    block_number: u64,
+    config: &SimulatorConfig,
+) -> Result<BackendOutput, SimulateViaRpcError> {
+    match params.provider_profile {
+        ProviderProfile::Remote => {
+            simulate_via_node(
+                params,
+                provider,
+                cache_db,
+                funded_slots,
+                approval,
+                block_number,
+                config,
+            )
+            .await
+        }
+        ProviderProfile::LocalFork if supports_eth_call_fallback(params) => {
+            simulate_via_eth_call(
+                params,
+                provider,
+                cache_db,
+                funded_slots,
+                approval,
+                block_number,
+            )
+            .await
+        }
+        ProviderProfile::LocalFork => {
+            simulate_via_rpc(
+                params,
+                provider,
+                funded_slots,
+                approval,
+                block_number,
+                config.call_many_timeout_ms,
+            )
+            .await
+        }
+    }
+}
+
+/// Simulates through the node with `eth_callMany`, or sequential `eth_call`s when the
+/// node doesn't know that method or [`SimulatorConfig::rpc_backend`] asks for them, and
+/// the simulation fits them. Retried when the node doesn't answer at all.
+async fn simulate_via_node<ExtDB: DatabaseRef<Error = DBTransportError>>(
+    params: &SimulationParams,
+    provider: &impl Provider,
+    cache_db: &mut CacheDB<ExtDB>,
+    funded_slots: &[FundedSlot],
+    approval: &Approval,
+    block_number: u64,
+    config: &SimulatorConfig,
+) -> Result<BackendOutput, SimulateViaRpcError> {
+    let mut attempts_left = config.rpc_retries;
+    loop {
+        let rpc_result = match config.rpc_backend {
+            RpcBackend::EthCallFallback if supports_eth_call_fallback(params) => Err(None),
+            _ => simulate_via_rpc(
+                params,
+                provider,
+                funded_slots,
+                approval,
+                block_number,
+                config.call_many_timeout_ms,
+            )
+            .await
+            .map_err(Some),
+        };
+
+        let rpc_result = match rpc_result {
+            Err(None) => {
+                simulate_via_eth_call(
+                    params,
+                    provider,
+                    cache_db,
+                    funded_slots,
+                    approval,
+                    block_number,
+                )
+                .await
+            }
+            Err(Some(SimulateViaRpcError::EthCallMany(e)))
+                if e.is_method_not_found() && supports_eth_call_fallback(params) =>
+            {
+                simulate_via_eth_call(
+                    params,
+                    provider,
+                    cache_db,
+                    funded_slots,
+                    approval,
+                    block_number,
+                )
+                .await
+            }
+            Err(Some(rpc_error)) => Err(rpc_error),
+            Ok(rpc_output) => Ok(rpc_output),
+        };
+
+        match rpc_result {
+            Err(SimulateViaRpcError::EthCallMany(e))
+                if e.is_transport_error() && attempts_left > 0 =>
+            {
+                attempts_left -= 1;
+            }
+            rpc_result => return rpc_result,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ApproveError {
+    #[error("loading the user account failed")]
+    LoadAccount(#[from] DBTransportError),
+    #[error("executing the approve transaction failed")]
+    Transact(#[from] EVMError<DBTransportError>),
+    #[error("execution failed: {0:?}")]
+    Execution(ExecutionResult),
+}
+
+fn approve<ExtDB: DatabaseRef<Error = DBTransportError>>(
+    params: &SimulationParams,
+    cache_db: &mut CacheDB<ExtDB>,
+    approval: &Approval,
+) -> Result<Bytes, ApproveError> {
+    let calldata = get_approve_calldata(params, approval);
+
+    let tx_env = build_tx_env(cache_db, params.user, params.token_in, calldata)?;
+
+    let mut evm = Context::mainnet()
+        .with_db(cache_db)
+        .modify_cfg_chained(|cfg| params.revm_cfg.apply(cfg))
+        .modify_block_chained(|block| override_block(params, block))
+        .build_mainnet();
+
+    let approve_res = evm.transact_commit(tx_env)?;
+
+    match approve_res {
+        ExecutionResult::Success { output, .. } => Ok(output.into_data()),
+        failed => Err(ApproveError::Execution(failed)),
+    }
+}
+
+/// Whether `increaseAllowance(spender, amount_in)` sent by `user` returns `true`, without
+/// committing it. Tokens lacking the function revert or return nothing.
+fn supports_increase_allowance<ExtDB: DatabaseRef<Error = DBTransportError>>(
+    params: &SimulationParams,
+    cache_db: &mut CacheDB<ExtDB>,
+) -> bool {
+    let calldata = get_approve_calldata(params, &Approval::IncreaseAllowance);
+    let Ok(tx_env) = build_tx_env(cache_db, params.user, params.token_in, calldata) else {
+        return false;
+    };
+
+    let mut evm = Context::mainnet()
+        .with_db(cache_db)
+        .modify_cfg_chained(|cfg| params.revm_cfg.apply(cfg))
+        .modify_block_chained(|block| override_block(params, block))
+        .build_mainnet();
+
+    match evm.transact_one(tx_env) {
+        Ok(ExecutionResult::Success { output, .. }) => {
+            increaseAllowanceCall::abi_decode_returns(output.data()).unwrap_or_default()
+        }
+        _ => false,
+    }
+}
+
+/// Sends 1 unit of `token_in` from the funded user to themselves, on a layer of its own,
+/// and reads why it failed. `None` when it goes through, or for ERC1155 tokens.
+fn diagnose_transfer<ExtDB: DatabaseRef<Error = DBTransportError>>(
+    params: &SimulationParams,
+    cache_db: &CacheDB<ExtDB>,
+    funded_slots: &[FundedSlot],
+) -> Option<DiagnosticHint> {
+    if params.token_standard != TokenStandard::Erc20 {
+        return None;
+    }
+
+    let mut probe_db = CacheDB::new(cache_db);
+    fund_slots(&mut probe_db, funded_slots).ok()?;
+
+    let calldata = transferCall {
+        to: params.user,
+        value: U256::from(1),
+    }
+    .abi_encode();
+    let tx_env = build_tx_env(&mut probe_db, params.user, params.token_in, calldata.into()).ok()?;
+
+    let mut evm = Context::mainnet()
+        .with_db(&mut probe_db)
+        .modify_cfg_chained(|cfg| params.revm_cfg.apply(cfg))
+        .modify_block_chained(|block| override_block(params, block))
+        .build_mainnet();
+
+    match evm.transact_one(tx_env).ok()? {
+        ExecutionResult::Success { output, .. }
+            if transferCall::abi_decode_returns(output.data()).unwrap_or(true) =>
+        {
+            None
+        }
+        ExecutionResult::Revert { output, .. } => {
+            Some(DiagnosticHint::from_failed_transfer(&output))
+        }
+        _ => Some(DiagnosticHint::TransferDisabled),
+    }
+}
+
+/// Mirrors the block overrides the RPC path sends in its [`Bundle`]
+fn override_block(params: &SimulationParams, block: &mut BlockEnv) {
+    if let Some(coinbase) = params.coinbase {
+        block.beneficiary = coinbase;
+    }
+    if let Some(timestamp) = params.timestamp {
+        block.timestamp = U256::from(timestamp);
+    }
+}
+
+/// Seconds between two Ethereum blocks, by which the next block follows the pinned one
+const BLOCK_TIME: u64 = 12;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BlockHeader {
+    timestamp: U64,
+    /// Absent before London
+    base_fee_per_gas: Option<U128>,
+}
+
+impl BlockHeader {
+    /// Timestamp the following block would have, for deadline-safe simulations
+    fn next_block_timestamp(&self) -> u64 {
+        self.timestamp.to::<u64>() + BLOCK_TIME
+    }
+}
+
+async fn fetch_block_header(
+    provider: &impl Provider,
+    block: BlockNumberOrTag,
+) -> Result<BlockHeader, SimulateError> {
+    let block: Option<BlockHeader> = provider
+        .client()
+        .request("eth_getBlockByNumber", (block, false))
+        .await?;
+
+    Ok(block.ok_or(RpcError::NullResp)?)
+}
+
+#[derive(Debug, Deserialize)]
+struct PendingBlock {
+    number: Option<U64>,
+}
+
+/// Number of the block a simulation on `block` reads: the latest, or the pending one,
+/// failing with [`SimulateError::PendingUnsupported`] when the node has none to serve.
+async fn fetch_block_number(
+    provider: &impl Provider,
+    block: SimulationBlock,
+) -> Result<u64, SimulateError> {
+    if block == SimulationBlock::Latest {
+        return Ok(provider.get_block_number().await?);
+    }
+
+    let pending: Option<PendingBlock> = match provider
+        .client()
+        .request("eth_getBlockByNumber", (BlockNumberOrTag::Pending, false))
+        .await
+    {
+        Ok(pending) => pending,
+        Err(RpcError::ErrorResp(payload)) => {
+            return Err(SimulateError::PendingUnsupported {
+                message: payload.message.to_string(),
+            });
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    match pending.and_then(|pending| pending.number) {
+        Some(number) => Ok(number.to()),
+        None => Err(SimulateError::PendingUnsupported {
+            message: "no pending block".to_string(),
+        }),
+    }
+}
+
+/// `approve(spender, MAX)` for ERC20, `setApprovalForAll(spender, true)` for ERC1155
+fn get_approve_max_calldata(token_standard: TokenStandard, spender: Address) -> Bytes {
+    let encoded = match token_standard {
+        TokenStandard::Erc20 => approveCall {
+            spender,
+            value: U256::MAX,
+        }
+        .abi_encode(),
+        TokenStandard::Erc1155 { .. } => setApprovalForAllCall {
+            operator: spender,
+            approved: true,
+        }
+        .abi_encode(),
+    };
+
+    encoded.into()
+}
+
+/// Calldata of the transaction granting `spender` its allowance under `approval`
+fn get_approve_calldata(params: &SimulationParams, approval: &Approval) -> Bytes {
+    match approval {
+        Approval::IncreaseAllowance => increaseAllowanceCall {
+            spender: params.spender(),
+            addedValue: params.amount_in,
+        }
+        .abi_encode()
+        .into(),
+        _ => get_approve_max_calldata(params.token_standard, params.spender()),
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SimulateViaRpcError {
+    #[error("eth_callMany call failed")]
+    EthCallMany(#[from] crate::eth_call_many::EthCallManyError),
+    #[error("approve transaction failed: {reason}")]
+    ApproveFailed {
+        reason: String,
+        /// Responses of the bundle the approval was sent in, the calls after it included
+        rpc_responses: Vec<DetailedTransactionResponse>,
+    },
+    #[error("no valid response from simulation")]
+    NoResponse,
+    #[error("token_out balanceOf failed: {0}")]
+    BalanceOfFailed(String),
+    #[error("allowance read failed: {0}")]
+    AllowanceFailed(String),
+    #[error("token_in recipient balanceOf failed: {0}")]
+    RecipientBalanceOfFailed(String),
+    #[error("vault conversion of amount_out failed: {0}")]
+    ConversionFailed(String),
+    #[error("eth_call fallback needs the allowance slot")]
+    AllowanceSlot(#[from] FindSlotError),
+    #[error("node failed to execute the call: {0}")]
+    NodeError(String),
+    /// The node answered, but its response failed the simulation, with
+    /// [`SimulationParams::capture_raw_response`]
+    #[error("node response rejected")]
+    ResponseRejected {
+        raw_rpc_response: Box<RawValue>,
+        #[source]
+        source: Box<SimulateViaRpcError>,
+    },
+}
+
+impl SimulateViaRpcError {
+    /// Response the node sent, when it was captured before failing the simulation
+    pub fn raw_rpc_response(&self) -> Option<&RawValue> {
+        match self {
+            SimulateViaRpcError::ResponseRejected {
+                raw_rpc_response, ..
+            } => Some(raw_rpc_response),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SimulateViaRevmError {
+    #[error("loading an account failed")]
+    LoadAccount(#[from] DBTransportError),
+    #[error("approving the spender failed")]
+    Approve(#[from] ApproveError),
+    #[error("executing the call failed")]
+    Transact(#[from] EVMError<DBTransportError>),
+    #[error("execution halted: {reason:?} (gas used: {gas_used})")]
+    Halt { reason: HaltReason, gas_used: u64 },
+    #[error("reading a token balance failed")]
+    ReadBalance(#[from] ReadBalanceError),
+    #[error("reading the allowance failed")]
+    ReadAllowance(#[source] ReadBalanceError),
+    #[error("converting amount_out through the vault failed")]
+    ConvertAmountOut(#[source] ReadBalanceError),
+}
+
+/// Failure of a view call returning a `uint256`: a balance, an allowance or a conversion
+#[derive(Debug, Error)]
+pub enum ReadBalanceError {
+    #[error("building the view call failed: {0:?}")]
+    TxBuild(TxEnvBuildError),
+    #[error("executing the view call failed")]
+    Transact(#[from] EVMError<DBTransportError>),
+    #[error("execution failed: {0:?}")]
+    Execution(ExecutionResult),
+    #[error("decoding the returned uint256 failed")]
+    Decoding(#[from] alloy::sol_types::Error),
+}
+
+impl From<TxEnvBuildError> for ReadBalanceError {
+    fn from(value: TxEnvBuildError) -> Self {
+        ReadBalanceError::TxBuild(value)
+    }
+}
+
+fn read_balance<ExtDB: DatabaseRef<Error = DBTransportError>>(
+    token: Address,
+    token_standard: TokenStandard,
+    user: Address,
+    cache_db: &mut CacheDB<ExtDB>,
+) -> Result<U256, ReadBalanceError> {
+    let tx_env = build_balance_of_tx_env(token, token_standard, user)?;
+
+    read_uint(tx_env, cache_db)
+}
+
+fn read_converted_amount<ExtDB: DatabaseRef<Error = DBTransportError>>(
+    token_out: Address,
+    conversion: VaultConversion,
+    amount: U256,
+    cache_db: &mut CacheDB<ExtDB>,
+) -> Result<U256, ReadBalanceError> {
+    let tx_env = TxEnv::builder()
+        .kind(TxKind::Call(conversion.vault(token_out)))
+        .data(conversion.calldata(amount))
+        .build()?;
+
+    read_uint(tx_env, cache_db)
+}
+
+/// `isApprovedForAll` of an ERC1155 token reads as 1 when approved.
+fn read_allowance<ExtDB: DatabaseRef<Error = DBTransportError>>(
+    params: &SimulationParams,
+    cache_db: &mut CacheDB<ExtDB>,
+) -> Result<U256, ReadBalanceError> {
+    let tx_env = build_allowance_tx_env(
+        params.token_in,
+        params.token_standard,
+        params.user,
+        params.spender(),
+    )?;
+
+    read_uint(tx_env, cache_db)
+}
+
+/// Runs a view call returning a single `uint256`, without committing it.
+fn read_uint<ExtDB: DatabaseRef<Error = DBTransportError>>(
+    tx_env: TxEnv,
+    cache_db: &mut CacheDB<ExtDB>,
+) -> Result<U256, ReadBalanceError> {
+    let mut evm = Context::mainnet()
+        .with_db(cache_db)
+        .modify_cfg_chained(|cfg| cfg.disable_nonce_check = true)
+        .build_mainnet();
+
+    match evm.transact_one(tx_env)? {
+        ExecutionResult::Success { output, .. } => Ok(decode_uint256(output.data())?),
+        failed => Err(ReadBalanceError::Execution(failed)),
+    }
+}
+
+fn simulate_via_revm<ExtDB: DatabaseRef<Error = DBTransportError>>(
+    params: &SimulationParams,
+    cache_db: &mut CacheDB<ExtDB>,
+    funded_slots: &[FundedSlot],
+    approval: &Approval,
+) -> Result<BackendOutput, SimulateViaRevmError> {
+    apply_code_overrides(cache_db, &params.code_overrides)?;
+    apply_storage_overrides(cache_db, &params.storage_overrides)?;
+    apply_authorizations(cache_db, &params.authorization_list)?;
+    if let Some(nonce) = params.nonce {
+        let mut info = cache_db.load_account(params.user)?.info.clone();
+        info.nonce = nonce;
+        cache_db.insert_account_info(params.user, info);
+    }
+    fund_slots(cache_db, funded_slots)?;
+
+    let approve_result = match approval {
+        Approval::Transaction | Approval::IncreaseAllowance => {
+            match approve(params, cache_db, approval) {
+                Ok(output) => Some(Ok(output)),
+                Err(ApproveError::Execution(reverted @ ExecutionResult::Revert { .. })) => {
+                    Some(into_simulation_result(reverted)?)
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Approval::SlotOverride(allowance_slot) => {
+            fund_slots(cache_db, &[(allowance_slot.clone(), U256::MAX)])?;
+            None
+        }
+        Approval::Existing => None,
+    };
+
+    let allowance = params
+        .report_allowance
+        .then(|| read_allowance(params, cache_db))
+        .transpose()
+        .map_err(SimulateViaRevmError::ReadAllowance)?;
+
+    let balance_before = params
+        .token_out
+        .map(|token_out| read_balance(token_out, TokenStandard::Erc20, params.user, cache_db))
+        .transpose()?;
+    let recipient_balance_before = params
+        .token_in_recipient
+        .map(|recipient| read_balance(params.token_in, params.token_standard, recipient, cache_db))
+        .transpose()?;
+
+    // Read up front, as the steps may commit over it, but only reported on failure. A
+    // failed read is left out rather than failing the simulation.
+    let pre_revert_state = PreRevertState {
+        approve_succeeded: *approval != Approval::Existing
+            && !matches!(approve_result, Some(Err(_))),
+        token_in_balance: read_balance(
+            params.token_in,
+            params.token_standard,
+            params.user,
+            cache_db,
+        )
+        .ok(),
+        allowance: match allowance {
+            Some(allowance) => Some(allowance),
+            None => read_allowance(params, cache_db).ok(),
+        },
+        token_out_balance: balance_before,
+    };
+
+    // The last step is only committed when its effect on balances must be read back
+    let commit_last = balance_before.is_some() || recipient_balance_before.is_some();
+    let steps_output = transact_steps(cache_db, params, commit_last)?;
+
+    let gas_used = steps_output.result.gas_used();
+    let gas_refunded = match &steps_output.result {
+        ExecutionResult::Success { gas_refunded, .. } => *gas_refunded,
+        _ => 0,
+    };
+    let failed_execution = FailedExecution::from_result(&steps_output.result);
+    let success_reason = match &steps_output.result {
+        ExecutionResult::Success { reason, .. } => Some(*reason),
+        _ => None,
+    };
+    let revert_data = match &steps_output.result {
+        ExecutionResult::Revert { output, .. } => Some(output.clone()),
+        _ => None,
+    };
+    let result = into_simulation_result(steps_output.result)?;
+    let pre_revert_state = result.is_err().then_some(pre_revert_state);
+
+    let (amount_out, token_out_loss) = match (params.token_out, balance_before) {
+        (Some(token_out), Some(balance_before)) => {
+            let balance_after =
+                read_balance(token_out, TokenStandard::Erc20, params.user, cache_db)?;
+            split_balance_delta(balance_before, balance_after)
+        }
+        _ => (None, None),
+    };
+
+    let amount_out_converted = match (params.token_out, params.amount_out_conversion, amount_out) {
+        (Some(token_out), Some(conversion), Some(amount_out)) => Some(
+            read_converted_amount(token_out, conversion, amount_out, cache_db)
+                .map_err(SimulateViaRevmError::ConvertAmountOut)?,
+        ),
+        _ => None,
+    };
+
+    let actual_amount_in = match (params.token_in_recipient, recipient_balance_before) {
+        (Some(recipient), Some(balance_before)) => {
+            let balance_after =
+                read_balance(params.token_in, params.token_standard, recipient, cache_db)?;
+            Some(balance_after.saturating_sub(balance_before))
+        }
+        _ => None,
+    };
+
+    Ok(BackendOutput {
+        result,
+        gas_used: Some(gas_used),
+        gas_refunded: Some(gas_refunded),
+        amount_out,
+        token_out_loss,
+        amount_out_converted,
+        rpc_responses: None,
+        raw_rpc_response: None,
+        gas_profile: steps_output.gas_profile,
+        allowance,
+        state_diff: steps_output.state_diff,
+        actual_amount_in,
+        rpc_backend: None,
+        failed_execution,
+        hops: steps_output.hops,
+        pre_revert_state,
+        approve_result,
+        success_reason,
+        revert_data,
+    })
+}
+
+/// Halts (out of gas, invalid opcode, ...) are reported as errors rather than reverts,
+/// since they usually point at the simulation setup instead of the called contract.
+/// A revert is reported by its decoded reason, as the RPC path reports it, and by its
+/// debug representation when it has none.
+/// Every [`SuccessReason`] is a success: a call ending in `STOP` or `SELFDESTRUCT`
+/// just returns no data.
+fn into_simulation_result(res: ExecutionResult) -> Result<CallResult, SimulateViaRevmError> {
+    match res {
+        ExecutionResult::Success { output, .. } => Ok(Ok(output.into_data())),
+        ExecutionResult::Halt { reason, gas_used } => {
+            Err(SimulateViaRevmError::Halt { reason, gas_used })
+        }
+        ExecutionResult::Revert { ref output, .. } => Ok(Err(
+            decode_revert_data(output).unwrap_or_else(|| format!("{:?}", res))
+        )),
+    }
+}
+
+fn build_tx_env<ExtDB: DatabaseRef<Error = DBTransportError>>(
+    cache_db: &mut CacheDB<ExtDB>,
+    from: Address,
+    to: Address,
+    calldata: Bytes,
+) -> Result<TxEnv, DBTransportError> {
+    let nonce = cache_db.load_account(from)?.info.nonce;
+
+    let tx_env = TxEnv::builder()
+        .kind(TxKind::Call(to))
+        .data(calldata)
+        .caller(from)
+        .nonce(nonce)
+        .build_fill();
+
+    Ok(tx_env)
+}
+
+/// A balance slot and the `balanceOf` it has to report
+type FundedSlot = (SlotWithAddress, U256);
+
+/// How the backends grant `spender` its allowance over `token_in`, resolved once per
+/// simulation from [`ApproveMode`] and [`SimulationParams::skip_approve_if_allowed`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Approval {
+    /// Send `approve(spender, MAX)` before the steps
+    Transaction,
+    /// REVM writes `U256::MAX` to this allowance slot, RPC still sends the transaction
+    SlotOverride(SlotWithAddress),
+    /// Send `increaseAllowance(spender, amount_in)` before the steps
+    IncreaseAllowance,
+    /// The on-chain allowance already covers `amount_in`, nothing is sent
+    Existing,
+}
+
+/// Whether the current `allowance(user, spender)` covers `amount_in`. A failed read
+/// counts as not covered, so that the approval is sent as usual.
+fn allowance_covers_amount<ExtDB: DatabaseRef<Error = DBTransportError>>(
+    params: &SimulationParams,
+    cache_db: &mut CacheDB<ExtDB>,
+) -> bool {
+    read_allowance(params, cache_db).is_ok_and(|allowance| match params.token_standard {
+        TokenStandard::Erc20 => allowance >= params.amount_in,
+        TokenStandard::Erc1155 { .. } => !allowance.is_zero(),
+    })
+}
+
+fn fund_slots<ExtDB: DatabaseRef>(
+    cache_db: &mut CacheDB<ExtDB>,
+    funded_slots: &[FundedSlot],
+) -> Result<(), ExtDB::Error> {
+    for (balance_slot, balance) in funded_slots {
+        cache_db.insert_account_storage(
+            balance_slot.address,
+            balance_slot.slot,
+            stored_balance(balance_slot, *balance),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Finds an amount whose stored value would overflow its slot, which only happens for
+/// rebasing tokens, where the amount is converted to shares, and for packed balances.
+fn overflowing_amount(funded_slots: &[FundedSlot]) -> Option<&FundedSlot> {
+    funded_slots.iter().find(|(balance_slot, balance)| {
+        balance_slot
+            .scale
+            .is_some_and(|scale| balance.checked_mul(scale.probe).is_none())
+            || balance_slot
+                .packing
+                .is_some_and(|packing| !packing.fits(*balance))
+    })
+}
+
+/// Value to store in the balance slot for `balanceOf` to return `balance`.
+/// Rounds up for rebasing tokens, so that `balanceOf` is not short after the index rounds down.
+/// Packed balances are spliced into the word the slot held, keeping the fields around them.
+fn stored_balance(balance_slot: &SlotWithAddress, balance: U256) -> U256 {
+    match (&balance_slot.scale, &balance_slot.packing) {
+        (Some(scale), _) => balance.saturating_mul(scale.probe).div_ceil(scale.balance),
+        (None, Some(packing)) => packing.splice(balance),
+        (None, None) => balance,
+    }
+}
+
+fn apply_code_overrides<ExtDB: DatabaseRef>(
+    cache_db: &mut CacheDB<ExtDB>,
+    code_overrides: &HashMap<Address, Bytes>,
+) -> Result<(), ExtDB::Error> {
+    for (address, code) in code_overrides {
+        let mut info = cache_db.load_account(*address)?.info.clone();
+        info.set_code(Bytecode::new_raw(code.clone()));
+        cache_db.insert_account_info(*address, info);
+    }
+
+    Ok(())
+}
+
+/// Delegates the code of each authorization's signer to its address, as EIP-7702 does
+/// before the transaction carrying them runs, or clears it for the zero address.
+/// Authorizations whose signer can't be recovered are skipped, as they would be on chain.
+fn apply_authorizations<ExtDB: DatabaseRef>(
+    cache_db: &mut CacheDB<ExtDB>,
+    authorization_list: &[SignedAuthorization],
+) -> Result<(), ExtDB::Error> {
+    for authorization in authorization_list {
+        let Ok(authority) = authorization.recover_authority() else {
+            continue;
+        };
+
+        let mut info = cache_db.load_account(authority)?.info.clone();
+        info.set_code(match authorization.address {
+            Address::ZERO => Bytecode::default(),
+            address => Bytecode::new_eip7702(address),
+        });
+        cache_db.insert_account_info(authority, info);
+    }
+
+    Ok(())
+}
+
+fn apply_storage_overrides<ExtDB: DatabaseRef>(
+    cache_db: &mut CacheDB<ExtDB>,
+    storage_overrides: &HashMap<Address, HashMap<U256, U256>>,
+) -> Result<(), ExtDB::Error> {
+    for (address, slots) in storage_overrides {
+        for (slot, value) in slots {
+            cache_db.insert_account_storage(*address, *slot, *value)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Storage written by a simulation: the final value of every changed slot, per account
+pub type StateDiff = HashMap<Address, HashMap<U256, U256>>;
+
+/// The designated result step of [`transact_steps`], and the storage all steps wrote
+struct StepsOutput {
+    result: ExecutionResult,
+    gas_profile: Option<GasProfile>,
+    state_diff: Option<StateDiff>,
+    hops: Option<Vec<HopResult>>,
+}
+
+/// Executes every step in order and returns the result of the designated result step,
+/// profiled when `gas_profile` is set and with its hops when `trace_hops` is, along with
+/// the storage writes when `state_diff` is.
+/// All steps but the last are committed so that later steps observe their effects.
+fn transact_steps<ExtDB: DatabaseRef>(
+    cache_db: &mut CacheDB<ExtDB>,
+    params: &SimulationParams,
+    commit_last: bool,
+) -> Result<StepsOutput, EVMError<ExtDB::Error>> {
+    let steps = params.steps();
+    let result_step = params.result_step();
+    let mut result = None;
+    let mut state_diff = params.state_diff.then(StateDiff::new);
+
+    for (index, step) in steps.iter().enumerate() {
+        let tx_env = build_call_tx_env(cache_db, params, step).map_err(EVMError::Database)?;
+
+        let (res, state, gas_profile) = if index == result_step && params.gas_profile {
+            let mut evm = Context::mainnet()
+                .with_db(&mut *cache_db)
+                .modify_cfg_chained(|cfg| params.revm_cfg.apply(cfg))
+                .modify_block_chained(|block| override_block(params, block))
+                .build_mainnet_with_inspector(GasProfileInspector::default());
+
+            let ExecResultAndState { result, state } = evm.inspect_tx(tx_env)?;
+            (result, state, Some(evm.inspector.profile()))
+        } else {
+            let mut evm = Context::mainnet()
+                .with_db(&mut *cache_db)
+                .modify_cfg_chained(|cfg| params.revm_cfg.apply(cfg))
+                .modify_block_chained(|block| override_block(params, block))
+                .build_mainnet();
+
+            let ExecResultAndState { result, state } = evm.transact(tx_env)?;
+            (result, state, None)
+        };
+
+        if let Some(state_diff) = &mut state_diff {
+            record_storage_writes(state_diff, &state);
+        }
+
+        if index + 1 < steps.len() || commit_last {
+            cache_db.commit(state);
+        }
+
+        if index == result_step {
+            let hops = params
+                .trace_hops
+                .then(|| hops_from_logs(res.logs(), params.user, step.to));
+            result = Some((res, gas_profile, hops));
+        }
+    }
+
+    let (result, gas_profile, hops) = result.expect("result step is clamped to the step count");
+
+    Ok(StepsOutput {
+        result,
+        gas_profile,
+        state_diff,
+        hops,
+    })
+}
+
+/// Merges the slots whose value changed during a transaction, later writes winning.
+fn record_storage_writes(state_diff: &mut StateDiff, state: &EvmState) {
+    for (address, account) in state {
+        let written = account
+            .changed_storage_slots()
+            .map(|(slot, value)| (*slot, value.present_value));
+
+        state_diff.entry(*address).or_default().extend(written);
+    }
+
+    state_diff.retain(|_, slots| !slots.is_empty());
+}
+
+/// Mirrors the fields the RPC path sends in its call [`Transaction`].
+fn build_call_tx_env<ExtDB: DatabaseRef>(
+    cache_db: &mut CacheDB<ExtDB>,
+    params: &SimulationParams,
+    step: &CallStep,
+) -> Result<TxEnv, ExtDB::Error> {
+    let nonce = cache_db.load_account(params.user)?.info.nonce;
+
+    let mut builder = TxEnv::builder()
+        .kind(TxKind::Call(step.to))
+        .data(step.calldata.clone())
+        .caller(params.user)
+        .nonce(nonce)
+        .value(step.value);
+
+    if let Some(gas_limit) = step.gas_limit.or(params.gas_limit) {
+        builder = builder.gas_limit(gas_limit);
+    }
+
+    if let Some(gas_price) = params.effective_gas_price() {
+        builder = builder.gas_price(gas_price);
+    }
+
+    Ok(builder.build_fill())
+}
+
+fn build_balance_of_tx(
+    token: Address,
+    token_standard: TokenStandard,
+    user: Address,
+) -> Transaction {
+    Transaction {
+        to: Some(token),
+        data: Some(token_standard.balance_of_calldata(user)),
+        ..Default::default()
+    }
+}
+
+fn build_conversion_tx(
+    token_out: Address,
+    conversion: VaultConversion,
+    amount: U256,
+) -> Transaction {
+    Transaction {
+        to: Some(conversion.vault(token_out)),
+        data: Some(conversion.calldata(amount)),
+        ..Default::default()
+    }
+}
+
+fn build_allowance_tx(params: &SimulationParams) -> Transaction {
+    Transaction {
+        to: Some(params.token_in),
+        data: Some(
+            params
+                .token_standard
+                .allowance_calldata(params.user, params.spender()),
+        ),
+        ..Default::default()
+    }
+}
+
+/// Builds `[approve, call]`, wrapped in `token_out` balance reads when `token_out` is set,
+/// with an allowance read after the approval when `report_allowance` is set. The approve
+/// transaction is left out for [`Approval::Existing`].
+fn build_approve_and_call_bundle(params: &SimulationParams, approval: &Approval) -> Bundle {
+    let approve = *approval != Approval::Existing;
+    let approve_calldata = get_approve_calldata(params, approval);
+
+    let approve_tx = Transaction {
+        from: Some(params.user),
+        to: Some(params.token_in),
+        data: Some(approve_calldata),
+        nonce: params.nonce,
+        ..Default::default()
+    };
+
+    // Reads are sent from no one, so only the approval and the steps take a nonce. An
+    // override without room for them is rejected up front by `check_nonce`.
+    let first_call_nonce = params
+        .nonce
+        .and_then(|nonce| nonce.checked_add(u64::from(approve)));
+    let call_txs = params.steps().into_iter().zip(0..).map(|(step, index)| {
+        let nonce = first_call_nonce.and_then(|nonce| nonce.checked_add(index));
+        build_step_tx(params, step, nonce)
+    });
+
+    let mut transactions = Vec::new();
+    if let Some(token_out) = params.token_out {
+        transactions.push(build_balance_of_tx(
+            token_out,
+            TokenStandard::Erc20,
+            params.user,
+        ));
+    }
+    if let Some(recipient) = params.token_in_recipient {
+        transactions.push(build_balance_of_tx(
+            params.token_in,
+            params.token_standard,
+            recipient,
+        ));
+    }
+    if approve {
+        transactions.push(approve_tx);
+    }
+    if params.report_allowance {
+        transactions.push(build_allowance_tx(params));
+    }
+    transactions.extend(call_txs);
+    if let Some(token_out) = params.token_out {
+        transactions.push(build_balance_of_tx(
+            token_out,
+            TokenStandard::Erc20,
+            params.user,
+        ));
+    }
+    if let Some(recipient) = params.token_in_recipient {
+        transactions.push(build_balance_of_tx(
+            params.token_in,
+            params.token_standard,
+            recipient,
+        ));
+    }
+
+    // Delegations take effect from the first transaction of `user` on
+    if !params.authorization_list.is_empty()
+        && let Some(first) = transactions
+            .iter_mut()
+            .find(|transaction| transaction.from == Some(params.user))
+    {
+        first.authorization_list = Some(params.authorization_list.clone());
+    }
+
+    Bundle {
+        transactions,
+        block_override: build_block_override(params.coinbase, params.timestamp),
+    }
+}
+
+/// A step of the call sequence, sent by `user`
+fn build_step_tx(params: &SimulationParams, step: CallStep, nonce: Option<u64>) -> Transaction {
+    Transaction {
+        from: Some(params.user),
+        to: Some(step.to),
+        gas: step.gas_limit.or(params.gas_limit).map(U256::from),
+        gas_price: params.gas_price.map(U256::from),
+        value: (!step.value.is_zero()).then_some(step.value),
+        data: Some(step.calldata),
+        nonce,
+        authorization_list: None,
+    }
+}
+
+fn build_block_override(
+    coinbase: Option<Address>,
+    timestamp: Option<u64>,
+) -> Option<BlockOverride> {
+    (coinbase.is_some() || timestamp.is_some()).then(|| BlockOverride {
+        coinbase,
+        timestamp,
+        ..Default::default()
+    })
+}
+
+/// Builds one [`Bundle`] per [`CallBundle`], the approval opening the first one, with the
+/// nonces of `user` following on from one bundle to the next.
+fn build_sequential_bundles(
+    params: &SimulationParams,
+    approval: &Approval,
+    bundles: &[CallBundle],
+) -> Vec<Bundle> {
+    // An override without room for every transaction is rejected up front by `check_nonce`
+    let mut nonce = params.nonce;
+    let mut next_nonce = || {
+        let current = nonce;
+        nonce = nonce.and_then(|nonce| nonce.checked_add(1));
+        current
+    };
+
+    let mut request: Vec<_> = bundles
+        .iter()
+        .map(|bundle| Bundle {
+            transactions: Vec::new(),
+            block_override: build_block_override(
+                bundle.coinbase.or(params.coinbase),
+                bundle.timestamp.or(params.timestamp),
+            ),
+        })
+        .collect();
+
+    if let Some(first) = request.first_mut()
+        && *approval != Approval::Existing
+    {
+        first.transactions.push(Transaction {
+            from: Some(params.user),
+            to: Some(params.token_in),
+            data: Some(get_approve_calldata(params, approval)),
+            nonce: next_nonce(),
+            ..Default::default()
+        });
+    }
+    for (bundle, call_bundle) in request.iter_mut().zip(bundles) {
+        for step in &call_bundle.steps {
+            let nonce = next_nonce();
+            bundle
+                .transactions
+                .push(build_step_tx(params, step.clone(), nonce));
+        }
+    }
+
+    // Delegations take effect from the first transaction of `user` on
+    if !params.authorization_list.is_empty()
+        && let Some(first) = request
+            .iter_mut()
+            .flat_map(|bundle| bundle.transactions.iter_mut())
+            .next()
+    {
+        first.authorization_list = Some(params.authorization_list.clone());
+    }
+
+    request
+}
+
+/// Reads back the responses of the bundles built by [`build_sequential_bundles`]
+fn parse_sequential_bundles(
+    approve: bool,
+    responses: Vec<Vec<DetailedTransactionResponse>>,
+) -> Result<Vec<BundleOutput>, SimulateViaRpcError> {
+    let mut bundles = Vec::with_capacity(responses.len());
+
+    for (index, responses) in responses.into_iter().enumerate() {
+        if approve && index == 0 {
+            match responses.first().map(|tx| &tx.response) {
+                Some(TransactionResponse::Success { .. }) => {}
+                Some(failed) => {
+                    return Err(SimulateViaRpcError::ApproveFailed {
+                        reason: failed.revert_reason().unwrap_or_default(),
+                        rpc_responses: responses,
+                    });
+                }
+                None => return Err(SimulateViaRpcError::NoResponse),
+            }
+        }
+        let responses = responses
+            .into_iter()
+            .skip(usize::from(approve && index == 0));
+
+        let mut bundle = BundleOutput {
+            results: Vec::new(),
+            gas_used: Vec::new(),
+        };
+        for tx in responses {
+            bundle.results.push(match tx.response {
+                TransactionResponse::Success { value } => Ok(value),
+                TransactionResponse::NodeError { message } => {
+                    return Err(SimulateViaRpcError::NodeError(message));
+                }
+                reverted => Err(reverted.revert_reason().unwrap_or_default()),
+            });
+            bundle
+                .gas_used
+                .push(tx.gas_used.map(|gas_used| gas_used.saturating_to()));
+        }
+        bundles.push(bundle);
+    }
+
+    Ok(bundles)
+}
+
+fn build_state_overrides(
+    params: &SimulationParams,
+    funded_slots: &[FundedSlot],
+) -> HashMap<Address, StateOverride> {
+    let mut state_overrides: HashMap<Address, StateOverride> = HashMap::new();
+
+    for (address, slots) in &params.storage_overrides {
+        state_overrides
+            .entry(*address)
+            .or_default()
+            .state_diff
+            .get_or_insert_default()
+            .extend(
+                slots
+                    .iter()
+                    .map(|(slot, value)| (B256::from(*slot), B256::from(*value))),
+            );
+    }
+
+    for (balance_slot, balance) in funded_slots {
+        state_overrides
+            .entry(balance_slot.address)
+            .or_default()
+            .state_diff
+            .get_or_insert_default()
+            .insert(
+                balance_slot.slot.into(),
+                stored_balance(balance_slot, *balance).into(),
+            );
+    }
+
+    for (address, code) in &params.code_overrides {
+        state_overrides.entry(*address).or_default().code = Some(code.clone());
+    }
+
+    if let Some(nonce) = params.nonce {
+        state_overrides.entry(params.user).or_default().nonce = Some(nonce);
+    }
+
+    state_overrides
+}
+
+async fn simulate_via_rpc(
+    params: &SimulationParams,
+    provider: &impl Provider,
+    funded_slots: &[FundedSlot],
+    approval: &Approval,
+    block_number: u64,
+    timeout_ms: u64,
+) -> Result<BackendOutput, SimulateViaRpcError> {
+    let eth_call_many = EthCallMany::new(provider.client());
+
+    let state_overrides = build_state_overrides(params, funded_slots);
+
+    let approve = *approval != Approval::Existing;
+    let bundle = build_approve_and_call_bundle(params, approval);
+
+    let simulation_context = build_simulation_context(params, block_number);
+
+    let raw = eth_call_many
+        .call_many_raw(
+            vec![bundle.clone()],
+            simulation_context.clone(),
+            Some(state_overrides.clone()),
+            Some(timeout_ms),
+        )
+        .await?;
+
+    let with_raw = |error: SimulateViaRpcError| match params.capture_raw_response {
+        true => SimulateViaRpcError::ResponseRejected {
+            raw_rpc_response: raw.clone(),
+            source: Box::new(error),
+        },
+        false => error,
+    };
+    let tx_responses = parse_call_many(&raw)
+        .map_err(|e| with_raw(e.into()))?
+        .into_iter()
+        .next()
+        .ok_or_else(|| with_raw(SimulateViaRpcError::NoResponse))?;
+
+    let mut output = parse_bundle_responses(params, approve, &tx_responses).map_err(with_raw)?;
+    output.rpc_responses = Some(tx_responses);
+    output.raw_rpc_response = params.capture_raw_response.then_some(raw);
+    output.rpc_backend = Some(RpcBackend::CallMany);
+
+    // The amount to convert is only known once the bundle ran, so it runs again with the
+    // conversion appended
+    if let (Some(token_out), Some(conversion), Some(amount_out)) = (
+        params.token_out,
+        params.amount_out_conversion,
+        output.amount_out,
+    ) {
+        let mut bundle = bundle;
+        bundle
+            .transactions
+            .push(build_conversion_tx(token_out, conversion, amount_out));
+
+        let result = eth_call_many
+            .call_many_detailed(
+                vec![bundle],
+                simulation_context,
+                Some(state_overrides),
+                Some(timeout_ms),
+            )
+            .await?;
+        let tx_responses = result
+            .into_iter()
+            .next()
+            .ok_or(SimulateViaRpcError::NoResponse)?;
+
+        output.amount_out_converted = Some(next_uint(
+            &mut tx_responses.iter().rev(),
+            SimulateViaRpcError::ConversionFailed,
+        )?);
+    }
+
+    Ok(output)
+}
+
+fn build_simulation_context(params: &SimulationParams, block_number: u64) -> SimulationContext {
+    SimulationContext {
+        block_number: params.block.at(block_number).into(),
+        transaction_index: params.transaction_index,
+        require_canonical: false,
+    }
+}
+
+/// Whether [`simulate_via_eth_call`] can reproduce the bundle: a lone call at the top of
+/// the block, without any balance or allowance read around it.
+fn supports_eth_call_fallback(params: &SimulationParams) -> bool {
+    params.transaction_index.is_none()
+        && params.coinbase.is_none()
+        && params.timestamp.is_none()
+        && params.steps().len() == 1
+        && params.token_out.is_none()
+        && params.token_in_recipient.is_none()
+        && !params.report_allowance
+        && params.authorization_list.is_empty()
+}
+
+/// Simulates with `eth_call` on nodes lacking `eth_callMany`. Separate calls share no
+/// state, so the approve call only checks that the approval goes through, while the
+/// allowance it grants is written to the allowance slot through the shared overrides.
+async fn simulate_via_eth_call<ExtDB: DatabaseRef<Error = DBTransportError>>(
+    params: &SimulationParams,
+    provider: &impl Provider,
+    cache_db: &mut CacheDB<ExtDB>,
+    funded_slots: &[FundedSlot],
+    approval: &Approval,
+    block_number: u64,
+) -> Result<BackendOutput, SimulateViaRpcError> {
+    let eth_call_many = EthCallMany::new(provider.client());
+    let block_id = BlockId::from(params.block.at(block_number));
+
+    let allowance_slot = match approval {
+        Approval::Transaction | Approval::IncreaseAllowance => Some(find_allowance_slot(
+            params.token_in,
+            params.user,
+            params.spender(),
+            cache_db,
+        )?),
+        Approval::SlotOverride(allowance_slot) => Some(allowance_slot.clone()),
+        Approval::Existing => None,
+    };
+    let approve = allowance_slot.is_some();
+    // An allowance of MAX would make `increaseAllowance` overflow
+    let allowance_override = match approval {
+        Approval::IncreaseAllowance => params.amount_in,
+        _ => U256::MAX,
+    };
+
+    let mut shared_slots = funded_slots.to_vec();
+    shared_slots.extend(allowance_slot.map(|allowance_slot| (allowance_slot, allowance_override)));
+    let state_overrides = build_state_overrides(params, &shared_slots);
+
+    let Bundle { transactions, .. } = build_approve_and_call_bundle(params, approval);
+
+    let mut tx_responses = Vec::with_capacity(transactions.len());
+    for transaction in transactions {
+        // No call is committed, so each one runs at the overridden nonce
+        let transaction = Transaction {
+            nonce: transaction.nonce.and(params.nonce),
+            ..transaction
+        };
+        let response = eth_call_many
+            .call(&transaction, block_id, Some(state_overrides.clone()))
+            .await?;
+        tx_responses.push(DetailedTransactionResponse {
+            response,
+            gas_used: None,
+            gas_refunded: None,
+        });
+    }
+
+    let mut output = parse_bundle_responses(params, approve, &tx_responses)?;
+    output.rpc_responses = Some(tx_responses);
+    output.rpc_backend = Some(RpcBackend::EthCallFallback);
+
+    Ok(output)
+}
+
+/// Reads back the responses of a bundle built by [`build_approve_and_call_bundle`].
+fn parse_bundle_responses(
+    params: &SimulationParams,
+    approve: bool,
+    bundle_responses: &[DetailedTransactionResponse],
+) -> Result<BackendOutput, SimulateViaRpcError> {
+    let mut tx_responses = bundle_responses.iter();
+
+    let balance_before = match params.token_out {
+        Some(_) => Some(next_uint(
+            &mut tx_responses,
+            SimulateViaRpcError::BalanceOfFailed,
+        )?),
+        None => None,
+    };
+    let recipient_balance_before = match params.token_in_recipient {
+        Some(_) => Some(next_uint(
+            &mut tx_responses,
+            SimulateViaRpcError::RecipientBalanceOfFailed,
+        )?),
+        None => None,
+    };
+
+    let approve_result = if approve {
+        match tx_responses.next().map(|tx| &tx.response) {
+            Some(TransactionResponse::Success { value }) => Some(Ok(value.clone())),
+            Some(TransactionResponse::NodeError { message }) => {
+                return Err(SimulateViaRpcError::NodeError(message.clone()));
+            }
+            Some(reverted) => Some(Err(reverted.revert_reason().unwrap_or_default())),
+            None => return Err(SimulateViaRpcError::NoResponse),
+        }
+    } else {
+        None
+    };
+
+    let allowance = if params.report_allowance {
+        Some(next_uint(
+            &mut tx_responses,
+            SimulateViaRpcError::AllowanceFailed,
+        )?)
+    } else {
+        None
+    };
+
+    let step_responses: Vec<_> = tx_responses.by_ref().take(params.steps().len()).collect();
+    // Unlike a revert, says nothing about the call, so REVM gets to simulate it
+    if let Some(TransactionResponse::NodeError { message }) = step_responses
+        .iter()
+        .map(|tx| &tx.response)
+        .find(|response| matches!(response, TransactionResponse::NodeError { .. }))
+    {
+        return Err(SimulateViaRpcError::NodeError(message.clone()));
+    }
+    let call = step_responses
+        .get(params.result_step())
+        .ok_or(SimulateViaRpcError::NoResponse)?;
+    let result = match &call.response {
+        TransactionResponse::Success { value } => Ok(value.clone()),
+        reverted => Err(reverted.revert_reason().unwrap_or_default()),
+    };
+    let pre_revert_state = result.is_err().then_some(PreRevertState {
+        approve_succeeded: matches!(approve_result, Some(Ok(_))),
+        token_in_balance: None,
+        allowance,
+        token_out_balance: balance_before,
+    });
+
+    let (amount_out, token_out_loss) = match balance_before {
+        Some(balance_before) => {
+            let balance_after = next_uint(&mut tx_responses, SimulateViaRpcError::BalanceOfFailed)?;
+            split_balance_delta(balance_before, balance_after)
+        }
+        None => (None, None),
+    };
+
+    let actual_amount_in = match recipient_balance_before {
+        Some(balance_before) => {
+            let balance_after = next_uint(
+                &mut tx_responses,
+                SimulateViaRpcError::RecipientBalanceOfFailed,
+            )?;
+            Some(balance_after.saturating_sub(balance_before))
+        }
+        None => None,
+    };
+
+    Ok(BackendOutput {
+        result,
+        gas_used: call.gas_used.map(|gas_used| gas_used.saturating_to()),
+        gas_refunded: call
+            .gas_refunded
+            .map(|gas_refunded| gas_refunded.saturating_to()),
+        amount_out,
+        token_out_loss,
+        amount_out_converted: None,
+        rpc_responses: None,
+        raw_rpc_response: None,
+        gas_profile: None,
+        allowance,
+        state_diff: None,
+        actual_amount_in,
+        rpc_backend: None,
+        failed_execution: None,
+        hops: None,
+        pre_revert_state,
+        approve_result,
+        success_reason: None,
+        revert_data: call.response.revert_data(),
+    })
+}
+
+/// `(amount_out, token_out_loss)` of a balance going from `before` to `after`, the gain
+/// being zero and the loss set when it fell
+fn split_balance_delta(before: U256, after: U256) -> (Option<U256>, Option<U256>) {
+    match after.checked_sub(before) {
+        Some(gain) => (Some(gain), None),
+        None => (Some(U256::ZERO), Some(before - after)),
+    }
+}
+
+/// Decodes the next response as a `uint256`, reporting failures through `failed`.
+fn next_uint<'a>(
+    tx_responses: &mut impl Iterator<Item = &'a DetailedTransactionResponse>,
+    failed: fn(String) -> SimulateViaRpcError,
+) -> Result<U256, SimulateViaRpcError> {
+    match tx_responses.next().map(|tx| &tx.response) {
+        Some(TransactionResponse::Success { value }) => {
+            decode_uint256(value).map_err(|e| failed(e.to_string()))
+        }
+        Some(response) => Err(failed(response.revert_reason().unwrap_or_default())),
+        None => Err(SimulateViaRpcError::NoResponse),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            Mutex,
+            atomic::{AtomicUsize, Ordering},
+        },
+        time::Duration,
+    };
+
+    use alloy::{
+        consensus::{SignableTransaction, TxEip1559},
+        eips::{eip2718::Encodable2718, eip7702::Authorization},
+        signers::{SignerSync, local::PrivateKeySigner},
+        sol_types::{Revert, SolValue},
+    };
+    use alloy_json_rpc::ErrorPayload;
+    use revm::{
+        bytecode::Bytecode,
+        context::result::OutOfGasError,
+        database::EmptyDB,
+        primitives::{B256, address, bytes, keccak256},
+        state::AccountInfo,
+    };
+    use serde_json::{Value, json};
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::{
+        abi::IERC20::{allowanceCall, balanceOfCall, totalSupplyCall, transferCall},
+        balance_slot::{
+            BalanceScale, FindSlotByMutationError, MappingKind, derive_user_slot, find_balance_slot,
+        },
+        gas_profile::StorageAccess,
+        mock_rpc::{NO_ANSWER, spawn_mock_rpc},
+        offline_db::{ERC20, OWNER_OF, OfflineDb, PACKED_BALANCE_OF, TOKEN, USER, offline_db},
+    };
+
+    /// Accepts connections and never answers, so every RPC request hangs.
+    async fn spawn_unresponsive_rpc() -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let mut connections = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                connections.push(socket);
+            }
+        });
+
+        format!("http://{addr}").parse().unwrap()
+    }
+
+    fn usdc_params() -> SimulationParams {
+        SimulationParams {
+            user: address!("0x282Cd0c363CCf32629BE74A0A2B1a0Ed6680aE8e"),
+            token_in: address!("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48"),
+            amount_in: U256::from(1_000_000u64),
+            to: address!("0x0000000000000000000000000000000000000001"),
+            calldata: Bytes::new(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_simulate_cancelled_mid_flight() {
+        let rpc_url = spawn_unresponsive_rpc().await;
+        let mut simulator = Simulator::new();
+        let cancellation = CancellationToken::new();
+
+        let canceller = cancellation.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            canceller.cancel();
+        });
+
+        let result = tokio::time::timeout(
+            Duration::from_secs(5),
+            simulator.simulate(1, rpc_url, usdc_params(), &cancellation),
+        )
+        .await
+        .expect("simulate did not observe cancellation");
+
+        assert!(matches!(result, Err(SimulateError::Cancelled)));
+    }
+
+    fn execute_code(code: Bytes, gas_limit: u64) -> ExecutionResult {
+        let contract = address!("0x1000000000000000000000000000000000000001");
+        let caller = address!("0x282Cd0c363CCf32629BE74A0A2B1a0Ed6680aE8e");
+
+        let mut db = CacheDB::new(EmptyDB::default());
+        db.insert_account_info(
+            contract,
+            AccountInfo::default().with_code(Bytecode::new_raw(code)),
+        );
+
+        let tx_env = TxEnv::builder()
+            .kind(TxKind::Call(contract))
+            .caller(caller)
+            .gas_limit(gas_limit)
+            .build_fill();
+
+        let mut evm = Context::mainnet().with_db(&mut db).build_mainnet();
+
+        evm.transact_one(tx_env).unwrap()
+    }
+
+    /// Runs an empty call from an unfunded caller, on a block with a 10 gwei base fee
+    /// and a 1M gas limit.
+    fn transact_priced_call(revm_cfg: RevmCfg, gas_limit: u64, gas_price: u128) -> bool {
+        let mut db = CacheDB::new(EmptyDB::default());
+
+        let tx_env = TxEnv::builder()
+            .kind(TxKind::Call(TOKEN))
+            .caller(USER)
+            .gas_limit(gas_limit)
+            .gas_price(gas_price)
+            .build_fill();
+
+        let mut evm = Context::mainnet()
+            .with_db(&mut db)
+            .modify_block_chained(|block| {
+                block.basefee = 10_000_000_000;
+                block.gas_limit = 1_000_000;
+            })
+            .modify_cfg_chained(|cfg| revm_cfg.apply(cfg))
+            .build_mainnet();
+
+        evm.transact_one(tx_env).is_ok()
+    }
+
+    #[test]
+    fn test_revm_cfg_base_fee() {
+        let strict = RevmCfg {
+            disable_base_fee: false,
+            ..Default::default()
+        };
+
+        assert!(transact_priced_call(
+            RevmCfg::default(),
+            100_000,
+            1_000_000_000
+        ));
+        assert!(!transact_priced_call(strict, 100_000, 1_000_000_000));
+    }
+
+    #[test]
+    fn test_revm_cfg_block_gas_limit() {
+        let strict = RevmCfg {
+            disable_block_gas_limit: false,
+            ..Default::default()
+        };
+
+        assert!(transact_priced_call(
+            RevmCfg::default(),
+            2_000_000,
+            10_000_000_000
+        ));
+        assert!(!transact_priced_call(strict, 2_000_000, 10_000_000_000));
+    }
+
+    #[test]
+    fn test_revm_cfg_balance_check() {
+        let strict = RevmCfg {
+            disable_balance_check: false,
+            ..Default::default()
+        };
+
+        assert!(transact_priced_call(
+            RevmCfg::default(),
+            100_000,
+            10_000_000_000
+        ));
+        assert!(!transact_priced_call(strict, 100_000, 10_000_000_000));
+    }
+
+    #[test]
+    fn test_out_of_gas_is_halt() {
+        // JUMPDEST PUSH0 JUMP: loops until gas runs out
+        let res = execute_code(bytes!("5b5f56"), 50_000);
+
+        match into_simulation_result(res) {
+            Err(SimulateViaRevmError::Halt { reason, gas_used }) => {
+                assert_eq!(reason, HaltReason::OutOfGas(OutOfGasError::Basic));
+                assert_eq!(gas_used, 50_000);
+            }
+            other => panic!("expected halt, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_failed_execution_kinds() {
+        let halt = FailedExecution::from_result(&execute_code(bytes!("5b5f56"), 50_000)).unwrap();
+        assert_eq!(halt.kind, FailedExecutionKind::Halt);
+        assert_eq!(halt.gas_used, 50_000);
+
+        // STOP
+        assert!(FailedExecution::from_result(&execute_code(bytes!("00"), 50_000)).is_none());
+
+        // PUSH0 PUSH0 RETURN
+        assert!(FailedExecution::from_result(&execute_code(bytes!("5f5ff3"), 50_000)).is_none());
+    }
+
+    #[test]
+    fn test_stop_is_successful_simulation() {
+        // A fallback ending in STOP: PUSH1 1 PUSH0 SSTORE STOP
+        let target = address!("0x5000000000000000000000000000000000000005");
+        let mut db = offline_db(&[(TOKEN, ERC20), (target, bytes!("60015f5500"))]);
+        let params = SimulationParams {
+            user: USER,
+            token_in: TOKEN,
+            to: target,
+            calldata: bytes!("deadbeef"),
+            ..Default::default()
+        };
+
+        let output = simulate_via_revm(&params, &mut db, &[], &Approval::Transaction).unwrap();
+
+        assert_eq!(output.result.unwrap(), Bytes::new());
+        assert_eq!(output.success_reason, Some(SuccessReason::Stop));
+        assert_eq!(output.failed_execution, None);
+        assert_eq!(output.pre_revert_state, None);
+    }
+
+    #[test]
+    fn test_revert_is_failed_simulation() {
+        // PUSH0 PUSH0 REVERT
+        let res = execute_code(bytes!("5f5ffd"), 50_000);
+
+        assert!(matches!(into_simulation_result(res), Ok(Err(_))));
+    }
+
+    fn base_rpc(method: &str, _: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "eth_chainId" => Ok(json!("0x2105")),
+            "eth_blockNumber" => Ok(json!("0x10")),
+            _ => Err((-32601, "method not found".to_string())),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_rpc() {
+        let rpc_url = spawn_mock_rpc(base_rpc).await;
+        let mut simulator = Simulator::new();
+
+        let chain_info = simulator
+            .check_rpc(8453, rpc_url, &RpcHeaders::new())
+            .await
+            .unwrap();
+
+        assert_eq!(chain_info.chain_id, 8453);
+        assert_eq!(chain_info.block_number, 16);
+    }
+
+    #[tokio::test]
+    async fn test_check_rpc_chain_id_mismatch() {
+        let rpc_url = spawn_mock_rpc(base_rpc).await;
+        let mut simulator = Simulator::new();
+
+        let result = simulator.check_rpc(1, rpc_url, &RpcHeaders::new()).await;
+
+        assert!(matches!(
+            result,
+            Err(SimulateError::ChainIdMismatch {
+                expected: 1,
+                actual: 8453
+            })
+        ));
+    }
+
+    fn approved_spender(tx: &Transaction) -> Address {
+        approveCall::abi_decode(tx.data.as_ref().unwrap())
+            .unwrap()
+            .spender
+    }
+
+    #[test]
+    fn test_approve_defaults_to_call_target() {
+        let params = usdc_params();
+
+        let bundle = build_approve_and_call_bundle(&params, &Approval::Transaction);
+
+        assert_eq!(bundle.transactions[0].to, Some(params.token_in));
+        assert_eq!(approved_spender(&bundle.transactions[0]), params.to);
+    }
+
+    #[test]
+    fn test_approve_distinct_spender() {
+        // Router at `to` pulls tokens through a separate transfer proxy
+        let transfer_proxy = address!("0x000000000022D473030F116dDEE9F6B43aC78BA3");
+        let params = SimulationParams {
+            spender: Some(transfer_proxy),
+            ..usdc_params()
+        };
+
+        let bundle = build_approve_and_call_bundle(&params, &Approval::Transaction);
+
+        assert_eq!(approved_spender(&bundle.transactions[0]), transfer_proxy);
+        assert_eq!(bundle.transactions[1].to, Some(params.to));
+    }
+
+    #[tokio::test]
+    async fn test_simulate_rejects_chain_id_mismatch() {
+        let rpc_url = spawn_mock_rpc(base_rpc).await;
+        let mut simulator = Simulator::new();
+
+        let result = simulator
+            .simulate(1, rpc_url, usdc_params(), &CancellationToken::new())
+            .await;
+
+        assert!(matches!(
+            result,
+            Err(SimulateError::ChainIdMismatch {
+                expected: 1,
+                actual: 8453
+            })
+        ));
+        assert!(!simulator.db_caches.contains_key(&1));
+    }
+
+    fn transfer_log(token: Address, from: Address, to: Address, value: u64) -> Log {
+        let transfer = Transfer {
+            from,
+            to,
+            value: U256::from(value),
+        };
+
+        Log {
+            address: token,
+            data: transfer.encode_log_data(),
+        }
+    }
+
+    #[test]
+    fn test_hops_of_two_hop_routes() {
+        let usdc = address!("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48");
+        let weth = address!("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
+        let dai = address!("0x6B175474E89094C44Da98b954EedeAC495271d0F");
+        let router = address!("0x7a250d5630B4cF539739dF2C5dAcb4c659F2488D");
+        let pool_1 = address!("0xB4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc");
+        let pool_2 = address!("0xA478c2975Ab1Ea89e8196811F51A7B7Ade33eB11");
+        let expected = [
+            HopResult {
+                token: weth,
+                amount: U256::from(500),
+            },
+            HopResult {
+                token: dai,
+                amount: U256::from(250),
+            },
+        ];
+
+        // Uniswap V2: pools pay the next pool directly
+        let v2_logs = [
+            transfer_log(usdc, USER, pool_1, 1_000),
+            transfer_log(weth, pool_1, pool_2, 500),
+            transfer_log(dai, pool_2, USER, 250),
+        ];
+        assert_eq!(hops_from_logs(&v2_logs, USER, router), expected);
+
+        // Uniswap V3: intermediate tokens go through the router, which pays each pool
+        // from its swap callback
+        let v3_logs = [
+            transfer_log(weth, pool_1, router, 500),
+            transfer_log(usdc, USER, pool_1, 1_000),
+            transfer_log(dai, pool_2, USER, 250),
+            transfer_log(weth, router, pool_2, 500),
+        ];
+        assert_eq!(hops_from_logs(&v3_logs, USER, router), expected);
+    }
+
+    #[test]
+    fn test_clear_cache() {
+        let mut simulator = Simulator::new();
+        let slot = SlotWithAddress {
+            address: TOKEN,
+            slot: U256::ZERO,
+            scale: None,
+            packing: None,
+        };
+        let layout = BalanceLayout {
+            address: TOKEN,
+            base_slot: U256::from(3),
+            kind: MappingKind::Solidity,
+            scale: None,
+            packing: None,
+        };
+        for chain_id in [1, 8453] {
+            simulator.db_caches.entry(chain_id).or_default();
+            simulator
+                .balance_layouts
+                .entry(chain_id)
+                .or_default()
+                .insert((TOKEN, TokenStandard::Erc20, false), layout.clone());
+            simulator
+                .balance_slots
+                .entry(chain_id)
+                .or_default()
+                .insert((TOKEN, TokenStandard::Erc20, USER, false), slot.clone());
+            simulator
+                .allowance_slots
+                .entry(chain_id)
+                .or_default()
+                .insert((TOKEN, USER, USER), slot.clone());
+        }
+
+        simulator.clear_cache(Some(1));
+
+        assert_eq!(simulator.db_caches.keys().collect::<Vec<_>>(), [&8453]);
+        assert_eq!(
+            simulator.balance_layouts.keys().collect::<Vec<_>>(),
+            [&8453]
+        );
+        assert_eq!(simulator.balance_slots.keys().collect::<Vec<_>>(), [&8453]);
+        assert_eq!(
+            simulator.allowance_slots.keys().collect::<Vec<_>>(),
+            [&8453]
+        );
+
+        simulator.clear_cache(None);
+
+        assert_eq!(simulator.db_caches.len(), 0);
+        assert_eq!(simulator.balance_layouts.len(), 0);
+        assert_eq!(simulator.balance_slots.len(), 0);
+        assert_eq!(simulator.allowance_slots.len(), 0);
+    }
+
+    #[test]
+    fn test_slot_cache_is_keyed_by_verification() {
+        let mut simulator = Simulator::new();
+        let mut params = SimulationParams {
+            token_in: TOKEN,
+            user: USER,
+            ..usdc_params()
+        };
+        let layout = BalanceLayout {
+            address: TOKEN,
+            base_slot: U256::from(3),
+            kind: MappingKind::Solidity,
+            scale: None,
+            packing: None,
+        };
+        let slots = CachedSlots {
+            layout: Some(layout.clone()),
+            balance_slot: None,
+            allowance_slot: None,
+        };
+        simulator.store_slots(1, &params, slots);
+
+        // A layout found without the totalSupply check must not pass for a checked one
+        params.verify_total_supply = true;
+        assert_eq!(simulator.cached_slots(1, &params).layout, None);
+
+        params.verify_total_supply = false;
+        assert_eq!(simulator.cached_slots(1, &params).layout, Some(layout));
+    }
+
+    #[test]
+    fn test_simulate_error_codes() {
+        let cases = [
+            (
+                SimulateError::FindSlot(
+                    FindSlotByMutationError {
+                        attempts: Vec::new(),
+                        transient_slots: Vec::new(),
+                    }
+                    .into(),
+                ),
+                "find_slot_failed",
+            ),
+            (
+                SimulateError::Rpc(RpcError::local_usage_str("unreachable")),
+                "rpc_error",
+            ),
+            (
+                BothSimulationsFailed {
+                    rpc_error: SimulateViaRpcError::NoResponse,
+                    revm_error: SimulateViaRevmError::Halt {
+                        reason: HaltReason::OpcodeNotFound,
+                        gas_used: 0,
+                    },
+                }
+                .into(),
+                "both_simulations_failed",
+            ),
+            (SimulateError::Cancelled, "cancelled"),
+            (SimulateError::Timeout { timeout_ms: 10 }, "timeout"),
+            (
+                SimulateError::ChainIdMismatch {
+                    expected: 1,
+                    actual: 8453,
+                },
+                "chain_id_mismatch",
+            ),
+            (
+                SimulateError::SwapReverted("STF".to_string()),
+                "swap_reverted",
+            ),
+            (
+                SimulateError::AmountOutNegative {
+                    loss: U256::from(40),
+                },
+                "amount_out_negative",
+            ),
+            (
+                SimulateError::StateUnavailable {
+                    block: 16,
+                    message: "missing trie node".to_string(),
+                },
+                "state_unavailable",
+            ),
+        ];
+
+        for (error, code) in cases {
+            assert_eq!(error.code(), code, "{error}");
+        }
+    }
+
+    #[test]
+    fn test_payable_call_with_value() {
+        let contract = address!("0x1000000000000000000000000000000000000001");
+        let params = SimulationParams {
+            to: contract,
+            value: U256::from(12345),
+            gas_limit: Some(100_000),
+            gas_price: Some(0),
+            ..usdc_params()
+        };
+
+        let mut db = CacheDB::new(EmptyDB::default());
+        // CALLVALUE PUSH0 MSTORE PUSH1 0x20 PUSH0 RETURN
+        db.insert_account_info(
+            contract,
+            AccountInfo::default().with_code(Bytecode::new_raw(bytes!("345f5260205ff3"))),
+        );
+        db.insert_account_info(
+            params.user,
+            AccountInfo::from_balance(U256::from(1_000_000)),
+        );
+
+        let tx_env = build_call_tx_env(&mut db, &params, &params.steps()[0]).unwrap();
+        assert_eq!(tx_env.gas_limit, 100_000);
+
+        let mut evm = Context::mainnet().with_db(&mut db).build_mainnet();
+        let res = evm.transact_one(tx_env).unwrap();
+
+        let output = into_simulation_result(res).unwrap().unwrap();
+        assert_eq!(U256::from_be_slice(&output), params.value);
+    }
+
+    #[test]
+    fn test_rpc_call_carries_value_and_gas() {
+        let params = SimulationParams {
+            value: U256::from(12345),
+            gas_limit: Some(100_000),
+            gas_price: Some(7),
+            ..usdc_params()
+        };
+
+        let bundle = build_approve_and_call_bundle(&params, &Approval::Transaction);
+        let call_tx = &bundle.transactions[1];
+
+        assert_eq!(call_tx.value, Some(U256::from(12345)));
+        assert_eq!(call_tx.gas, Some(U256::from(100_000)));
+        assert_eq!(call_tx.gas_price, Some(U256::from(7)));
+    }
+
+    #[test]
+    fn test_step_gas_limit_overrides_params() {
+        let step = |gas_limit| CallStep {
+            to: MOCK_SPENDER,
+            gas_limit,
+            ..Default::default()
+        };
+        let params = SimulationParams {
+            gas_limit: Some(100_000),
+            steps: vec![step(Some(30_000)), step(None)],
+            ..usdc_params()
+        };
+
+        let bundle = build_approve_and_call_bundle(&params, &Approval::Transaction);
+        let gas = |index: usize| bundle.transactions[index].gas;
+        assert_eq!(gas(0), None);
+        assert_eq!(gas(1), Some(U256::from(30_000)));
+        assert_eq!(gas(2), Some(U256::from(100_000)));
+
+        let mut db = CacheDB::new(EmptyDB::default());
+        let tx_env = build_call_tx_env(&mut db, &params, &params.steps()[0]).unwrap();
+        assert_eq!(tx_env.gas_limit, 30_000);
+    }
+
+    fn detailed(response: TransactionResponse) -> DetailedTransactionResponse {
+        DetailedTransactionResponse {
+            response,
+            gas_used: None,
+            gas_refunded: None,
+        }
+    }
+
+    fn uint_response(value: u64) -> DetailedTransactionResponse {
+        detailed(TransactionResponse::Success {
+            value: U256::from(value).abi_encode().into(),
+        })
+    }
+
+    fn swap_params() -> SimulationParams {
+        SimulationParams {
+            // Uniswap V2 router swapping USDC for WETH
+            to: address!("0x7a250d5630B4cF539739dF2C5dAcb4c659F2488D"),
+            token_out: Some(address!("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2")),
+            ..usdc_params()
+        }
+    }
+
+    #[test]
+    fn test_swap_bundle_wraps_call_in_balance_reads() {
+        let params = swap_params();
+
+        let bundle = build_approve_and_call_bundle(&params, &Approval::Transaction);
+        let transactions = &bundle.transactions;
+
+        assert_eq!(transactions.len(), 4);
+        assert_eq!(transactions[0].to, params.token_out);
+        assert_eq!(transactions[1].to, Some(params.token_in));
+        assert_eq!(transactions[2].to, Some(params.to));
+        assert_eq!(transactions[3].to, params.token_out);
+    }
+
+    #[test]
+    fn test_parse_swap_responses_measures_amount_out() {
+        let responses = [
+            uint_response(100),
+            uint_response(1),
+            DetailedTransactionResponse {
+                response: TransactionResponse::Success {
+                    value: bytes!("1234"),
+                },
+                gas_used: Some(U256::from(120_000)),
+                gas_refunded: Some(U256::from(4_800)),
+            },
+            uint_response(600),
+        ];
+
+        let output = parse_bundle_responses(&swap_params(), true, &responses).unwrap();
+
+        assert_eq!(output.result, Ok(bytes!("1234")));
+        assert_eq!(output.amount_out, Some(U256::from(500)));
+        assert_eq!(output.gas_used, Some(120_000));
+        assert_eq!(output.gas_refunded, Some(4_800));
+    }
+
+    #[test]
+    fn test_parse_reverted_swap_responses() {
+        let responses = [
+            uint_response(100),
+            uint_response(1),
+            detailed(TransactionResponse::Reverted {
+                reason: "UniswapV2Router: EXPIRED".to_string(),
+                data: None,
+            }),
+            uint_response(100),
+        ];
+
+        let output = parse_bundle_responses(&swap_params(), true, &responses).unwrap();
+
+        assert_eq!(
+            output.result,
+            Err("execution reverted: UniswapV2Router: EXPIRED".to_string())
+        );
+        assert_eq!(output.amount_out, Some(U256::ZERO));
+
+        let pre_revert_state = output.pre_revert_state.unwrap();
+        assert!(pre_revert_state.approve_succeeded);
+        assert_eq!(pre_revert_state.token_out_balance, Some(U256::from(100)));
+        assert_eq!(pre_revert_state.token_in_balance, None);
+    }
+
+    #[test]
+    fn test_backends_report_the_same_revert_reason() {
+        let revert_data = Bytes::from(Revert::from("STF").abi_encode());
+
+        let revm_result = into_simulation_result(ExecutionResult::Revert {
+            gas_used: 30_000,
+            output: revert_data.clone(),
+        })
+        .unwrap();
+
+        let responses = [
+            uint_response(100),
+            uint_response(1),
+            detailed(TransactionResponse::Reverted {
+                reason: "STF".to_string(),
+                data: Some(revert_data),
+            }),
+            uint_response(100),
+        ];
+        let rpc_result = parse_bundle_responses(&swap_params(), true, &responses)
+            .unwrap()
+            .result;
+
+        assert_eq!(revm_result, Err("revert: STF".to_string()));
+        assert_eq!(rpc_result, revm_result);
+    }
+
+    #[test]
+    fn test_node_error_falls_back_to_revm() {
+        let responses = [
+            uint_response(100),
+            uint_response(1),
+            detailed(TransactionResponse::NodeError {
+                message: "gas required exceeds allowance (30000000)".to_string(),
+            }),
+            uint_response(100),
+        ];
+
+        let result = parse_bundle_responses(&swap_params(), true, &responses);
+
+        assert!(matches!(result, Err(SimulateViaRpcError::NodeError(_))));
+    }
+
+    #[test]
+    fn test_failed_approve_keeps_bundle_responses() {
+        let responses = vec![
+            vec![
+                detailed(TransactionResponse::Reverted {
+                    reason: "paused".to_string(),
+                    data: None,
+                }),
+                uint_response(1),
+            ],
+            vec![uint_response(2)],
+        ];
+
+        let result = parse_sequential_bundles(true, responses);
+
+        let Err(SimulateViaRpcError::ApproveFailed {
+            reason,
+            rpc_responses,
+        }) = result
+        else {
+            panic!("the approval reverted");
+        };
+        assert_eq!(reason, "execution reverted: paused");
+        assert_eq!(rpc_responses.len(), 2);
+    }
+
+    // PUSH0 SLOAD PUSH1 1 ADD DUP1 PUSH0 SSTORE PUSH0 MSTORE PUSH1 0x20 PUSH0 RETURN:
+    // increments a counter and returns its new value
+    const COUNTER: Bytes = bytes!("5f54600101805f555f5260205ff3");
+
+    fn counter_steps(result_step: Option<usize>) -> (SimulationParams, CacheDB<EmptyDB>) {
+        let counter = address!("0x1000000000000000000000000000000000000001");
+        let step = CallStep {
+            to: counter,
+            ..Default::default()
+        };
+        let params = SimulationParams {
+            steps: vec![step.clone(), step.clone(), step],
+            result_step,
+            ..usdc_params()
+        };
+
+        let mut db = CacheDB::new(EmptyDB::default());
+        db.insert_account_info(
+            counter,
+            AccountInfo::default().with_code(Bytecode::new_raw(COUNTER)),
+        );
+
+        (params, db)
+    }
+
+    #[test]
+    fn test_revm_steps_observe_previous_steps() {
+        let (params, mut db) = counter_steps(None);
+
+        let res = transact_steps(&mut db, &params, false).unwrap().result;
+
+        let output = into_simulation_result(res).unwrap().unwrap();
+        assert_eq!(U256::from_be_slice(&output), U256::from(3));
+        assert_eq!(db.load_account(params.user).unwrap().info.nonce, 2);
+    }
+
+    #[test]
+    fn test_nonce_numbers_approval_and_steps() {
+        let (params, _) = counter_steps(None);
+        let params = SimulationParams {
+            nonce: Some(7),
+            token_out: Some(MOCK_TOKEN),
+            ..params
+        };
+
+        let sent_nonces = |approve| {
+            build_approve_and_call_bundle(&params, approve)
+                .transactions
+                .into_iter()
+                .filter(|tx| tx.from.is_some())
+                .map(|tx| tx.nonce)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            sent_nonces(&Approval::Transaction),
+            [Some(7), Some(8), Some(9), Some(10)]
+        );
+        assert_eq!(
+            sent_nonces(&Approval::Existing),
+            [Some(7), Some(8), Some(9)]
+        );
+        assert_eq!(
+            build_state_overrides(&params, &[])[&params.user].nonce,
+            Some(7)
+        );
+    }
+
+    #[test]
+    fn test_revm_starts_at_nonce_override() {
+        let (params, _) = counter_steps(None);
+        let params = SimulationParams {
+            nonce: Some(7),
+            ..params
+        };
+        let mut db = offline_db(&[(params.steps[0].to, COUNTER)]);
+
+        simulate_via_revm(&params, &mut db, &[], &Approval::Existing).unwrap();
+
+        // The first two steps are committed, the result step isn't
+        assert_eq!(db.load_account(params.user).unwrap().info.nonce, 9);
+    }
+
+    #[test]
+    fn test_revm_designated_result_step() {
+        let (params, mut db) = counter_steps(Some(1));
+
+        let res = transact_steps(&mut db, &params, false).unwrap().result;
+
+        let output = into_simulation_result(res).unwrap().unwrap();
+        assert_eq!(U256::from_be_slice(&output), U256::from(2));
+    }
+
+    #[test]
+    fn test_revm_profiles_only_result_step() {
+        let (params, mut db) = counter_steps(Some(0));
+        let params = SimulationParams {
+            gas_profile: true,
+            ..params
+        };
+
+        let gas_profile = transact_steps(&mut db, &params, false).unwrap().gas_profile;
+
+        // First step pays the cold SLOAD and the zero-to-nonzero SSTORE
+        let gas_profile = gas_profile.unwrap();
+        assert_eq!(gas_profile.storage, 2100 + 20_000);
+    }
+
+    #[test]
+    fn test_rpc_bundle_with_steps() {
+        let (params, _) = counter_steps(Some(1));
+
+        let bundle = build_approve_and_call_bundle(&params, &Approval::Transaction);
+
+        assert_eq!(bundle.transactions.len(), 4);
+        assert_eq!(bundle.transactions[0].to, Some(params.token_in));
+        for (tx, step) in bundle.transactions[1..].iter().zip(&params.steps) {
+            assert_eq!(tx.to, Some(step.to));
+        }
+
+        let responses = [
+            uint_response(1),
+            uint_response(1),
+            uint_response(2),
+            uint_response(3),
+        ];
+
+        let output = parse_bundle_responses(&params, true, &responses).unwrap();
+
+        assert_eq!(output.result, Ok(U256::from(2).abi_encode().into()));
+    }
+
+    #[test]
+    fn test_code_override_reaches_both_backends() {
+        let contract = address!("0x1000000000000000000000000000000000000001");
+        // PUSH1 0x2a PUSH0 MSTORE PUSH1 0x20 PUSH0 RETURN
+        let mock_code = bytes!("602a5f5260205ff3");
+        let params = SimulationParams {
+            to: contract,
+            code_overrides: HashMap::from([(contract, mock_code.clone())]),
+            ..usdc_params()
+        };
+        let balance_slot = SlotWithAddress {
+            address: params.token_in,
+            slot: U256::from(9),
+            scale: None,
+            packing: None,
+        };
+
+        let mut db = CacheDB::new(EmptyDB::default());
+        // Deployed code returns 1
+        db.insert_account_info(
+            contract,
+            AccountInfo::default().with_code(Bytecode::new_raw(bytes!("60015f5260205ff3"))),
+        );
+
+        apply_code_overrides(&mut db, &params.code_overrides).unwrap();
+        let res = transact_steps(&mut db, &params, false).unwrap().result;
+
+        let output = into_simulation_result(res).unwrap().unwrap();
+        assert_eq!(U256::from_be_slice(&output), U256::from(0x2a));
+
+        let state_overrides = build_state_overrides(&params, &[(balance_slot, params.amount_in)]);
+        assert_eq!(state_overrides[&contract].code, Some(mock_code));
+        assert!(state_overrides[&params.token_in].state_diff.is_some());
+    }
+
+    const MOCK_TOKEN: Address = address!("0x2000000000000000000000000000000000000002");
+    // balanceOf(address) reading `balances[account]` at mapping slot 3
+    const SOLIDITY_BALANCE_OF: Bytes = bytes!("6004355f52600360205260405f20545f5260205ff3");
+
+    static TOKEN_RPC_REQUESTS: AtomicUsize = AtomicUsize::new(0);
+
+    /// Serves a chain holding a single ERC20-like token, counting every request.
+    fn token_rpc(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        TOKEN_RPC_REQUESTS.fetch_add(1, Ordering::SeqCst);
+
+        token_chain(method, params)
+    }
+
+    /// Latest block of the mock chains, past Osaka so that REVM runs the latest spec
+    const MOCK_BLOCK: u64 = 24_000_000;
+
+    fn token_chain(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "eth_chainId" => Ok(json!("0x1")),
+            "eth_blockNumber" => Ok(json!(U64::from(MOCK_BLOCK))),
+            "eth_getBalance" | "eth_getTransactionCount" => Ok(json!("0x0")),
+            "eth_getCode" => {
+                let address: Address = serde_json::from_value(params[0].clone()).unwrap();
+                match address {
+                    MOCK_TOKEN => Ok(json!(SOLIDITY_BALANCE_OF)),
+                    _ => Ok(json!("0x")),
+                }
+            }
+            "eth_getStorageAt" => Ok(json!(B256::ZERO)),
+            "eth_callMany" => {
+                Ok(json!([[{ "value": B256::with_last_byte(1) }, { "value": "0x" }]]))
+            }
+            _ => Err((-32601, "method not found".to_string())),
+        }
+    }
+
+    const MOCK_SPENDER: Address = address!("0x3000000000000000000000000000000000000003");
+    // Returns MOCK_TOKEN.allowance(msg.sender, address(this))
+    const ALLOWANCE_READER: Bytes = bytes!(
+        "63dd62ed3e60e01b5f52336004523060245260205f60445f7320000000000000000000000000000000000000025afa5060205ff3"
+    );
+
+    /// Serves [`ERC20`] and [`ALLOWANCE_READER`], and rejects `eth_callMany` so
+    /// that simulations run in REVM.
+    fn erc20_revm_chain(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "eth_getCode" => {
+                let address: Address = serde_json::from_value(params[0].clone()).unwrap();
+                match address {
+                    MOCK_TOKEN => Ok(json!(ERC20)),
+                    MOCK_SPENDER => Ok(json!(ALLOWANCE_READER)),
+                    _ => Ok(json!("0x")),
+                }
+            }
+            "eth_callMany" => Err((-32601, "method not found".to_string())),
+            _ => token_chain(method, params),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_cached_layout_serves_other_users() {
+        let rpc_url = spawn_mock_rpc(token_chain).await;
+        let other_user = address!("0x282Cd0c363CCf32629BE74A0A2B1a0Ed6680aE8e");
+        let params = |user| SimulationParams {
+            user,
+            token_in: MOCK_TOKEN,
+            ..usdc_params()
+        };
+
+        let mut simulator = Simulator::new();
+        let first = simulator
+            .simulate(
+                1,
+                rpc_url.clone(),
+                params(usdc_params().user),
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(first.slot_strategy, Some(SlotStrategy::FastPath));
+        let layout = first.balance_layout.unwrap();
+        assert_eq!(layout.base_slot, U256::from(3));
+        assert!(simulator.balance_slots.is_empty());
+
+        let second = simulator
+            .simulate(1, rpc_url, params(other_user), &CancellationToken::new())
+            .await
+            .unwrap();
+
+        assert_eq!(second.balance_layout, Some(layout.clone()));
+        assert_eq!(second.slot_strategy, None);
+        assert_eq!(second.balance_slot, Some(layout.user_slot(other_user)));
+        assert_eq!(
+            second.balance_slot.unwrap().slot,
+            derive_user_slot(U256::from(3), other_user)
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_raw_rpc_response_is_captured() {
+        let rpc_url = spawn_mock_rpc(token_chain).await;
+        let mut simulator = Simulator::new();
+        let params = || SimulationParams {
+            token_in: MOCK_TOKEN,
+            ..usdc_params()
+        };
+
+        let output = simulator
+            .simulate(
+                1,
+                rpc_url.clone(),
+                SimulationParams {
+                    capture_raw_response: true,
+                    ..params()
+                },
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+
+        let raw = output.raw_rpc_response.unwrap();
+        let callmany_response = token_chain("eth_callMany", &Value::Null).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Value>(raw.get()).unwrap(),
+            callmany_response
+        );
+
+        let output = simulator
+            .simulate(1, rpc_url, params(), &CancellationToken::new())
+            .await
+            .unwrap();
+
+        assert!(output.rpc_responses.is_some());
+        assert!(output.raw_rpc_response.is_none());
+    }
+
+    /// [`token_chain`] answering `eth_callMany` without any bundle
+    fn empty_callmany_chain(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "eth_callMany" => Ok(json!([])),
+            _ => token_chain(method, params),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_raw_rpc_response_is_kept_on_fallback() {
+        let rpc_url = spawn_mock_rpc(empty_callmany_chain).await;
+        let params = SimulationParams {
+            token_in: MOCK_TOKEN,
+            capture_raw_response: true,
+            ..usdc_params()
+        };
+
+        let output = Simulator::new()
+            .simulate(1, rpc_url, params, &CancellationToken::new())
+            .await
+            .unwrap();
+
+        assert_eq!(output.path, SimulationPath::Revm);
+        let rpc_err = output.simulation_via_rpc_err.unwrap();
+        assert_eq!(rpc_err.raw_rpc_response().map(RawValue::get), Some("[]"));
+        assert_eq!(output.raw_rpc_response.unwrap().get(), "[]");
+    }
+
+    /// [`token_chain`] as Arbitrum One
+    fn arbitrum_chain(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "eth_chainId" => Ok(json!("0xa4b1")),
+            _ => token_chain(method, params),
+        }
+    }
+
+    /// [`erc20_revm_chain`] as Arbitrum One, rejecting `eth_callMany`
+    fn arbitrum_revm_chain(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "eth_chainId" => Ok(json!("0xa4b1")),
+            _ => erc20_revm_chain(method, params),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_arbitrum_uses_rpc_path() {
+        let rpc_url = spawn_mock_rpc(arbitrum_chain).await;
+        // Each of these would run REVM beside the node elsewhere
+        let params = SimulationParams {
+            token_in: MOCK_TOKEN,
+            strategy: Some(SimulationStrategy::Both),
+            gas_profile: true,
+            state_diff: true,
+            trace_hops: true,
+            ..usdc_params()
+        };
+
+        let output = Simulator::new()
+            .simulate(42161, rpc_url, params, &CancellationToken::new())
+            .await
+            .unwrap();
+
+        assert_eq!(output.path, SimulationPath::Rpc);
+        assert!(output.simulation_via_rpc_err.is_none());
+        assert!(output.divergence.is_none());
+        assert!(output.gas_profile.is_none());
+        assert!(output.state_diff.is_none());
+        assert!(output.hops.is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_arbitrum_has_no_revm_fallback() {
+        let rpc_url = spawn_mock_rpc(arbitrum_revm_chain).await;
+        let params = SimulationParams {
+            token_in: MOCK_TOKEN,
+            to: MOCK_SPENDER,
+            ..usdc_params()
+        };
+
+        let error = Simulator::new()
+            .simulate(42161, rpc_url, params, &CancellationToken::new())
+            .await
+            .unwrap_err();
+
+        assert!(matches!(error, SimulateError::RpcOnly(_)));
+        assert_eq!(error.code(), "rpc_error");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_zero_amount_skips_balance_slot() {
+        let rpc_url = spawn_mock_rpc(token_chain).await;
+        // No code is served for USDC, so looking up its balance slot would fail
+        let params = SimulationParams {
+            amount_in: U256::ZERO,
+            ..usdc_params()
+        };
+
+        let output = Simulator::new()
+            .simulate(1, rpc_url, params, &CancellationToken::new())
+            .await
+            .unwrap();
+
+        assert!(output.result.is_ok());
+        assert!(output.balance_slot.is_none());
+    }
+
+    #[test]
+    fn test_amount_overflowing_rebasing_slot_is_rejected() {
+        let plain_slot = mapping_slot(USER);
+        let rebasing_slot = SlotWithAddress {
+            scale: Some(BalanceScale {
+                probe: U256::from(1_000u64),
+                balance: U256::from(1_100u64),
+            }),
+            packing: None,
+            ..plain_slot.clone()
+        };
+
+        assert!(overflowing_amount(&[(plain_slot, U256::MAX)]).is_none());
+        assert!(overflowing_amount(&[(rebasing_slot.clone(), U256::from(1_000u64))]).is_none());
+        assert!(overflowing_amount(&[(rebasing_slot, U256::MAX)]).is_some());
+    }
+
+    #[test]
+    fn test_simulate_via_revm_offline() {
+        let mut db = offline_db(&[(TOKEN, ERC20)]);
+        let balance_slot = find_balance_slot(TOKEN, USER, &mut db).unwrap();
+
+        let params = SimulationParams {
+            user: USER,
+            token_in: TOKEN,
+            amount_in: U256::from(1_000_000u64),
+            to: TOKEN,
+            calldata: balanceOfCall { account: USER }.abi_encode().into(),
+            report_allowance: true,
+            ..Default::default()
+        };
+
+        let output = simulate_via_revm(
+            &params,
+            &mut db,
+            &[(balance_slot, params.amount_in)],
+            &Approval::Transaction,
+        )
+        .unwrap();
+
+        let balance = U256::from_be_slice(&output.result.unwrap());
+        assert_eq!(balance, params.amount_in);
+        assert_eq!(output.allowance, Some(U256::MAX));
+        assert!(output.pre_revert_state.is_none());
+    }
+
+    #[test]
+    fn test_revert_reports_pre_revert_state() {
+        let mut db = offline_db(&[(TOKEN, ERC20)]);
+        let balance_slot = find_balance_slot(TOKEN, USER, &mut db).unwrap();
+
+        // The token reverts on any unknown selector
+        let params = SimulationParams {
+            user: USER,
+            token_in: TOKEN,
+            amount_in: U256::from(1_000_000u64),
+            to: TOKEN,
+            calldata: bytes!("deadbeef"),
+            ..Default::default()
+        };
+
+        let output = simulate_via_revm(
+            &params,
+            &mut db,
+            &[(balance_slot, params.amount_in)],
+            &Approval::Transaction,
+        )
+        .unwrap();
+
+        assert!(output.result.is_err());
+        assert_eq!(
+            output.pre_revert_state,
+            Some(PreRevertState {
+                approve_succeeded: true,
+                token_in_balance: Some(params.amount_in),
+                allowance: Some(U256::MAX),
+                token_out_balance: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_contract_wallet_caller() {
+        // Returns CALLER
+        let caller_echo = address!("0x2000000000000000000000000000000000000002");
+        // Wallet code the user is deployed with, never executed as the transaction origin
+        let wallet_code = bytes!("5f5ffd");
+
+        let mut db = offline_db(&[
+            (TOKEN, ERC20),
+            (caller_echo, bytes!("335f5260205ff3")),
+            (USER, wallet_code.clone()),
+        ]);
+        let balance_slot = find_balance_slot(TOKEN, USER, &mut db).unwrap();
+
+        let mut params = SimulationParams {
+            user: USER,
+            token_in: TOKEN,
+            amount_in: U256::from(1_000u64),
+            to: caller_echo,
+            ..Default::default()
+        };
+        let funded = [(balance_slot, params.amount_in)];
+
+        let mut wallet_db = db.clone();
+        let output =
+            simulate_via_revm(&params, &mut wallet_db, &funded, &Approval::Transaction).unwrap();
+        assert_eq!(output.result.unwrap(), USER.abi_encode());
+
+        // Bumped by the committed approval
+        let wallet = wallet_db.load_account(USER).unwrap();
+        assert_eq!(wallet.info.nonce, 1);
+        assert_eq!(
+            wallet.info.code.as_ref().unwrap().original_bytes(),
+            wallet_code
+        );
+
+        params.revm_cfg.disable_eip3607 = false;
+        assert!(simulate_via_revm(&params, &mut db, &funded, &Approval::Transaction).is_err());
+    }
+
+    fn delegate_to(signer: &PrivateKeySigner, address: Address) -> SignedAuthorization {
+        let authorization = Authorization {
+            chain_id: U256::from(1),
+            address,
+            nonce: 0,
+        };
+        let signature = signer
+            .sign_hash_sync(&authorization.signature_hash())
+            .unwrap();
+
+        authorization.into_signed(signature)
+    }
+
+    #[test]
+    fn test_call_through_delegated_eoa() {
+        let signer = PrivateKeySigner::from_bytes(&B256::with_last_byte(1)).unwrap();
+        let user = signer.address();
+        // Returns ADDRESS, which is the EOA when run as its delegated code
+        let delegate = address!("0x2000000000000000000000000000000000000002");
+
+        let mut db = offline_db(&[(TOKEN, ERC20), (delegate, bytes!("305f5260205ff3"))]);
+        let mut params = SimulationParams {
+            user,
+            token_in: TOKEN,
+            to: user,
+            ..Default::default()
+        };
+
+        // A plain EOA has no code to run, the call stops without returning anything
+        let output =
+            simulate_via_revm(&params, &mut db.clone(), &[], &Approval::Transaction).unwrap();
+        assert_eq!(output.result.unwrap(), Bytes::new());
+        assert_eq!(output.success_reason, Some(SuccessReason::Stop));
+
+        params.authorization_list = vec![delegate_to(&signer, delegate)];
+        let output = simulate_via_revm(&params, &mut db, &[], &Approval::Transaction).unwrap();
+        assert_eq!(output.result.unwrap(), user.abi_encode());
+
+        let code = db.load_account(user).unwrap().info.code.clone().unwrap();
+        assert_eq!(code, Bytecode::new_eip7702(delegate));
+
+        let transactions =
+            build_approve_and_call_bundle(&params, &Approval::Transaction).transactions;
+        assert_eq!(
+            transactions[0].authorization_list,
+            Some(params.authorization_list.clone())
+        );
+        assert_eq!(transactions[1].authorization_list, None);
+        assert!(!supports_eth_call_fallback(&params));
+    }
+
+    #[test]
+    fn test_preset_allowance_skips_approve() {
+        let params = SimulationParams {
+            user: USER,
+            token_in: TOKEN,
+            amount_in: U256::from(1_000u64),
+            to: TOKEN,
+            calldata: balanceOfCall { account: USER }.abi_encode().into(),
+            skip_approve_if_allowed: true,
+            ..Default::default()
+        };
+        let inner = keccak256((USER, U256::from(4)).abi_encode());
+        let allowance_slot = U256::from_be_bytes(keccak256((TOKEN, inner).abi_encode()).0);
+
+        let mut db = offline_db(&[(TOKEN, ERC20)]);
+        assert!(!allowance_covers_amount(&params, &mut db));
+
+        db.insert_account_storage(TOKEN, allowance_slot, params.amount_in)
+            .unwrap();
+        assert!(allowance_covers_amount(&params, &mut db));
+
+        let mut nonces = Vec::new();
+        for approval in [Approval::Transaction, Approval::Existing] {
+            let mut db = db.clone();
+            simulate_via_revm(&params, &mut db, &[], &approval).unwrap();
+            nonces.push(db.load_account(USER).unwrap().info.nonce);
+        }
+        assert_eq!(nonces, [1, 0]);
+
+        let transactions = build_approve_and_call_bundle(&params, &Approval::Existing).transactions;
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(transactions[0].to, Some(TOKEN));
+
+        let output = parse_bundle_responses(&params, false, &[uint_response(7)]).unwrap();
+        assert_eq!(output.result.unwrap(), U256::from(7).abi_encode());
+    }
+
+    #[test]
+    fn test_state_diff_holds_transfer_balance_writes() {
+        let recipient = address!("0x3000000000000000000000000000000000000003");
+        let mut db = offline_db(&[(TOKEN, ERC20)]);
+        let balance_slot = find_balance_slot(TOKEN, USER, &mut db).unwrap();
+
+        let params = SimulationParams {
+            user: USER,
+            token_in: TOKEN,
+            amount_in: U256::from(1_000u64),
+            to: TOKEN,
+            calldata: transferCall {
+                to: recipient,
+                value: U256::from(400u64),
+            }
+            .abi_encode()
+            .into(),
+            state_diff: true,
+            ..Default::default()
+        };
+
+        let output = simulate_via_revm(
+            &params,
+            &mut db,
+            &[(balance_slot.clone(), params.amount_in)],
+            &Approval::Transaction,
+        )
+        .unwrap();
+        assert!(output.result.is_ok());
+
+        let recipient_slot =
+            U256::from_be_bytes(keccak256((recipient, U256::from(3)).abi_encode()).0);
+        let state_diff = output.state_diff.unwrap();
+        assert_eq!(
+            state_diff[&TOKEN],
+            HashMap::from([
+                (balance_slot.slot, U256::from(600u64)),
+                (recipient_slot, U256::from(400u64)),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_gas_profile_counts_transfer_storage_accesses() {
+        let recipient = address!("0x3000000000000000000000000000000000000003");
+        let mut db = offline_db(&[(TOKEN, ERC20)]);
+        let balance_slot = find_balance_slot(TOKEN, USER, &mut db).unwrap();
+        let params = SimulationParams {
+            gas_profile: true,
+            token_in_recipient: None,
+            ..transfer_params(recipient, U256::from(1_000u64))
+        };
+
+        let output = simulate_via_revm(
+            &params,
+            &mut db,
+            &[(balance_slot, params.amount_in)],
+            &Approval::Transaction,
+        )
+        .unwrap();
+        assert!(output.result.is_ok());
+
+        // Both balances are read cold, then written while warm
+        let storage_access = output.gas_profile.unwrap().storage_access;
+        assert_eq!(
+            storage_access,
+            StorageAccess {
+                cold_sloads: 2,
+                warm_sloads: 0,
+                cold_sstores: 0,
+                warm_sstores: 2,
+            }
+        );
+    }
+
+    // ERC20 like the offline fixture, whose `transfer` burns 1% of the amount
+    const FEE_ON_TRANSFER_ERC20: Bytes = bytes!(
+        "5f3560e01c806370a0823114610035578063095ea7b31461004b578063dd62ed3e14610070578063a9059cbb14610092575b5f5ffd5b6004355f52600360205260405f20545f5260205ff35b335f52600460205260405f206020526004355f5260243560405f205560015f5260205ff35b6004355f52600460205260405f206020526024355f5260405f20545f5260205ff35b335f52600360205260405f20805460243580821061003157900390556004355f5260405f2080546024358060649004900301905560015f5260205ff3"
+    );
+
+    fn transfer_params(recipient: Address, amount: U256) -> SimulationParams {
+        SimulationParams {
+            user: USER,
+            token_in: TOKEN,
+            amount_in: amount,
+            to: TOKEN,
+            calldata: transferCall {
+                to: recipient,
+                value: amount,
+            }
+            .abi_encode()
+            .into(),
+            token_in_recipient: Some(recipient),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_actual_amount_in_reveals_transfer_fee() {
+        let recipient = address!("0x3000000000000000000000000000000000000003");
+        let params = transfer_params(recipient, U256::from(1_000u64));
+
+        let mut received = Vec::new();
+        for code in [ERC20, FEE_ON_TRANSFER_ERC20] {
+            let mut db = offline_db(&[(TOKEN, code)]);
+            let balance_slot = find_balance_slot(TOKEN, USER, &mut db).unwrap();
+
+            let output = simulate_via_revm(
+                &params,
+                &mut db,
+                &[(balance_slot, params.amount_in)],
+                &Approval::Transaction,
+            )
+            .unwrap();
+            assert!(output.result.is_ok());
+            received.push(output.actual_amount_in);
+        }
+
+        assert_eq!(
+            received,
+            [Some(U256::from(1_000u64)), Some(U256::from(990u64))]
+        );
+    }
+
+    #[test]
+    fn test_rpc_bundle_reads_recipient_balance_around_steps() {
+        let recipient = address!("0x3000000000000000000000000000000000000003");
+        let params = transfer_params(recipient, U256::from(1_000u64));
+
+        let transactions =
+            build_approve_and_call_bundle(&params, &Approval::Transaction).transactions;
+        assert_eq!(transactions.len(), 4);
+        let recipient_balance_of = build_balance_of_tx(TOKEN, TokenStandard::Erc20, recipient).data;
+        assert_eq!(transactions[0].data, recipient_balance_of);
+        assert_eq!(transactions[3].data, recipient_balance_of);
+
+        let uint = |value: u64| DetailedTransactionResponse {
+            response: TransactionResponse::Success {
+                value: U256::from(value).abi_encode().into(),
+            },
+            gas_used: None,
+            gas_refunded: None,
+        };
+        let output =
+            parse_bundle_responses(&params, true, &[uint(5), uint(1), uint(1), uint(995)]).unwrap();
+
+        assert_eq!(output.actual_amount_in, Some(U256::from(990u64)));
+    }
+
+    // Anvil fork left on its default chain id, which also serves `eth_callMany`
+    fn anvil_chain(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "eth_chainId" => Ok(json!("0x7a69")),
+            "eth_callMany" => token_chain(method, params),
+            _ => erc20_revm_chain(method, params),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_local_fork_profile() {
+        let rpc_url = spawn_mock_rpc(anvil_chain).await;
+        let params = || SimulationParams {
+            token_in: MOCK_TOKEN,
+            to: MOCK_SPENDER,
+            ..usdc_params()
+        };
+
+        let result = Simulator::new()
+            .simulate(1, rpc_url.clone(), params(), &CancellationToken::new())
+            .await;
+        assert!(matches!(
+            result,
+            Err(SimulateError::ChainIdMismatch {
+                expected: 1,
+                actual: 31337
+            })
+        ));
+
+        let mut simulator = Simulator::new();
+        let output = simulator
+            .simulate(
+                1,
+                rpc_url,
+                SimulationParams {
+                    provider_profile: ProviderProfile::LocalFork,
+                    ..params()
+                },
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(output.path, SimulationPath::Revm);
+        assert!(output.simulation_via_rpc_err.is_none());
+        assert_eq!(
+            U256::from_be_slice(output.result.as_ref().unwrap()),
+            U256::MAX
+        );
+        // The fork's state and slots are not kept as chain 1's
+        assert!(output.balance_slot.is_some());
+        assert!(simulator.db_caches.is_empty());
+        assert!(simulator.balance_layouts.is_empty());
+        assert!(simulator.balance_slots.is_empty());
+        assert!(simulator.allowance_slots.is_empty());
+    }
+
+    #[cfg(feature = "anvil-tests")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_simulate_against_anvil() {
+        // anvil --fork-url <mainnet RPC>
+        dotenvy::dotenv().ok();
+        let rpc_url = std::env::var("ANVIL_RPC")
+            .unwrap_or_else(|_| "http://localhost:8545".to_string())
+            .parse()
+            .unwrap();
+
+        let params = SimulationParams {
+            calldata: balanceOfCall {
+                account: usdc_params().user,
+            }
+            .abi_encode()
+            .into(),
+            to: usdc_params().token_in,
+            provider_profile: ProviderProfile::LocalFork,
+            ..usdc_params()
+        };
+
+        let output = Simulator::new()
+            .simulate(1, rpc_url, params, &CancellationToken::new())
+            .await
+            .unwrap();
+
+        assert_eq!(output.path, SimulationPath::Revm);
+        assert_eq!(
+            U256::from_be_slice(output.result.as_ref().unwrap()),
+            usdc_params().amount_in
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_allowance_slot_override_matches_approve_tx() {
+        let rpc_url = spawn_mock_rpc(erc20_revm_chain).await;
+
+        let mut outputs = Vec::new();
+        for approve_mode in [ApproveMode::Transaction, ApproveMode::SlotOverride] {
+            let params = SimulationParams {
+                token_in: MOCK_TOKEN,
+                to: MOCK_SPENDER,
+                approve_mode: Some(approve_mode),
+                ..usdc_params()
+            };
+
+            let output = Simulator::new()
+                .simulate(1, rpc_url.clone(), params, &CancellationToken::new())
+                .await
+                .unwrap();
+            assert_eq!(output.path, SimulationPath::Revm);
+            outputs.push(output);
+        }
+
+        let user = usdc_params().user;
+        let inner = keccak256((user, U256::from(4)).abi_encode());
+        let expected_slot = keccak256((MOCK_SPENDER, inner).abi_encode());
+
+        assert_eq!(outputs[0].allowance_slot, None);
+        assert_eq!(
+            outputs[1].allowance_slot.as_ref().map(|slot| slot.slot),
+            Some(U256::from_be_bytes(expected_slot.0))
+        );
+        assert_eq!(
+            outputs[0].result.as_ref().unwrap(),
+            outputs[1].result.as_ref().unwrap()
+        );
+        assert_eq!(
+            U256::from_be_slice(outputs[1].result.as_ref().unwrap()),
+            U256::MAX
+        );
+    }
+
+    const POOL: Address = address!("0x6000000000000000000000000000000000000006");
+    const RESERVE_SLOT: U256 = U256::from_limbs([8, 0, 0, 0]);
+
+    /// Serves [`ERC20`] and a pool returning its reserve at slot 8, which the node reads
+    /// from the `stateDiff` overrides of the request, as a node applying them would.
+    fn pool_chain(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "eth_getCode" => {
+                let address: Address = serde_json::from_value(params[0].clone()).unwrap();
+                match address {
+                    MOCK_TOKEN => Ok(json!(ERC20)),
+                    // PUSH1 8 SLOAD PUSH0 MSTORE PUSH1 0x20 PUSH0 RETURN
+                    POOL => Ok(json!(bytes!("6008545f5260205ff3"))),
+                    _ => Ok(json!("0x")),
+                }
+            }
+            "eth_callMany" => {
+                let overrides: HashMap<Address, Value> =
+                    serde_json::from_value(params[2].clone()).unwrap();
+                let reserve = overrides
+                    .get(&POOL)
+                    .and_then(|state| {
+                        state["stateDiff"][B256::from(RESERVE_SLOT).to_string()].as_str()
+                    })
+                    .map_or(B256::ZERO, |value| value.parse().unwrap());
+
+                Ok(json!([[{ "value": B256::with_last_byte(1) }, { "value": reserve }]]))
+            }
+            _ => token_chain(method, params),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_storage_overrides_apply_to_both_backends() {
+        let rpc_url = spawn_mock_rpc(pool_chain).await;
+        let reserve = U256::from(123_456_789);
+        let params = SimulationParams {
+            token_in: MOCK_TOKEN,
+            to: POOL,
+            storage_overrides: HashMap::from([(POOL, HashMap::from([(RESERVE_SLOT, reserve)]))]),
+            strategy: Some(SimulationStrategy::Both),
+            ..usdc_params()
+        };
+
+        let output = Simulator::new()
+            .simulate(1, rpc_url, params, &CancellationToken::new())
+            .await
+            .unwrap();
+
+        let divergence = output.divergence.unwrap();
+        assert!(divergence.agree);
+        assert_eq!(
+            divergence.revm_output.unwrap().result,
+            Ok(B256::from(reserve).into())
+        );
+        assert_eq!(output.result, Ok(B256::from(reserve).into()));
+    }
+
+    const BASEFEE_READER: Address = address!("0x5000000000000000000000000000000000000005");
+
+    /// Serves [`ERC20`] and a contract returning `block.basefee`, which the node answers
+    /// with 7 gwei while REVM runs with a zero base fee.
+    fn basefee_chain(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "eth_getCode" => {
+                let address: Address = serde_json::from_value(params[0].clone()).unwrap();
+                match address {
+                    MOCK_TOKEN => Ok(json!(ERC20)),
+                    // BASEFEE PUSH0 MSTORE PUSH1 0x20 PUSH0 RETURN
+                    BASEFEE_READER => Ok(json!(bytes!("485f5260205ff3"))),
+                    _ => Ok(json!("0x")),
+                }
+            }
+            "eth_callMany" => Ok(json!([[
+                { "value": B256::with_last_byte(1) },
+                { "value": B256::from(U256::from(7_000_000_000u64)) },
+            ]])),
+            _ => token_chain(method, params),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_both_strategy_reports_divergence() {
+        let rpc_url = spawn_mock_rpc(basefee_chain).await;
+        let params = |strategy| SimulationParams {
+            token_in: MOCK_TOKEN,
+            to: BASEFEE_READER,
+            strategy,
+            ..usdc_params()
+        };
+
+        let mut simulator = Simulator::new();
+        let fallback = simulator
+            .simulate(
+                1,
+                rpc_url.clone(),
+                params(Some(SimulationStrategy::Fallback)),
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        assert!(fallback.divergence.is_none());
+
+        let both = simulator
+            .simulate(
+                1,
+                rpc_url,
+                params(Some(SimulationStrategy::Both)),
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(both.path, SimulationPath::Rpc);
+        assert_eq!(both.result, fallback.result);
+        let divergence = both.divergence.unwrap();
+        assert!(!divergence.agree);
+        assert_eq!(
+            divergence.rpc_output.unwrap().result,
+            Ok(B256::from(U256::from(7_000_000_000u64)).into())
+        );
+        let revm_output = divergence.revm_output.unwrap();
+        assert_eq!(revm_output.result, Ok(B256::ZERO.into()));
+        assert!(revm_output.gas_used.is_some());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_config_defaults_flow_into_simulate() {
+        let rpc_url = spawn_mock_rpc(erc20_revm_chain).await;
+        let params = |strategy| SimulationParams {
+            token_in: MOCK_TOKEN,
+            to: MOCK_SPENDER,
+            strategy,
+            ..usdc_params()
+        };
+
+        let mut simulator = Simulator::new_with_config(SimulatorConfig {
+            strategy: SimulationStrategy::Both,
+            approve_mode: ApproveMode::SlotOverride,
+            ..Default::default()
+        });
+        let configured = simulator
+            .simulate(1, rpc_url.clone(), params(None), &CancellationToken::new())
+            .await
+            .unwrap();
+        assert!(configured.divergence.is_some());
+        assert!(configured.allowance_slot.is_some());
+
+        let overridden = simulator
+            .simulate(
+                1,
+                rpc_url,
+                params(Some(SimulationStrategy::Fallback)),
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        assert!(overridden.divergence.is_none());
+        assert!(overridden.allowance_slot.is_some());
+    }
+
+    static STORAGE_READS: AtomicUsize = AtomicUsize::new(0);
+
+    /// [`erc20_revm_chain`], counting the storage slots fetched
+    fn storage_counting_chain(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        if method == "eth_getStorageAt" {
+            STORAGE_READS.fetch_add(1, Ordering::SeqCst);
+        }
+
+        erc20_revm_chain(method, params)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_cache_storage_skips_refetching_slots() {
+        let rpc_url = spawn_mock_rpc(storage_counting_chain).await;
+        let params = || SimulationParams {
+            token_in: MOCK_TOKEN,
+            to: MOCK_SPENDER,
+            ..usdc_params()
+        };
+
+        // Storage reads of the second simulation at the same block
+        let mut second_reads = Vec::new();
+        for cache_storage in [false, true] {
+            let mut simulator = Simulator::new_with_config(SimulatorConfig {
+                cache_storage,
+                ..Default::default()
+            });
+
+            let first = simulator
+                .simulate(1, rpc_url.clone(), params(), &CancellationToken::new())
+                .await
+                .unwrap();
+            let reads_before = STORAGE_READS.load(Ordering::SeqCst);
+            let second = simulator
+                .simulate(1, rpc_url.clone(), params(), &CancellationToken::new())
+                .await
+                .unwrap();
+            second_reads.push(STORAGE_READS.load(Ordering::SeqCst) - reads_before);
+
+            assert_eq!(second.block_number, first.block_number);
+            // The approval of the first simulation must not leak into the second
+            assert_eq!(second.result, first.result);
+            assert_eq!(second.result, Ok(B256::from(U256::MAX).into()));
+        }
+
+        assert!(second_reads[0] > 0);
+        assert!(
+            second_reads[1] < second_reads[0],
+            "reads without and with cache_storage: {second_reads:?}"
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_offline_replay_of_prepared_state() {
+        let rpc_url = spawn_mock_rpc(erc20_revm_chain).await;
+        let params = || SimulationParams {
+            token_in: MOCK_TOKEN,
+            to: MOCK_SPENDER,
+            approve_mode: Some(ApproveMode::SlotOverride),
+            ..usdc_params()
+        };
+
+        let mut simulator = Simulator::new();
+        let online = simulator
+            .simulate(1, rpc_url.clone(), params(), &CancellationToken::new())
+            .await
+            .unwrap();
+        let state = simulator.prepare(1, rpc_url, &params()).await.unwrap();
+        assert_eq!(state.block_number, online.block_number);
+        assert!(state.accounts.contains_key(&MOCK_TOKEN));
+
+        let offline = Simulator::simulate_offline(state, params()).unwrap();
+
+        assert_eq!(offline.path, SimulationPath::Revm);
+        assert_eq!(offline.result, online.result);
+        assert_eq!(offline.gas_used, online.gas_used);
+        assert_eq!(offline.balance_slot, online.balance_slot);
+        assert_eq!(offline.allowance_slot, online.allowance_slot);
+    }
+
+    /// Like [`erc20_revm_chain`], but answers `eth_call`: the call to [`MOCK_SPENDER`]
+    /// echoes the allowance only when the overrides grant it, as REVM would have.
+    fn eth_call_only_chain(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "eth_call" => {
+                let to: Address = serde_json::from_value(params[0]["to"].clone()).unwrap();
+                let state_diff = params[2][MOCK_TOKEN.to_string()]["stateDiff"]
+                    .as_object()
+                    .cloned()
+                    .unwrap_or_default();
+                let granted = state_diff
+                    .values()
+                    .any(|value| *value == json!(B256::from(U256::MAX)));
+                match to {
+                    MOCK_TOKEN => Ok(json!(B256::with_last_byte(1))),
+                    MOCK_SPENDER if granted => Ok(json!(B256::from(U256::MAX))),
+                    _ => Err((3, "execution reverted".to_string())),
+                }
+            }
+            _ => erc20_revm_chain(method, params),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_eth_call_fallback_when_call_many_is_rejected() {
+        let rpc_url = spawn_mock_rpc(eth_call_only_chain).await;
+
+        let params = SimulationParams {
+            token_in: MOCK_TOKEN,
+            to: MOCK_SPENDER,
+            ..usdc_params()
+        };
+
+        let output = Simulator::new()
+            .simulate(1, rpc_url, params, &CancellationToken::new())
+            .await
+            .unwrap();
+
+        assert_eq!(output.path, SimulationPath::Rpc);
+        assert_eq!(output.rpc_backend, Some(RpcBackend::EthCallFallback));
+        assert_eq!(
+            U256::from_be_slice(output.result.as_ref().unwrap()),
+            U256::MAX
+        );
+        assert_eq!(
+            output.rpc_responses.map(|responses| responses.len()),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn test_eth_call_fallback_needs_a_lone_call() {
+        assert!(supports_eth_call_fallback(&usdc_params()));
+        assert!(!supports_eth_call_fallback(&SimulationParams {
+            token_out: Some(MOCK_TOKEN),
+            ..usdc_params()
+        }));
+        assert!(!supports_eth_call_fallback(&SimulationParams {
+            report_allowance: true,
+            ..usdc_params()
+        }));
+        assert!(!supports_eth_call_fallback(&SimulationParams {
+            transaction_index: Some(3),
+            ..usdc_params()
+        }));
+    }
+
+    #[test]
+    fn test_coinbase_payment() {
+        let builder = address!("0x4000000000000000000000000000000000000004");
+        // Pays CALLVALUE to COINBASE and returns the balance of COINBASE
+        let tipper = address!("0x2000000000000000000000000000000000000002");
+        let mut db = offline_db(&[
+            (TOKEN, ERC20),
+            (tipper, bytes!("5f5f5f5f34415af15041315f5260205ff3")),
+        ]);
+
+        let params = SimulationParams {
+            user: USER,
+            token_in: TOKEN,
+            to: tipper,
+            value: U256::from(1_000_000u64),
+            coinbase: Some(builder),
+            ..Default::default()
+        };
+
+        let output = simulate_via_revm(&params, &mut db, &[], &Approval::Transaction).unwrap();
+        assert_eq!(U256::from_be_slice(&output.result.unwrap()), params.value);
+
+        let bundle = serde_json::to_value(build_approve_and_call_bundle(
+            &params,
+            &Approval::Transaction,
+        ))
+        .unwrap();
+        assert_eq!(bundle["blockOverride"], json!({ "coinbase": builder }));
+        assert!(!supports_eth_call_fallback(&params));
+    }
+
+    const VAULT: Address = address!("0x7000000000000000000000000000000000000007");
+    // convertToAssets(shares) returns 2 * shares, balanceOf(account) reads slot `account`,
+    // and any other call mints 100 shares to the caller
+    const DOUBLING_VAULT: Bytes = bytes!(
+        "5f3560e01c806307a2d13a14602157806370a0823114602e5760643354013355005b6004356002025f5260205ff35b600435545f5260205ff3"
+    );
+
+    fn deposit_params() -> SimulationParams {
+        SimulationParams {
+            user: USER,
+            token_in: TOKEN,
+            to: VAULT,
+            token_out: Some(VAULT),
+            amount_out_conversion: Some(VaultConversion::ToAssets),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_vault_shares_convert_to_assets() {
+        let mut db = offline_db(&[(TOKEN, ERC20), (VAULT, DOUBLING_VAULT)]);
+
+        let output =
+            simulate_via_revm(&deposit_params(), &mut db, &[], &Approval::Transaction).unwrap();
+
+        assert_eq!(output.amount_out, Some(U256::from(100)));
+        assert_eq!(output.amount_out_converted, Some(U256::from(200)));
+    }
+
+    #[test]
+    fn test_conversion_calldata() {
+        let assets = IERC4626::convertToAssetsCall::SELECTOR;
+        let shares = IERC4626::convertToSharesCall::SELECTOR;
+        assert_eq!(assets, [0x07, 0xa2, 0xd1, 0x3a]);
+        assert_eq!(
+            VaultConversion::ToShares { vault: VAULT }.calldata(U256::from(1))[..4],
+            shares
+        );
+        assert_eq!(VaultConversion::ToAssets.vault(TOKEN), TOKEN);
+        assert_eq!(
+            VaultConversion::ToShares { vault: VAULT }.vault(TOKEN),
+            VAULT
+        );
+    }
+
+    /// Serves a vault whose deposit mints 100 shares convertible to 200 assets. The node
+    /// answers the conversion appended to the bundle when it has one more transaction.
+    fn vault_chain(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "eth_getCode" => {
+                let address: Address = serde_json::from_value(params[0].clone()).unwrap();
+                match address {
+                    MOCK_TOKEN => Ok(json!(ERC20)),
+                    VAULT => Ok(json!(DOUBLING_VAULT)),
+                    _ => Ok(json!("0x")),
+                }
+            }
+            "eth_callMany" => {
+                let mut responses = vec![
+                    json!({ "value": B256::ZERO }),
+                    json!({ "value": B256::with_last_byte(1) }),
+                    json!({ "value": "0x" }),
+                    json!({ "value": B256::with_last_byte(100) }),
+                ];
+                if params[0][0]["transactions"].as_array().unwrap().len() == 5 {
+                    responses.push(json!({ "value": B256::with_last_byte(200) }));
+                }
+
+                Ok(json!([responses]))
+            }
+            _ => token_chain(method, params),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_vault_conversion_via_rpc() {
+        let rpc_url = spawn_mock_rpc(vault_chain).await;
+        let params = SimulationParams {
+            token_in: MOCK_TOKEN,
+            ..deposit_params()
+        };
+
+        let output = Simulator::new()
+            .simulate(1, rpc_url, params, &CancellationToken::new())
+            .await
+            .unwrap();
+
+        assert_eq!(output.path, SimulationPath::Rpc);
+        assert_eq!(output.amount_out, Some(U256::from(100)));
+        assert_eq!(output.amount_out_converted, Some(U256::from(200)));
+    }
+
+    #[cfg(feature = "network-tests")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sdai_deposit_converts_back_to_dai() {
+        dotenvy::dotenv().ok();
+        let rpc_url = std::env::var("ETH_RPC")
+            .expect("ETH_RPC not set in .env")
+            .parse()
+            .unwrap();
+
+        let dai = address!("0x6B175474E89094C44Da98b954EedeAC495271d0F");
+        let sdai = address!("0x83F20F44975D03b1b09e64809B757c47f942BEeA");
+        let amount_in = U256::from(1_000_000_000_000_000_000u128);
+        // deposit(uint256 assets, address receiver)
+        let calldata = [
+            &bytes!("6e553f65")[..],
+            &amount_in.to_be_bytes::<32>(),
+            &B256::left_padding_from(USER.as_slice())[..],
+        ]
+        .concat();
+
+        let params = SimulationParams {
+            user: USER,
+            token_in: dai,
+            to: sdai,
+            calldata: calldata.into(),
+            amount_in,
+            token_out: Some(sdai),
+            amount_out_conversion: Some(VaultConversion::ToAssets),
+            ..Default::default()
+        };
+
+        let output = Simulator::new()
+            .simulate(1, rpc_url, params, &CancellationToken::new())
+            .await
+            .unwrap();
+
+        assert!(output.result.is_ok());
+        let shares = output.amount_out.unwrap();
+        assert!(!shares.is_zero() && shares < amount_in);
+        // The vault rounds down, on the deposit and again on the conversion
+        let assets = output.amount_out_converted.unwrap();
+        assert!(assets <= amount_in && amount_in - assets <= U256::from(2));
+    }
+
+    /// [`token_chain`] where every `MOCK_TOKEN` balance reads 42 at block 5, and 0 at
+    /// the latest block
+    fn balance_history_chain(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "eth_getStorageAt" if params[2] == json!("0x5") => Ok(json!(B256::with_last_byte(42))),
+            _ => token_chain(method, params),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_balance_of_reads_at_block() {
+        let rpc_url = spawn_mock_rpc(balance_history_chain).await;
+        let mut simulator = Simulator::new();
+        let headers = RpcHeaders::default();
+
+        let past = simulator
+            .balance_of(1, rpc_url.clone(), &headers, MOCK_TOKEN, USER, Some(5))
+            .await
+            .unwrap();
+        let latest = simulator
+            .balance_of(1, rpc_url, &headers, MOCK_TOKEN, USER, None)
+            .await
+            .unwrap();
+
+        assert_eq!(past, U256::from(42));
+        assert_eq!(latest, U256::ZERO);
+    }
+
+    #[cfg(feature = "network-tests")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_usdc_balance_of_matches_node() {
+        dotenvy::dotenv().ok();
+        let rpc_url: Url = std::env::var("ETH_RPC")
+            .expect("ETH_RPC not set in .env")
+            .parse()
+            .unwrap();
+
+        let usdc = usdc_params().token_in;
+        // Binance hot wallet
+        let holder = address!("0x28C6c06298d514Db089934071355E5743bf21d60");
+
+        let provider = alloy::providers::ProviderBuilder::new().connect_http(rpc_url.clone());
+        let block_number = provider.get_block_number().await.unwrap();
+        let expected = provider
+            .call(
+                alloy::rpc::types::TransactionRequest::default()
+                    .to(usdc)
+                    .input(balanceOfCall { account: holder }.abi_encode().into()),
+            )
+            .block(BlockId::number(block_number))
+            .await
+            .unwrap();
+
+        let balance = Simulator::new()
+            .balance_of(
+                1,
+                rpc_url,
+                &RpcHeaders::default(),
+                usdc,
+                holder,
+                Some(block_number),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(balance, decode_uint256(&expected).unwrap());
+    }
+
+    /// Serves [`token_chain`] at a 1 gwei base fee, with the node reporting the gas used
+    fn fee_chain(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "eth_getBlockByNumber" => Ok(json!({
+                "number": params[0],
+                "timestamp": "0x64",
+                "baseFeePerGas": "0x3b9aca00",
+            })),
+            "eth_callMany" => Ok(json!([[
+                { "value": B256::with_last_byte(1) },
+                { "value": "0x", "gasUsed": "0xb411" },
+            ]])),
+            _ => token_chain(method, params),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_gas_cost_prices_gas_used() {
+        let rpc_url = spawn_mock_rpc(fee_chain).await;
+        let params = |gas_price| SimulationParams {
+            token_in: MOCK_TOKEN,
+            gas_price,
+            priority_fee: 2_000_000_000,
+            ..usdc_params()
+        };
+
+        let mut simulator = Simulator::new();
+        let output = simulator
+            .simulate(1, rpc_url.clone(), params(None), &CancellationToken::new())
+            .await
+            .unwrap();
+
+        assert_eq!(output.gas_used, Some(0xb411));
+        assert_eq!(output.effective_gas_price, Some(3_000_000_000));
+        let gas_cost = output.gas_cost_wei.unwrap();
+        assert!(!gas_cost.is_zero());
+        assert_eq!(gas_cost, U256::from(0xb411u64 * 3_000_000_000));
+
+        // An explicit gas price is what the transaction pays
+        let output = simulator
+            .simulate(
+                1,
+                rpc_url.clone(),
+                params(Some(5)),
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(output.effective_gas_price, Some(5));
+        assert_eq!(output.gas_cost_wei, Some(U256::from(0xb411u64 * 5)));
+
+        // A max fee caps the base fee plus tip
+        let output = simulator
+            .simulate(
+                1,
+                rpc_url,
+                SimulationParams {
+                    max_fee_per_gas: Some(2_500_000_000),
+                    ..params(None)
+                },
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(output.effective_gas_price, Some(2_500_000_000));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_gas_cost_unknown_without_block_header() {
+        let rpc_url = spawn_mock_rpc(token_chain).await;
+        let params = SimulationParams {
+            token_in: MOCK_TOKEN,
+            ..usdc_params()
+        };
+
+        let output = Simulator::new()
+            .simulate(1, rpc_url, params, &CancellationToken::new())
+            .await
+            .unwrap();
+
+        assert!(output.result.is_ok());
+        assert!(output.effective_gas_price.is_none());
+        assert!(output.gas_cost_wei.is_none());
+    }
+
+    const DEADLINE_ROUTER: Address = address!("0x5000000000000000000000000000000000000005");
+    // Reverts when TIMESTAMP exceeds the deadline in the first calldata word, returns
+    // TIMESTAMP otherwise
+    const DEADLINE_ROUTER_CODE: Bytes = bytes!("5f354211600e57425f5260205ff35b5f5ffd");
+
+    fn deadline_chain(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "eth_getBlockByNumber" => Ok(json!({ "number": params[0], "timestamp": "0x64" })),
+            "eth_getCode" if params[0] == json!(DEADLINE_ROUTER) => Ok(json!(DEADLINE_ROUTER_CODE)),
+            _ => erc20_revm_chain(method, params),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_deadline_safe_timestamp() {
+        let rpc_url = spawn_mock_rpc(deadline_chain).await;
+        // Deadline a minute after the pinned block, mined at 100
+        let params = || SimulationParams {
+            token_in: MOCK_TOKEN,
+            to: DEADLINE_ROUTER,
+            calldata: U256::from(160).abi_encode().into(),
+            ..usdc_params()
+        };
+
+        let mut simulator = Simulator::new();
+        let mut simulate = async |params| {
+            simulator
+                .simulate(1, rpc_url.clone(), params, &CancellationToken::new())
+                .await
+                .unwrap()
+                .result
+        };
+
+        // REVM runs at timestamp 1 without override
+        assert_eq!(
+            simulate(params()).await,
+            Ok(U256::from(1).abi_encode().into())
+        );
+
+        let deadline_safe = SimulationParams {
+            deadline_safe: true,
+            ..params()
+        };
+        assert_eq!(
+            simulate(deadline_safe).await,
+            Ok(U256::from(112).abi_encode().into())
+        );
+
+        let expired = SimulationParams {
+            timestamp: Some(161),
+            deadline_safe: true,
+            ..params()
+        };
+        assert!(simulate(expired).await.is_err());
+
+        let bundle = build_approve_and_call_bundle(
+            &SimulationParams {
+                timestamp: Some(112),
+                ..params()
+            },
+            &Approval::Transaction,
+        );
+        assert_eq!(
+            serde_json::to_value(bundle.block_override).unwrap(),
+            json!({ "timestamp": 112 })
+        );
+    }
+
+    #[test]
+    fn test_simulation_context_sends_transaction_index() {
+        let context = serde_json::to_value(build_simulation_context(&usdc_params(), 16)).unwrap();
+        assert!(context.get("transactionIndex").is_none());
+
+        let params = SimulationParams {
+            transaction_index: Some(3),
+            ..usdc_params()
+        };
+        let context = serde_json::to_value(build_simulation_context(&params, 16)).unwrap();
+        assert_eq!(
+            context,
+            json!({ "blockNumber": "0x10", "transactionIndex": 3 })
+        );
+    }
+
+    async fn count_simulate_requests(simulator: &mut Simulator, rpc_url: Url) -> usize {
+        let params = SimulationParams {
+            token_in: MOCK_TOKEN,
+            ..usdc_params()
+        };
+
+        TOKEN_RPC_REQUESTS.store(0, Ordering::SeqCst);
+        let output = simulator
+            .simulate(1, rpc_url, params, &CancellationToken::new())
+            .await
+            .unwrap();
+        assert!(output.result.is_ok());
+
+        TOKEN_RPC_REQUESTS.load(Ordering::SeqCst)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_warm_reduces_simulate_requests() {
+        let rpc_url = spawn_mock_rpc(token_rpc).await;
+        let user = usdc_params().user;
+
+        let mut cold_simulator = Simulator::new();
+        let cold_requests = count_simulate_requests(&mut cold_simulator, rpc_url.clone()).await;
+
+        let mut warm_simulator = Simulator::new();
+        warm_simulator
+            .warm(
+                1,
+                rpc_url.clone(),
+                &RpcHeaders::new(),
+                vec![(MOCK_TOKEN, user)],
+            )
+            .await
+            .unwrap();
+        let warm_requests = count_simulate_requests(&mut warm_simulator, rpc_url).await;
+
+        assert!(
+            warm_requests < cold_requests,
+            "warm: {warm_requests}, cold: {cold_requests}"
+        );
+    }
+
+    #[test]
+    fn test_stored_balance_undoes_rebasing_index() {
+        let mut balance_slot = SlotWithAddress {
+            address: MOCK_TOKEN,
+            slot: U256::from(9),
+            scale: None,
+            packing: None,
+        };
+        let amount = U256::from(1_000_000);
+
+        assert_eq!(stored_balance(&balance_slot, amount), amount);
+
+        // Index of 1.1: storing 1234567890 shares made balanceOf return 1358024679
+        balance_slot.scale = Some(BalanceScale {
+            probe: U256::from(1_234_567_890u64),
+            balance: U256::from(1_358_024_679u64),
+        });
+
+        // 909091 * 1.1 rounds down to exactly 1000000
+        assert_eq!(stored_balance(&balance_slot, amount), U256::from(909_091));
+    }
+
+    #[test]
+    fn test_reported_allowance_equals_approved_amount() {
+        let params = SimulationParams {
+            report_allowance: true,
+            ..swap_params()
+        };
+
+        let bundle = build_approve_and_call_bundle(&params, &Approval::Transaction);
+        let allowance_tx = &bundle.transactions[2];
+        let allowance_call =
+            allowanceCall::abi_decode(allowance_tx.data.as_ref().unwrap()).unwrap();
+
+        assert_eq!(allowance_tx.to, Some(params.token_in));
+        assert_eq!(allowance_call.owner, params.user);
+        assert_eq!(allowance_call.spender, params.spender());
+
+        let responses = [
+            uint_response(100),
+            uint_response(1),
+            detailed(TransactionResponse::Success {
+                value: U256::MAX.abi_encode().into(),
+            }),
+            uint_response(0),
+            uint_response(600),
+        ];
+
+        let output = parse_bundle_responses(&params, true, &responses).unwrap();
+
+        // `approve` grants the maximum allowance
+        assert_eq!(output.allowance, Some(U256::MAX));
+        assert_eq!(output.amount_out, Some(U256::from(500)));
+    }
+
+    static HEAD: AtomicUsize = AtomicUsize::new(0x10);
+    static REQUESTED_BLOCKS: Mutex<Vec<Value>> = Mutex::new(Vec::new());
+
+    /// Same chain as [`token_chain`], but a new block lands after every request.
+    /// Records the block each state read and `eth_callMany` targets.
+    fn advancing_head_rpc(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        let head = HEAD.fetch_add(1, Ordering::SeqCst);
+
+        let block = match method {
+            "eth_blockNumber" => return Ok(json!(format!("{head:#x}"))),
+            "eth_getBalance" | "eth_getTransactionCount" | "eth_getCode" => params[1].clone(),
+            "eth_getStorageAt" => params[2].clone(),
+            "eth_callMany" => params[1]["blockNumber"].clone(),
+            _ => return token_chain(method, params),
+        };
+        REQUESTED_BLOCKS.lock().unwrap().push(block);
+
+        token_chain(method, params)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_simulation_pinned_to_resolved_block() {
+        let rpc_url = spawn_mock_rpc(advancing_head_rpc).await;
+        let params = SimulationParams {
+            token_in: MOCK_TOKEN,
+            ..usdc_params()
+        };
+
+        let output = Simulator::new()
+            .simulate(1, rpc_url, params, &CancellationToken::new())
+            .await
+            .unwrap();
+
+        let pinned = json!(format!("{:#x}", output.block_number));
+        let requested_blocks = REQUESTED_BLOCKS.lock().unwrap();
+        assert!(HEAD.load(Ordering::SeqCst) as u64 > output.block_number + 1);
+        assert!(requested_blocks.len() > 1);
+        assert!(
+            requested_blocks.iter().all(|block| *block == pinned),
+            "pinned to {pinned}, requested {requested_blocks:?}"
+        );
+    }
+
+    fn mapping_slot(holder: Address) -> SlotWithAddress {
+        SlotWithAddress {
+            address: MOCK_TOKEN,
+            slot: keccak256((holder, U256::from(3)).abi_encode()).into(),
+            scale: None,
+            packing: None,
+        }
+    }
+
+    #[test]
+    fn test_fund_two_distinct_holders() {
+        let borrower = address!("0x3000000000000000000000000000000000000003");
+        let liquidator = address!("0x4000000000000000000000000000000000000004");
+        let funded_slots = [
+            (mapping_slot(borrower), U256::from(100)),
+            (mapping_slot(liquidator), U256::from(200)),
+        ];
+
+        let mut db = CacheDB::new(EmptyDB::default());
+        db.insert_account_info(
+            MOCK_TOKEN,
+            AccountInfo::default().with_code(Bytecode::new_raw(SOLIDITY_BALANCE_OF)),
+        );
+        fund_slots(&mut db, &funded_slots).unwrap();
+
+        for (holder, expected) in [(borrower, 100), (liquidator, 200)] {
+            let tx_env = build_balance_of_tx_env(MOCK_TOKEN, TokenStandard::Erc20, holder).unwrap();
+            let mut evm = Context::mainnet().with_db(&mut db).build_mainnet();
+            let output = into_simulation_result(evm.transact_one(tx_env).unwrap())
+                .unwrap()
+                .unwrap();
+
+            assert_eq!(U256::from_be_slice(&output), U256::from(expected));
+        }
+
+        let state_overrides = build_state_overrides(&usdc_params(), &funded_slots);
+        let state_diff = state_overrides[&MOCK_TOKEN].state_diff.as_ref().unwrap();
+        assert_eq!(state_diff.len(), 2);
+        assert_eq!(
+            state_diff[&B256::from(funded_slots[1].0.slot)],
+            B256::from(U256::from(200))
+        );
+    }
+
+    fn read_token_uint(db: &mut OfflineDb, data: Bytes) -> U256 {
+        let tx_env = TxEnv::builder()
+            .kind(TxKind::Call(TOKEN))
+            .data(data)
+            .build()
+            .unwrap();
+        let mut evm = Context::mainnet().with_db(db).build_mainnet();
+        let output = into_simulation_result(evm.transact_one(tx_env).unwrap())
+            .unwrap()
+            .unwrap();
+
+        U256::from_be_slice(&output)
+    }
+
+    #[test]
+    fn test_fund_packed_balance_keeps_neighbour_field() {
+        let neighbour = U256::from(0xabcdef);
+        let mut db = offline_db(&[(TOKEN, PACKED_BALANCE_OF)]);
+        db.insert_account_storage(TOKEN, U256::from(5), (neighbour << 96) | U256::from(42))
+            .unwrap();
+        let (balance_slot, _, _) = find_token_balance_layout_fast(
+            TOKEN,
+            TokenStandard::Erc20,
+            USER,
+            false,
+            DEFAULT_MAX_CANDIDATES,
+            &mut db,
+        )
+        .unwrap();
+        let amount = U256::from(1_000_000);
+        let balance_of = balanceOfCall { account: USER }.abi_encode();
+        let total_supply = totalSupplyCall {}.abi_encode();
+
+        // Writing the amount over the whole word wipes the field next to the balance
+        let mut naive_db = db.clone();
+        naive_db
+            .insert_account_storage(TOKEN, balance_slot.slot, amount)
+            .unwrap();
+        assert_eq!(
+            read_token_uint(&mut naive_db, total_supply.clone().into()),
+            U256::ZERO
+        );
+
+        fund_slots(&mut db, &[(balance_slot.clone(), amount)]).unwrap();
+
+        assert_eq!(read_token_uint(&mut db, balance_of.into()), amount);
+        assert_eq!(read_token_uint(&mut db, total_supply.into()), neighbour);
+        assert!(overflowing_amount(&[(balance_slot, U256::ONE << 96)]).is_some());
+    }
+
+    fn sign_raw_tx(signer: &PrivateKeySigner, chain_id: u64, to: TxKind, input: Bytes) -> Bytes {
+        let tx = TxEip1559 {
+            chain_id,
+            nonce: 7,
+            gas_limit: 200_000,
+            max_fee_per_gas: 2_000_000_000,
+            max_priority_fee_per_gas: 1_000_000_000,
+            to,
+            value: U256::ZERO,
+            access_list: Default::default(),
+            input,
+        };
+        let signature = signer.sign_hash_sync(&tx.signature_hash()).unwrap();
+
+        TxEnvelope::from(tx.into_signed(signature))
+            .encoded_2718()
+            .into()
+    }
+
+    #[test]
+    fn test_decode_raw_tx() {
+        let signer = PrivateKeySigner::from_bytes(&B256::with_last_byte(1)).unwrap();
+        let calldata = bytes!("12345678");
+        let raw_tx = sign_raw_tx(&signer, 1, TxKind::Call(MOCK_SPENDER), calldata.clone());
+
+        let params = SimulationParams {
+            token_in: MOCK_TOKEN,
+            steps: vec![CallStep::default()],
+            ..usdc_params()
+        }
+        .with_raw_tx(&decode_raw_tx(&raw_tx).unwrap());
+
+        assert_eq!(params.user, signer.address());
+        assert_eq!(params.to, MOCK_SPENDER);
+        assert_eq!(params.calldata, calldata);
+        assert_eq!(params.gas_limit, Some(200_000));
+        assert_eq!(params.nonce, Some(7));
+        assert_eq!(params.gas_price, None);
+        assert_eq!(params.priority_fee, 1_000_000_000);
+        assert_eq!(params.max_fee_per_gas, Some(2_000_000_000));
+        assert!(params.steps.is_empty());
+        // Balance overrides are kept from the overrides
+        assert_eq!(params.token_in, MOCK_TOKEN);
+        assert_eq!(params.amount_in, usdc_params().amount_in);
+
+        assert!(matches!(
+            decode_raw_tx(&raw_tx[..raw_tx.len() - 1]),
+            Err(RawTxError::Decode(_))
+        ));
+        let create = sign_raw_tx(&signer, 1, TxKind::Create, calldata);
+        assert!(matches!(decode_raw_tx(&create), Err(RawTxError::Create)));
+    }
+
+    static RAW_TX_BUNDLES: Mutex<Vec<Value>> = Mutex::new(Vec::new());
+
+    /// [`token_chain`], recording the bundles sent with `eth_callMany`
+    fn raw_tx_rpc(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        if method == "eth_callMany" {
+            RAW_TX_BUNDLES.lock().unwrap().push(params[0][0].clone());
+        }
+
+        match method {
+            // The transaction calls MOCK_SPENDER, which must be a contract
+            "eth_getCode"
+                if serde_json::from_value::<Address>(params[0].clone()).unwrap()
+                    == MOCK_SPENDER =>
+            {
+                Ok(json!(ALLOWANCE_READER))
+            }
+            _ => token_chain(method, params),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_simulate_raw_tx() {
+        let signer = PrivateKeySigner::from_bytes(&B256::with_last_byte(1)).unwrap();
+        let calldata = bytes!("12345678");
+        let raw_tx = sign_raw_tx(&signer, 1, TxKind::Call(MOCK_SPENDER), calldata.clone());
+        let overrides = || SimulationParams {
+            token_in: MOCK_TOKEN,
+            ..usdc_params()
+        };
+
+        // The node is sent the transaction as signed, after the approval
+        let rpc_url = spawn_mock_rpc(raw_tx_rpc).await;
+        let output = Simulator::new()
+            .simulate_raw_tx(
+                1,
+                rpc_url.clone(),
+                raw_tx.clone(),
+                overrides(),
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(output.path, SimulationPath::Rpc);
+
+        let bundle = RAW_TX_BUNDLES.lock().unwrap()[0].clone();
+        let call = &bundle["transactions"][1];
+        assert_eq!(call["from"], json!(signer.address()));
+        assert_eq!(call["to"], json!(MOCK_SPENDER));
+        assert_eq!(call["data"], json!(calldata));
+        // The approval takes the signed nonce
+        assert_eq!(bundle["transactions"][0]["nonce"], json!("0x7"));
+        assert_eq!(call["nonce"], json!("0x8"));
+
+        // REVM funds and approves the signer the same way
+        let rpc_url = spawn_mock_rpc(erc20_revm_chain).await;
+        let output = Simulator::new()
+            .simulate_raw_tx(
+                1,
+                rpc_url.clone(),
+                raw_tx.clone(),
+                overrides(),
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(output.path, SimulationPath::Revm);
+        assert!(!U256::from_be_slice(&output.result.unwrap()).is_zero());
+
+        let result = Simulator::new()
+            .simulate_raw_tx(10, rpc_url, raw_tx, overrides(), &CancellationToken::new())
+            .await;
+        assert!(matches!(
+            result,
+            Err(SimulateError::ChainIdMismatch {
+                expected: 10,
+                actual: 1
+            })
+        ));
+    }
+
+    static SERVED_REQUESTS: AtomicUsize = AtomicUsize::new(0);
+
+    /// [`token_chain`], counting the requests it serves
+    fn served_requests_rpc(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        SERVED_REQUESTS.fetch_add(1, Ordering::SeqCst);
+
+        token_chain(method, params)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_rpc_request_count_drops_once_warm() {
+        let rpc_url = spawn_mock_rpc(served_requests_rpc).await;
+        let params = || SimulationParams {
+            token_in: MOCK_TOKEN,
+            ..usdc_params()
+        };
+        let mut simulator = Simulator::new();
+
+        let mut counts = Vec::new();
+        for _ in 0..2 {
+            let served_before = SERVED_REQUESTS.load(Ordering::SeqCst);
+            let output = simulator
+                .simulate(1, rpc_url.clone(), params(), &CancellationToken::new())
+                .await
+                .unwrap();
+
+            let served = SERVED_REQUESTS.load(Ordering::SeqCst) - served_before;
+            assert_eq!(output.rpc_request_count, served as u64);
+            counts.push(output.rpc_request_count);
+        }
+
+        assert!(
+            counts[1] < counts[0],
+            "cold: {}, warm: {}",
+            counts[0],
+            counts[1]
+        );
+    }
+
+    const GAS_PRICE_READER: Address = address!("0x6000000000000000000000000000000000000006");
+
+    /// [`fee_chain`] with [`ERC20`] and a contract returning `tx.gasprice`, rejecting
+    /// `eth_callMany` so that simulations run in REVM
+    fn gas_price_revm_chain(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "eth_getCode" => {
+                let address: Address = serde_json::from_value(params[0].clone()).unwrap();
+                match address {
+                    MOCK_TOKEN => Ok(json!(ERC20)),
+                    // GASPRICE PUSH0 MSTORE PUSH1 0x20 PUSH0 RETURN
+                    GAS_PRICE_READER => Ok(json!(bytes!("3a5f5260205ff3"))),
+                    _ => Ok(json!("0x")),
+                }
+            }
+            "eth_callMany" => Err((-32601, "method not found".to_string())),
+            _ => fee_chain(method, params),
+        }
+    }
+
+    static GAS_PRICE_BUNDLES: Mutex<Vec<Value>> = Mutex::new(Vec::new());
+
+    /// [`fee_chain`], recording the bundles sent with `eth_callMany`
+    fn gas_price_rpc_chain(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        if method == "eth_callMany" {
+            GAS_PRICE_BUNDLES.lock().unwrap().push(params[0][0].clone());
+        }
+
+        fee_chain(method, params)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_gas_price_defaults_to_base_fee() {
+        let params = |gas_price| SimulationParams {
+            token_in: MOCK_TOKEN,
+            to: GAS_PRICE_READER,
+            gas_price,
+            priority_fee: 2_000_000_000,
+            ..usdc_params()
+        };
+
+        let rpc_url = spawn_mock_rpc(gas_price_revm_chain).await;
+        let mut simulator = Simulator::new();
+        for (gas_price, expected) in [(None, 3_000_000_000u64), (Some(5), 5)] {
+            let output = simulator
+                .simulate(
+                    1,
+                    rpc_url.clone(),
+                    params(gas_price),
+                    &CancellationToken::new(),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(output.path, SimulationPath::Revm);
+            assert_eq!(output.result.unwrap(), U256::from(expected).abi_encode());
+        }
+
+        // The node is only sent an explicit price, as it would charge the default one to
+        // a user without ETH
+        let rpc_url = spawn_mock_rpc(gas_price_rpc_chain).await;
+        let mut simulator = Simulator::new();
+        for gas_price in [None, Some(5)] {
+            let output = simulator
+                .simulate(
+                    1,
+                    rpc_url.clone(),
+                    params(gas_price),
+                    &CancellationToken::new(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(output.path, SimulationPath::Rpc);
+            assert_eq!(
+                output.effective_gas_price,
+                Some(gas_price.unwrap_or(3_000_000_000))
+            );
+        }
+
+        let bundles = GAS_PRICE_BUNDLES.lock().unwrap().clone();
+        assert_eq!(bundles[0]["transactions"][1].get("gasPrice"), None);
+        assert_eq!(
+            bundles[1]["transactions"][1]["gasPrice"],
+            json!(U256::from(5))
+        );
+    }
+
+    /// [`token_chain`] on a node that pruned the storage of the block
+    fn pruned_chain(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "eth_getStorageAt" => Err((
+                -32000,
+                "missing trie node 6f2b7c0e0a5d (path ) state 0x6f2b7c0e0a5d is not available"
+                    .to_string(),
+            )),
+            _ => token_chain(method, params),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_pruned_state_is_state_unavailable() {
+        let rpc_url = spawn_mock_rpc(pruned_chain).await;
+        let params = SimulationParams {
+            token_in: MOCK_TOKEN,
+            ..usdc_params()
+        };
+
+        let error = Simulator::new()
+            .simulate(1, rpc_url, params, &CancellationToken::new())
+            .await
+            .unwrap_err();
+
+        let SimulateError::StateUnavailable { block, message } = &error else {
+            panic!("expected StateUnavailable, got {error:?}");
+        };
+        assert_eq!(*block, MOCK_BLOCK);
+        assert!(message.starts_with("missing trie node"), "{message}");
+        assert_eq!(error.code(), "state_unavailable");
+        assert!(error.to_string().contains("archive node"));
+    }
+
+    #[test]
+    fn test_other_rpc_errors_are_kept() {
+        let error = SimulateError::Rpc(RpcError::ErrorResp(ErrorPayload {
+            code: -32000,
+            message: "execution reverted".into(),
+            data: None,
+        }));
+
+        assert!(matches!(
+            error.or_state_unavailable(16),
+            SimulateError::Rpc(_)
+        ));
+    }
+
+    /// [`ERC20`] layout whose `approve` reverts, leaving `increaseAllowance` as the only
+    /// way to grant an allowance
+    const INCREASE_ONLY_ERC20: Bytes = bytes!(
+        "5f3560e01c8063dd62ed3e14602557806339509351146047576370a08231146070575f5ffd5b6004355f52600460205260405f206020526024355f5260405f20545f5260205ff35b335f52600460205260405f206020526004355f5260405f20805460243501905560015f5260205ff35b6004355f52600360205260405f20545f5260205ff3"
+    );
+
+    fn increase_only_chain(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "eth_getCode"
+                if serde_json::from_value::<Address>(params[0].clone()).unwrap() == MOCK_TOKEN =>
+            {
+                Ok(json!(INCREASE_ONLY_ERC20))
+            }
+            _ => erc20_revm_chain(method, params),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_increase_allowance_mode() {
+        let params = |approve_mode| SimulationParams {
+            token_in: MOCK_TOKEN,
+            to: MOCK_SPENDER,
+            approve_mode: Some(approve_mode),
+            ..usdc_params()
+        };
+        let simulate = |rpc_url, approve_mode| async move {
+            Simulator::new()
+                .simulate(1, rpc_url, params(approve_mode), &CancellationToken::new())
+                .await
+        };
+
+        let rpc_url = spawn_mock_rpc(increase_only_chain).await;
+        let output = simulate(rpc_url.clone(), ApproveMode::IncreaseAllowance)
+            .await
+            .unwrap();
+        assert_eq!(output.path, SimulationPath::Revm);
+        assert_eq!(
+            U256::from_be_slice(output.result.as_ref().unwrap()),
+            usdc_params().amount_in
+        );
+        // `approve` reverts, which is reported rather than failing the simulation
+        let output = simulate(rpc_url, ApproveMode::Transaction).await.unwrap();
+        assert!(matches!(output.approve_result, Some(Err(_))));
+
+        // Without `increaseAllowance`, the token is approved as usual
+        let rpc_url = spawn_mock_rpc(erc20_revm_chain).await;
+        let output = simulate(rpc_url, ApproveMode::IncreaseAllowance)
+            .await
+            .unwrap();
+        assert_eq!(
+            U256::from_be_slice(output.result.as_ref().unwrap()),
+            U256::MAX
+        );
+    }
+
+    #[test]
+    fn test_increase_allowance_bundle() {
+        let params = SimulationParams {
+            approve_mode: Some(ApproveMode::IncreaseAllowance),
+            ..usdc_params()
+        };
+
+        let bundle = build_approve_and_call_bundle(&params, &Approval::IncreaseAllowance);
+        let approve_tx = bundle
+            .transactions
+            .iter()
+            .find(|tx| tx.to == Some(params.token_in))
+            .unwrap();
+        let call = increaseAllowanceCall::abi_decode(approve_tx.data.as_ref().unwrap()).unwrap();
+        assert_eq!(call.spender, params.spender());
+        assert_eq!(call.addedValue, params.amount_in);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_simulate_with_provider() {
+        let rpc_url = spawn_mock_rpc(erc20_revm_chain).await;
+        let params = || SimulationParams {
+            token_in: MOCK_TOKEN,
+            to: MOCK_SPENDER,
+            ..usdc_params()
+        };
+        // Another provider type than the simulator's own connections
+        let provider = alloy::providers::ProviderBuilder::new()
+            .disable_recommended_fillers()
+            .connect_http(rpc_url.clone());
+
+        let mut simulator = Simulator::new();
+        let output = simulator
+            .simulate_with_provider(1, provider.clone(), params(), &CancellationToken::new())
+            .await
+            .unwrap();
+        let expected = Simulator::new()
+            .simulate(1, rpc_url, params(), &CancellationToken::new())
+            .await
+            .unwrap();
+
+        assert_eq!(output.path, SimulationPath::Revm);
+        assert_eq!(output.result, expected.result);
+        assert_eq!(output.gas_used, expected.gas_used);
+        assert_eq!(output.rpc_request_count, 0);
+        assert!(simulator.db_caches.contains_key(&1));
+
+        let mismatch = simulator
+            .simulate_with_provider(10, provider, params(), &CancellationToken::new())
+            .await;
+        assert!(matches!(
+            mismatch,
+            Err(SimulateError::ChainIdMismatch { actual: 1, .. })
+        ));
+    }
+
+    /// [`ERC20`] layout that accepts `approve` but reverts every other call, transfers
+    /// included, with `Pausable: paused`
+    const PAUSED_ERC20: Bytes = bytes!(
+        "5f3560e01c806370a0823114602b5763095ea7b314602257606460415f3960645ffd5b60015f5260205ff35b6004355f52600360205260405f20545f5260205ff308c379a0000000000000000000000000000000000000000000000000000000000000002000000000000000000000000000000000000000000000000000000000000000105061757361626c653a2070617573656400000000000000000000000000000000"
+    );
+
+    fn paused_chain(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "eth_getCode"
+                if serde_json::from_value::<Address>(params[0].clone()).unwrap() == MOCK_TOKEN =>
+            {
+                Ok(json!(PAUSED_ERC20))
+            }
+            _ => erc20_revm_chain(method, params),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_paused_token_is_diagnosed() {
+        let rpc_url = spawn_mock_rpc(paused_chain).await;
+        let params = |diagnose_failures| SimulationParams {
+            token_in: MOCK_TOKEN,
+            to: MOCK_TOKEN,
+            calldata: transferCall {
+                to: MOCK_SPENDER,
+                value: U256::from(1),
+            }
+            .abi_encode()
+            .into(),
+            diagnose_failures,
+            ..usdc_params()
+        };
+
+        let output = Simulator::new()
+            .simulate(1, rpc_url.clone(), params(true), &CancellationToken::new())
+            .await
+            .unwrap();
+        assert_eq!(output.result, Err("revert: Pausable: paused".to_string()));
+        assert_eq!(output.diagnostic_hint, Some(DiagnosticHint::TokenPaused));
+
+        let output = Simulator::new()
+            .simulate(1, rpc_url, params(false), &CancellationToken::new())
+            .await
+            .unwrap();
+        assert!(output.result.is_err());
+        assert_eq!(output.diagnostic_hint, None);
+    }
+
+    #[test]
+    fn test_diagnostic_hint_from_revert_pattern() {
+        let hint =
+            |reason: &str| DiagnosticHint::from_failed_transfer(&Revert::from(reason).abi_encode());
+
+        assert_eq!(hint("Pausable: paused"), DiagnosticHint::TokenPaused);
+        assert_eq!(
+            hint("Blacklistable: account is blacklisted"),
+            DiagnosticHint::Blacklisted
+        );
+        assert_eq!(hint("Account frozen"), DiagnosticHint::Blacklisted);
+        assert_eq!(
+            hint("Trading not enabled"),
+            DiagnosticHint::TransferDisabled
+        );
+        assert_eq!(
+            DiagnosticHint::from_failed_transfer(&EnforcedPause {}.abi_encode()),
+            DiagnosticHint::TokenPaused
+        );
+        assert_eq!(
+            DiagnosticHint::from_failed_transfer(&[]),
+            DiagnosticHint::TransferDisabled
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_approve_result_is_reported() {
+        let encoded_true = Bytes::from(true.abi_encode());
+        let params = |approve_mode| SimulationParams {
+            token_in: MOCK_TOKEN,
+            to: MOCK_SPENDER,
+            approve_mode: Some(approve_mode),
+            ..usdc_params()
+        };
+
+        let rpc_url = spawn_mock_rpc(token_chain).await;
+        let output = Simulator::new()
+            .simulate(
+                1,
+                rpc_url,
+                params(ApproveMode::Transaction),
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(output.path, SimulationPath::Rpc);
+        assert_eq!(output.approve_result, Some(Ok(encoded_true.clone())));
+
+        let rpc_url = spawn_mock_rpc(erc20_revm_chain).await;
+        let output = Simulator::new()
+            .simulate(
+                1,
+                rpc_url.clone(),
+                params(ApproveMode::Transaction),
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(output.path, SimulationPath::Revm);
+        assert_eq!(output.approve_result, Some(Ok(encoded_true)));
+
+        // No transaction is sent when the allowance slot is written instead
+        let output = Simulator::new()
+            .simulate(
+                1,
+                rpc_url,
+                params(ApproveMode::SlotOverride),
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(output.approve_result, None);
+    }
+
+    #[test]
+    fn test_reverted_approve_is_reported() {
+        // Both the token and the target revert every call
+        let target = address!("0x5000000000000000000000000000000000000005");
+        let mut db = offline_db(&[(TOKEN, bytes!("5f5ffd")), (target, bytes!("5f5ffd"))]);
+        let params = SimulationParams {
+            user: USER,
+            token_in: TOKEN,
+            to: target,
+            ..Default::default()
+        };
+
+        let output = simulate_via_revm(&params, &mut db, &[], &Approval::Transaction).unwrap();
+
+        assert!(matches!(output.approve_result, Some(Err(_))));
+        assert!(!output.pre_revert_state.unwrap().approve_succeeded);
+
+        let responses = [
+            uint_response(100),
+            detailed(TransactionResponse::Reverted {
+                reason: "paused".to_string(),
+                data: None,
+            }),
+            detailed(TransactionResponse::Reverted {
+                reason: "STF".to_string(),
+                data: None,
+            }),
+            uint_response(100),
+        ];
+
+        let output = parse_bundle_responses(&swap_params(), true, &responses).unwrap();
+
+        assert_eq!(
+            output.approve_result,
+            Some(Err("execution reverted: paused".to_string()))
+        );
+        assert!(!output.pre_revert_state.unwrap().approve_succeeded);
+    }
+
+    #[test]
+    fn test_revm_errors_display_each_variant() {
+        let revert = || ExecutionResult::Revert {
+            gas_used: 21_000,
+            output: Bytes::new(),
+        };
+        let db_error = || DBTransportError(RpcError::local_usage_str("connection refused"));
+        let reverted = "execution failed: Revert { gas_used: 21000, output: 0x }";
+
+        let cases: [(SimulateViaRevmError, &[&str]); 7] = [
+            (db_error().into(), &["loading an account failed"]),
+            (
+                ApproveError::Execution(revert()).into(),
+                &["approving the spender failed", reverted],
+            ),
+            (
+                ApproveError::from(db_error()).into(),
+                &[
+                    "approving the spender failed",
+                    "loading the user account failed",
+                ],
+            ),
+            (
+                EVMError::Database(db_error()).into(),
+                &["executing the call failed"],
+            ),
+            (
+                ReadBalanceError::Execution(revert()).into(),
+                &["reading a token balance failed", reverted],
+            ),
+            (
+                SimulateViaRevmError::ReadAllowance(bool::abi_decode(&[]).unwrap_err().into()),
+                &[
+                    "reading the allowance failed",
+                    "decoding the returned uint256 failed",
+                ],
+            ),
+            (
+                SimulateViaRevmError::ConvertAmountOut(ReadBalanceError::TxBuild(
+                    TxEnvBuildError::MissingTargetForEip4844,
+                )),
+                &[
+                    "converting amount_out through the vault failed",
+                    "building the view call failed: MissingTargetForEip4844",
+                ],
+            ),
+        ];
+
+        for (error, expected) in cases {
+            let chain: Vec<_> = std::iter::successors(
+                Some(&error as &(dyn std::error::Error + 'static)),
+                |error| error.source(),
+            )
+            .map(ToString::to_string)
+            .collect();
+            assert_eq!(&chain[..expected.len()], expected);
+        }
+    }
+
+    /// Answers each transaction of a bundle with the `block.timestamp` it was sent with
+    fn timestamp_echo_chain(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "eth_callMany" => Ok(params[0]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|bundle| {
+                    let timestamp = bundle["blockOverride"]["timestamp"].as_u64().unwrap();
+                    let value = B256::from(U256::from(timestamp));
+                    bundle["transactions"]
+                        .as_array()
+                        .unwrap()
+                        .iter()
+                        .map(|_| json!({ "value": value }))
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>()
+                .into()),
+            _ => token_chain(method, params),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_simulate_bundles_at_different_timestamps() {
+        let rpc_url = spawn_mock_rpc(timestamp_echo_chain).await;
+        let params = SimulationParams {
+            token_in: MOCK_TOKEN,
+            ..usdc_params()
+        };
+        let step = CallStep {
+            to: MOCK_SPENDER,
+            ..Default::default()
+        };
+        let bundles = vec![
+            CallBundle {
+                steps: vec![step.clone()],
+                timestamp: Some(1_700_000_000),
+                ..Default::default()
+            },
+            CallBundle {
+                steps: vec![step.clone(), step],
+                timestamp: Some(1_700_086_400),
+                ..Default::default()
+            },
+        ];
+
+        let output = Simulator::new()
+            .simulate_bundles(1, rpc_url, params, bundles, &CancellationToken::new())
+            .await
+            .unwrap();
+
+        let values = |bundle: &BundleOutput| {
+            bundle
+                .results
+                .iter()
+                .map(|result| U256::from_be_slice(result.as_ref().unwrap()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(output.bundles.len(), 2);
+        // The approval opening the first bundle is left out of its results
+        assert_eq!(values(&output.bundles[0]), [U256::from(1_700_000_000)]);
+        assert_eq!(values(&output.bundles[1]), [U256::from(1_700_086_400); 2]);
+        assert_eq!(output.block_number, MOCK_BLOCK);
+    }
+
+    #[test]
+    fn test_sequential_bundles_chain_nonces() {
+        let params = SimulationParams {
+            nonce: Some(7),
+            timestamp: Some(1),
+            ..usdc_params()
+        };
+        let step = CallStep {
+            to: params.to,
+            ..Default::default()
+        };
+        let bundles = [
+            CallBundle {
+                steps: vec![step.clone()],
+                ..Default::default()
+            },
+            CallBundle {
+                steps: vec![step],
+                timestamp: Some(2),
+                ..Default::default()
+            },
+        ];
+
+        let request = build_sequential_bundles(&params, &Approval::Transaction, &bundles);
+
+        let nonces: Vec<_> = request
+            .iter()
+            .map(|bundle| {
+                bundle
+                    .transactions
+                    .iter()
+                    .map(|tx| tx.nonce)
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        assert_eq!(nonces, [vec![Some(7), Some(8)], vec![Some(9)]]);
+        assert_eq!(request[0].transactions[0].to, Some(params.token_in));
+        let timestamps: Vec<_> = request
+            .iter()
+            .map(|bundle| bundle.block_override.as_ref().unwrap().timestamp)
+            .collect();
+        assert_eq!(timestamps, [Some(1), Some(2)]);
+    }
+
+    /// [`token_chain`] never answering storage reads, which stalls the slot lookup
+    fn stalled_storage_chain(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "eth_getStorageAt" => Err((NO_ANSWER, String::new())),
+            _ => token_chain(method, params),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_simulate_timeout_aborts_stalled_slot_lookup() {
+        let rpc_url = spawn_mock_rpc(stalled_storage_chain).await;
+        let mut simulator = Simulator::new_with_config(SimulatorConfig {
+            simulate_timeout_ms: Some(200),
+            ..Default::default()
+        });
+        let params = SimulationParams {
+            token_in: MOCK_TOKEN,
+            ..usdc_params()
+        };
+
+        let result = tokio::time::timeout(
+            Duration::from_secs(5),
+            simulator.simulate(1, rpc_url, params, &CancellationToken::new()),
+        )
+        .await
+        .expect("simulate outlived its timeout");
+
+        assert!(
+            matches!(result, Err(SimulateError::Timeout { timeout_ms: 200 })),
+            "{result:?}"
+        );
+        assert!(simulator.db_caches.contains_key(&1));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_eoa_target_is_rejected() {
+        let rpc_url = spawn_mock_rpc(token_chain).await;
+        let eoa = address!("0x4000000000000000000000000000000000000004");
+        let params = |to| SimulationParams {
+            token_in: MOCK_TOKEN,
+            to,
+            calldata: approveCall {
+                spender: eoa,
+                value: U256::ZERO,
+            }
+            .abi_encode()
+            .into(),
+            ..usdc_params()
+        };
+
+        let result = Simulator::new()
+            .simulate(1, rpc_url.clone(), params(eoa), &CancellationToken::new())
+            .await;
+        assert!(
+            matches!(result, Err(SimulateError::TargetNotContract(to)) if to == eoa),
+            "{result:?}"
+        );
+
+        let output = Simulator::new()
+            .simulate(1, rpc_url, params(MOCK_TOKEN), &CancellationToken::new())
+            .await
+            .unwrap();
+        assert_eq!(output.selector, Some(approveCall::SELECTOR.into()));
+    }
+
+    #[test]
+    fn test_selector_of_result_step() {
+        let step = |calldata| CallStep {
+            to: MOCK_TOKEN,
+            calldata,
+            ..Default::default()
+        };
+        let params = SimulationParams {
+            steps: vec![step(bytes!("a9059cbb00")), step(bytes!("0902f1"))],
+            ..usdc_params()
+        };
+        assert_eq!(params.selector(), None);
+
+        let params = SimulationParams {
+            result_step: Some(0),
+            ..params
+        };
+        assert_eq!(params.selector(), Some(transferCall::SELECTOR.into()));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_raw_view_call() {
+        let rpc_url = spawn_mock_rpc(erc20_revm_chain).await;
+        // No token to fund, which the slot lookup would fail on
+        let params = |raw| SimulationParams {
+            token_in: Address::ZERO,
+            to: MOCK_TOKEN,
+            calldata: balanceOfCall {
+                account: usdc_params().user,
+            }
+            .abi_encode()
+            .into(),
+            raw,
+            ..usdc_params()
+        };
+
+        let funded = Simulator::new()
+            .simulate(1, rpc_url.clone(), params(false), &CancellationToken::new())
+            .await;
+        assert!(
+            matches!(funded, Err(SimulateError::FindSlot(_))),
+            "{funded:?}"
+        );
+
+        let output = Simulator::new()
+            .simulate(1, rpc_url, params(true), &CancellationToken::new())
+            .await
+            .unwrap();
+        assert_eq!(output.path, SimulationPath::Revm);
+        assert_eq!(output.result.unwrap(), U256::ZERO.abi_encode());
+        assert_eq!(output.balance_slot, None);
+        assert_eq!(output.approve_result, None);
+    }
+
+    #[test]
+    fn test_slot_confidence() {
+        let five = U256::from(5);
+
+        assert_eq!(
+            SlotVerification::new(five, five).confidence,
+            SlotConfidence::High
+        );
+        assert_eq!(
+            SlotVerification::new(U256::ZERO, U256::ZERO).confidence,
+            SlotConfidence::Medium
+        );
+        assert_eq!(
+            SlotVerification::new(five, U256::from(10)).confidence,
+            SlotConfidence::Low
+        );
+    }
+
+    /// Serves [`token_chain`] with every slot holding 5
+    fn held_token_chain(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "eth_getStorageAt" => Ok(json!(B256::with_last_byte(5))),
+            _ => token_chain(method, params),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_verify_balance_slot_against_chain() {
+        let rpc_url = spawn_mock_rpc(held_token_chain).await;
+        let params = |verify_against_chain| SimulationParams {
+            token_in: MOCK_TOKEN,
+            verify_against_chain,
+            ..usdc_params()
+        };
+
+        let unverified = Simulator::new()
+            .simulate(1, rpc_url.clone(), params(false), &CancellationToken::new())
+            .await
+            .unwrap();
+        assert_eq!(unverified.slot_verification, None);
+
+        let output = Simulator::new()
+            .simulate(1, rpc_url, params(true), &CancellationToken::new())
+            .await
+            .unwrap();
+        let verification = output.slot_verification.unwrap();
+        assert_eq!(verification.stored, U256::from(5));
+        assert_eq!(verification.balance, U256::from(5));
+        assert_eq!(verification.confidence, SlotConfidence::High);
+    }
+
+    #[cfg(feature = "network-tests")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_usdc_slot_verifies_with_high_confidence() {
+        dotenvy::dotenv().ok();
+        let rpc_url: Url = std::env::var("ETH_RPC")
+            .expect("ETH_RPC not set in .env")
+            .parse()
+            .unwrap();
+        let params = SimulationParams {
+            // Binance hot wallet, which always holds USDC
+            user: address!("0x28C6c06298d514Db089934071355E5743bf21d60"),
+            verify_against_chain: true,
+            ..usdc_params()
+        };
+
+        let output = Simulator::new()
+            .simulate(1, rpc_url, params, &CancellationToken::new())
+            .await
+            .unwrap();
+
+        let verification = output.slot_verification.unwrap();
+        assert_eq!(verification.confidence, SlotConfidence::High);
+        assert_eq!(verification.stored, verification.balance);
+    }
+
+    /// Serves [`token_chain`], but caps the gas of a whole `eth_callMany`
+    fn gas_capped_chain(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "eth_callMany" => Err((
+                -32000,
+                "gas required exceeds the RPC gas cap of 50000000".to_string(),
+            )),
+            _ => token_chain(method, params),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_gas_cap_exceeded_is_reported() {
+        let rpc_url = spawn_mock_rpc(gas_capped_chain).await;
+        let params = SimulationParams {
+            token_in: MOCK_TOKEN,
+            ..usdc_params()
+        };
+        let bundles = vec![CallBundle {
+            steps: vec![CallStep {
+                to: MOCK_TOKEN,
+                ..Default::default()
+            }],
+            ..Default::default()
+        }];
+
+        let error = Simulator::new()
+            .simulate_bundles(1, rpc_url, params, bundles, &CancellationToken::new())
+            .await
+            .unwrap_err();
+
+        assert_eq!(error.code(), "gas_cap_exceeded");
+    }
+
+    #[test]
+    fn test_slippage_report() {
+        let amount = |value: u64| U256::from(value);
+
+        let short = SlippageReport::new(amount(990), Some(amount(995)), Some(amount(1000)));
+        assert!(!short.min_met);
+        assert_eq!(short.slippage_bps, Some(100));
+
+        let better = SlippageReport::new(amount(1005), Some(amount(995)), Some(amount(1000)));
+        assert!(better.min_met);
+        assert_eq!(better.slippage_bps, Some(-50));
+
+        let unquoted = SlippageReport::new(amount(990), None, Some(U256::ZERO));
+        assert!(unquoted.min_met);
+        assert_eq!(unquoted.slippage_bps, None);
+    }
+
+    /// Serves [`token_chain`], with `user` holding 100 of `token_out` before the swap and
+    /// 150 after it
+    fn swap_chain(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "eth_callMany" => {
+                let transactions = params[0][0]["transactions"].as_array().unwrap().len();
+                let responses = (0..transactions)
+                    .map(|index| match index {
+                        0 => json!({ "value": B256::with_last_byte(100) }),
+                        _ if index == transactions - 1 => {
+                            json!({ "value": B256::with_last_byte(150) })
+                        }
+                        _ => json!({ "value": B256::with_last_byte(1) }),
+                    })
+                    .collect::<Vec<_>>();
+                Ok(json!([responses]))
+            }
+            _ => token_chain(method, params),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_swap_below_min_amount_out() {
+        let rpc_url = spawn_mock_rpc(swap_chain).await;
+        let params = SwapParams {
+            user: usdc_params().user,
+            token_in: MOCK_TOKEN,
+            amount_in: U256::from(1000),
+            token_out: MOCK_TOKEN,
+            router: usdc_params().to,
+            calldata: Bytes::new(),
+            rpc_headers: RpcHeaders::default(),
+            min_amount_out: Some(U256::from(60)),
+            expected_amount_out: Some(U256::from(80)),
+        };
+
+        let swap = Simulator::new()
+            .simulate_swap(1, rpc_url, params, &CancellationToken::new())
+            .await
+            .unwrap();
+
+        assert_eq!(swap.amount_out, U256::from(50));
+        assert_eq!(
+            swap.slippage,
+            Some(SlippageReport {
+                amount_out: U256::from(50),
+                min_met: false,
+                slippage_bps: Some(3750),
+            })
+        );
+    }
+
+    const SWAP_TOKEN_OUT: Address = address!("0x6000000000000000000000000000000000000006");
+    const SWAP_ROUTER: Address = address!("0x7000000000000000000000000000000000000007");
+    // Calls SWAP_TOKEN_OUT.transfer(msg.sender, 500) and reverts if it fails
+    const SWAP_ROUTER_CODE: Bytes = bytes!(
+        "63a9059cbb60e01b5f52336004526101f460245260205f60445f5f7360000000000000000000000000000000000000065af16038575f5ffd5b00"
+    );
+
+    /// Serves [`ERC20`] as both tokens, with [`SWAP_ROUTER`] holding 1000 of
+    /// [`SWAP_TOKEN_OUT`] and `user` 100, and rejects `eth_callMany` so that swaps run in
+    /// REVM.
+    fn router_chain(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "eth_getCode" => {
+                let address: Address = serde_json::from_value(params[0].clone()).unwrap();
+                match address {
+                    MOCK_TOKEN | SWAP_TOKEN_OUT => Ok(json!(ERC20)),
+                    SWAP_ROUTER => Ok(json!(SWAP_ROUTER_CODE)),
+                    _ => Ok(json!("0x")),
+                }
+            }
+            "eth_getStorageAt" => {
+                let address: Address = serde_json::from_value(params[0].clone()).unwrap();
+                let slot: U256 = serde_json::from_value(params[1].clone()).unwrap();
+                let balance = [(SWAP_ROUTER, 1000), (usdc_params().user, 100)]
+                    .into_iter()
+                    .find(|(holder, _)| slot == derive_user_slot(U256::from(3), *holder))
+                    .filter(|_| address == SWAP_TOKEN_OUT)
+                    .map_or(0, |(_, balance)| balance);
+                Ok(json!(B256::from(U256::from(balance))))
+            }
+            "eth_callMany" => Err((-32601, "method not found".to_string())),
+            _ => token_chain(method, params),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_simulate_swap_through_router() {
+        let rpc_url = spawn_mock_rpc(router_chain).await;
+        let params = |router, calldata| SwapParams {
+            user: usdc_params().user,
+            token_in: MOCK_TOKEN,
+            amount_in: U256::from(1000),
+            token_out: SWAP_TOKEN_OUT,
+            router,
+            calldata,
+            rpc_headers: RpcHeaders::default(),
+            min_amount_out: None,
+            expected_amount_out: None,
+        };
+        let mut simulator = Simulator::new();
+
+        let swap = simulator
+            .simulate_swap(
+                1,
+                rpc_url.clone(),
+                params(SWAP_ROUTER, Bytes::new()),
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(swap.path, SimulationPath::Revm);
+        assert_eq!(swap.amount_out, U256::from(500));
+        assert!(swap.gas_used > 21_000);
+
+        // The "swap" sends 40 of the user's token_out away instead
+        let calldata = transferCall {
+            to: SWAP_ROUTER,
+            value: U256::from(40),
+        }
+        .abi_encode();
+        let result = simulator
+            .simulate_swap(
+                1,
+                rpc_url,
+                params(SWAP_TOKEN_OUT, calldata.into()),
+                &CancellationToken::new(),
+            )
+            .await;
+
+        assert!(matches!(
+            result,
+            Err(SimulateError::AmountOutNegative { loss }) if loss == U256::from(40)
+        ));
+    }
+
+    /// [`router_chain`], with the node running the swap
+    fn router_rpc_chain(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "eth_callMany" => swap_chain(method, params),
+            _ => router_chain(method, params),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_both_strategy_profiles_the_compared_run() {
+        let params = |strategy| SimulationParams {
+            token_in: MOCK_TOKEN,
+            amount_in: U256::from(1000),
+            to: SWAP_ROUTER,
+            calldata: Bytes::new(),
+            token_out: Some(SWAP_TOKEN_OUT),
+            strategy,
+            gas_profile: true,
+            state_diff: true,
+            ..usdc_params()
+        };
+
+        // REVM alone, on a fresh state
+        let rpc_url = spawn_mock_rpc(router_chain).await;
+        let revm = Simulator::new()
+            .simulate(1, rpc_url, params(None), &CancellationToken::new())
+            .await
+            .unwrap();
+        assert_eq!(revm.path, SimulationPath::Revm);
+
+        let rpc_url = spawn_mock_rpc(router_rpc_chain).await;
+        let both = Simulator::new()
+            .simulate(
+                1,
+                rpc_url,
+                params(Some(SimulationStrategy::Both)),
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(both.path, SimulationPath::Rpc);
+
+        // A second REVM run would start with the router already paid out
+        let revm_output = both.divergence.unwrap().revm_output.unwrap();
+        assert_eq!(revm_output.gas_used, revm.gas_used);
+        assert_eq!(revm_output.amount_out, Some(U256::from(500)));
+        assert_eq!(both.gas_profile, revm.gas_profile);
+        let token_out_writes = &both.state_diff.unwrap()[&SWAP_TOKEN_OUT];
+        assert_eq!(
+            token_out_writes[&derive_user_slot(U256::from(3), SWAP_ROUTER)],
+            U256::from(500)
+        );
+        assert_eq!(
+            token_out_writes[&derive_user_slot(U256::from(3), usdc_params().user)],
+            U256::from(600)
+        );
+    }
+
+    const PUSH0_TARGET: Address = address!("0x5000000000000000000000000000000000000005");
+    // PUSH0 PUSH0 MSTORE PUSH1 0x20 PUSH0 RETURN: returns a zero word, using PUSH0
+    const PUSH0_RETURNER: Bytes = bytes!("5f5f5260205ff3");
+
+    /// Serves [`PUSH0_RETURNER`] at [`PUSH0_TARGET`], and rejects `eth_callMany` so that
+    /// simulations run in REVM.
+    fn push0_chain(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "eth_getCode" => {
+                let address: Address = serde_json::from_value(params[0].clone()).unwrap();
+                match address {
+                    PUSH0_TARGET => Ok(json!(PUSH0_RETURNER)),
+                    _ => Ok(json!("0x")),
+                }
+            }
+            "eth_callMany" => Err((-32601, "method not found".to_string())),
+            _ => token_chain(method, params),
+        }
+    }
+
+    /// [`push0_chain`] before Shanghai introduced PUSH0
+    fn pre_shanghai_chain(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "eth_blockNumber" => Ok(json!(U64::from(16_000_000))),
+            _ => push0_chain(method, params),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_push0_is_invalid_before_shanghai() {
+        let params = || SimulationParams {
+            to: PUSH0_TARGET,
+            calldata: bytes!("01"),
+            raw: true,
+            ..usdc_params()
+        };
+
+        let rpc_url = spawn_mock_rpc(push0_chain).await;
+        let output = Simulator::new()
+            .simulate(1, rpc_url, params(), &CancellationToken::new())
+            .await
+            .unwrap();
+        assert_eq!(output.path, SimulationPath::Revm);
+        assert_eq!(output.result.unwrap(), U256::ZERO.abi_encode());
+
+        let rpc_url = spawn_mock_rpc(pre_shanghai_chain).await;
+        let error = Simulator::new()
+            .simulate(1, rpc_url, params(), &CancellationToken::new())
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                &error,
+                SimulateError::BothSimulationsFailed(both) if matches!(
+                    both.revm_error,
+                    SimulateViaRevmError::Halt { reason: HaltReason::NotActivated, .. }
+                )
+            ),
+            "{error:?}"
+        );
+    }
+
+    #[test]
+    fn test_custom_error_revert_data_is_preserved() {
+        // PUSH4 0x12345678 PUSH0 MSTORE PUSH1 4 PUSH1 0x1c REVERT: reverts with a
+        // bare custom error selector
+        let target = address!("0x5000000000000000000000000000000000000005");
+        let mut db = offline_db(&[(TOKEN, ERC20), (target, bytes!("63123456785f526004601cfd"))]);
+        let params = SimulationParams {
+            user: USER,
+            token_in: TOKEN,
+            to: target,
+            calldata: bytes!("deadbeef"),
+            ..Default::default()
+        };
+
+        let output = simulate_via_revm(&params, &mut db, &[], &Approval::Transaction).unwrap();
+
+        assert!(output.result.is_err());
+        assert_eq!(output.revert_data, Some(bytes!("12345678")));
+
+        let responses = [
+            uint_response(100),
+            uint_response(1),
+            detailed(TransactionResponse::Reverted {
+                reason: "custom error 0x12345678".to_string(),
+                data: None,
+            }),
+            uint_response(100),
+        ];
+        let rpc_output = parse_bundle_responses(&swap_params(), true, &responses).unwrap();
+
+        assert_eq!(rpc_output.revert_data, Some(bytes!("12345678")));
+    }
+
+    static PENDING_REQUESTED_BLOCKS: Mutex<Vec<Value>> = Mutex::new(Vec::new());
+
+    /// Same chain as [`token_chain`], serving a pending block on top of [`MOCK_BLOCK`].
+    /// Records the block each state read, header fetch and `eth_callMany` targets.
+    fn pending_chain(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        let block = match method {
+            "eth_getBalance" | "eth_getTransactionCount" | "eth_getCode" => params[1].clone(),
+            "eth_getStorageAt" => params[2].clone(),
+            "eth_callMany" => params[1]["blockNumber"].clone(),
+            "eth_getBlockByNumber" => params[0].clone(),
+            _ => return token_chain(method, params),
+        };
+        PENDING_REQUESTED_BLOCKS.lock().unwrap().push(block);
+
+        match method {
+            "eth_getBlockByNumber" if params[0] == "pending" => Ok(json!({
+                "number": U64::from(MOCK_BLOCK + 1),
+                "timestamp": U64::from(1_753_000_000),
+                "baseFeePerGas": U64::from(7),
+            })),
+            "eth_getBlockByNumber" => Ok(Value::Null),
+            _ => token_chain(method, params),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_pending_block_is_read_by_both_backends() {
+        let rpc_url = spawn_mock_rpc(pending_chain).await;
+        let params = SimulationParams {
+            token_in: MOCK_TOKEN,
+            block: SimulationBlock::Pending,
+            ..usdc_params()
+        };
+
+        let output = Simulator::new()
+            .simulate(1, rpc_url, params, &CancellationToken::new())
+            .await
+            .unwrap();
+
+        let requested_blocks = PENDING_REQUESTED_BLOCKS.lock().unwrap();
+        assert_eq!(output.block_number, MOCK_BLOCK + 1);
+        assert_eq!(output.effective_gas_price, Some(7));
+        assert!(requested_blocks.len() > 1);
+        assert!(
+            requested_blocks.iter().all(|block| *block == "pending"),
+            "requested {requested_blocks:?}"
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_pending_block_unsupported() {
+        fn no_pending_chain(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+            match method {
+                "eth_getBlockByNumber" => Ok(Value::Null),
+                _ => token_chain(method, params),
+            }
+        }
+        let rpc_url = spawn_mock_rpc(no_pending_chain).await;
+        let params = SimulationParams {
+            token_in: MOCK_TOKEN,
+            block: SimulationBlock::Pending,
+            ..usdc_params()
+        };
+
+        let error = Simulator::new()
+            .simulate(1, rpc_url, params, &CancellationToken::new())
+            .await
+            .unwrap_err();
+
+        assert!(matches!(error, SimulateError::PendingUnsupported { .. }));
+        assert_eq!(error.code(), "pending_unsupported");
+    }
+
+    #[cfg(feature = "network-tests")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_usdc_transfer_on_pending_block() {
+        dotenvy::dotenv().ok();
+        let rpc_url: Url = std::env::var("ETH_RPC")
+            .expect("ETH_RPC not set in .env")
+            .parse()
+            .unwrap();
+        let params = SimulationParams {
+            block: SimulationBlock::Pending,
+            ..usdc_params()
+        };
+
+        let mut simulator = Simulator::new();
+        let latest = simulator
+            .check_rpc(1, rpc_url.clone(), &RpcHeaders::default())
+            .await
+            .unwrap();
+        let output = simulator
+            .simulate(1, rpc_url, params, &CancellationToken::new())
+            .await
+            .unwrap();
+
+        assert!(output.result.is_ok());
+        assert!(output.block_number > latest.block_number);
+    }
+
+    /// Serves [`token_chain`], with the call of the bundle reverting
+    fn reverting_chain(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "eth_callMany" => Ok(json!([[
+                { "value": B256::with_last_byte(1) },
+                { "error": "execution reverted: STF" },
+            ]])),
+            _ => token_chain(method, params),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_simulate_outcomes() {
+        let token_rpc = spawn_mock_rpc(token_chain).await;
+        let reverting_rpc = spawn_mock_rpc(reverting_chain).await;
+        let revm_rpc = spawn_mock_rpc(erc20_revm_chain).await;
+        let on_spender = |code: Option<Bytes>| SimulationParams {
+            token_in: MOCK_TOKEN,
+            to: MOCK_SPENDER,
+            code_overrides: code
+                .map(|code| HashMap::from([(MOCK_SPENDER, code)]))
+                .unwrap_or_default(),
+            ..usdc_params()
+        };
+        let cases = [
+            (&token_rpc, on_spender(None), "rpc_success"),
+            (&reverting_rpc, on_spender(None), "rpc_revert"),
+            (&revm_rpc, on_spender(None), "rpc_failed_revm_success"),
+            // PUSH0 PUSH0 REVERT
+            (
+                &revm_rpc,
+                on_spender(Some(bytes!("5f5ffd"))),
+                "rpc_failed_revm_revert",
+            ),
+            // REVM runs first on a local fork, the node never failing
+            (
+                &revm_rpc,
+                SimulationParams {
+                    provider_profile: ProviderProfile::LocalFork,
+                    ..on_spender(None)
+                },
+                "revm_success",
+            ),
+            (
+                &revm_rpc,
+                SimulationParams {
+                    provider_profile: ProviderProfile::LocalFork,
+                    ..on_spender(Some(bytes!("5f5ffd")))
+                },
+                "revm_revert",
+            ),
+            // INVALID
+            (&revm_rpc, on_spender(Some(bytes!("fe"))), "both_failed"),
+            (
+                &token_rpc,
+                SimulationParams {
+                    token_in: MOCK_TOKEN,
+                    to: address!("0x4000000000000000000000000000000000000004"),
+                    calldata: bytes!("deadbeef"),
+                    ..usdc_params()
+                },
+                "error",
+            ),
+        ];
+
+        for (rpc_url, params, expected) in cases {
+            let outcome = Simulator::new()
+                .simulate_outcome(1, rpc_url.clone(), params, &CancellationToken::new())
+                .await;
+
+            assert_eq!(outcome.as_str(), expected, "{outcome:?}");
+            assert_eq!(
+                outcome.output().map(|output| output.result.is_ok()),
+                match expected {
+                    "rpc_success" | "rpc_failed_revm_success" | "revm_success" => Some(true),
+                    "rpc_revert" | "rpc_failed_revm_revert" | "revm_revert" => Some(false),
+                    _ => None,
+                }
+            );
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_cache_stats_grow_and_reset() {
+        let rpc_url = spawn_mock_rpc(token_chain).await;
+        let params = SimulationParams {
+            token_in: MOCK_TOKEN,
+            ..usdc_params()
+        };
+        let mut simulator = Simulator::new();
+        assert_eq!(simulator.cache_stats(), CacheStats::default());
+
+        simulator
+            .simulate(1, rpc_url, params, &CancellationToken::new())
+            .await
+            .unwrap();
+        let stats = simulator.cache_stats();
+        assert_eq!(stats.chains, 1);
+        assert!(stats.accounts_per_chain[&1] > 0);
+        assert!(stats.approx_bytes > 0);
+
+        simulator.clear_cache(None);
+        assert_eq!(simulator.cache_stats(), CacheStats::default());
+    }
+
+    const MOCK_NFT: Address = address!("0x6000000000000000000000000000000000000006");
+    const NFT_HOLDER: Address = address!("0x7000000000000000000000000000000000000007");
+    // Returns 1 when the caller owns MOCK_NFT token 1, reverts otherwise
+    const NFT_GATE: Bytes = bytes!(
+        "636352211e60e01b5f52600160045260205f60245f7360000000000000000000000000000000000000065afa5f513314166037575f5ffd5b60015f5260205ff3"
+    );
+
+    /// Serves [`erc20_revm_chain`], with [`NFT_HOLDER`] owning token 1 of [`MOCK_NFT`]
+    /// and [`NFT_GATE`] at [`MOCK_SPENDER`]
+    fn nft_chain(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        let address: Address = serde_json::from_value(params[0].clone()).unwrap_or_default();
+        match (method, address) {
+            ("eth_getCode", MOCK_NFT) => Ok(json!(OWNER_OF)),
+            ("eth_getCode", MOCK_SPENDER) => Ok(json!(NFT_GATE)),
+            ("eth_getStorageAt", MOCK_NFT) => {
+                let slot: U256 = serde_json::from_value(params[1].clone()).unwrap();
+                let owner_slot = keccak256((U256::from(1), U256::from(2)).abi_encode());
+                match slot == U256::from_be_bytes(owner_slot.0) {
+                    true => Ok(json!(NFT_HOLDER.into_word())),
+                    false => Ok(json!(B256::ZERO)),
+                }
+            }
+            _ => erc20_revm_chain(method, params),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_owned_nft_passes_ownership_gate() {
+        let rpc_url = spawn_mock_rpc(nft_chain).await;
+        let params = |owned_nfts| SimulationParams {
+            token_in: MOCK_TOKEN,
+            to: MOCK_SPENDER,
+            calldata: bytes!("deadbeef"),
+            owned_nfts,
+            ..usdc_params()
+        };
+        let mut simulator = Simulator::new();
+
+        let gated = simulator
+            .simulate(
+                1,
+                rpc_url.clone(),
+                params(vec![]),
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        assert!(gated.result.is_err());
+
+        let owned = simulator
+            .simulate(
+                1,
+                rpc_url,
+                params(vec![(MOCK_NFT, U256::from(1))]),
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(owned.result, Ok(B256::with_last_byte(1).into()));
+    }
+
+    // PUSH0 PUSH0 SSTORE STOP: clears slot 0
+    const STORAGE_CLEARER: Bytes = bytes!("5f5f5500");
+
+    /// Like [`erc20_revm_chain`], with [`STORAGE_CLEARER`] at [`MOCK_SPENDER`] and its
+    /// slot 0 set.
+    fn storage_clearing_chain(method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        let address: Address = serde_json::from_value(params[0].clone()).unwrap_or_default();
+        match (method, address) {
+            ("eth_getCode", MOCK_SPENDER) => Ok(json!(STORAGE_CLEARER)),
+            ("eth_getStorageAt", MOCK_SPENDER) => Ok(json!(B256::with_last_byte(1))),
+            _ => erc20_revm_chain(method, params),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_cleared_storage_is_refunded() {
+        let rpc_url = spawn_mock_rpc(storage_clearing_chain).await;
+        let params = SimulationParams {
+            token_in: MOCK_TOKEN,
+            to: MOCK_SPENDER,
+            calldata: bytes!("deadbeef"),
+            ..usdc_params()
+        };
+
+        let output = Simulator::new()
+            .simulate(1, rpc_url, params, &CancellationToken::new())
+            .await
+            .unwrap();
+
+        assert_eq!(output.path, SimulationPath::Revm);
+        assert_eq!(output.result, Ok(Bytes::new()));
+        // EIP-3529 refund of resetting a slot to zero
+        assert_eq!(output.gas_refunded, Some(4_800));
+        assert!(output.gas_refunded < output.gas_used);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_delegation_does_not_leak_into_cache() {
+        let rpc_url = spawn_mock_rpc(erc20_revm_chain).await;
+        let signer = PrivateKeySigner::from_bytes(&B256::with_last_byte(1)).unwrap();
+        let params = SimulationParams {
+            user: signer.address(),
+            token_in: MOCK_TOKEN,
+            to: MOCK_TOKEN,
+            authorization_list: vec![delegate_to(&signer, MOCK_SPENDER)],
+            ..usdc_params()
+        };
+
+        let mut simulator = Simulator::new();
+        let output = simulator
+            .simulate(1, rpc_url, params, &CancellationToken::new())
+            .await
+            .unwrap();
+
+        assert_eq!(output.path, SimulationPath::Revm);
+        assert!(
+            !simulator.db_caches[&1]
+                .accounts
+                .contains_key(&signer.address())
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_nonce_override_is_checked_and_evicted() {
+        let rpc_url = spawn_mock_rpc(erc20_revm_chain).await;
+        let params = |nonce| SimulationParams {
+            token_in: MOCK_TOKEN,
+            to: MOCK_TOKEN,
+            nonce: Some(nonce),
+            ..usdc_params()
+        };
+
+        let mut simulator = Simulator::new();
+        let error = simulator
+            .simulate(
+                1,
+                rpc_url.clone(),
+                params(u64::MAX - 1),
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            SimulateError::NonceOverflow {
+                transactions: 2,
+                ..
+            }
+        ));
+        assert_eq!(error.code(), "invalid_params");
+
+        simulator
+            .simulate(1, rpc_url, params(7), &CancellationToken::new())
+            .await
+            .unwrap();
+        assert!(
+            !simulator.db_caches[&1]
+                .accounts
+                .contains_key(&usdc_params().user)
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_simulate_bundles_caches_slots() {
+        let rpc_url = spawn_mock_rpc(timestamp_echo_chain).await;
+        let params = || SimulationParams {
+            token_in: MOCK_TOKEN,
+            ..usdc_params()
+        };
+        let bundles = || {
+            vec![CallBundle {
+                steps: vec![CallStep {
+                    to: MOCK_SPENDER,
+                    ..Default::default()
+                }],
+                timestamp: Some(1_700_000_000),
+                ..Default::default()
+            }]
+        };
+
+        let mut simulator = Simulator::new();
+        let output = simulator
+            .simulate_bundles(
+                1,
+                rpc_url.clone(),
+                params(),
+                bundles(),
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+
+        let layout = output.balance_layout.unwrap();
+        assert_eq!(simulator.cached_slots(1, &params()).layout, Some(layout));
+
+        let error = simulator
+            .simulate_bundles(
+                1,
+                rpc_url,
+                SimulationParams {
+                    nonce: Some(u64::MAX - 1),
+                    ..params()
+                },
+                bundles(),
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            SimulateError::NonceOverflow {
+                transactions: 2,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_allowance_slot_lookup_failure_is_reported() {
+        // PUSH1 1 PUSH0 MSTORE PUSH1 0x20 PUSH0 RETURN: every view returns 1, whatever
+        // its storage holds
+        let mut db = offline_db(&[(TOKEN, bytes!("60015f5260205ff3"))]);
+        let params = SimulationParams {
+            user: USER,
+            token_in: TOKEN,
+            to: TOKEN,
+            approve_mode: Some(ApproveMode::SlotOverride),
+            ..Default::default()
+        };
+
+        let funding = resolve_funding(&params, &mut db, None, None, None).unwrap();
+
+        assert_eq!(funding.approval, Approval::Transaction);
+        assert!(matches!(
+            funding.allowance_slot_err,
+            Some(FindSlotError::FindSlotByMutation(_))
+        ));
+    }
 }